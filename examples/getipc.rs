@@ -1,7 +1,7 @@
 use anyhow::Result;
 use rustable::medusa::{
-    AccessType, Config, ConfigError, Connection, Context, HandlerArgs, HandlerFlags, MedusaAnswer,
-    Node, SpaceBuilder, Tree,
+    AccessType, Config, ConfigError, Connection, Context, Event, HandlerArgs, HandlerFlags,
+    MedusaAnswer, Node, SpaceBuilder, Tree,
 };
 use rustable_codegen::handler;
 use std::fs::OpenOptions;
@@ -15,7 +15,7 @@ async fn getprocess_handler(ctx: &Context, args: HandlerArgs<'_>) -> Result<Medu
     let evtype = args.evtype;
     let mut subject = args.subject;
 
-    subject.enter_tree(ctx, &evtype, "domains", "/").await;
+    subject.enter_tree(ctx, &evtype, "domains", "/").await?;
 
     println!(
         "subject cmdline = {}",
@@ -28,7 +28,7 @@ async fn getprocess_handler(ctx: &Context, args: HandlerArgs<'_>) -> Result<Medu
         subject.set_attribute("med_sact", 0x3fffffff)?;
     }
 
-    subject.update(ctx).await;
+    subject.update(ctx).await?;
 
     Ok(MedusaAnswer::Allow)
 }
@@ -44,7 +44,7 @@ async fn getipc_handler(ctx: &Context, args: HandlerArgs<'_>) -> Result<MedusaAn
     subject.clear_vs()?;
     subject.add_vs(*ctx.config().name_to_space_bit("all_files").unwrap())?;
 
-    subject.update(ctx).await;
+    subject.update(ctx).await?;
 
     Ok(MedusaAnswer::Allow)
 }
@@ -124,7 +124,7 @@ fn create_config() -> Result<Config, ConfigError> {
         .add_space(special)
         .add_space(one)
 
-        .add_hierarchy_event_handler("getfile", "fs", Some("filename"), HandlerFlags::FROM_OBJECT)
+        .add_hierarchy_event_handler(Event::GetFile, "fs", Some("filename"), HandlerFlags::FROM_OBJECT)
         .add_custom_event_handler(getprocess_handler)
         .add_custom_event_handler(getipc_handler)
         .add_custom_event_handler(msgsnd_handler)
@@ -1,6 +1,7 @@
 use anyhow::Result;
 use rustable::medusa::{
-    Config, ConfigError, Connection, Context, HandlerArgs, HandlerFlags, MedusaAnswer, SpaceBuilder,
+    Config, ConfigError, Connection, Context, Event, HandlerArgs, HandlerFlags, MedusaAnswer,
+    SpaceBuilder,
 };
 use rustable_codegen::handler;
 use std::fs::OpenOptions;
@@ -12,7 +13,7 @@ async fn getprocess_handler(ctx: &Context, args: HandlerArgs<'_>) -> Result<Medu
     let evtype = args.evtype;
     let mut subject = args.subject;
 
-    subject.enter_tree(ctx, &evtype, "domains", "/").await;
+    subject.enter_tree(ctx, &evtype, "domains", "/").await?;
 
     Ok(MedusaAnswer::Allow)
 }
@@ -44,7 +45,7 @@ fn create_config() -> Result<Config, ConfigError> {
         .add_space(all_files)
         .add_space(all_domains)
 
-        .add_hierarchy_event_handler("getfile", "fs", Some("filename"), HandlerFlags::FROM_OBJECT)
+        .add_hierarchy_event_handler(Event::GetFile, "fs", Some("filename"), HandlerFlags::FROM_OBJECT)
         .add_custom_event_handler(getprocess_handler)
         .add_custom_event_handler(mkdir_handler)
         .add_custom_event_handler(rmdir_handler)
@@ -1,8 +1,10 @@
 //! https://gitlab.com/apparmor/apparmor/-/blob/eb8f9302aa664e8ac84a03eaf11b1cb1372b1e44/profiles/apparmor/profiles/extras/usr.sbin.sshd
 
+#![allow(clippy::vec_init_then_push)]
+
 use anyhow::Result;
 use rustable::medusa::{
-    Config, ConfigBuilder, ConfigError, Connection, Context, HandlerArgs, HandlerFlags,
+    Config, ConfigBuilder, ConfigError, Connection, Context, Event, HandlerArgs, HandlerFlags,
     MedusaAnswer, SpaceBuilder,
 };
 use rustable_codegen::handler;
@@ -21,16 +23,16 @@ async fn getprocess_handler(ctx: &Context, args: HandlerArgs<'_>) -> Result<Medu
     if cmdline.contains("/usr/sbin/sshd") {
         subject
             .enter_tree(ctx, &evtype, "domains", "/usr/sbin/sshd")
-            .await;
+            .await?;
     } else if cmdline.contains("/usr/bin/passwd") {
         subject
             .enter_tree(ctx, &evtype, "domains", "/usr/bin/passwd")
-            .await;
+            .await?;
     } else {
-        subject.enter_tree(ctx, &evtype, "domains", "/").await;
+        subject.enter_tree(ctx, &evtype, "domains", "/").await?;
     }
 
-    subject.update(ctx).await;
+    subject.update(ctx).await?;
 
     Ok(MedusaAnswer::Allow)
 }
@@ -228,7 +230,7 @@ fn create_config() -> Result<Config, ConfigError> {
         .add_space(krb5cc)
         .add_space(sshd)
         .add_spaces(reads)
-        .add_hierarchy_event_handler("getfile", "fs", Some("filename"), HandlerFlags::FROM_OBJECT)
+        .add_hierarchy_event_handler(Event::GetFile, "fs", Some("filename"), HandlerFlags::FROM_OBJECT)
         .add_custom_event_handler(getprocess_handler)
         .build()
 }
@@ -2,8 +2,13 @@ use proc_macro::TokenStream;
 use quote::{format_ident, quote};
 use syn::parse_macro_input;
 
+enum EventArg {
+    Known(syn::LitStr),
+    Custom(syn::LitStr),
+}
+
 struct Args {
-    event: syn::LitStr,
+    event: EventArg,
     subject: syn::LitStr,
     object: Option<syn::LitStr>,
 }
@@ -11,6 +16,7 @@ struct Args {
 impl Args {
     fn new(args: syn::AttributeArgs) -> syn::Result<Self> {
         let mut event = None;
+        let mut custom_event = None;
         let mut subject = None;
         let mut object = None;
 
@@ -27,6 +33,16 @@ impl Args {
                                 ))
                             }
                         }
+                    } else if nv.path.is_ident("custom_event") {
+                        match nv.lit {
+                            syn::Lit::Str(val) => custom_event = Some(val),
+                            _ => {
+                                return Err(syn::Error::new_spanned(
+                                    nv.lit,
+                                    "Expects string literal for attribute custom_event.",
+                                ))
+                            }
+                        }
                     } else if nv.path.is_ident("subject_vs") {
                         match nv.lit {
                             syn::Lit::Str(val) => subject = Some(val),
@@ -55,8 +71,20 @@ impl Args {
             }
         }
 
+        let event = match (event, custom_event) {
+            (Some(event), None) => EventArg::Known(event),
+            (None, Some(event)) => EventArg::Custom(event),
+            (Some(_), Some(custom_event)) => {
+                return Err(syn::Error::new_spanned(
+                    custom_event,
+                    "Attributes event and custom_event are mutually exclusive.",
+                ))
+            }
+            (None, None) => panic!("Missing mandatory attribute event"),
+        };
+
         Ok(Self {
-            event: event.expect("Missing mandatory attribute event"),
+            event,
             subject: subject.expect("Missing mandatory attribute subject"),
             object,
         })
@@ -79,6 +107,7 @@ pub(crate) fn new(args: TokenStream, input: TokenStream) -> TokenStream {
     };
 
     let struct_name = ast.sig.ident.clone();
+    let name = struct_name.to_string();
 
     let Args {
         event,
@@ -95,6 +124,23 @@ pub(crate) fn new(args: TokenStream, input: TokenStream) -> TokenStream {
         quote!(::rustable::medusa::Space::ByName(#subject))
     };
 
+    // names of known events are checked at compile time
+    let event = match event {
+        EventArg::Known(event) => quote!({
+            const EVENT: ::rustable::medusa::Event = ::rustable::medusa::Event::from_name(#event);
+            const _: () = ::std::assert!(
+                EVENT.is_known(),
+                ::std::concat!(
+                    "unknown event `",
+                    #event,
+                    "`, use custom_event for events unknown to rustable"
+                )
+            );
+            EVENT
+        }),
+        EventArg::Custom(event) => quote!(::rustable::medusa::Event::Custom(#event)),
+    };
+
     let object = match object {
         Some(object) => {
             if object.value() == "*" {
@@ -115,6 +161,7 @@ pub(crate) fn new(args: TokenStream, input: TokenStream) -> TokenStream {
         impl ::rustable::medusa::handler::CustomHandler for #struct_name {
             fn define(self) -> ::rustable::medusa::handler::CustomHandlerDef {
                 ::rustable::medusa::handler::CustomHandlerDef {
+                    name: #name,
                     event: #event,
                     subject: #subject,
                     object: #object,
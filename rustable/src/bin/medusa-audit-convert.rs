@@ -0,0 +1,47 @@
+//! Converter of audit records in the compact binary format to JSON or CSV, see
+//! [`rustable::medusa::compact`].
+//!
+//! ```text
+//! medusa-audit-convert json|csv [<input>]
+//! ```
+//!
+//! Reads the records written by `CompactSink` from `<input>`, or from the standard input if it
+//! is not given, and writes them to the standard output.
+
+use anyhow::Context;
+use rustable::medusa::compact::{self, ConvertFormat};
+use std::fs::File;
+use std::io::{self, BufWriter, Read};
+use std::process::ExitCode;
+
+fn main() -> anyhow::Result<ExitCode> {
+    let args = std::env::args().collect::<Vec<_>>();
+    let (format, input) = match &args[1..] {
+        [format] => (format, None),
+        [format, input] => (format, Some(input)),
+        _ => {
+            eprintln!("usage: {} json|csv [<input>]", args[0]);
+            return Ok(ExitCode::from(2));
+        }
+    };
+
+    let format = match format.as_str() {
+        "json" => ConvertFormat::Json,
+        "csv" => ConvertFormat::Csv,
+        _ => {
+            eprintln!("unknown format `{}`, expected `json` or `csv`", format);
+            return Ok(ExitCode::from(2));
+        }
+    };
+
+    let input: Box<dyn Read> = match input {
+        Some(path) => Box::new(File::open(path).with_context(|| format!("cannot open {}", path))?),
+        None => Box::new(io::stdin().lock()),
+    };
+    let output = BufWriter::new(io::stdout().lock());
+
+    let converted = compact::convert(input, output, format)?;
+    eprintln!("{} records converted", converted);
+
+    Ok(ExitCode::SUCCESS)
+}
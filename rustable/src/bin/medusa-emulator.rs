@@ -0,0 +1,62 @@
+//! Emulated security module for testing authorization servers without the Medusa LSM, see
+//! [`rustable::medusa::emulator`].
+//!
+//! ```text
+//! medusa-emulator <script.yaml> <socket>
+//! ```
+//!
+//! Listens on Unix socket `<socket>`, plays the script to the first authorization server
+//! connecting to it and prints the answers. Exits with a non-zero status if an answer differs
+//! from the expected one.
+
+use anyhow::Context;
+use rustable::medusa::emulator::{Emulator, Script};
+use std::fs;
+use std::os::unix::net::UnixListener;
+use std::process::ExitCode;
+use std::time::Duration;
+
+/// Time to wait for a single message of the authorization server.
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+fn main() -> anyhow::Result<ExitCode> {
+    let args = std::env::args().collect::<Vec<_>>();
+    let (script, socket) = match &args[1..] {
+        [script, socket] => (script, socket),
+        _ => {
+            eprintln!("usage: {} <script.yaml> <socket>", args[0]);
+            return Ok(ExitCode::from(2));
+        }
+    };
+
+    let script = Script::load(script).with_context(|| format!("cannot load {}", script))?;
+    let mut emulator = Emulator::new(script)?;
+
+    let _ = fs::remove_file(socket);
+    let listener = UnixListener::bind(socket).with_context(|| format!("cannot bind {}", socket))?;
+    println!("waiting for an authorization server on {}", socket);
+
+    let (mut stream, _) = listener.accept()?;
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+    let report = emulator.run(&mut stream)?;
+
+    for outcome in &report.outcomes {
+        match outcome.expected {
+            Some(expected) if !outcome.is_expected() => println!(
+                "request {} {}: {:?}, expected {:?}",
+                outcome.request_id, outcome.event, outcome.answer, expected
+            ),
+            _ => println!(
+                "request {} {}: {:?}",
+                outcome.request_id, outcome.event, outcome.answer
+            ),
+        }
+    }
+    println!("{} updates, {} fetches", report.updates, report.fetches);
+
+    Ok(if report.is_success() {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    })
+}
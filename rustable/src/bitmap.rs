@@ -7,7 +7,7 @@ const BITMAP_BLOCK_MASK: usize = BITMAP_BLOCK_SIZE - 1;
 /// bytes are treated as ones.
 ///
 /// Returns an exclusive reference to `left`.
-pub fn and<'a, 'b>(left: &'a mut [u8], right: &'b [u8]) -> &'a mut [u8] {
+pub fn and<'a>(left: &'a mut [u8], right: &[u8]) -> &'a mut [u8] {
     let len = left.len().min(right.len());
 
     // optimize bounds checking
@@ -21,6 +21,20 @@ pub fn and<'a, 'b>(left: &'a mut [u8], right: &'b [u8]) -> &'a mut [u8] {
     left
 }
 
+/// Performs logical or, modifying the `left` argument. If the sizes do not match, additional
+/// bytes of `right` are ignored.
+///
+/// Returns an exclusive reference to `left`.
+pub fn or<'a>(left: &'a mut [u8], right: &[u8]) -> &'a mut [u8] {
+    let len = left.len().min(right.len());
+
+    for (l, r) in left[..len].iter_mut().zip(&right[..len]) {
+        *l |= r;
+    }
+
+    left
+}
+
 /// Returns `true` if all bits are 1.
 pub fn all(vec: &[u8]) -> bool {
     vec.iter().all(|&x| x == 0xff)
@@ -51,6 +65,11 @@ pub fn set_bit(vec: &mut [u8], n: usize) {
     vec[n / BITMAP_BLOCK_SIZE] |= 1 << (n & BITMAP_BLOCK_MASK);
 }
 
+/// Returns `true` if bit at an index `n` is 1.
+pub fn test_bit(vec: &[u8], n: usize) -> bool {
+    vec[n / BITMAP_BLOCK_SIZE] & (1 << (n & BITMAP_BLOCK_MASK)) != 0
+}
+
 /// Clears bit at an index `n`.
 pub fn clear_bit(vec: &mut [u8], n: usize) {
     vec[n / BITMAP_BLOCK_SIZE] &= !(1 << (n & BITMAP_BLOCK_MASK));
@@ -2,10 +2,10 @@
 //! [Medusa](https://github.com/Medusa-Team/linux-medusa) security module.
 //!
 //! # Example
-//! ```
+//! ```no_run
 //! use anyhow::Result;
 //! use rustable::medusa::{
-//!     Config, ConfigError, Connection, Context, HandlerArgs, HandlerFlags, MedusaAnswer,
+//!     Config, ConfigError, Connection, Context, Event, HandlerArgs, HandlerFlags, MedusaAnswer,
 //!     SpaceBuilder,
 //! };
 //! use rustable_codegen::handler;
@@ -16,7 +16,7 @@
 //!     let evtype = args.evtype;
 //!     let mut subject = args.subject;
 //!
-//!     subject.enter_tree(ctx, &evtype, "domains", "/").await;
+//!     subject.enter_tree(ctx, &evtype, "domains", "/").await?;
 //!
 //!     Ok(MedusaAnswer::Allow)
 //! }
@@ -37,7 +37,7 @@
 //!         .add_space(all_files)
 //!         .add_space(all_domains)
 //!
-//!         .add_hierarchy_event_handler("getfile", "fs", Some("filename"), HandlerFlags::FROM_OBJECT)
+//!         .add_hierarchy_event_handler(Event::GetFile, "fs", Some("filename"), HandlerFlags::FROM_OBJECT)
 //!         .add_custom_event_handler(getprocess_handler)
 //!         .build()
 //! }
@@ -65,7 +65,16 @@ extern crate lazy_static;
 pub mod bitmap;
 pub mod medusa;
 
-/// Converts null terminated bytes to [`std::string::String`].
+/// Returns `bytes` without the NUL padding at their end. Unlike [`cstr_to_string`], embedded
+/// NULs and invalid UTF-8 are kept, so that comparisons see exactly what the security module
+/// sent.
+pub fn trim_nul_padding(bytes: &[u8]) -> &[u8] {
+    let len = bytes.iter().rposition(|&b| b != 0).map_or(0, |x| x + 1);
+    &bytes[..len]
+}
+
+/// Converts null terminated bytes to [`std::string::String`]. Bytes after the first NUL are
+/// dropped and invalid UTF-8 is replaced, see [`trim_nul_padding`] for a lossless alternative.
 pub fn cstr_to_string(cstr: &[u8]) -> String {
     let vec = cstr
         .iter()
@@ -0,0 +1,225 @@
+//! Alerting on spikes of denials.
+
+use crate::medusa::audit::{AuditRecord, AuditSink};
+use crate::medusa::{CompletedRequest, Context};
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::io::Write;
+use std::os::unix::net::UnixDatagram;
+use std::process::{Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// keys without a denial in the current window are forgotten once there are more of them
+const MAX_IDLE_KEYS: usize = 1024;
+
+const SYSLOG_SOCKET: &str = "/dev/log";
+
+// facility `authpriv` (10) with severity `alert` (1)
+const SYSLOG_PRIORITY: u8 = 10 * 8 + 1;
+
+/// Function assigning denials into groups which are counted separately.
+pub type AlertKey = Arc<dyn Fn(&AuditRecord, &CompletedRequest) -> String + Send + Sync>;
+
+/// What happens when the threshold of an [`AlertSink`] is exceeded. The [`AlertSummary`] is
+/// passed as JSON.
+#[derive(Debug, Clone)]
+pub enum AlertAction {
+    /// Sends the summary as the body of an HTTP POST request to the URL.
+    #[cfg(feature = "webhook")]
+    Webhook(String),
+
+    /// Executes the program with arguments and writes the summary into its standard input.
+    Command(String, Vec<String>),
+
+    /// Sends the summary to the local syslog daemon as a message of the `authpriv` facility
+    /// with the `alert` severity.
+    Syslog,
+}
+
+/// Payload of an alert.
+#[derive(Debug, Clone, Serialize)]
+pub struct AlertSummary {
+    /// Group of the denials, see [`AlertSink::group_by`].
+    pub key: String,
+
+    /// Number of denials within the window.
+    pub denials: usize,
+
+    /// Length of the window in seconds.
+    pub window_secs: u64,
+
+    /// The last denial which triggered the alert.
+    pub last: AuditRecord,
+}
+
+/// Audit sink which triggers an [`AlertAction`] when more than `threshold` denials of one group
+/// occur within `window`. By default, denials are grouped by the subject class and its virtual
+/// spaces, so for example more than 50 denials per minute of one domain can be detected.
+///
+/// After an alert, the group is counted again from zero. Actions run in a separate thread.
+pub struct AlertSink {
+    threshold: usize,
+    window: Duration,
+    action: AlertAction,
+    key: AlertKey,
+    quarantine: bool,
+
+    denials: Mutex<HashMap<String, VecDeque<Instant>>>,
+}
+
+impl AlertSink {
+    /// Creates new `AlertSink`.
+    pub fn new(threshold: usize, window: Duration, action: AlertAction) -> Self {
+        Self {
+            threshold,
+            window,
+            action,
+            key: Arc::new(|record, _| {
+                format!("{}[{}]", record.subject, record.subject_spaces.join(","))
+            }),
+            quarantine: false,
+            denials: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Quarantines the subject of the denial which triggered an alert, see
+    /// [`Context::quarantine`].
+    ///
+    /// Returns `Self`.
+    pub fn quarantine(mut self) -> Self {
+        self.quarantine = true;
+        self
+    }
+
+    /// Sets the function which assigns denials into separately counted groups.
+    ///
+    /// Returns `Self`.
+    pub fn group_by<F>(mut self, key: F) -> Self
+    where
+        F: Fn(&AuditRecord, &CompletedRequest) -> String + Send + Sync + 'static,
+    {
+        self.key = Arc::new(key);
+        self
+    }
+
+    fn count(&self, key: String, now: Instant) -> Option<usize> {
+        let mut denials = self.denials.lock().unwrap();
+
+        if denials.len() > MAX_IDLE_KEYS {
+            denials.retain(|_, times| {
+                times
+                    .back()
+                    .is_some_and(|&last| now.duration_since(last) < self.window)
+            });
+        }
+
+        let times = denials.entry(key).or_default();
+        while let Some(&first) = times.front() {
+            if now.duration_since(first) < self.window {
+                break;
+            }
+            times.pop_front();
+        }
+        times.push_back(now);
+
+        if times.len() <= self.threshold {
+            return None;
+        }
+
+        let count = times.len();
+        times.clear();
+        Some(count)
+    }
+
+    fn fire(&self, summary: AlertSummary) {
+        println!(
+            "alert: {} denials of {} within {:?}",
+            summary.denials, summary.key, self.window
+        );
+
+        let action = self.action.clone();
+        thread::spawn(move || {
+            if let Err(e) = run_action(&action, &summary) {
+                eprintln!("alert action {:?} failed: {}", action, e);
+            }
+        });
+    }
+
+    /// Counts the denial of `record`, if it is one. Returns `true` if it triggered an alert.
+    fn record_denial(&self, record: &AuditRecord, completed: &CompletedRequest) -> bool {
+        if !record.is_denial() {
+            return false;
+        }
+
+        let key = (self.key)(record, completed);
+        let denials = match self.count(key.clone(), Instant::now()) {
+            Some(denials) => denials,
+            None => return false,
+        };
+
+        self.fire(AlertSummary {
+            key,
+            denials,
+            window_secs: self.window.as_secs(),
+            last: record.clone(),
+        });
+        true
+    }
+}
+
+impl AuditSink for AlertSink {
+    fn record(&self, _record: &AuditRecord) {
+        // grouping needs the complete request, see `record_completed`
+    }
+
+    fn record_completed(&self, record: &AuditRecord, completed: &CompletedRequest) {
+        self.record_denial(record, completed);
+    }
+
+    fn record_on(&self, ctx: &Context, record: &AuditRecord, completed: &CompletedRequest) {
+        if self.record_denial(record, completed) && self.quarantine {
+            let mut subject = completed.data.subject.clone();
+            println!("alert: quarantining subject {}", record.subject_id);
+            ctx.quarantine_no_wait(&mut subject);
+        }
+    }
+}
+
+/// Runs `action` with `payload` passed as JSON.
+pub(crate) fn run_action<T: Serialize>(action: &AlertAction, payload: &T) -> anyhow::Result<()> {
+    let payload = serde_json::to_vec(payload)?;
+
+    match action {
+        #[cfg(feature = "webhook")]
+        AlertAction::Webhook(url) => {
+            ureq::post(url)
+                .set("Content-Type", "application/json")
+                .send_bytes(&payload)?;
+        }
+        AlertAction::Command(program, args) => {
+            let mut child = Command::new(program)
+                .args(args)
+                .stdin(Stdio::piped())
+                .spawn()?;
+            child
+                .stdin
+                .take()
+                .expect("stdin is piped")
+                .write_all(&payload)?;
+            let status = child.wait()?;
+            if !status.success() {
+                anyhow::bail!("{}", status);
+            }
+        }
+        AlertAction::Syslog => {
+            let mut message = format!("<{}>rustable: ", SYSLOG_PRIORITY).into_bytes();
+            message.extend_from_slice(&payload);
+            let socket = UnixDatagram::unbound()?;
+            socket.send_to(&message, SYSLOG_SOCKET)?;
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,369 @@
+//! Detection of unusual access patterns, see [`ConfigBuilder::anomaly_detection`].
+//!
+//! [`ConfigBuilder::anomaly_detection`]: crate::medusa::ConfigBuilder::anomaly_detection
+
+use crate::medusa::{audit, AuthRequestData, Config, FastDashMap, FastHasher, MedusaAnswer};
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// Number of rows of a count-min sketch, each hashing with a different seed.
+const SKETCH_DEPTH: usize = 4;
+
+/// Default number of counters in a row of a count-min sketch, see
+/// [`AnomalyDetection::sketch_width`].
+const SKETCH_DEFAULT_WIDTH: usize = 1024;
+
+/// Learns which spaces processes of each domain access through which events and reports
+/// accesses deviating from what was learned.
+///
+/// During the learning window, allowed requests are counted per domain, the subject class and
+/// its virtual spaces, by the event and the virtual spaces of the object. Counts are kept in
+/// count-min sketches, so memory does not grow with the number of distinct accesses, while
+/// counts may only be overestimated. Afterwards, an access seen fewer than
+/// [`AnomalyDetection::min_count`] times while learning is a deviation. Deviations are
+/// reported, or denied with [`AnomalyDetection::deny`]. Domains which made no request while
+/// learning have no baseline and are not judged.
+///
+/// A compromised service suddenly touching unusual parts of the filesystem is surfaced this
+/// way, as long as the learning window saw only its regular behavior.
+#[derive(Debug, Clone)]
+pub struct AnomalyDetection {
+    learning: Duration,
+    min_count: u32,
+    deny: bool,
+    sketch_width: usize,
+}
+
+impl AnomalyDetection {
+    /// Creates new `AnomalyDetection` learning for `learning` after the connection is
+    /// established.
+    pub fn new(learning: Duration) -> Self {
+        Self {
+            learning,
+            min_count: 1,
+            deny: false,
+            sketch_width: SKETCH_DEFAULT_WIDTH,
+        }
+    }
+
+    /// Sets the number of times an access has to be seen while learning not to be a
+    /// deviation, 1 by default.
+    ///
+    /// Returns `Self`.
+    pub fn min_count(mut self, min_count: u32) -> Self {
+        self.min_count = min_count.max(1);
+        self
+    }
+
+    /// Denies deviations instead of only reporting them.
+    ///
+    /// Returns `Self`.
+    pub fn deny(mut self) -> Self {
+        self.deny = true;
+        self
+    }
+
+    /// Sets the number of counters in each of the rows of the sketch of a domain, 1024 by
+    /// default. Wider sketches overestimate less when domains make many distinct accesses.
+    ///
+    /// Returns `Self`.
+    pub fn sketch_width(mut self, width: usize) -> Self {
+        self.sketch_width = width.max(1);
+        self
+    }
+}
+
+/// Count-min sketch of accesses of a domain.
+struct Sketch {
+    width: usize,
+    counters: Box<[AtomicU32]>,
+}
+
+impl Sketch {
+    fn new(width: usize) -> Self {
+        Self {
+            width,
+            counters: (0..SKETCH_DEPTH * width)
+                .map(|_| AtomicU32::new(0))
+                .collect(),
+        }
+    }
+
+    fn add(&self, hashers: &[FastHasher; SKETCH_DEPTH], access: &str) {
+        for (row, hasher) in hashers.iter().enumerate() {
+            let i = row * self.width + hasher.hash_one(access) as usize % self.width;
+            let _ = self.counters[i]
+                .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |x| x.checked_add(1));
+        }
+    }
+
+    fn estimate(&self, hashers: &[FastHasher; SKETCH_DEPTH], access: &str) -> u32 {
+        hashers
+            .iter()
+            .enumerate()
+            .map(|(row, hasher)| {
+                let i = row * self.width + hasher.hash_one(access) as usize % self.width;
+                self.counters[i].load(Ordering::Relaxed)
+            })
+            .min()
+            .unwrap_or_default()
+    }
+}
+
+/// Baselines of the domains of a connection.
+pub(crate) struct AnomalyDetector {
+    settings: AnomalyDetection,
+    started: Instant,
+    hashers: [FastHasher; SKETCH_DEPTH],
+    sketches: FastDashMap<String, Sketch>,
+    deviations: AtomicU64,
+}
+
+impl AnomalyDetector {
+    pub(crate) fn new(settings: AnomalyDetection) -> Self {
+        let hashers = [(); SKETCH_DEPTH].map(|_| FastHasher::new());
+
+        Self {
+            settings,
+            started: Instant::now(),
+            hashers,
+            sketches: Default::default(),
+            deviations: AtomicU64::new(0),
+        }
+    }
+
+    /// Returns the number of deviations found so far.
+    pub(crate) fn deviations(&self) -> u64 {
+        self.deviations.load(Ordering::Relaxed)
+    }
+
+    /// Learns or judges the request answered with `answer`. Returns the answer, which is
+    /// [`MedusaAnswer::Deny`] for a denied deviation.
+    pub(crate) fn check(
+        &self,
+        config: &Config,
+        auth_data: &AuthRequestData,
+        answer: MedusaAnswer,
+    ) -> MedusaAnswer {
+        if matches!(answer, MedusaAnswer::Deny | MedusaAnswer::Err) {
+            return answer;
+        }
+
+        let domain = audit::domain_name(config, &auth_data.subject);
+        let accesses = accesses(config, auth_data);
+
+        if self.started.elapsed() < self.settings.learning {
+            let sketch = self
+                .sketches
+                .entry(domain)
+                .or_insert_with(|| Sketch::new(self.settings.sketch_width));
+            for access in &accesses {
+                sketch.add(&self.hashers, access);
+            }
+            return answer;
+        }
+
+        let sketch = match self.sketches.get(&domain) {
+            Some(sketch) => sketch,
+            None => return answer,
+        };
+        let deviation = accesses
+            .iter()
+            .find(|x| sketch.estimate(&self.hashers, x) < self.settings.min_count);
+        let deviation = match deviation {
+            Some(deviation) => deviation,
+            None => return answer,
+        };
+
+        self.deviations.fetch_add(1, Ordering::Relaxed);
+        if self.settings.deny {
+            println!(
+                "anomaly: request {} of {} denied, {} not learned",
+                auth_data.request_id, domain, deviation
+            );
+            return MedusaAnswer::Deny;
+        }
+
+        println!(
+            "anomaly: request {} of {}, {} not learned",
+            auth_data.request_id, domain, deviation
+        );
+        // reported once, a flagged access is learned from now on
+        sketch.add(&self.hashers, deviation);
+        answer
+    }
+}
+
+/// Returns the accesses of a request, the event with each virtual space of the object.
+fn accesses(config: &Config, auth_data: &AuthRequestData) -> Vec<String> {
+    let event = auth_data.evtype.name();
+    let spaces = auth_data
+        .object
+        .as_ref()
+        .map(|x| audit::space_names(config, x))
+        .unwrap_or_default();
+
+    if spaces.is_empty() {
+        return vec![event.to_owned()];
+    }
+
+    spaces
+        .iter()
+        .map(|space| format!("{} {}", event, space))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::medusa::SpaceBuilder;
+
+    const LEARNING: Duration = Duration::from_secs(3600);
+
+    fn config() -> Config {
+        let space = |name: &'static str, path: &'static str| {
+            SpaceBuilder::new()
+                .with_name(name)
+                .with_path_recursive(path)
+        };
+        Config::builder()
+            .add_space(space("web", "fs/srv/www"))
+            .add_space(space("logs", "fs/var/log"))
+            .add_space(space("secrets", "fs/etc/secrets"))
+            .build()
+            .unwrap()
+    }
+
+    fn open(config: &Config, subject: &str, object: &str) -> AuthRequestData {
+        AuthRequestData::for_test(config, "open", &[subject], Some(&[object]))
+    }
+
+    /// Ends the learning window of `detector` without waiting for it.
+    fn stop_learning(detector: &mut AnomalyDetector) {
+        detector.settings.learning = Duration::ZERO;
+    }
+
+    #[test]
+    fn judges_after_learning() {
+        let config = config();
+        let allow = MedusaAnswer::Allow;
+        let mut detector = AnomalyDetector::new(AnomalyDetection::new(LEARNING));
+
+        let learned = open(&config, "web", "logs");
+        let unusual = open(&config, "web", "secrets");
+        // nothing is judged while learning
+        assert_eq!(detector.check(&config, &learned, allow), allow);
+        assert_eq!(detector.deviations(), 0);
+
+        stop_learning(&mut detector);
+        assert_eq!(detector.check(&config, &learned, allow), allow);
+        assert_eq!(detector.deviations(), 0);
+
+        // reported, but not denied, and learned from now on
+        assert_eq!(detector.check(&config, &unusual, allow), allow);
+        assert_eq!(detector.deviations(), 1);
+        assert_eq!(detector.check(&config, &unusual, allow), allow);
+        assert_eq!(detector.deviations(), 1);
+
+        // accesses without an object are learned by the event alone
+        let fork = AuthRequestData::for_test(&config, "fork", &["web"], None);
+        assert_eq!(detector.check(&config, &fork, allow), allow);
+        assert_eq!(detector.deviations(), 2);
+    }
+
+    #[test]
+    fn requires_min_count() {
+        let config = config();
+        let allow = MedusaAnswer::Allow;
+        let mut detector = AnomalyDetector::new(AnomalyDetection::new(LEARNING).min_count(2));
+
+        let once = open(&config, "web", "secrets");
+        let twice = open(&config, "web", "logs");
+        detector.check(&config, &once, allow);
+        detector.check(&config, &twice, allow);
+        detector.check(&config, &twice, allow);
+
+        stop_learning(&mut detector);
+        assert_eq!(detector.check(&config, &twice, allow), allow);
+        assert_eq!(detector.deviations(), 0);
+        assert_eq!(detector.check(&config, &once, allow), allow);
+        assert_eq!(detector.deviations(), 1);
+    }
+
+    #[test]
+    fn denies_deviations() {
+        let config = config();
+        let mut detector = AnomalyDetector::new(AnomalyDetection::new(LEARNING).deny());
+
+        let learned = open(&config, "web", "logs");
+        let unusual = open(&config, "web", "secrets");
+        detector.check(&config, &learned, MedusaAnswer::Allow);
+
+        stop_learning(&mut detector);
+        assert_eq!(
+            detector.check(&config, &learned, MedusaAnswer::Allow),
+            MedusaAnswer::Allow
+        );
+        // a denied deviation is not learned
+        for deviations in 1..=2 {
+            assert_eq!(
+                detector.check(&config, &unusual, MedusaAnswer::Allow),
+                MedusaAnswer::Deny
+            );
+            assert_eq!(detector.deviations(), deviations);
+        }
+    }
+
+    #[test]
+    fn does_not_judge_unknown_domains() {
+        let config = config();
+        let allow = MedusaAnswer::Allow;
+
+        let detector = AnomalyDetector::new(AnomalyDetection::new(Duration::ZERO).deny());
+        assert_eq!(
+            detector.check(&config, &open(&config, "web", "secrets"), allow),
+            allow
+        );
+
+        let mut detector = AnomalyDetector::new(AnomalyDetection::new(LEARNING).deny());
+        detector.check(&config, &open(&config, "web", "logs"), allow);
+        stop_learning(&mut detector);
+        // `logs` made no request while learning, so it has no baseline
+        assert_eq!(
+            detector.check(&config, &open(&config, "logs", "secrets"), allow),
+            allow
+        );
+        assert_eq!(detector.deviations(), 0);
+    }
+
+    #[test]
+    fn ignores_denied_and_failed_requests() {
+        let config = config();
+        let mut detector = AnomalyDetector::new(AnomalyDetection::new(LEARNING));
+
+        let learned = open(&config, "web", "logs");
+        let denied = open(&config, "web", "secrets");
+        detector.check(&config, &learned, MedusaAnswer::Allow);
+        assert_eq!(
+            detector.check(&config, &denied, MedusaAnswer::Deny),
+            MedusaAnswer::Deny
+        );
+        assert_eq!(
+            detector.check(&config, &denied, MedusaAnswer::Err),
+            MedusaAnswer::Err
+        );
+
+        stop_learning(&mut detector);
+        for answer in [MedusaAnswer::Deny, MedusaAnswer::Err] {
+            assert_eq!(detector.check(&config, &denied, answer), answer);
+        }
+        assert_eq!(detector.deviations(), 0);
+
+        // not learned from the denied requests
+        assert_eq!(
+            detector.check(&config, &denied, MedusaAnswer::Allow),
+            MedusaAnswer::Allow
+        );
+        assert_eq!(detector.deviations(), 1);
+    }
+}
@@ -1,9 +1,31 @@
-use crate::cstr_to_string;
 use crate::medusa::constants::*;
-use crate::medusa::AttributeError;
-use hashlink::LinkedHashMap;
+use crate::medusa::{AttributeError, FastHashMap, FastHasher};
+use crate::{cstr_to_string, trim_nul_padding};
+use std::collections::HashSet;
+use std::ffi::OsString;
+use std::ops::{Deref, Range};
+use std::os::unix::ffi::OsStringExt;
+use std::sync::{Arc, Mutex};
 use std::{fmt, mem};
 
+lazy_static! {
+    // names of attributes of all classes and events, see `intern_name`
+    static ref ATTRIBUTE_NAMES: Mutex<HashSet<Arc<str>, FastHasher>> = Default::default();
+}
+
+/// Returns the shared copy of attribute name `name`. Attributes of the same name in different
+/// classes and events, as well as keys of [`MedusaAttributes`], then use a single allocation.
+pub(crate) fn intern_name(name: &str) -> Arc<str> {
+    let mut names = ATTRIBUTE_NAMES.lock().unwrap();
+    if let Some(name) = names.get(name) {
+        return Arc::clone(name);
+    }
+
+    let name: Arc<str> = name.into();
+    names.insert(Arc::clone(&name));
+    name
+}
+
 #[allow(dead_code)]
 #[derive(Debug, Clone)]
 pub struct MedusaAttributeHeader {
@@ -12,7 +34,9 @@ pub struct MedusaAttributeHeader {
     pub(crate) mods: AttributeMods,
     pub(crate) endianness: AttributeEndianness,
     pub(crate) data_type: AttributeDataType,
-    pub(crate) name: String,
+    pub(crate) raw_type: u8,
+    // interned, see `intern_name`
+    pub(crate) name: Arc<str>,
 }
 
 impl MedusaAttributeHeader {
@@ -24,6 +48,16 @@ impl MedusaAttributeHeader {
         self.mods.contains(AttributeMods::READ_ONLY)
     }
 
+    /// Returns whether this attribute identifies the object it belongs to.
+    pub fn is_primary_key(&self) -> bool {
+        self.mods.contains(AttributeMods::PRIMARY_KEY)
+    }
+
+    /// Returns the type byte exactly as it was received from the security module.
+    pub fn raw_type(&self) -> u8 {
+        self.raw_type
+    }
+
     pub const fn size() -> usize {
         mem::size_of::<i16>()
             + mem::size_of::<i16>()
@@ -35,7 +69,52 @@ impl MedusaAttributeHeader {
 #[derive(Clone)]
 pub struct MedusaAttribute {
     pub(crate) header: MedusaAttributeHeader,
-    pub(crate) data: Vec<u8>,
+    pub(crate) data: AttributeData,
+}
+
+/// Data of an attribute.
+///
+/// Attributes of an authorization request refer to the attribute data of the request as it was
+/// read, see [`MedusaAttributes::set_from_shared`]. That buffer serves as an arena of the
+/// request: parsing the request and the copies of it made for each handler allocate nothing
+/// per attribute, and the buffer is released together with the last copy, once the decision
+/// is written and the hooks are done with it. The data of an attribute is copied out of the
+/// buffer only when it is modified.
+#[derive(Clone)]
+pub(crate) enum AttributeData {
+    Shared { raw: Arc<[u8]>, range: Range<usize> },
+    Owned(Vec<u8>),
+}
+
+impl AttributeData {
+    /// Returns the data for modification, copying it out of the request first.
+    fn to_mut(&mut self) -> &mut Vec<u8> {
+        if let Self::Shared { raw, range } = self {
+            *self = Self::Owned(raw[range.clone()].to_vec());
+        }
+
+        match self {
+            Self::Owned(data) => data,
+            Self::Shared { .. } => unreachable!("data is owned"),
+        }
+    }
+}
+
+impl Default for AttributeData {
+    fn default() -> Self {
+        Self::Owned(Vec::new())
+    }
+}
+
+impl Deref for AttributeData {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            Self::Shared { raw, range } => &raw[range.clone()],
+            Self::Owned(data) => data,
+        }
+    }
 }
 
 impl fmt::Debug for MedusaAttribute {
@@ -56,7 +135,7 @@ impl fmt::Debug for MedusaAttribute {
                 )
             }
         } else if self.header.data_type == AttributeDataType::Signed {
-            let data = self.data.clone();
+            let data = self.data.to_vec();
             if self.header.length == 1 {
                 format!("(i8) {}", i8::from_le_bytes(data.try_into().unwrap()))
             } else if self.header.length == 2 {
@@ -73,11 +152,11 @@ impl fmt::Debug for MedusaAttribute {
         } else if self.header.data_type == AttributeDataType::String {
             cstr_to_string(&self.data)
         } else if self.header.data_type == AttributeDataType::Bitmap {
-            format!("(bitmap) {:?}", &self.data)
+            format!("(bitmap) {:?}", &self.data[..])
         } else if self.header.data_type == AttributeDataType::Bytes {
-            format!("(bytes) {:?}", &self.data)
+            format!("(bytes) {:?}", &self.data[..])
         } else {
-            format!("(unknown type) {:?}", &self.data)
+            format!("(unknown type) {:?}", &self.data[..])
         };
 
         f.debug_struct("MedusaAttribute")
@@ -87,7 +166,62 @@ impl fmt::Debug for MedusaAttribute {
     }
 }
 
+/// Content of an attribute interpreted according to its data type.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AttributeValue {
+    Unsigned(u64),
+    Signed(i64),
+    String(String),
+
+    /// Bitmaps, raw bytes and data of unknown types.
+    Bytes(Vec<u8>),
+}
+
 impl MedusaAttribute {
+    /// Returns the name of this attribute.
+    pub fn name(&self) -> &str {
+        self.header.name()
+    }
+
+    /// Returns the content of this attribute interpreted according to its data type and byte order.
+    pub fn value(&self) -> AttributeValue {
+        let data = self.pack_data();
+
+        match self.header.data_type {
+            AttributeDataType::Unsigned | AttributeDataType::Signed if data.len() <= 8 => {
+                let mut bytes = [0; 8];
+                let big_endian = match self.header.endianness {
+                    AttributeEndianness::Big => true,
+                    AttributeEndianness::Native => cfg!(target_endian = "big"),
+                    AttributeEndianness::Little | AttributeEndianness::Unused => false,
+                };
+                let value = if big_endian {
+                    bytes[8 - data.len()..].copy_from_slice(&data);
+                    u64::from_be_bytes(bytes)
+                } else {
+                    bytes[..data.len()].copy_from_slice(&data);
+                    u64::from_le_bytes(bytes)
+                };
+
+                if self.header.data_type == AttributeDataType::Signed && !data.is_empty() {
+                    // sign-extend from the width of the attribute
+                    let shift = 64 - 8 * data.len() as u32;
+                    AttributeValue::Signed(((value as i64) << shift) >> shift)
+                } else {
+                    AttributeValue::Unsigned(value)
+                }
+            }
+            AttributeDataType::String => AttributeValue::String(cstr_to_string(&data)),
+            _ => AttributeValue::Bytes(data),
+        }
+    }
+
+    /// Returns the data of this attribute without the NUL padding at its end, see
+    /// [`trim_nul_padding`](crate::trim_nul_padding).
+    pub fn bytes_trimmed(&self) -> &[u8] {
+        trim_nul_padding(&self.data)
+    }
+
     fn pack_data(&self) -> Vec<u8> {
         self.data
             .iter()
@@ -133,6 +267,21 @@ impl AttributeBytes for String {
     }
 }
 
+/// Strings which need not be valid UTF-8, with all bytes up to the NUL padding kept.
+impl AttributeBytes for OsString {
+    fn to_bytes(self) -> Vec<u8> {
+        let mut vec = self.into_vec();
+        vec.push(0);
+
+        vec
+    }
+
+    fn from_bytes(mut bytes: Vec<u8>) -> Self {
+        bytes.truncate(trim_nul_padding(&bytes).len());
+        OsString::from_vec(bytes)
+    }
+}
+
 impl AttributeBytes for Vec<u8> {
     fn to_bytes(self) -> Vec<u8> {
         self
@@ -143,64 +292,235 @@ impl AttributeBytes for Vec<u8> {
     }
 }
 
+/// Attribute of a single class layout, see [`MedusaClass::attr_handle`]. Unlike a name, it is
+/// resolved without hashing.
+///
+/// [`MedusaClass::attr_handle`]: crate::medusa::MedusaClass::attr_handle
+#[derive(Clone, Debug)]
+pub struct AttributeHandle {
+    index: usize,
+    name: Arc<str>,
+
+    // layout in which `index` is valid
+    layout: Arc<FastHashMap<Arc<str>, usize>>,
+}
+
+impl AttributeHandle {
+    /// Returns the name of the attribute.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
 /// A container for attributes.
+///
+/// Attributes are stored ordered by their offset in the attribute data of the security module,
+/// so that unpacking and packing proceed sequentially. Indices of attributes, see
+/// [`MedusaAttributes::index_of`], are the same for all copies of a class or an event.
 #[derive(Default, Clone, Debug)]
 pub struct MedusaAttributes {
-    inner: LinkedHashMap<String, MedusaAttribute>,
+    attributes: Vec<MedusaAttribute>,
+
+    // layout is fixed once defined, so copies made for each request share it
+    index: Arc<FastHashMap<Arc<str>, usize>>,
 }
 
 impl MedusaAttributes {
     pub fn set(&mut self, attr_name: &str, data: Vec<u8>) -> Result<(), AttributeError> {
-        let attr = self
-            .inner
-            .get_mut(attr_name)
-            .ok_or_else(|| AttributeError::UnknownAttributeError(attr_name.to_owned()))?;
+        let attr = self.attribute_mut(attr_name)?;
 
         if attr.header.is_read_only() {
             return Err(AttributeError::ModifyReadOnlyError(attr_name.to_owned()));
         }
 
-        attr.data = data;
+        attr.data = AttributeData::Owned(data);
 
         Ok(())
     }
 
     pub fn get(&self, attr_name: &str) -> Result<&[u8], AttributeError> {
-        self.inner
-            .get(attr_name)
-            .map(|x| &x.data[..])
-            .ok_or_else(|| AttributeError::UnknownAttributeError(attr_name.to_owned()))
+        self.attribute(attr_name).map(|x| &x.data[..])
+    }
+
+    /// Returns the data of attribute `attr_name` without the NUL padding at its end, see
+    /// [`MedusaAttribute::bytes_trimmed`].
+    pub fn get_bytes_trimmed(&self, attr_name: &str) -> Result<&[u8], AttributeError> {
+        self.attribute(attr_name)
+            .map(MedusaAttribute::bytes_trimmed)
+    }
+
+    /// Returns the content of attribute `attr_name` interpreted according to its data type.
+    pub fn value(&self, attr_name: &str) -> Result<AttributeValue, AttributeError> {
+        self.attribute(attr_name).map(MedusaAttribute::value)
+    }
+
+    /// Returns an iterator over all attributes ordered by their offset.
+    pub fn iter(&self) -> impl Iterator<Item = &MedusaAttribute> {
+        self.attributes.iter()
     }
 
     pub fn get_mut(&mut self, attr_name: &str) -> Result<&mut [u8], AttributeError> {
-        let attr = self
-            .inner
-            .get_mut(attr_name)
+        self.attribute_mut(attr_name)
+            .map(|x| &mut x.data.to_mut()[..])
+    }
+
+    /// Returns the index of attribute `attr_name`, which may be used by
+    /// [`MedusaAttributes::get_by_index`] to access the attribute without looking its name up.
+    pub fn index_of(&self, attr_name: &str) -> Option<usize> {
+        self.index.get(attr_name).copied()
+    }
+
+    /// Returns the data of the attribute at `index`, see [`MedusaAttributes::index_of`].
+    pub fn get_by_index(&self, index: usize) -> Option<&[u8]> {
+        self.attributes.get(index).map(|x| &x.data[..])
+    }
+
+    /// Returns the mutable data of the attribute at `index`, see
+    /// [`MedusaAttributes::index_of`].
+    pub fn get_mut_by_index(&mut self, index: usize) -> Option<&mut [u8]> {
+        self.attributes
+            .get_mut(index)
+            .map(|x| &mut x.data.to_mut()[..])
+    }
+
+    /// Returns a handle of attribute `attr_name`, valid for all copies of this layout.
+    pub fn handle(&self, attr_name: &str) -> Result<AttributeHandle, AttributeError> {
+        let index = self
+            .index_of(attr_name)
             .ok_or_else(|| AttributeError::UnknownAttributeError(attr_name.to_owned()))?;
 
-        Ok(&mut attr.data)
+        Ok(AttributeHandle {
+            index,
+            name: Arc::clone(&self.attributes[index].header.name),
+            layout: Arc::clone(&self.index),
+        })
+    }
+
+    /// Returns the data of the attribute of `handle`.
+    pub fn get_by_handle(&self, handle: &AttributeHandle) -> Result<&[u8], AttributeError> {
+        self.check_handle(handle)?;
+        Ok(&self.attributes[handle.index].data)
+    }
+
+    /// Sets the attribute of `handle` to `data`.
+    pub fn set_by_handle(
+        &mut self,
+        handle: &AttributeHandle,
+        data: Vec<u8>,
+    ) -> Result<(), AttributeError> {
+        self.check_handle(handle)?;
+
+        let attr = &mut self.attributes[handle.index];
+        if attr.header.is_read_only() {
+            return Err(AttributeError::ModifyReadOnlyError(
+                handle.name().to_owned(),
+            ));
+        }
+
+        attr.data = AttributeData::Owned(data);
+
+        Ok(())
     }
 
     pub fn set_from_raw(&mut self, raw_data: &[u8]) {
-        for attr in self.inner.values_mut() {
+        for attr in &mut self.attributes {
+            let offset = attr.header.offset as usize;
+            let length = attr.header.length as usize;
+            let src = &raw_data[offset..][..length];
+            match &mut attr.data {
+                AttributeData::Owned(data) => {
+                    data.clear();
+                    data.extend_from_slice(src);
+                }
+                data => *data = AttributeData::Owned(src.to_vec()),
+            }
+        }
+    }
+
+    /// Same as [`MedusaAttributes::set_from_raw`], but the attributes refer to `raw_data`
+    /// instead of copying it, until they are modified.
+    pub(crate) fn set_from_shared(&mut self, raw_data: &Arc<[u8]>) {
+        for attr in &mut self.attributes {
             let offset = attr.header.offset as usize;
             let length = attr.header.length as usize;
-            attr.data = raw_data[offset..][..length].to_vec();
+            assert!(
+                offset + length <= raw_data.len(),
+                "attribute `{}` exceeds the attribute data",
+                attr.name()
+            );
+
+            attr.data = AttributeData::Shared {
+                raw: Arc::clone(raw_data),
+                range: offset..offset + length,
+            };
         }
     }
 
+    /// Returns data of the primary key attributes in packed `raw_data`.
+    pub(crate) fn primary_key_from_raw(&self, raw_data: &[u8]) -> Vec<u8> {
+        self.attributes
+            .iter()
+            .filter(|x| x.header.is_primary_key())
+            .flat_map(|x| {
+                let offset = x.header.offset as usize;
+                let length = x.header.length as usize;
+                raw_data.get(offset..offset + length).unwrap_or_default()
+            })
+            .copied()
+            .collect()
+    }
+
     pub fn pack(&self, res: &mut [u8]) {
-        for attribute in self.inner.values() {
-            let data = attribute.pack_data();
+        for attribute in &self.attributes {
+            let offset = attribute.header.offset as usize;
+            let length = attribute.header.length as usize;
+            let dst = &mut res[offset..][..length];
 
-            // TODO make faster, `slice::copy_from_slice()` did not work
-            for i in 0..attribute.header.length as usize {
-                res[attribute.header.offset as usize + i] = data[i];
-            }
+            // shorter data is padded with zeros, as in `MedusaAttribute::pack_data`
+            let copied = attribute.data.len().min(length);
+            dst[..copied].copy_from_slice(&attribute.data[..copied]);
+            dst[copied..].fill(0);
         }
     }
 
     pub fn push(&mut self, attribute: MedusaAttribute) {
-        self.inner.insert(attribute.header.name.clone(), attribute);
+        if let Some(index) = self.index_of(attribute.name()) {
+            self.attributes.remove(index);
+        }
+
+        let offset = attribute.header.offset;
+        let position = self
+            .attributes
+            .partition_point(|x| x.header.offset <= offset);
+        self.attributes.insert(position, attribute);
+
+        self.index = Arc::new(
+            self.attributes
+                .iter()
+                .enumerate()
+                .map(|(i, x)| (Arc::clone(&x.header.name), i))
+                .collect(),
+        );
+    }
+
+    fn check_handle(&self, handle: &AttributeHandle) -> Result<(), AttributeError> {
+        if !Arc::ptr_eq(&handle.layout, &self.index) {
+            return Err(AttributeError::StaleHandleError(handle.name().to_owned()));
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn attribute(&self, attr_name: &str) -> Result<&MedusaAttribute, AttributeError> {
+        self.index_of(attr_name)
+            .map(|i| &self.attributes[i])
+            .ok_or_else(|| AttributeError::UnknownAttributeError(attr_name.to_owned()))
+    }
+
+    fn attribute_mut(&mut self, attr_name: &str) -> Result<&mut MedusaAttribute, AttributeError> {
+        match self.index_of(attr_name) {
+            Some(i) => Ok(&mut self.attributes[i]),
+            None => Err(AttributeError::UnknownAttributeError(attr_name.to_owned())),
+        }
     }
 }
@@ -0,0 +1,182 @@
+//! Audit records of authorization decisions.
+
+use crate::bitmap;
+use crate::medusa::{CompletedRequest, Config, Context, MedusaAnswer, MedusaClass, Session};
+use serde::Serialize;
+use std::io::Write;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Record of a single authorization decision.
+#[derive(Clone, Debug, Serialize)]
+pub struct AuditRecord {
+    /// Milliseconds since the Unix epoch at which the decision was made.
+    pub timestamp_ms: u64,
+
+    /// Identification of the authorization request.
+    pub request_id: u64,
+
+    /// Name of the event.
+    pub event: String,
+
+    /// Name of the subject class.
+    pub subject: String,
+
+    /// Identity of the subject, see [`SubjectId`].
+    ///
+    /// [`SubjectId`]: crate::medusa::SubjectId
+    pub subject_id: String,
+
+    /// Login session of the subject, if known, see [`Session`].
+    ///
+    /// [`Session`]: crate::medusa::Session
+    pub session: Option<Session>,
+
+    /// Virtual spaces the subject is a member of.
+    pub subject_spaces: Vec<String>,
+
+    /// Name of the object class, if the event has an object.
+    pub object: Option<String>,
+
+    /// Identity of the object, if the event has an object.
+    pub object_id: Option<String>,
+
+    /// Virtual spaces the object is a member of.
+    pub object_spaces: Vec<String>,
+
+    /// Final verdict.
+    pub answer: String,
+
+    /// Hash of the policy the decision was made with, see [`Config::policy_hash`].
+    pub policy_hash: Option<String>,
+
+    /// Number of identical decisions this record stands for. It is 1 unless the record was
+    /// aggregated by [`SuppressingSink`].
+    ///
+    /// [`SuppressingSink`]: crate::medusa::SuppressingSink
+    pub count: u64,
+}
+
+impl AuditRecord {
+    /// Creates a record describing `completed` request decided under `config`.
+    pub fn new(config: &Config, completed: &CompletedRequest) -> Self {
+        let data = &completed.data;
+        let timestamp_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|x| x.as_millis() as u64)
+            .unwrap_or_default();
+
+        Self {
+            timestamp_ms,
+            request_id: data.request_id,
+            event: data.evtype.name().to_owned(),
+            subject: data.subject.header.name().to_owned(),
+            subject_id: data.subject.subject_id().to_string(),
+            session: data.subject.session(),
+            subject_spaces: space_names(config, &data.subject),
+            object: data.object.as_ref().map(|x| x.header.name().to_owned()),
+            object_id: data.object.as_ref().map(|x| x.subject_id().to_string()),
+            object_spaces: data
+                .object
+                .as_ref()
+                .map(|x| space_names(config, x))
+                .unwrap_or_default(),
+            answer: answer_name(completed.answer).to_owned(),
+            policy_hash: config.policy_hash().map(|x| x.to_owned()),
+            count: 1,
+        }
+    }
+
+    /// Returns `true` if the operation was denied.
+    pub fn is_denial(&self) -> bool {
+        self.answer == answer_name(MedusaAnswer::Deny)
+    }
+}
+
+/// Destination of audit records.
+pub trait AuditSink: Send + Sync {
+    /// Stores the record. Called after the answer has been sent to the security module.
+    fn record(&self, record: &AuditRecord);
+
+    /// Stores the record of `completed` request. Sinks which need the complete request data
+    /// override this method, by default it calls [`AuditSink::record`].
+    fn record_completed(&self, record: &AuditRecord, _completed: &CompletedRequest) {
+        self.record(record);
+    }
+
+    /// Stores the record of `completed` request answered on the connection of `ctx`. Sinks
+    /// which act on the connection, e.g. [`AlertSink::quarantine`], override this method, by
+    /// default it calls [`AuditSink::record_completed`].
+    ///
+    /// [`AlertSink::quarantine`]: crate::medusa::AlertSink::quarantine
+    fn record_on(&self, _ctx: &Context, record: &AuditRecord, completed: &CompletedRequest) {
+        self.record_completed(record, completed);
+    }
+}
+
+/// Writes every audit record as a single line of JSON.
+pub struct JsonSink<W: Write + Send> {
+    writer: Mutex<W>,
+}
+
+impl<W: Write + Send> JsonSink<W> {
+    /// Creates new `JsonSink` writing into `writer`.
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer: Mutex::new(writer),
+        }
+    }
+}
+
+impl<W: Write + Send> AuditSink for JsonSink<W> {
+    fn record(&self, record: &AuditRecord) {
+        let mut writer = self.writer.lock().unwrap();
+        if let Err(e) = serde_json::to_writer(&mut *writer, record) {
+            eprintln!("failed to write audit record: {}", e);
+            return;
+        }
+        let _ = writer.write_all(b"\n");
+    }
+}
+
+pub(crate) fn answer_name(answer: MedusaAnswer) -> &'static str {
+    match answer {
+        MedusaAnswer::Err => "err",
+        MedusaAnswer::Yes => "yes",
+        MedusaAnswer::Deny => "deny",
+        MedusaAnswer::Skip => "skip",
+        MedusaAnswer::Allow => "allow",
+    }
+}
+
+/// Returns `data` encoded as lowercase hexadecimal text.
+pub(crate) fn to_hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Returns the domain of `subject`, its class with its virtual spaces, such as
+/// `process[app,net]`.
+pub(crate) fn domain_name(config: &Config, subject: &MedusaClass) -> String {
+    format!(
+        "{}[{}]",
+        subject.header.name(),
+        space_names(config, subject).join(",")
+    )
+}
+
+/// Returns names of the virtual spaces `class` is a member of.
+pub(crate) fn space_names(config: &Config, class: &MedusaClass) -> Vec<String> {
+    let vs = match class.get_vs() {
+        Ok(vs) => vs,
+        Err(_) => return Vec::new(),
+    };
+
+    if bitmap::none(vs) {
+        return Vec::new();
+    }
+
+    (0..vs.len() * 8)
+        .filter(|&bit| bitmap::test_bit(vs, bit))
+        .filter_map(|bit| config.space_bit_to_name(&bit).cloned())
+        .collect()
+}
@@ -0,0 +1,189 @@
+//! Batching of update requests, see [`Context::queue_update`], and skipping of repeated ones,
+//! see [`ConfigBuilder::deduplicate_updates`].
+//!
+//! [`Context::queue_update`]: crate::medusa::Context::queue_update
+//! [`ConfigBuilder::deduplicate_updates`]: crate::medusa::ConfigBuilder::deduplicate_updates
+
+use crate::medusa::executor::Executor;
+use crate::medusa::pending::PendingRequests;
+use crate::medusa::{MedusaRequest, RequestType, SubjectId, UpdateAnswer, UpdateStatus, Writer};
+use dashmap::DashMap;
+use hashlink::LinkedHashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Default time updates are collected for before they are sent.
+pub(crate) const UPDATE_FLUSH_DEFAULT_INTERVAL: Duration = Duration::from_millis(1);
+
+/// Number of remembered updates above which the expired ones are forgotten.
+const RECENT_UPDATES_PRUNE_LEN: usize = 4096;
+
+/// Last update sent for each object.
+pub(crate) struct RecentUpdates {
+    // `None` if deduplication is disabled
+    window: Option<Duration>,
+    last: DashMap<SubjectId, (Instant, Vec<u8>)>,
+}
+
+impl RecentUpdates {
+    pub(crate) fn new(window: Option<Duration>) -> Self {
+        Self {
+            window,
+            last: Default::default(),
+        }
+    }
+
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.window.is_some()
+    }
+
+    /// Returns `true` if the same `data` was sent to object `key` within the window, otherwise
+    /// remembers them as the last update of the object.
+    pub(crate) fn is_duplicate(&self, key: &SubjectId, data: &[u8]) -> bool {
+        let window = match self.window {
+            Some(window) => window,
+            None => return false,
+        };
+
+        let now = Instant::now();
+        if let Some(last) = self.last.get(key) {
+            if last.1 == data && now.duration_since(last.0) < window {
+                return true;
+            }
+        }
+
+        if self.last.len() >= RECENT_UPDATES_PRUNE_LEN {
+            self.last
+                .retain(|_, (sent, _)| now.duration_since(*sent) < window);
+        }
+        self.last.insert(key.clone(), (now, data.to_vec()));
+
+        false
+    }
+
+    /// Forgets the last update of object `key`, so that it is sent again, e.g. after it failed.
+    pub(crate) fn forget(&self, key: &SubjectId) {
+        self.last.remove(key);
+    }
+
+    /// Forgets the last updates of all objects of class `class_id`.
+    pub(crate) fn forget_class(&self, class_id: u64) {
+        self.last.retain(|key, _| key.class_id() != class_id);
+    }
+
+    /// Returns the number of remembered updates.
+    pub(crate) fn len(&self) -> usize {
+        self.last.len()
+    }
+}
+
+pub(crate) struct UpdateQueue {
+    interval: Duration,
+    pending: Mutex<LinkedHashMap<SubjectId, Vec<u8>>>,
+
+    writer: Writer,
+    pending_requests: Arc<PendingRequests>,
+    recent: Arc<RecentUpdates>,
+    executor: Arc<dyn Executor>,
+}
+
+impl UpdateQueue {
+    pub(crate) fn new(
+        interval: Duration,
+        writer: Writer,
+        pending_requests: Arc<PendingRequests>,
+        recent: Arc<RecentUpdates>,
+        executor: Arc<dyn Executor>,
+    ) -> Self {
+        Self {
+            interval,
+            pending: Default::default(),
+            writer,
+            pending_requests,
+            recent,
+            executor,
+        }
+    }
+
+    /// Queues update of object `key` with `data`, replacing an update of the same object which
+    /// has not been sent yet. The first update queued after a flush schedules the next one.
+    pub(crate) fn push(self: &Arc<Self>, key: SubjectId, data: Vec<u8>) {
+        let mut pending = self.pending.lock().unwrap();
+        let schedule = pending.is_empty();
+        pending.replace(key, data);
+        drop(pending);
+
+        if schedule {
+            let queue = Arc::clone(self);
+            self.executor.spawn(Box::pin(async move {
+                queue.executor.sleep(queue.interval).await;
+                queue.flush();
+            }));
+        }
+    }
+
+    /// Drops the update of object `key` if it has not been sent yet.
+    pub(crate) fn discard(&self, key: &SubjectId) {
+        self.pending.lock().unwrap().remove(key);
+    }
+
+    /// Drops the updates of objects of class `class_id` which have not been sent yet. Returns
+    /// the number of dropped updates.
+    pub(crate) fn discard_class(&self, class_id: u64) -> usize {
+        let mut pending = self.pending.lock().unwrap();
+        let len = pending.len();
+        pending.retain(|key, _| key.class_id() != class_id);
+        len - pending.len()
+    }
+
+    /// Returns the number of updates which have not been sent yet.
+    pub(crate) fn len(&self) -> usize {
+        self.pending.lock().unwrap().len()
+    }
+
+    /// Sends all queued updates in a single write. Failed updates are logged.
+    fn flush(&self) {
+        let pending = std::mem::take(&mut *self.pending.lock().unwrap());
+
+        let mut buf = Vec::new();
+        let mut answers = Vec::with_capacity(pending.len());
+        for (key, data) in pending {
+            if self.recent.is_duplicate(&key, &data) {
+                continue;
+            }
+
+            let (id, receiver) = self.pending_requests.register_update();
+            let req = MedusaRequest {
+                req_type: RequestType::Update,
+                class_id: key.class_id(),
+                id,
+                data: &data,
+            };
+            answers.push((key, id, receiver));
+
+            buf.extend(req.to_vec());
+        }
+
+        if answers.is_empty() {
+            return;
+        }
+
+        self.writer.write(Arc::from(buf));
+
+        let recent = Arc::clone(&self.recent);
+        self.executor.spawn(Box::pin(async move {
+            for (key, id, receiver) in answers {
+                // abandoned by the sweeper
+                let answer = receiver.await.unwrap_or(UpdateAnswer {
+                    class_id: key.class_id(),
+                    msg_seq: id,
+                    status: UpdateStatus::TimedOut,
+                });
+                if let Err(e) = answer.result() {
+                    recent.forget(&key);
+                    eprintln!("queued {}", e);
+                }
+            }
+        }));
+    }
+}
@@ -1,9 +1,13 @@
 use crate::bitmap;
 use crate::medusa::constants::*;
+use crate::medusa::executor;
 use crate::medusa::space::VirtualSpace;
 use crate::medusa::{
-    AttributeBytes, AttributeError, Context, MedusaAttributes, MedusaEvtype, Monitoring, Node,
+    AttributeBytes, AttributeError, AttributeHandle, ConsistencyError, Context, FetchStatus,
+    FetchedObject, MedusaAttributes, MedusaEvtype, Monitoring, Node, Session, SubjectId,
+    UpdateCallback, UpdateError,
 };
+use std::ffi::OsString;
 use std::sync::atomic::Ordering;
 use std::sync::Arc;
 use std::{fmt, mem};
@@ -20,6 +24,17 @@ impl MedusaClassHeader {
         &self.name
     }
 
+    /// Returns identification of the class.
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Returns size of the attribute data of the class in bytes. Not to be confused with
+    /// [`MedusaClassHeader::size`], the size of the header itself.
+    pub fn data_size(&self) -> usize {
+        self.size as usize
+    }
+
     pub const fn size() -> usize {
         mem::size_of::<u64>() + mem::size_of::<i16>() + MEDUSA_COMM_KCLASSNAME_MAX
     }
@@ -44,45 +59,25 @@ pub struct MedusaClass {
 
 impl MedusaClass {
     /// Manually enters this entity into tree.
+    ///
+    /// Returns `UpdateError` if the security module did not update this entity.
     pub async fn enter_tree(
         &mut self,
         ctx: &Context,
         evtype: &MedusaEvtype,
         primary_tree: &str,
         path: &str,
-    ) {
+    ) -> Result<(), UpdateError> {
         assert!(path.starts_with('/'));
 
-        let tree = ctx
-            .config()
+        let config = ctx.config();
+        let tree = config
             .tree_by_name(primary_tree)
             .unwrap_or_else(|| panic!("primary tree `{}` not found", primary_tree));
 
-        let mut node = tree.root();
-        let mut recursive_parent = if node.is_recursive() {
-            Some(node)
-        } else {
-            None
-        };
-        let mut recursed = false;
-        if path != "/" {
-            // skip empty string caused by leading '/'
-            for part in path.split_terminator('/').skip(1) {
-                let child = node.child_by_path(part);
-                match child {
-                    Some(ch) => {
-                        if ch.is_recursive() {
-                            recursive_parent = Some(ch);
-                        }
-                        node = ch;
-                    }
-                    None => {
-                        node = recursive_parent.expect("{part} not covered by tree");
-                        recursed = true;
-                    }
-                }
-            }
-        }
+        let (node, recursed) = tree
+            .resolve(path)
+            .unwrap_or_else(|| panic!("{} not covered by tree", path));
 
         println!(
             "{}: \"{}\" -> \"{}\"{}",
@@ -92,22 +87,37 @@ impl MedusaClass {
             if recursed { " (recursion)" } else { "" }
         );
 
-        self.enter_tree_with_node(ctx, evtype, node, recursed).await;
+        self.enter_tree_with_node(ctx, evtype, node, recursed).await
     }
 
     /// Manually enters this entity into specific node.
+    ///
+    /// Returns `UpdateError` if the security module did not update this entity.
     pub async fn enter_tree_with_node(
         &mut self,
         ctx: &Context,
         evtype: &MedusaEvtype,
         node: &Arc<Node>,
         recursed: bool,
+    ) -> Result<(), UpdateError> {
+        self.set_node(ctx, evtype, node, recursed);
+        self.update(ctx).await
+    }
+
+    /// Sets the attributes of this entity for `node` without updating it.
+    pub(crate) fn set_node(
+        &mut self,
+        ctx: &Context,
+        evtype: &MedusaEvtype,
+        node: &Arc<Node>,
+        recursed: bool,
     ) {
-        let cinfo = Arc::as_ptr(node) as usize;
+        let cinfo = node.cinfo();
 
         self.set_access_types(node.virtual_space());
 
-        let covered_events = ctx.config().covered_events_mask.load(Ordering::SeqCst);
+        let covered_events =
+            node.monitored_mask(ctx.config().covered_events_mask.load(Ordering::SeqCst));
         let _ = self.set_attribute::<u64>(MEDUSA_OACT_ATTR_NAME, covered_events);
         let _ = self.set_attribute::<u64>(MEDUSA_SACT_ATTR_NAME, covered_events);
 
@@ -118,8 +128,30 @@ impl MedusaClass {
         }
 
         self.set_object_cinfo(cinfo).unwrap();
+        if let Some(positions) = &ctx.positions {
+            positions.record(&ctx.config(), self, cinfo);
+        }
 
-        self.update(ctx).await;
+        if ctx.is_quarantined(self) {
+            ctx.set_quarantine(self);
+        }
+    }
+
+    /// Removes this entity from its node and all virtual spaces without updating it. It has no
+    /// access until it is entered into a tree again.
+    pub(crate) fn clear_node(&mut self) {
+        for attr_name in [
+            MEDUSA_VS_ATTR_NAME,
+            MEDUSA_VSR_ATTR_NAME,
+            MEDUSA_VSW_ATTR_NAME,
+            MEDUSA_VSS_ATTR_NAME,
+        ] {
+            if let Ok(vs) = self.attributes.get_mut(attr_name) {
+                vs.fill(0);
+            }
+        }
+
+        let _ = self.set_object_cinfo(0);
     }
 
     /// Copies access types from `vs`.
@@ -130,23 +162,50 @@ impl MedusaClass {
         let _ = self.set_vs_see(vs.to_at_bytes(AccessType::See));
     }
 
+    /// Returns the identity of this entity, see [`SubjectId`].
+    pub fn subject_id(&self) -> SubjectId {
+        SubjectId::from_raw(self, &self.pack_attributes())
+    }
+
+    /// Returns the login session of this entity, or `None` if it is not known, see [`Session`].
+    pub fn session(&self) -> Option<Session> {
+        Session::from_class(self)
+    }
+
     /// Performs `update` request on this entity.
-    pub async fn update(&self, ctx: &Context) -> i32 {
+    ///
+    /// Returns `UpdateError` if the security module did not update this entity.
+    pub async fn update(&self, ctx: &Context) -> Result<(), UpdateError> {
         let data = self.pack_attributes();
         let id = self.header.id;
 
         let answer = ctx.update_request(id, &data).await;
 
-        answer.status
+        answer.result()
+    }
+
+    /// Same as [`MedusaClass::update`], but blocks the calling thread until the answer arrives.
+    pub fn update_blocking(&self, ctx: &Context) -> Result<(), UpdateError> {
+        ctx.update_request_blocking(self.header.id, &self.pack_attributes())
+            .result()
+    }
+
+    /// Performs `update` request on this entity without waiting for the answer, see
+    /// [`Context::update_request_no_wait`].
+    pub fn update_no_wait(&self, ctx: &Context, callback: Option<UpdateCallback>) {
+        ctx.update_request_no_wait(self.header.id, &self.pack_attributes(), callback);
     }
 
     /// Performs `fetch` request. In case that the returned object has not yet been registered,
-    /// `None` is returned.
+    /// or no answer arrived in time, `None` is returned.
     pub async fn fetch(&self, ctx: &Context) -> Option<MedusaClass> {
         let data = self.pack_attributes();
         let id = self.header.id;
 
         let answer = ctx.fetch_request(id, &data).await;
+        if answer.status != FetchStatus::Ok {
+            return None;
+        }
 
         let mut object = ctx.empty_class_from_id(&answer.class_id)?;
         object.attributes.set_from_raw(&answer.data);
@@ -154,6 +213,57 @@ impl MedusaClass {
         Some(object)
     }
 
+    /// Same as [`MedusaClass::fetch`], but blocks the calling thread until the answer arrives.
+    pub fn fetch_blocking(&self, ctx: &Context) -> Option<MedusaClass> {
+        executor::block_on(self.fetch(ctx))
+    }
+
+    /// Fetches this entity, modifies it by `modify` and writes it back unless it changed since
+    /// it was fetched, see [`FetchedObject`]. On a change, the whole read-modify-write is
+    /// repeated up to `attempts` times in total, so `modify` has to be repeatable.
+    ///
+    /// Returns the written object or `ConsistencyError` if it kept changing, if `modify` failed,
+    /// e.g. with an [`AttributeError`](crate::medusa::AttributeError), or if the security module
+    /// did not update it.
+    pub async fn update_with<F>(
+        &self,
+        ctx: &Context,
+        attempts: usize,
+        mut modify: F,
+    ) -> Result<MedusaClass, ConsistencyError>
+    where
+        F: FnMut(&mut MedusaClass) -> Result<(), ConsistencyError>,
+    {
+        let mut attempt = 1;
+        loop {
+            let mut fetched = FetchedObject::fetch(ctx, self).await?;
+            modify(fetched.object_mut())?;
+
+            match fetched.update(ctx).await {
+                Err(ConsistencyError::ChangedError(id)) if attempt < attempts => {
+                    println!("{} changed since fetched, attempt {}", id, attempt);
+                    attempt += 1;
+                }
+                Err(e) => return Err(e),
+                Ok(()) => return Ok(fetched.into_inner()),
+            }
+        }
+    }
+
+    /// Same as [`MedusaClass::update_with`], but blocks the calling thread until the answers
+    /// arrive.
+    pub fn update_with_blocking<F>(
+        &self,
+        ctx: &Context,
+        attempts: usize,
+        modify: F,
+    ) -> Result<MedusaClass, ConsistencyError>
+    where
+        F: FnMut(&mut MedusaClass) -> Result<(), ConsistencyError>,
+    {
+        executor::block_on(self.update_with(ctx, attempts, modify))
+    }
+
     /// Adds virtual space.
     pub fn add_vs(&mut self, n: usize) -> Result<(), AttributeError> {
         let vs = self.attributes.get_mut(MEDUSA_VS_ATTR_NAME)?;
@@ -347,6 +457,65 @@ impl MedusaClass {
         Ok(T::from_bytes(self.attributes.get(attr_name)?.to_vec()))
     }
 
+    /// Returns the bytes of string attribute `attr_name` up to its NUL padding. Unlike
+    /// `get_attribute::<String>`, embedded NULs and invalid UTF-8 are kept, so comparisons of
+    /// names such as `cmdline` cannot be bypassed by them.
+    pub fn get_attribute_bytes_trimmed(&self, attr_name: &str) -> Result<&[u8], AttributeError> {
+        self.attributes.get_bytes_trimmed(attr_name)
+    }
+
+    /// Returns string attribute `attr_name` as an [`OsString`], see
+    /// [`MedusaClass::get_attribute_bytes_trimmed`].
+    pub fn get_attribute_os_string(&self, attr_name: &str) -> Result<OsString, AttributeError> {
+        self.get_attribute(attr_name)
+    }
+
+    /// Returns a handle of attribute `attr_name`, which may be kept and passed to
+    /// [`MedusaClass::get_attribute_by_handle`] and [`MedusaClass::set_attribute_by_handle`] of
+    /// any entity of this class, so that the name is not looked up on every event.
+    pub fn attr_handle(&self, attr_name: &str) -> Result<AttributeHandle, AttributeError> {
+        self.attributes.handle(attr_name)
+    }
+
+    /// Sets attribute of `handle` to value `data` of type `T`.
+    pub fn set_attribute_by_handle<T: AttributeBytes>(
+        &mut self,
+        handle: &AttributeHandle,
+        data: T,
+    ) -> Result<(), AttributeError> {
+        self.attributes.set_by_handle(handle, data.to_bytes())
+    }
+
+    /// Returns value of attribute of `handle` with type `T`.
+    pub fn get_attribute_by_handle<T: AttributeBytes>(
+        &self,
+        handle: &AttributeHandle,
+    ) -> Result<T, AttributeError> {
+        Ok(T::from_bytes(
+            self.attributes.get_by_handle(handle)?.to_vec(),
+        ))
+    }
+
+    /// Returns header of this class.
+    pub fn header(&self) -> &MedusaClassHeader {
+        &self.header
+    }
+
+    /// Returns name of this class.
+    pub fn name(&self) -> &str {
+        self.header.name()
+    }
+
+    /// Returns identification of this class.
+    pub fn id(&self) -> u64 {
+        self.header.id
+    }
+
+    /// Returns attributes of this entity.
+    pub fn attributes(&self) -> &MedusaAttributes {
+        &self.attributes
+    }
+
     /// Packs attributes into vector of bytes.
     pub fn pack_attributes(&self) -> Vec<u8> {
         let mut res = vec![0; self.header.size as usize];
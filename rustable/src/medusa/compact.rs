@@ -0,0 +1,598 @@
+//! Compact binary format of audit records, see [`CompactSink`].
+//!
+//! A stream starts with the magic bytes `RSAUDIT\0` followed by the version of the format as
+//! a little-endian `u16`. Then frames follow, each starting with its tag byte:
+//!
+//! ```text
+//! 0x01 string  len: u16, UTF-8 bytes
+//! 0x02 record  timestamp_ms: u64, request_id: u64, count: u64, flags: u8,
+//!              event, subject, answer: u32, [object: u32], [policy_hash: u32],
+//!              [login_uid: u32], [session_id: u32],
+//!              subject_spaces: u16, object_spaces: u16, space: u32 for each,
+//!              subject_id, [object_id]: len: u16, UTF-8 bytes
+//! ```
+//!
+//! All integers are little-endian. A `u32` names a string defined by an earlier string frame,
+//! the first one defined has index 0. Names of events, classes and spaces, answers and policy
+//! hashes repeat in most records, so each is written only once per stream, identities of
+//! subjects and objects are written in place. Fields in brackets are present only if their bit
+//! of `flags` is set, see the `HAS_*` constants.
+
+use crate::medusa::audit::{AuditRecord, AuditSink};
+use crate::medusa::error::AuditFormatError;
+use crate::medusa::{FastHashMap, Session};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::sync::Mutex;
+
+const MAGIC: &[u8; 8] = b"RSAUDIT\0";
+const VERSION: u16 = 1;
+
+const STRING_FRAME: u8 = 0x01;
+const RECORD_FRAME: u8 = 0x02;
+
+const HAS_OBJECT: u8 = 1 << 0;
+const HAS_POLICY_HASH: u8 = 1 << 1;
+const HAS_LOGIN_UID: u8 = 1 << 2;
+const HAS_SESSION_ID: u8 = 1 << 3;
+
+/// Columns of the CSV produced by [`convert`].
+const CSV_COLUMNS: &[&str] = &[
+    "timestamp_ms",
+    "request_id",
+    "event",
+    "subject",
+    "subject_id",
+    "login_uid",
+    "session_id",
+    "subject_spaces",
+    "object",
+    "object_id",
+    "object_spaces",
+    "answer",
+    "policy_hash",
+    "count",
+];
+
+/// Writes audit records in the compact binary format, for systems with event rates at which
+/// encoding records as JSON, see [`JsonSink`], becomes a measurable cost. [`convert`] turns the
+/// written stream into JSON or CSV offline.
+///
+/// [`JsonSink`]: crate::medusa::JsonSink
+pub struct CompactSink<W: Write + Send> {
+    state: Mutex<CompactWriter<W>>,
+}
+
+struct CompactWriter<W: Write> {
+    writer: W,
+    header_written: bool,
+    strings: FastHashMap<String, u32>,
+    buf: Vec<u8>,
+}
+
+impl<W: Write + Send> CompactSink<W> {
+    /// Creates new `CompactSink` writing a stream into `writer`. The header of the stream is
+    /// written together with the first record.
+    pub fn new(writer: W) -> Self {
+        Self {
+            state: Mutex::new(CompactWriter {
+                writer,
+                header_written: false,
+                strings: Default::default(),
+                buf: Vec::new(),
+            }),
+        }
+    }
+}
+
+impl<W: Write + Send> AuditSink for CompactSink<W> {
+    fn record(&self, record: &AuditRecord) {
+        let mut state = self.state.lock().unwrap();
+        if let Err(e) = state.write(record) {
+            eprintln!("failed to write audit record: {}", e);
+        }
+    }
+}
+
+impl<W: Write> CompactWriter<W> {
+    fn write(&mut self, record: &AuditRecord) -> io::Result<()> {
+        let mut buf = std::mem::take(&mut self.buf);
+        buf.clear();
+        let defined = self.strings.len() as u32;
+
+        if !self.header_written {
+            buf.extend_from_slice(MAGIC);
+            buf.extend_from_slice(&VERSION.to_le_bytes());
+        }
+
+        let session = record.session.unwrap_or_default();
+        let mut flags = 0;
+        for (flag, present) in [
+            (HAS_OBJECT, record.object.is_some()),
+            (HAS_POLICY_HASH, record.policy_hash.is_some()),
+            (HAS_LOGIN_UID, session.login_uid.is_some()),
+            (HAS_SESSION_ID, session.session_id.is_some()),
+        ] {
+            if present {
+                flags |= flag;
+            }
+        }
+
+        // strings are defined ahead of the record using them
+        let mut fixed = Vec::with_capacity(64);
+        fixed.extend_from_slice(&record.timestamp_ms.to_le_bytes());
+        fixed.extend_from_slice(&record.request_id.to_le_bytes());
+        fixed.extend_from_slice(&record.count.to_le_bytes());
+        fixed.push(flags);
+        for name in [&record.event, &record.subject, &record.answer] {
+            fixed.extend_from_slice(&self.intern(name, &mut buf).to_le_bytes());
+        }
+        for name in [&record.object, &record.policy_hash].into_iter().flatten() {
+            fixed.extend_from_slice(&self.intern(name, &mut buf).to_le_bytes());
+        }
+        for value in [session.login_uid, session.session_id]
+            .into_iter()
+            .flatten()
+        {
+            fixed.extend_from_slice(&value.to_le_bytes());
+        }
+        for spaces in [&record.subject_spaces, &record.object_spaces] {
+            fixed.extend_from_slice(&(spaces.len().min(u16::MAX as usize) as u16).to_le_bytes());
+        }
+        let spaces = record.subject_spaces.iter().take(u16::MAX as usize);
+        let spaces = spaces.chain(record.object_spaces.iter().take(u16::MAX as usize));
+        for space in spaces {
+            fixed.extend_from_slice(&self.intern(space, &mut buf).to_le_bytes());
+        }
+        write_str(&mut fixed, &record.subject_id);
+        if record.object.is_some() {
+            write_str(&mut fixed, record.object_id.as_deref().unwrap_or_default());
+        }
+
+        buf.push(RECORD_FRAME);
+        buf.extend_from_slice(&fixed);
+
+        // strings of a record which could not be written are defined again by the next one
+        let res = self.writer.write_all(&buf);
+        match res {
+            Ok(()) => self.header_written = true,
+            Err(_) => self.strings.retain(|_, x| *x < defined),
+        }
+        self.buf = buf;
+
+        res
+    }
+
+    /// Returns the index of `name`, appending its string frame to `buf` if it is new.
+    fn intern(&mut self, name: &str, buf: &mut Vec<u8>) -> u32 {
+        if let Some(index) = self.strings.get(name) {
+            return *index;
+        }
+
+        let index = self.strings.len() as u32;
+        self.strings.insert(name.to_owned(), index);
+        buf.push(STRING_FRAME);
+        write_str(buf, name);
+
+        index
+    }
+}
+
+/// Writes `value` prefixed by its length, truncated to the longest length that fits.
+fn write_str(buf: &mut Vec<u8>, value: &str) {
+    let mut len = value.len().min(u16::MAX as usize);
+    while !value.is_char_boundary(len) {
+        len -= 1;
+    }
+
+    buf.extend_from_slice(&(len as u16).to_le_bytes());
+    buf.extend_from_slice(&value.as_bytes()[..len]);
+}
+
+/// Reads audit records written by [`CompactSink`].
+pub struct CompactReader<R: Read> {
+    reader: BufReader<R>,
+    header_read: bool,
+    strings: Vec<String>,
+}
+
+impl<R: Read> CompactReader<R> {
+    /// Creates new `CompactReader` reading a stream from `reader`.
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader: BufReader::new(reader),
+            header_read: false,
+            strings: Vec::new(),
+        }
+    }
+
+    /// Returns the next record, or `None` at the end of the stream.
+    ///
+    /// Returns `AuditFormatError` if the stream is not in the compact format or is truncated.
+    pub fn read_record(&mut self) -> Result<Option<AuditRecord>, AuditFormatError> {
+        if !self.header_read {
+            if self.reader.fill_buf()?.is_empty() {
+                return Ok(None);
+            }
+
+            let mut magic = [0; MAGIC.len()];
+            let read = self.reader.read_exact(&mut magic);
+            if read.is_err() || &magic != MAGIC {
+                return Err(AuditFormatError::InvalidHeaderError);
+            }
+            let version = self.read_u16()?;
+            if version != VERSION {
+                return Err(AuditFormatError::UnsupportedVersionError(version));
+            }
+            self.header_read = true;
+        }
+
+        loop {
+            if self.reader.fill_buf()?.is_empty() {
+                return Ok(None);
+            }
+
+            match self.read_u8()? {
+                STRING_FRAME => {
+                    let value = self.read_str()?;
+                    self.strings.push(value);
+                }
+                RECORD_FRAME => return self.read_record_frame().map(Some),
+                tag => return Err(AuditFormatError::UnknownFrameError(tag)),
+            }
+        }
+    }
+
+    fn read_record_frame(&mut self) -> Result<AuditRecord, AuditFormatError> {
+        let timestamp_ms = self.read_u64()?;
+        let request_id = self.read_u64()?;
+        let count = self.read_u64()?;
+        let flags = self.read_u8()?;
+
+        let event = self.read_string_ref()?;
+        let subject = self.read_string_ref()?;
+        let answer = self.read_string_ref()?;
+        let object = self.read_if(flags, HAS_OBJECT, Self::read_string_ref)?;
+        let policy_hash = self.read_if(flags, HAS_POLICY_HASH, Self::read_string_ref)?;
+        let login_uid = self.read_if(flags, HAS_LOGIN_UID, Self::read_u32)?;
+        let session_id = self.read_if(flags, HAS_SESSION_ID, Self::read_u32)?;
+
+        let subject_spaces = self.read_u16()?;
+        let object_spaces = self.read_u16()?;
+        let subject_spaces = (0..subject_spaces)
+            .map(|_| self.read_string_ref())
+            .collect::<Result<_, _>>()?;
+        let object_spaces = (0..object_spaces)
+            .map(|_| self.read_string_ref())
+            .collect::<Result<_, _>>()?;
+
+        let subject_id = self.read_str()?;
+        let object_id = self.read_if(flags, HAS_OBJECT, Self::read_str)?;
+
+        let session = Session {
+            login_uid,
+            session_id,
+        };
+
+        Ok(AuditRecord {
+            timestamp_ms,
+            request_id,
+            event,
+            subject,
+            subject_id,
+            session: (session != Session::default()).then_some(session),
+            subject_spaces,
+            object,
+            object_id,
+            object_spaces,
+            answer,
+            policy_hash,
+            count,
+        })
+    }
+
+    fn read_if<T>(
+        &mut self,
+        flags: u8,
+        flag: u8,
+        read: fn(&mut Self) -> Result<T, AuditFormatError>,
+    ) -> Result<Option<T>, AuditFormatError> {
+        if flags & flag == 0 {
+            return Ok(None);
+        }
+
+        read(self).map(Some)
+    }
+
+    fn read_string_ref(&mut self) -> Result<String, AuditFormatError> {
+        let index = self.read_u32()?;
+        self.strings
+            .get(index as usize)
+            .cloned()
+            .ok_or(AuditFormatError::UnknownStringError(index))
+    }
+
+    fn read_str(&mut self) -> Result<String, AuditFormatError> {
+        let len = self.read_u16()?;
+        let mut data = vec![0; len as usize];
+        self.reader.read_exact(&mut data)?;
+
+        String::from_utf8(data).map_err(|_| AuditFormatError::InvalidStringError)
+    }
+
+    fn read_u8(&mut self) -> Result<u8, AuditFormatError> {
+        let mut data = [0; 1];
+        self.reader.read_exact(&mut data)?;
+        Ok(data[0])
+    }
+
+    fn read_u16(&mut self) -> Result<u16, AuditFormatError> {
+        let mut data = [0; 2];
+        self.reader.read_exact(&mut data)?;
+        Ok(u16::from_le_bytes(data))
+    }
+
+    fn read_u32(&mut self) -> Result<u32, AuditFormatError> {
+        let mut data = [0; 4];
+        self.reader.read_exact(&mut data)?;
+        Ok(u32::from_le_bytes(data))
+    }
+
+    fn read_u64(&mut self) -> Result<u64, AuditFormatError> {
+        let mut data = [0; 8];
+        self.reader.read_exact(&mut data)?;
+        Ok(u64::from_le_bytes(data))
+    }
+}
+
+impl<R: Read> Iterator for CompactReader<R> {
+    type Item = Result<AuditRecord, AuditFormatError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_record().transpose()
+    }
+}
+
+/// Format [`convert`] produces.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConvertFormat {
+    /// A line of JSON for every record, as written by [`JsonSink`].
+    ///
+    /// [`JsonSink`]: crate::medusa::JsonSink
+    Json,
+
+    /// A header line and a line of comma-separated values for every record. Virtual spaces of
+    /// a record are separated by semicolons.
+    Csv,
+}
+
+/// Converts the compact stream read from `input` into `format` written into `output`. Returns
+/// the number of converted records.
+///
+/// Returns `AuditFormatError` if the stream is not in the compact format, is truncated, or if
+/// writing fails. The records converted before the error are written.
+pub fn convert<R: Read, W: Write>(
+    input: R,
+    mut output: W,
+    format: ConvertFormat,
+) -> Result<usize, AuditFormatError> {
+    if format == ConvertFormat::Csv {
+        writeln!(output, "{}", CSV_COLUMNS.join(","))?;
+    }
+
+    let mut converted = 0;
+    for record in CompactReader::new(input) {
+        let record = record?;
+        match format {
+            ConvertFormat::Json => {
+                serde_json::to_writer(&mut output, &record).map_err(io::Error::from)?;
+                writeln!(output)?;
+            }
+            ConvertFormat::Csv => writeln!(output, "{}", csv_row(&record))?,
+        }
+        converted += 1;
+    }
+    output.flush()?;
+
+    Ok(converted)
+}
+
+fn csv_row(record: &AuditRecord) -> String {
+    let session = record.session.unwrap_or_default();
+    let optional = |x: Option<String>| x.unwrap_or_default();
+
+    [
+        record.timestamp_ms.to_string(),
+        record.request_id.to_string(),
+        record.event.clone(),
+        record.subject.clone(),
+        record.subject_id.clone(),
+        optional(session.login_uid.map(|x| x.to_string())),
+        optional(session.session_id.map(|x| x.to_string())),
+        record.subject_spaces.join(";"),
+        optional(record.object.clone()),
+        optional(record.object_id.clone()),
+        record.object_spaces.join(";"),
+        record.answer.clone(),
+        optional(record.policy_hash.clone()),
+        record.count.to_string(),
+    ]
+    .iter()
+    .map(|x| escape_csv(x))
+    .collect::<Vec<_>>()
+    .join(",")
+}
+
+/// Quotes `value` if it contains a separator, a quote or a line break, as RFC 4180 requires.
+fn escape_csv(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(request_id: u64, object: bool) -> AuditRecord {
+        AuditRecord {
+            timestamp_ms: 1_700_000_000_000 + request_id,
+            request_id,
+            event: "getfile".into(),
+            subject: "process".into(),
+            subject_id: format!("pid {}", request_id),
+            session: object.then_some(Session {
+                login_uid: Some(1000),
+                session_id: None,
+            }),
+            subject_spaces: vec!["domains".into(), "users".into()],
+            object: object.then(|| "file".into()),
+            object_id: object.then(|| "/etc/passwd, \"copy\"".into()),
+            object_spaces: if object { vec!["etc".into()] } else { vec![] },
+            answer: "ALLOW".into(),
+            policy_hash: object.then(|| "abc".into()),
+            count: 1,
+        }
+    }
+
+    fn write(records: &[AuditRecord]) -> Vec<u8> {
+        let sink = CompactSink::new(Vec::new());
+        for record in records {
+            sink.record(record);
+        }
+        sink.state.into_inner().unwrap().writer
+    }
+
+    fn json(record: &AuditRecord) -> serde_json::Value {
+        serde_json::to_value(record).unwrap()
+    }
+
+    #[test]
+    fn round_trips_records() {
+        let records = [record(1, true), record(2, false), record(3, true)];
+        let data = write(&records);
+
+        let read = CompactReader::new(&data[..])
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(
+            read.iter().map(json).collect::<Vec<_>>(),
+            records.iter().map(json).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn writes_strings_once() {
+        let one = write(&[record(1, true)]).len();
+        let two = write(&[record(1, true), record(2, true)]).len();
+
+        // the second record only repeats its fixed fields, string indices and identities
+        let identities = 2 + "pid 2".len() + 2 + "/etc/passwd, \"copy\"".len();
+        assert_eq!(
+            two - one,
+            1 + 8 * 3 + 1 + 4 * 5 + 4 + 2 * 2 + 4 * 3 + identities
+        );
+    }
+
+    #[test]
+    fn defines_strings_again_after_failed_write() {
+        struct Failing(Vec<u8>, bool);
+
+        impl Write for Failing {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                if std::mem::take(&mut self.1) {
+                    return Err(io::Error::other("full"));
+                }
+                self.0.write(buf)
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let sink = CompactSink::new(Failing(Vec::new(), false));
+        sink.record(&record(1, false));
+        sink.state.lock().unwrap().writer.1 = true;
+        sink.record(&record(2, true));
+        sink.record(&record(3, true));
+
+        let data = sink.state.into_inner().unwrap().writer.0;
+        let read = CompactReader::new(&data[..])
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(
+            read.iter().map(json).collect::<Vec<_>>(),
+            [json(&record(1, false)), json(&record(3, true))]
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_streams() {
+        assert!(CompactReader::new(&[][..]).read_record().unwrap().is_none());
+        assert!(matches!(
+            CompactReader::new(&b"{\"event\":1}"[..]).read_record(),
+            Err(AuditFormatError::InvalidHeaderError)
+        ));
+
+        let mut data = MAGIC.to_vec();
+        data.extend_from_slice(&2u16.to_le_bytes());
+        assert!(matches!(
+            CompactReader::new(&data[..]).read_record(),
+            Err(AuditFormatError::UnsupportedVersionError(2))
+        ));
+
+        let data = write(&[record(1, true)]);
+        assert!(matches!(
+            CompactReader::new(&data[..data.len() - 1]).read_record(),
+            Err(AuditFormatError::IOError(_))
+        ));
+
+        let mut data = MAGIC.to_vec();
+        data.extend_from_slice(&VERSION.to_le_bytes());
+        data.push(0x7f);
+        assert!(matches!(
+            CompactReader::new(&data[..]).read_record(),
+            Err(AuditFormatError::UnknownFrameError(0x7f))
+        ));
+    }
+
+    #[test]
+    fn truncates_long_strings_at_char_boundary() {
+        let mut buf = Vec::new();
+        write_str(&mut buf, &"é".repeat(u16::MAX as usize));
+        let len = u16::from_le_bytes([buf[0], buf[1]]) as usize;
+        assert_eq!(len, u16::MAX as usize - 1);
+        assert!(std::str::from_utf8(&buf[2..]).is_ok());
+    }
+
+    #[test]
+    fn converts_to_csv_and_json() {
+        let data = write(&[record(1, true), record(2, false)]);
+
+        let mut csv = Vec::new();
+        assert_eq!(convert(&data[..], &mut csv, ConvertFormat::Csv).unwrap(), 2);
+        let csv = String::from_utf8(csv).unwrap();
+        let lines = csv.lines().collect::<Vec<_>>();
+        assert_eq!(lines[0], CSV_COLUMNS.join(","));
+        assert_eq!(
+            lines[1],
+            "1700000000001,1,getfile,process,pid 1,1000,,domains;users,file,\
+             \"/etc/passwd, \"\"copy\"\"\",etc,ALLOW,abc,1"
+        );
+        assert_eq!(
+            lines[2],
+            "1700000000002,2,getfile,process,pid 2,,,domains;users,,,,ALLOW,,1"
+        );
+
+        let mut json = Vec::new();
+        assert_eq!(
+            convert(&data[..], &mut json, ConvertFormat::Json).unwrap(),
+            2
+        );
+        let first: serde_json::Value =
+            serde_json::from_str(String::from_utf8(json).unwrap().lines().next().unwrap()).unwrap();
+        assert_eq!(first, self::json(&record(1, true)));
+    }
+}
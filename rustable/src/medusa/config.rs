@@ -1,24 +1,236 @@
 #![allow(dead_code)]
 
-use crate::medusa::constants::{HandlerFlags, NODE_HIGHEST_PRIORITY};
+use crate::bitmap;
+use crate::medusa::audit::AuditSink;
+use crate::medusa::batch::UPDATE_FLUSH_DEFAULT_INTERVAL;
+use crate::medusa::constants::{
+    AccessType, HandlerFlags, KernelCapabilities, NODE_HIGHEST_PRIORITY,
+};
+use crate::medusa::decision::{DecisionTable, Relation};
+use crate::medusa::domains::{UserDomains, UserDomainsBuilder};
 use crate::medusa::error::ConfigError;
+use crate::medusa::executor::{Executor, TokioExecutor};
 use crate::medusa::handler::{CustomHandler, EventHandler, EventHandlerBuilder};
-use crate::medusa::space::{SpaceBuilder, SpaceDef};
-use crate::medusa::tree::{Node, NodeBuilder, Tree, TreeBuilder};
-use std::collections::HashMap;
-use std::sync::atomic::AtomicU64;
+use crate::medusa::mcp::DISPATCH_QUEUE_DEFAULT_CAPACITY;
+use crate::medusa::migrate::LabelMigration;
+use crate::medusa::pending::PENDING_REQUEST_DEFAULT_MAX_AGE;
+use crate::medusa::plugin::{PluginHandler, PluginRegistry};
+use crate::medusa::policy;
+#[cfg(feature = "recording")]
+use crate::medusa::recording::SessionRecording;
+use crate::medusa::redact;
+use crate::medusa::rule::Rule;
+use crate::medusa::sched::ThreadScheduling;
+use crate::medusa::space::{RetiredSpaceBits, Space, SpaceBuilder, SpaceDef, VirtualSpace};
+use crate::medusa::tree::{self, LazyNodes, Node, NodeBuilder, Tree, TreeBuildState, TreeBuilder};
+use crate::medusa::{
+    AnomalyDetection, AttributeBytes, AttributeDataType, AttributeError, AttributeExpectation,
+    AttributeMismatch, AuthRequestData, CompletedRequest, ConfigDiff, Event, ExecutableMap,
+    Explanation, FastHashMap, LatencySlo, LayoutChange, MedusaAnswer, MedusaClass,
+    MedusaEvtypeHeader, Redaction, ResourceAccounting, Tripwire, UpdateError,
+};
+use derivative::Derivative;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-#[derive(Debug)]
+// source of `Config::generation`, 0 is left for events not registered with any config
+static NEXT_GENERATION: AtomicU64 = AtomicU64::new(1);
+
+/// Callback invoked after the answer to an authorization request has been written.
+pub type CompletionHook = Arc<dyn Fn(&CompletedRequest) + Send + Sync>;
+
+/// Callback invoked when no message arrived from the security module within the liveness timeout.
+pub type LivenessHook = Arc<dyn Fn(Liveness) + Send + Sync>;
+
+/// Callback invoked when a class is registered with a layout different from the previous one.
+pub type LayoutHook = Arc<dyn Fn(&[LayoutChange]) + Send + Sync>;
+
+/// Callback invoked when an update request failed and no retries are left, see
+/// [`ConfigBuilder::on_update_failure`].
+pub type UpdateEscalation = Arc<dyn Fn(&UpdateError) + Send + Sync>;
+
+/// Names of the spaces a space has access to by access type and of the spaces it denies.
+type SpaceAccessNames = (
+    [Vec<&'static str>; AccessType::Length as usize],
+    Vec<&'static str>,
+);
+
+/// State of the connection reported to the [`LivenessHook`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Liveness {
+    /// Nothing arrived for the given time, but no answers from the security module are pending.
+    Idle(Duration),
+
+    /// Fetch or update requests are pending and the security module did not send anything within
+    /// the timeout, or the connection was closed. [`Connection::run`] returns an error afterwards.
+    ///
+    /// [`Connection::run`]: crate::medusa::Connection::run
+    KernelGone,
+}
+
+/// Determines how authorization requests are dispatched to event handlers.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum DispatchMode {
+    /// Every authorization request is handled in its own task, except those of events whose
+    /// handlers are all fast, see [`EventHandlerBuilder::with_fast_handler`].
+    ///
+    /// [`EventHandlerBuilder::with_fast_handler`]: crate::medusa::EventHandlerBuilder::with_fast_handler
+    #[default]
+    Concurrent,
+
+    /// Authorization requests are handled one after another in the order they were received.
+    /// Fetch and update answers are still processed while a handler is running.
+    Sequential,
+}
+
+/// Determines on which runtime the connection loop and its tasks run.
+#[derive(Debug, Default, Clone)]
+pub enum RuntimeMode {
+    /// The runtime calling [`Connection::new`] and [`Connection::run`].
+    ///
+    /// [`Connection::new`]: crate::medusa::Connection::new
+    /// [`Connection::run`]: crate::medusa::Connection::run
+    #[default]
+    Caller,
+
+    /// A runtime provided by the caller, e.g. to keep event handlers off the worker threads of
+    /// the application. It must be multi-threaded or driven by another thread.
+    Handle(tokio::runtime::Handle),
+
+    /// A current-thread runtime owned by the connection and driven by a thread of its own, so
+    /// that event handlers run on a single thread.
+    CurrentThread,
+}
+
+/// Determines what happens when a message from the security module cannot be processed.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum RecoveryStrategy {
+    /// The connection loop returns the error.
+    #[default]
+    Abort,
+
+    /// The error is logged and bytes are skipped until something that looks like the beginning
+    /// of a message is found, a zero followed by a known command or the id of a registered
+    /// event, where the connection continues. Messages do not declare their size, so the rest of
+    /// the offending message is scanned rather than skipped as a whole, and may be mistaken for
+    /// the beginning of a message. Authorization requests which could not be decoded are
+    /// answered with [`MedusaAnswer::Err`]. I/O errors always abort the connection.
+    ///
+    /// [`MedusaAnswer::Err`]: crate::medusa::MedusaAnswer::Err
+    Resynchronize,
+}
+
+/// Statistics of building a [`Config`], so that the startup time of big policies can be
+/// followed, see [`Config::build_report`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct BuildReport {
+    /// Number of nodes of all trees.
+    pub nodes: usize,
+
+    /// Number of distinct regular expressions of node paths.
+    pub regexes: usize,
+
+    /// Time spent compiling the regular expressions, on all cores with the `parallel` feature.
+    pub regex_compilation: Duration,
+
+    /// Time spent building the config, including the compilation.
+    pub total: Duration,
+}
+
+impl fmt::Display for BuildReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "config of {} nodes built in {:?}, {} regexes compiled in {:?}",
+            self.nodes, self.total, self.regexes, self.regex_compilation
+        )
+    }
+}
+
+#[derive(Derivative)]
+#[derivative(Debug)]
 pub struct Config {
+    // distinguishes configs, so that ids of events interned by another one are not used
+    pub(crate) generation: u64,
+    build_report: BuildReport,
+
     trees: Box<[Tree]>,
-    cinfo_nodes: HashMap<usize, Arc<Node>>,
+    cinfo_nodes: FastHashMap<usize, Arc<Node>>,
+    lazy_nodes: Arc<LazyNodes>,
 
-    event_handlers: HashMap<String, Box<[EventHandler]>>,
+    // event names interned to indices of `event_handlers`
+    event_ids: FastHashMap<String, usize>,
+    event_handlers: Box<[Box<[EventHandler]>]>,
+    // whether all handlers of an event are fast, indexed like `event_handlers`
+    fast_events: Box<[bool]>,
+    pub(crate) decision_table: Option<DecisionTable>,
     name_to_space_bit: HashMap<String, usize>,
-    space_bit_to_name: HashMap<usize, String>,
+    pub(crate) space_bit_to_name: HashMap<usize, String>,
+    // see `ConfigBuilder::replacing`
+    pub(crate) retired_space_bits: Arc<RetiredSpaceBits>,
 
     pub(crate) covered_events_mask: AtomicU64,
+    pub(crate) covered_events: Option<Box<[String]>>,
+    pub(crate) dispatch_mode: DispatchMode,
+    pub(crate) dispatch_queue_capacity: usize,
+    pub(crate) io_scheduling: Option<ThreadScheduling>,
+    pub(crate) runtime_mode: RuntimeMode,
+    pub(crate) recovery_strategy: RecoveryStrategy,
+    pub(crate) liveness_timeout: Option<Duration>,
+    pub(crate) update_flush_interval: Duration,
+    pub(crate) update_dedup_window: Option<Duration>,
+    pub(crate) pending_request_max_age: Duration,
+    pub(crate) update_retries: u32,
+    pub(crate) update_backoff: Duration,
+    pub(crate) handler_watchdog: Option<Duration>,
+    pub(crate) latency_slo: Option<LatencySlo>,
+    pub(crate) anomaly_detection: Option<AnomalyDetection>,
+    pub(crate) resource_accounting: Option<ResourceAccounting>,
+    pub(crate) assumed_capabilities: Option<KernelCapabilities>,
+    pub(crate) required_capabilities: KernelCapabilities,
+    pub(crate) policy_hash: Option<String>,
+    pub(crate) enforce: bool,
+    pub(crate) expected_events: Box<[String]>,
+    pub(crate) critical_events: Box<[String]>,
+    pub(crate) latency_critical_events: Box<[String]>,
+    pub(crate) expected_attributes: Box<[AttributeExpectation]>,
+    pub(crate) default_attributes: FastHashMap<String, Vec<(String, Vec<u8>)>>,
+    pub(crate) redactions: FastHashMap<String, Redaction>,
+    pub(crate) generation_attributes: FastHashMap<String, String>,
+    pub(crate) shadow: Option<Box<Config>>,
+    pub(crate) observe: Option<MedusaAnswer>,
+    pub(crate) track_processes: bool,
+    pub(crate) separate_tree_positions: bool,
+    pub(crate) label_migration: Option<LabelMigration>,
+    pub(crate) control_socket: Option<PathBuf>,
+    pub(crate) snapshot_path: Option<PathBuf>,
+    #[cfg(feature = "signing")]
+    pub(crate) policy_verifying_key: Option<[u8; 32]>,
+    #[cfg(feature = "recording")]
+    pub(crate) session_recording: Option<SessionRecording>,
+    pub(crate) user_domains: Option<UserDomains>,
+    pub(crate) quarantine: Option<VirtualSpace>,
+    space_access: HashMap<String, VirtualSpace>,
+
+    #[derivative(Debug = "ignore")]
+    pub(crate) completion_hooks: Box<[CompletionHook]>,
+    #[derivative(Debug = "ignore")]
+    pub(crate) liveness_hook: Option<LivenessHook>,
+    #[derivative(Debug = "ignore")]
+    pub(crate) layout_hook: Option<LayoutHook>,
+    #[derivative(Debug = "ignore")]
+    pub(crate) update_escalation: Option<UpdateEscalation>,
+    #[derivative(Debug = "ignore")]
+    pub(crate) audit_sinks: Box<[Arc<dyn AuditSink>]>,
+    #[derivative(Debug = "ignore")]
+    pub(crate) executor: Arc<dyn Executor>,
+    #[derivative(Debug = "ignore")]
+    plugins: PluginRegistry,
     // TODO medusa connections, default answer
 }
 
@@ -30,11 +242,52 @@ impl Config {
         ConfigBuilder::new()
     }
 
+    /// Returns the differences between this config and `other`, which is regarded as the new
+    /// one, e.g. for review before [`Context::replace_config`].
+    ///
+    /// [`Context::replace_config`]: crate::medusa::Context::replace_config
+    pub fn diff(&self, other: &Config) -> ConfigDiff {
+        ConfigDiff::new(self, other)
+    }
+
+    /// Explains how this config decides `request`, e.g. one built with the emulator or recorded
+    /// from a live connection: the static relation which answers it, the handlers of its event
+    /// which would be run and the virtual spaces the others miss, and the node its path resolves
+    /// to for hierarchy handlers. Handlers are not run.
+    pub fn explain(&self, request: &AuthRequestData) -> Explanation {
+        Explanation::new(self, request)
+    }
+
+    /// Resolves `paths` in tree `tree`, so that later lookups of their nodes are answered from
+    /// the caches of the nodes, see [`ConfigBuilder::warm_up`].
+    ///
+    /// Returns the number of paths covered by the tree.
+    pub fn warm_up<I>(&self, tree: &str, paths: I) -> usize
+    where
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+    {
+        let tree = match self.tree_by_name(tree) {
+            Some(tree) => tree,
+            None => return 0,
+        };
+
+        paths
+            .into_iter()
+            .filter(|x| tree.warm_up(x.as_ref()))
+            .count()
+    }
+
     /// Returns a tree having the given name.
     pub fn tree_by_name(&self, name: &str) -> Option<&Tree> {
         self.trees.iter().find(|x| x.name() == name)
     }
 
+    #[cfg(any(feature = "scripting", feature = "wasm"))]
+    pub(crate) fn trees(&self) -> &[Tree] {
+        &self.trees
+    }
+
     /// Returns bit of a virtual space having the given name.
     pub fn name_to_space_bit(&self, name: &str) -> Option<&usize> {
         self.name_to_space_bit.get(name)
@@ -45,16 +298,307 @@ impl Config {
         self.space_bit_to_name.get(bit)
     }
 
+    /// Returns whether a subject in spaces `subject_spaces` has access `at` to an object in
+    /// spaces `object_spaces`, see [`Context::check`].
+    ///
+    /// [`Context::check`]: crate::medusa::Context::check
+    pub(crate) fn check(
+        &self,
+        subject_spaces: &[&str],
+        object_spaces: &[&str],
+        at: AccessType,
+    ) -> Result<bool, ConfigError> {
+        // denied spaces of one space override access granted by another, as in a shared node
+        let mut subject = VirtualSpace::new();
+        for name in subject_spaces {
+            let vs = self
+                .space_access
+                .get(*name)
+                .ok_or_else(|| ConfigError::UnknownSpaceError(name.to_string()))?;
+            subject.merge(vs);
+        }
+
+        let mut object = Vec::new();
+        for name in object_spaces {
+            let bit = self
+                .name_to_space_bit(name)
+                .ok_or_else(|| ConfigError::UnknownSpaceError(name.to_string()))?;
+            object.resize(object.len().max(bit / 8 + 1), 0);
+            bitmap::set_bit(&mut object, *bit);
+        }
+
+        let mut access = subject.to_at_bytes(at);
+        Ok(bitmap::any(bitmap::and(&mut access, &object)))
+    }
+
+    pub(crate) fn nodes(&self) -> impl Iterator<Item = &Arc<Node>> {
+        let lazy_nodes = self
+            .lazy_nodes
+            .cinfos()
+            .into_iter()
+            .filter_map(|x| self.lazy_node_by_cinfo(&x));
+
+        self.cinfo_nodes
+            .values()
+            .chain(lazy_nodes)
+            .chain(self.user_domains.iter().flat_map(|x| x.nodes()))
+    }
+
+    /// Returns names of all virtual spaces with their bits.
+    pub(crate) fn space_names(&self) -> impl Iterator<Item = (&str, usize)> {
+        self.name_to_space_bit.iter().map(|(k, v)| (k.as_str(), *v))
+    }
+
     pub(crate) fn node_by_cinfo(&self, cinfo: &usize) -> Option<&Arc<Node>> {
-        self.cinfo_nodes.get(cinfo)
+        self.cinfo_nodes
+            .get(cinfo)
+            .or_else(|| self.lazy_node_by_cinfo(cinfo))
+            .or_else(|| self.user_domains.as_ref()?.node_by_cinfo(cinfo))
+    }
+
+    /// Returns the node `cinfo` of a lazily compiled subtree, see [`NodeBuilder::lazy`].
+    fn lazy_node_by_cinfo(&self, cinfo: &usize) -> Option<&Arc<Node>> {
+        let (root, indices) = self.lazy_nodes.position(cinfo)?;
+
+        let mut node = self.cinfo_nodes.get(&root)?;
+        for i in indices.iter() {
+            node = node.children().get(*i)?;
+        }
+
+        Some(node)
+    }
+
+    /// Returns the node an entity named `path` is entered into under `parent` and whether it
+    /// was reached by recursion, `None` if neither `parent` nor any of its ancestors is
+    /// recursive.
+    pub(crate) fn child_node<'a>(
+        &'a self,
+        parent: &'a Arc<Node>,
+        path: &str,
+    ) -> Option<(&'a Arc<Node>, bool)> {
+        if let Some(child) = parent.child_by_path(path) {
+            return Some((child, false));
+        }
+
+        // find first recursive ancestor
+        let mut node = parent;
+        while !node.is_recursive() {
+            let pcinfo = node.parent_cinfo()?;
+            node = self.node_by_cinfo(&pcinfo)?;
+        }
+
+        Some((node, true))
+    }
+
+    /// Returns name of the tree containing node `cinfo` and paths of the nodes leading to it,
+    /// starting with the root.
+    pub(crate) fn node_location(&self, cinfo: &usize) -> Option<(&str, Vec<&str>)> {
+        let mut node = self.node_by_cinfo(cinfo)?;
+        let mut node_cinfo = *cinfo;
+        let mut paths = vec![node.path()];
+
+        while let Some(parent_cinfo) = node.parent_cinfo() {
+            node = self.node_by_cinfo(&parent_cinfo)?;
+            node_cinfo = parent_cinfo;
+            paths.push(node.path());
+        }
+        paths.reverse();
+
+        let tree = self.trees.iter().find(|x| x.root().cinfo() == node_cinfo)?;
+
+        Some((tree.name(), paths))
+    }
+
+    /// Returns name of the tree containing node `cinfo`.
+    pub(crate) fn tree_name_of(&self, cinfo: usize) -> Option<&str> {
+        let mut node_cinfo = cinfo;
+        while let Some(parent_cinfo) = self.node_by_cinfo(&node_cinfo)?.parent_cinfo() {
+            node_cinfo = parent_cinfo;
+        }
+
+        self.trees
+            .iter()
+            .find(|x| x.root().cinfo() == node_cinfo)
+            .map(|x| x.name())
+    }
+
+    /// Returns `cinfo` of a node found by [`Config::node_location`], possibly of another config.
+    pub(crate) fn cinfo_by_location(&self, tree: &str, paths: &[&str]) -> Option<usize> {
+        let (root_path, paths) = paths.split_first()?;
+
+        let mut node = self.tree_by_name(tree)?.root();
+        if node.path() != *root_path {
+            return None;
+        }
+
+        for path in paths {
+            node = node.children().iter().find(|x| x.path() == *path)?;
+        }
+
+        Some(node.cinfo())
+    }
+
+    /// Returns the interned id of `event`, `None` if it has no handlers. The id is looked up
+    /// once when the event is registered, so that requests find their handlers without
+    /// hashing the event name.
+    pub(crate) fn event_id(&self, event: &str) -> Option<usize> {
+        self.event_ids.get(event).copied()
+    }
+
+    /// Returns the interned id of the event of `header`. It is the one assigned by
+    /// [`Config::register_evtype`] unless the event was registered with another config, e.g.
+    /// before [`Context::replace_config`].
+    ///
+    /// [`Context::replace_config`]: crate::medusa::Context::replace_config
+    pub(crate) fn event_id_of(&self, header: &MedusaEvtypeHeader) -> Option<usize> {
+        if header.config_generation == self.generation {
+            header.event_id
+        } else {
+            self.event_id(header.name())
+        }
+    }
+
+    /// Registers the event of `header` with the handlers and the nodes of this config.
+    pub(crate) fn register_evtype(&self, header: &mut MedusaEvtypeHeader) {
+        let name = Arc::clone(&header.name);
+        header.event_id = self.event_id(&name);
+        header.config_generation = self.generation;
+
+        self.lazy_nodes.register_event(&name, header.monitoring_bit);
+        for node in self.nodes() {
+            node.register_event(&name, header.monitoring_bit);
+        }
+
+        if self.is_covered(&name) {
+            let mask = 1 << header.monitoring_bit;
+            self.covered_events_mask.fetch_or(mask, Ordering::SeqCst);
+        }
+    }
+
+    pub(crate) fn handlers_by_event_id(&self, id: usize) -> &[EventHandler] {
+        &self.event_handlers[id]
     }
 
-    pub(crate) fn handlers_by_event(&self, event: &str) -> Option<&[EventHandler]> {
-        self.event_handlers.get(event).map(|x| x.as_ref())
+    /// Returns whether requests of event `id` can be answered right after they are read, see
+    /// [`EventHandlerBuilder::with_fast_handler`]. Events without handlers are answered
+    /// immediately as well.
+    ///
+    /// [`EventHandlerBuilder::with_fast_handler`]: crate::medusa::EventHandlerBuilder::with_fast_handler
+    pub(crate) fn is_fast_path(&self, id: Option<usize>) -> bool {
+        self.dispatch_mode == DispatchMode::Concurrent && id.is_none_or(|id| self.fast_events[id])
     }
 
+    /// Returns whether `event` has a handler other than the built-in ones of tripwires.
     pub(crate) fn has_handler(&self, event: &str) -> bool {
-        self.event_handlers.contains_key(event)
+        self.event_id(event)
+            .is_some_and(|id| self.event_handlers[id].iter().any(|x| !x.is_tripwire()))
+    }
+
+    /// Returns whether `event` is monitored, see [`ConfigBuilder::cover_events`]. Events with
+    /// handlers or relations are monitored by default.
+    pub(crate) fn is_covered(&self, event: &str) -> bool {
+        match &self.covered_events {
+            Some(events) => events.iter().any(|x| x == event),
+            None => self.event_ids.contains_key(event),
+        }
+    }
+
+    /// Returns plugins filling the plugin handler slots.
+    pub fn plugins(&self) -> &PluginRegistry {
+        &self.plugins
+    }
+
+    /// Returns all event handlers.
+    pub fn handlers(&self) -> impl Iterator<Item = &EventHandler> {
+        self.event_handlers.iter().flat_map(|x| x.iter())
+    }
+
+    pub(crate) fn has_handler_named(&self, name: &str) -> bool {
+        self.handlers().any(|x| x.name() == name)
+    }
+
+    /// Returns the mode in which authorization requests are dispatched.
+    pub fn dispatch_mode(&self) -> DispatchMode {
+        self.dispatch_mode
+    }
+
+    /// Returns the strategy used when a message from the security module cannot be processed.
+    pub fn recovery_strategy(&self) -> RecoveryStrategy {
+        self.recovery_strategy
+    }
+
+    /// Returns hash of the policy file this configuration was loaded from, if any.
+    pub fn policy_hash(&self) -> Option<&str> {
+        self.policy_hash.as_deref()
+    }
+
+    /// Returns capabilities of the security module declared in the configuration, if any.
+    pub fn assumed_capabilities(&self) -> Option<KernelCapabilities> {
+        self.assumed_capabilities
+    }
+
+    /// Returns capabilities which the security module has to provide.
+    pub fn required_capabilities(&self) -> KernelCapabilities {
+        self.required_capabilities
+    }
+
+    /// Returns path of the control socket, if enabled.
+    pub fn control_socket(&self) -> Option<&Path> {
+        self.control_socket.as_deref()
+    }
+
+    /// Returns the time after which a silent connection is checked for liveness.
+    pub fn liveness_timeout(&self) -> Option<Duration> {
+        self.liveness_timeout
+    }
+
+    /// Returns statistics of building this config.
+    pub fn build_report(&self) -> BuildReport {
+        self.build_report
+    }
+
+    /// Returns attributes of `class` not meeting the expectations of this config, see
+    /// [`ConfigBuilder::expect_attribute`].
+    pub fn attribute_mismatches(&self, class: &MedusaClass) -> Vec<AttributeMismatch> {
+        self.expected_attributes
+            .iter()
+            .filter_map(|x| x.check(class))
+            .collect()
+    }
+
+    /// Returns how values of attribute `attribute` are written into verbose output, see
+    /// [`ConfigBuilder::redact`].
+    pub(crate) fn redaction(&self, attribute: &str) -> Redaction {
+        self.redactions
+            .get(attribute)
+            .copied()
+            .unwrap_or(Redaction::Keep)
+    }
+
+    /// Returns the attribute of `class` which changes whenever an object of it changes, see
+    /// [`ConfigBuilder::generation_attribute`].
+    pub(crate) fn generation_attribute(&self, class: &str) -> Option<&str> {
+        self.generation_attributes.get(class).map(String::as_str)
+    }
+
+    /// Returns whether decisions of `event` are written ahead of others, see
+    /// [`ConfigBuilder::latency_critical`].
+    pub(crate) fn is_latency_critical(&self, event: &str) -> bool {
+        self.latency_critical_events.iter().any(|x| x == event)
+    }
+
+    /// Sets the default values of attributes of `class`, see
+    /// [`ConfigBuilder::default_attribute`]. Returns errors of attributes which cannot be set.
+    pub(crate) fn apply_default_attributes(&self, class: &mut MedusaClass) -> Vec<AttributeError> {
+        let defaults = match self.default_attributes.get(class.name()) {
+            Some(defaults) => defaults,
+            None => return Vec::new(),
+        };
+
+        defaults
+            .iter()
+            .filter_map(|(name, data)| class.attributes.set(name, data.clone()).err())
+            .collect()
     }
 }
 
@@ -85,9 +629,65 @@ pub struct ConfigBuilder {
 
     include_space: HashMap<&'static str, Vec<&'static str>>,
     exclude_space: HashMap<&'static str, Vec<&'static str>>,
-    space_to_path: HashMap<&'static str, (&'static str, bool)>,
+    space_to_path: HashMap<&'static str, (&'static str, bool, u16)>,
+    // access types and denied spaces of each space, see `Config::check`
+    space_access: HashMap<&'static str, SpaceAccessNames>,
 
     event_handlers: HashMap<String, Vec<EventHandlerBuilder>>,
+    relations: Vec<Relation>,
+    covered_events: Option<Vec<String>>,
+
+    dispatch_mode: DispatchMode,
+    dispatch_queue_capacity: Option<usize>,
+    io_scheduling: Option<ThreadScheduling>,
+    runtime_mode: RuntimeMode,
+    recovery_strategy: RecoveryStrategy,
+    liveness_timeout: Option<Duration>,
+    update_flush_interval: Option<Duration>,
+    update_dedup_window: Option<Duration>,
+    pending_request_max_age: Option<Duration>,
+    update_retries: u32,
+    update_backoff: Duration,
+    handler_watchdog: Option<Duration>,
+    latency_slo: Option<LatencySlo>,
+    anomaly_detection: Option<AnomalyDetection>,
+    resource_accounting: Option<ResourceAccounting>,
+    assumed_capabilities: Option<KernelCapabilities>,
+    required_capabilities: KernelCapabilities,
+    policy_hash: Option<String>,
+    enforce: bool,
+    expected_events: Vec<String>,
+    critical_events: Vec<String>,
+    latency_critical_events: Vec<String>,
+    expected_attributes: Vec<AttributeExpectation>,
+    default_attributes: FastHashMap<String, Vec<(String, Vec<u8>)>>,
+    redactions: FastHashMap<String, Redaction>,
+    generation_attributes: FastHashMap<String, String>,
+    shadow: Option<Config>,
+    observe: Option<MedusaAnswer>,
+    track_processes: bool,
+    separate_tree_positions: bool,
+    label_migration: Option<LabelMigration>,
+    warm_up_paths: Vec<(String, String)>,
+    warm_up_files: Vec<(String, PathBuf)>,
+    lazy_subtrees: Vec<&'static str>,
+    replaced_spaces: Option<(HashMap<String, usize>, Arc<RetiredSpaceBits>)>,
+    control_socket: Option<PathBuf>,
+    snapshot_path: Option<PathBuf>,
+    #[cfg(feature = "signing")]
+    policy_verifying_key: Option<[u8; 32]>,
+    #[cfg(feature = "recording")]
+    session_recording: Option<SessionRecording>,
+    user_domains: Option<UserDomainsBuilder>,
+    quarantine_space: Option<&'static str>,
+    tripwires: Vec<Tripwire>,
+    completion_hooks: Vec<CompletionHook>,
+    liveness_hook: Option<LivenessHook>,
+    layout_hook: Option<LayoutHook>,
+    update_escalation: Option<UpdateEscalation>,
+    audit_sinks: Vec<Arc<dyn AuditSink>>,
+    executor: Option<Arc<dyn Executor>>,
+    plugins: Vec<Arc<dyn PluginHandler>>,
 }
 
 impl ConfigBuilder {
@@ -103,23 +703,52 @@ impl ConfigBuilder {
         let name = space.name();
         let path = space.path();
         let recursive = space.recursive();
+        let priority = space.priority;
 
-        if self.space_to_path.insert(name, (path, recursive)).is_some() {
+        if self
+            .space_to_path
+            .insert(name, (path, recursive, priority))
+            .is_some()
+        {
             panic!("duplicate space name \"{name}\"");
         }
 
         let parsed_path = ParsedPath::new(path);
-        let last_node = self.update_or_create_tree_by_path(parsed_path, recursive, name, true);
+        let last_node =
+            self.update_or_create_tree_by_path(parsed_path, recursive, priority, name, true);
         last_node.set_access_without_member(&space.at_names);
+        last_node.add_denied(&space.denied);
+        if let Some(events) = space.monitored_events {
+            last_node.add_monitored_events(events);
+        }
+        for pattern in &space.exclude_matching {
+            last_node.exclude_matching(pattern, name);
+        }
 
-        for (include_path, recursive) in space.include_path {
+        for (include_path, recursive, include_priority) in space.include_path {
             let parsed_path = ParsedPath::new(include_path);
-            self.update_or_create_tree_by_path(parsed_path, recursive, name, true);
+            let include_priority = include_priority.unwrap_or(priority);
+            let node = self.update_or_create_tree_by_path(
+                parsed_path,
+                recursive,
+                include_priority,
+                name,
+                true,
+            );
+            for pattern in &space.exclude_matching {
+                node.exclude_matching(pattern, name);
+            }
         }
 
         for (exclude_path, recursive) in space.exclude_path {
             let parsed_path = ParsedPath::new(exclude_path);
-            self.update_or_create_tree_by_path(parsed_path, recursive, name, false);
+            self.update_or_create_tree_by_path(
+                parsed_path,
+                recursive,
+                NODE_HIGHEST_PRIORITY,
+                name,
+                false,
+            );
         }
 
         self.include_space
@@ -130,6 +759,8 @@ impl ConfigBuilder {
             .entry(name)
             .or_default()
             .extend(space.exclude_space);
+        self.space_access
+            .insert(name, (space.at_names, space.denied));
 
         self
     }
@@ -147,6 +778,11 @@ impl ConfigBuilder {
         self
     }
 
+    /// Returns whether a virtual space named `name` has been added.
+    pub(crate) fn has_space(&self, name: &str) -> bool {
+        self.space_to_path.contains_key(name)
+    }
+
     /// Adds a tree.
     ///
     /// Returns `Self`.
@@ -156,6 +792,28 @@ impl ConfigBuilder {
         self
     }
 
+    /// Sets the space subjects are confined to by [`Context::quarantine`]. A quarantined
+    /// subject is a member of this space only and keeps the access of the node of the space,
+    /// as given by [`SpaceBuilder::reads`], [`SpaceBuilder::writes`] and
+    /// [`SpaceBuilder::sees`]. Without it, quarantined subjects have no access at all.
+    ///
+    /// Returns `Self` or `ConfigError` on build if there is no such space.
+    ///
+    /// [`Context::quarantine`]: crate::medusa::Context::quarantine
+    pub fn quarantine_space(mut self, name: &'static str) -> Self {
+        self.quarantine_space = Some(name);
+        self
+    }
+
+    /// Adds a tripwire, answering and alerting on any access to objects in its space, see
+    /// [`Tripwire`]. The space is typically a decoy path added with [`ConfigBuilder::add_space`].
+    ///
+    /// Returns `Self` or `ConfigError` on build if there is no such space.
+    pub fn tripwire(mut self, tripwire: Tripwire) -> Self {
+        self.tripwires.push(tripwire);
+        self
+    }
+
     /// Adds a custom event handler using builder.
     ///
     /// Returns `Self`.
@@ -168,12 +826,26 @@ impl ConfigBuilder {
         self
     }
 
+    /// Adds multiple custom event handlers using builders, e.g. from
+    /// [`handlers`](crate::medusa::handlers).
+    ///
+    /// Returns `Self`.
+    pub fn add_event_handlers<I>(mut self, event_handlers: I) -> Self
+    where
+        I: IntoIterator<Item = EventHandlerBuilder>,
+    {
+        for event_handler in event_handlers {
+            self = self.add_event_handler(event_handler);
+        }
+        self
+    }
+
     /// Adds a hierarchy event handler for `primary_tree` tree.
     ///
     /// Returns `Self`.
     pub fn add_hierarchy_event_handler(
         mut self,
-        event: &'static str,
+        event: Event,
         primary_tree: &str,
         attribute: Option<&str>,
         flags: HandlerFlags,
@@ -190,6 +862,151 @@ impl ConfigBuilder {
         self
     }
 
+    /// Adds a handler for rename-type `event` moving its subject, the renamed entity, to the node
+    /// of its new name in `primary_tree`, so that an entity moved across policy boundaries does
+    /// not keep the labels of its old location. Attribute `attribute` of the event holds the new
+    /// name, an absolute path, or with [`HandlerFlags::FROM_OBJECT`] the name within the new
+    /// parent directory, which is the object of the event.
+    ///
+    /// If the new name is not covered by the tree, the entity is removed from its node and all
+    /// virtual spaces until it is entered into the tree again.
+    ///
+    /// Returns `Self`.
+    pub fn add_rename_event_handler(
+        self,
+        event: Event,
+        primary_tree: &str,
+        attribute: &str,
+        flags: HandlerFlags,
+    ) -> Self {
+        self.add_event_handler(EventHandlerBuilder::new().event(event).with_rename_handler(
+            primary_tree,
+            attribute,
+            flags,
+        ))
+    }
+
+    /// Adds a handler for delete or close `event` forgetting the cached state of its subject, or
+    /// of its object with [`HandlerFlags::FROM_OBJECT`], see [`Context::forget_object`]. Once an
+    /// entity is deleted, its identification, e.g. an inode number, may be recycled for an
+    /// unrelated entity, which must not be mistaken for the deleted one.
+    ///
+    /// Returns `Self`.
+    ///
+    /// [`Context::forget_object`]: crate::medusa::Context::forget_object
+    pub fn add_invalidation_event_handler(self, event: Event, flags: HandlerFlags) -> Self {
+        self.add_event_handler(
+            EventHandlerBuilder::new()
+                .event(event)
+                .with_invalidation_handler(flags),
+        )
+    }
+
+    /// Adds a plugin handler slot named `name` for `event`, see
+    /// [`plugin`](crate::medusa::plugin).
+    ///
+    /// Returns `Self`.
+    pub fn add_plugin_event_handler(
+        self,
+        event: Event,
+        name: &str,
+        subject: Space,
+        object: Option<Space>,
+    ) -> Self {
+        self.add_event_handler(
+            EventHandlerBuilder::new()
+                .event(event)
+                .with_plugin_handler(name, subject, object),
+        )
+    }
+
+    /// Adds a handler for `event` evaluating `rules` in order. The first rule whose condition
+    /// holds decides the answer, if there is none, the request is allowed, so that the rules
+    /// refine answers of the preceding handlers. A list of allowed operations therefore ends
+    /// with `deny if true`.
+    ///
+    /// Returns `Self`.
+    pub fn add_rule_event_handler(self, event: Event, rules: Vec<Rule>) -> Self {
+        self.add_event_handler(
+            EventHandlerBuilder::new()
+                .event(event)
+                .with_rule_handler(rules),
+        )
+    }
+
+    /// Adds a `getprocess` handler entering processes into domains by their executable, see
+    /// [`ExecutableMap`].
+    ///
+    /// Returns `Self`.
+    pub fn add_executable_map(self, map: ExecutableMap) -> Self {
+        self.add_event_handler(
+            EventHandlerBuilder::new()
+                .event(Event::GetProcess)
+                .with_executable_map_handler(map),
+        )
+    }
+
+    /// Enables per-user domains, see [`UserDomainsBuilder`]. Processes are entered into them by
+    /// a `getprocess` handler named `user_domains`.
+    ///
+    /// Returns `Self`.
+    pub fn user_domains(mut self, domains: UserDomainsBuilder) -> Self {
+        if self.user_domains.replace(domains).is_some() {
+            panic!("user domains already set");
+        }
+
+        self.add_event_handler(
+            EventHandlerBuilder::new()
+                .event(Event::GetProcess)
+                .name("user_domains")
+                .with_user_domain_handler(),
+        )
+    }
+
+    /// Adds a relation allowing `event` of subjects in the `subject` space with objects in the
+    /// `object` space, any object if `None`.
+    ///
+    /// Relations are compiled into a table which is consulted before the handlers of the event,
+    /// a request matching a relation is answered without running them. Denying relations take
+    /// precedence over allowing ones. Requests matching no relation are passed to the handlers.
+    ///
+    /// Returns `Self`.
+    pub fn allow_relation(self, event: Event, subject: Space, object: Option<Space>) -> Self {
+        self.add_relation(MedusaAnswer::Allow, event, subject, object)
+    }
+
+    /// Adds a relation denying `event` of subjects in the `subject` space with objects in the
+    /// `object` space, any object if `None`, see [`ConfigBuilder::allow_relation`].
+    ///
+    /// Returns `Self`.
+    pub fn deny_relation(self, event: Event, subject: Space, object: Option<Space>) -> Self {
+        self.add_relation(MedusaAnswer::Deny, event, subject, object)
+    }
+
+    fn add_relation(
+        mut self,
+        answer: MedusaAnswer,
+        event: Event,
+        subject: Space,
+        object: Option<Space>,
+    ) -> Self {
+        self.relations.push(Relation {
+            answer,
+            event: event.name(),
+            subject,
+            object,
+        });
+        self
+    }
+
+    /// Registers a plugin which is available from the start.
+    ///
+    /// Returns `Self`.
+    pub fn add_plugin(mut self, plugin: impl PluginHandler + 'static) -> Self {
+        self.plugins.push(Arc::new(plugin));
+        self
+    }
+
     /// Adds a custom event handler.
     ///
     /// Returns `Self`.
@@ -204,81 +1021,948 @@ impl ConfigBuilder {
         self
     }
 
-    /// Builds this config representation into usable form.
+    /// Sets the mode in which authorization requests are dispatched to event handlers.
     ///
-    /// Returns `Config` or `ConfigError` on error.
-    pub fn build(mut self) -> Result<Config, ConfigError> {
-        let mut def = SpaceDef::new();
-        let mut cinfo = HashMap::new();
+    /// Returns `Self`.
+    pub fn dispatch_mode(mut self, dispatch_mode: DispatchMode) -> Self {
+        self.dispatch_mode = dispatch_mode;
+        self
+    }
 
-        for (space, includes) in self.include_space.clone() {
-            for include in includes {
-                let &(path, recursive) = self
-                    .space_to_path
-                    .get(include)
-                    .unwrap_or_else(|| panic!("Space {include} does not exist"));
-                let parsed_path = ParsedPath::new(path);
-                self.update_or_create_tree_by_path(parsed_path, recursive, space, true);
-            }
-        }
+    /// Sets the number of read authorization requests waiting to be parsed and dispatched to
+    /// event handlers, 1024 by default. Reading from the security module pauses while the queue
+    /// is full.
+    ///
+    /// Returns `Self`.
+    pub fn dispatch_queue_capacity(mut self, capacity: usize) -> Self {
+        self.dispatch_queue_capacity = Some(capacity);
+        self
+    }
 
-        for (space, excludes) in self.exclude_space.clone() {
-            for exclude in excludes {
-                let &(path, recursive) = self
-                    .space_to_path
-                    .get(exclude)
-                    .unwrap_or_else(|| panic!("Space {exclude} does not exist"));
-                let parsed_path = ParsedPath::new(path);
-                self.update_or_create_tree_by_path(parsed_path, recursive, space, false);
-            }
-        }
+    /// Sets scheduling of the threads reading from and writing to the security module. Both run
+    /// on dedicated threads then and [`Connection::run`] blocks the calling thread.
+    ///
+    /// Returns `Self`.
+    ///
+    /// [`Connection::run`]: crate::medusa::Connection::run
+    pub fn io_scheduling(mut self, scheduling: ThreadScheduling) -> Self {
+        self.io_scheduling = Some(scheduling);
+        self
+    }
 
-        let trees = self
-            .trees
-            .into_iter()
-            .map(|(_, x)| x.build(&mut def, &mut cinfo))
-            .collect::<Result<_, _>>()?;
+    /// Sets the runtime on which the connection loop and event handlers run. Unless it is
+    /// [`RuntimeMode::Caller`], [`Connection::run`] blocks the calling thread.
+    ///
+    /// Returns `Self`.
+    ///
+    /// [`Connection::run`]: crate::medusa::Connection::run
+    pub fn runtime(mut self, mode: RuntimeMode) -> Self {
+        self.runtime_mode = mode;
+        self
+    }
 
-        let event_handlers = self
-            .event_handlers
-            .into_iter()
-            .map(|(k, v)| (k, v.into_iter().map(|x| x.build(&def)).collect()))
-            .collect::<HashMap<String, Box<[EventHandler]>>>();
+    /// Sets the executor running tasks of the connection, [`TokioExecutor`] by default, see
+    /// [`executor`](crate::medusa::executor).
+    ///
+    /// Returns `Self`.
+    pub fn executor(mut self, executor: impl Executor + 'static) -> Self {
+        self.executor = Some(Arc::new(executor));
+        self
+    }
 
-        let name_to_space_bit = def.name_to_id_owned();
+    /// Sets the strategy used when a message from the security module cannot be processed.
+    ///
+    /// Returns `Self`.
+    pub fn recovery_strategy(mut self, recovery_strategy: RecoveryStrategy) -> Self {
+        self.recovery_strategy = recovery_strategy;
+        self
+    }
+
+    /// Sets hash identifying the policy this configuration is built from. It is included in
+    /// audit records. Set automatically when loading a policy file.
+    ///
+    /// Returns `Self`.
+    pub fn policy_hash(mut self, hash: impl Into<String>) -> Self {
+        self.policy_hash = Some(hash.into());
+        self
+    }
+
+    /// Adds events the server monitors. Once the security module registers such an event, its
+    /// monitoring bit is set in objects and subjects entered into trees. Events which are not
+    /// registered by the time the first authorization request arrives are reported.
+    ///
+    /// If this is never called, exactly the events having handlers are monitored.
+    ///
+    /// Returns `Self`.
+    pub fn cover_events<I>(mut self, events: I) -> Self
+    where
+        I: IntoIterator<Item = Event>,
+    {
+        self.covered_events
+            .get_or_insert_with(Vec::new)
+            .extend(events.into_iter().map(|x| x.name().to_owned()));
+        self
+    }
+
+    /// Enables deny-by-default enforcement. The server starts in permissive mode, in which
+    /// requests that would be denied are allowed and logged. Once the security module has
+    /// registered all events having handlers, covered events and those added by
+    /// [`ConfigBuilder::expect_event`], handlers of `getprocess`, `getfile` and events added by
+    /// [`ConfigBuilder::require_handler`] are present and the config passes a coverage lint, the
+    /// server switches to enforcing mode. Then requests no handler is applicable to are denied,
+    /// regardless of the build profile.
+    ///
+    /// The checks prevent a broken config from making the system unusable. Enforcing mode is
+    /// never left for permissive mode.
+    ///
+    /// Returns `Self`.
+    pub fn enforce(mut self) -> Self {
+        self.enforce = true;
+        self
+    }
+
+    /// Adds an event which has to be registered by the security module before switching to
+    /// enforcing mode, see [`ConfigBuilder::enforce`].
+    ///
+    /// Returns `Self`.
+    pub fn expect_event(mut self, event: Event) -> Self {
+        self.expected_events.push(event.name().to_owned());
+        self
+    }
+
+    /// Requires class `class` registered by the security module to have attribute `attribute`
+    /// of type `data_type`, e.g. `cmdline` of `process` as [`AttributeDataType::String`] for
+    /// an [`ExecutableMap`]. A class not meeting its expectations fails the connection with
+    /// [`CommunicationError::AttributeMismatchError`] listing all mismatched attributes as soon
+    /// as it is registered, instead of failing handlers reading the attributes later.
+    ///
+    /// Returns `Self`.
+    ///
+    /// [`CommunicationError::AttributeMismatchError`]: crate::medusa::CommunicationError::AttributeMismatchError
+    pub fn expect_attribute(
+        mut self,
+        class: &str,
+        attribute: &str,
+        data_type: AttributeDataType,
+    ) -> Self {
+        self.expected_attributes.push(AttributeExpectation {
+            class: class.to_owned(),
+            attribute: attribute.to_owned(),
+            data_type,
+        });
+        self
+    }
+
+    /// Sets the default value of attribute `attribute` of class `class`, e.g. of `med_oact`.
+    /// Empty entities returned by [`Context::empty_class`] and [`Context::empty_class_from_id`]
+    /// carry the default values instead of zeros, so that handlers do not have to set them
+    /// each time. Attributes received from the security module, such as entities of requests
+    /// or fetched ones, are never changed. Defaults of attributes the class lacks or which are
+    /// read-only are reported when the class is registered.
+    ///
+    /// Returns `Self`.
+    ///
+    /// [`Context::empty_class`]: crate::medusa::Context::empty_class
+    /// [`Context::empty_class_from_id`]: crate::medusa::Context::empty_class_from_id
+    pub fn default_attribute<T: AttributeBytes>(
+        mut self,
+        class: &str,
+        attribute: &str,
+        value: T,
+    ) -> Self {
+        let defaults = self.default_attributes.entry(class.to_owned()).or_default();
+        defaults.retain(|(name, _)| name != attribute);
+        defaults.push((attribute.to_owned(), value.to_bytes()));
+        self
+    }
+
+    /// Sets the attribute of class `class` which the security module changes whenever an
+    /// object of the class changes, such as a generation or sequence number. A
+    /// [`FetchedObject`] of the class is then compared by this attribute alone, which also
+    /// detects changes reverted meanwhile. Classes without a generation attribute, or whose
+    /// objects lack it, are compared byte by byte.
+    ///
+    /// Returns `Self`.
+    ///
+    /// [`FetchedObject`]: crate::medusa::FetchedObject
+    pub fn generation_attribute(mut self, class: &str, attribute: &str) -> Self {
+        self.generation_attributes
+            .insert(class.to_owned(), attribute.to_owned());
+        self
+    }
+
+    /// Sets how values of attributes named `attribute` are written into verbose output of
+    /// handlers, see [`Context::set_handler_debug`], so that secrets passed e.g. on command
+    /// lines do not end up in logs. By default, arguments of `cmdline` are hashed and `environ`
+    /// is left out, [`Redaction::Keep`] writes them as they are. Audit records carry no
+    /// attribute values besides the identities of entities.
+    ///
+    /// Returns `Self`.
+    ///
+    /// [`Context::set_handler_debug`]: crate::medusa::Context::set_handler_debug
+    pub fn redact(mut self, attribute: &str, redaction: Redaction) -> Self {
+        self.redactions.insert(attribute.to_owned(), redaction);
+        self
+    }
+
+    /// Marks `event` as security-critical besides `getprocess` and `getfile`. Once the security
+    /// module has registered its events, critical events it registered without any handler or
+    /// relation are reported. With [`ConfigBuilder::enforce`], the server does not switch to
+    /// enforcing mode while such an event is left unhandled.
+    ///
+    /// Returns `Self`.
+    pub fn require_handler(mut self, event: Event) -> Self {
+        self.critical_events.push(event.name().to_owned());
+        self
+    }
+
+    /// Marks `event` as latency-critical, e.g. `getprocess` on which `exec` waits. Decisions
+    /// of latency-critical events jump ahead of decisions of other events waiting to be
+    /// written, so interactive operations stay responsive while e.g. a background scan floods
+    /// the security module with `getfile` requests. Decisions never overtake fetches and
+    /// updates sent before them, which the decided handler may depend on.
+    ///
+    /// Returns `Self`.
+    pub fn latency_critical(mut self, event: Event) -> Self {
+        self.latency_critical_events.push(event.name().to_owned());
+        self
+    }
+
+    /// Enables the control socket at `path`, which allows administration of the running server,
+    /// see [`control`](crate::medusa::control) for the supported commands. Only the owner can
+    /// connect to the socket. The socket is served by tokio regardless of
+    /// [`ConfigBuilder::executor`].
+    ///
+    /// Returns `Self`.
+    pub fn control_socket<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.control_socket = Some(path.as_ref().to_owned());
+        self
+    }
+
+    /// Writes a snapshot of the connection as JSON to `path` whenever the process receives
+    /// `SIGUSR1`, see [`Context::snapshot`]. Signals are received through tokio regardless of
+    /// [`ConfigBuilder::executor`].
+    ///
+    /// Returns `Self`.
+    ///
+    /// [`Context::snapshot`]: crate::medusa::Context::snapshot
+    pub fn snapshot_on_signal<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.snapshot_path = Some(path.as_ref().to_owned());
+        self
+    }
+
+    /// Requires policies loaded by the `reload` command of the control socket, see
+    /// [`control`], to have a valid signature made by the owner of the ed25519 `public_key`, see
+    /// [`PolicyLoader::verify_with`]. Configs prepared by [`ConfigBuilder::replacing`] keep the
+    /// key of the config they replace.
+    ///
+    /// Returns `Self`.
+    ///
+    /// [`control`]: crate::medusa::control
+    /// [`PolicyLoader::verify_with`]: crate::medusa::PolicyLoader::verify_with
+    #[cfg(feature = "signing")]
+    pub fn verify_policies_with(mut self, public_key: [u8; 32]) -> Self {
+        self.policy_verifying_key = Some(public_key);
+        self
+    }
+
+    /// Records everything read from the security module into zstd-compressed chunks, see
+    /// [`recording`]. The recording starts with the connection and can be replayed with
+    /// [`SessionReplay`].
+    ///
+    /// Returns `Self`.
+    ///
+    /// [`recording`]: crate::medusa::recording
+    /// [`SessionReplay`]: crate::medusa::SessionReplay
+    #[cfg(feature = "recording")]
+    pub fn record_session(mut self, recording: SessionRecording) -> Self {
+        self.session_recording = Some(recording);
+        self
+    }
+
+    /// Only observes the security module. Every authorization request is read and decoded, then
+    /// answered with `answer`, e.g. [`MedusaAnswer::Allow`], without running handlers or
+    /// evaluating relations. No update requests are sent. Pipeline statistics, completion hooks,
+    /// audit sinks and the candidate of [`ConfigBuilder::shadow`] still see every request, which
+    /// allows measuring the volume of events and building an initial policy on a production
+    /// system. Deny-by-default enforcement does not apply.
+    ///
+    /// The mode is a setting of the connection, it is not changed by
+    /// [`Context::replace_config`].
+    ///
+    /// Returns `Self`.
+    ///
+    /// [`Context::replace_config`]: crate::medusa::Context::replace_config
+    pub fn observe(mut self, answer: MedusaAnswer) -> Self {
+        self.observe = Some(answer);
+        self
+    }
+
+    /// Tracks parents of processes seen as subjects or objects of authorization requests, so
+    /// that handlers can query them with [`Context::ancestors`], e.g. to allow an action only
+    /// if a process descends from a process in the `sshd` domain. A process is known once it
+    /// took part in a request, its virtual spaces are the ones it had in the last such request.
+    /// Process ids are reused, so a process is forgotten once another one with its id is seen.
+    ///
+    /// Processes are recognized by their `pid` and `parent_pid` attributes. The setting is kept
+    /// by [`Context::replace_config`].
+    ///
+    /// Returns `Self`.
+    ///
+    /// [`Context::ancestors`]: crate::medusa::Context::ancestors
+    /// [`Context::replace_config`]: crate::medusa::Context::replace_config
+    pub fn track_processes(mut self) -> Self {
+        self.track_processes = true;
+        self
+    }
+
+    /// Remembers the node of every tree an entity was entered into, so that policies with
+    /// several trees, e.g. of files, domains and network addresses, track positions of entities
+    /// in each of them independently. The `o_cinfo` attribute holds a single node, the one of
+    /// the tree the entity was entered into last, so without this, a hierarchy handler of
+    /// another tree would continue from a node which is not in its tree.
+    ///
+    /// With this setting, a hierarchy handler whose tree does not contain the node in `o_cinfo`
+    /// continues from the node of its tree the entity was last entered into, see
+    /// [`Context::node_in_tree`], or from the root if there is none. An entity is told apart by
+    /// its [`SubjectId`], so entities of classes without a primary key are always entered at
+    /// the root and a new entity does not inherit the positions of its parent in other trees
+    /// than the last one. [`Context::forget_object`] forgets the positions of an entity. The
+    /// setting is kept by [`Context::replace_config`].
+    ///
+    /// Returns `Self`.
+    ///
+    /// [`Context::node_in_tree`]: crate::medusa::Context::node_in_tree
+    /// [`Context::forget_object`]: crate::medusa::Context::forget_object
+    /// [`Context::replace_config`]: crate::medusa::Context::replace_config
+    /// [`SubjectId`]: crate::medusa::SubjectId
+    pub fn separate_tree_positions(mut self) -> Self {
+        self.separate_tree_positions = true;
+        self
+    }
+
+    /// Classifies processes which were running before the connection was established once the
+    /// security module registered its classes and events, see [`LabelMigration`]. Without it, a
+    /// restarted server classifies a process only at its next monitored event.
+    ///
+    /// Returns `Self`.
+    pub fn migrate_labels(mut self, migration: LabelMigration) -> Self {
+        self.label_migration = Some(migration);
+        self
+    }
+
+    /// Resolves `paths` in `tree` when the config is built, so that the nodes of well-known
+    /// paths, e.g. of binaries and libraries, are looked up before the first requests come.
+    /// Every node remembers which of its children the components of looked up paths match,
+    /// or that none does, so that entering the entities right after boot, when most of them
+    /// are classified at once, does not match regular expressions of nodes again. Paths not
+    /// covered by the tree are ignored, see also [`Config::warm_up`].
+    ///
+    /// Returns `Self`.
+    pub fn warm_up<I>(mut self, tree: &str, paths: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: Into<String>,
+    {
+        self.warm_up_paths
+            .extend(paths.into_iter().map(|x| (tree.to_owned(), x.into())));
+        self
+    }
+
+    /// Compiles the descendants of the node at `path`, given as `<tree>/<path>` like paths of
+    /// spaces, lazily when they are first looked up, see [`NodeBuilder::lazy`]. Meant for big
+    /// subtrees which are rarely used, e.g. of imported profiles, so that the config builds
+    /// faster.
+    ///
+    /// Returns `Self`.
+    pub fn lazy_subtree(mut self, path: &'static str) -> Self {
+        self.lazy_subtrees.push(path);
+        self
+    }
+
+    /// Same as [`ConfigBuilder::warm_up`] with the paths listed in file `path`, one per line.
+    /// Empty lines and lines starting with `#` are skipped. The file is read when the config
+    /// is built.
+    ///
+    /// Returns `Self`.
+    pub fn warm_up_file<P: AsRef<Path>>(mut self, tree: &str, path: P) -> Self {
+        self.warm_up_files
+            .push((tree.to_owned(), path.as_ref().to_owned()));
+        self
+    }
+
+    /// Sets a candidate config which is evaluated for every authorization request alongside this
+    /// one. Answers of the candidate are never sent to the security module and its handlers
+    /// cannot update kernel objects. Requests for which the answers differ are logged, so that
+    /// a policy change can be validated against live traffic before switching to it.
+    ///
+    /// Virtual spaces and tree nodes of the kernel objects are translated into the candidate's
+    /// by their names and paths. Hooks and connection settings of the candidate are ignored.
+    ///
+    /// Returns `Self`.
+    pub fn shadow(mut self, candidate: Config) -> Self {
+        self.shadow = Some(candidate);
+        self
+    }
+
+    /// Prepares the config to replace `previous`, see [`Context::replace_config`]. Virtual
+    /// spaces keep the bits they have in `previous`, so that entities need not be entered into
+    /// trees again, and bits of removed spaces are retired.
+    ///
+    /// Entities still carrying a retired bit have it cleared and are updated whenever they take
+    /// part in an authorization request. Entities taking part in no request keep it, so a
+    /// retired bit is not given to a new space until it is released by
+    /// [`Context::release_retired_space_bits`].
+    ///
+    /// Returns `Self`.
+    ///
+    /// [`Context::replace_config`]: crate::medusa::Context::replace_config
+    /// [`Context::release_retired_space_bits`]: crate::medusa::Context::release_retired_space_bits
+    pub fn replacing(mut self, previous: &Config) -> Self {
+        self.replaced_spaces = Some((
+            previous.name_to_space_bit.clone(),
+            Arc::clone(&previous.retired_space_bits),
+        ));
+        #[cfg(feature = "signing")]
+        if self.policy_verifying_key.is_none() {
+            self.policy_verifying_key = previous.policy_verifying_key;
+        }
+        self
+    }
+
+    /// Adds a sink which receives an audit record of every authorization decision.
+    ///
+    /// Returns `Self`.
+    pub fn add_audit_sink(mut self, sink: impl AuditSink + 'static) -> Self {
+        self.audit_sinks.push(Arc::new(sink));
+        self
+    }
+
+    /// Declares capabilities of the security module instead of deriving them from the protocol
+    /// version, for kernels built without some of the features.
+    ///
+    /// Returns `Self`.
+    pub fn assume_kernel_capabilities(mut self, capabilities: KernelCapabilities) -> Self {
+        self.assumed_capabilities = Some(capabilities);
+        self
+    }
+
+    /// Requires the security module to provide `capabilities`, otherwise the connection is
+    /// refused.
+    ///
+    /// Returns `Self`.
+    pub fn require_kernel_capabilities(mut self, capabilities: KernelCapabilities) -> Self {
+        self.required_capabilities |= capabilities;
+        self
+    }
+
+    /// Enables liveness detection. If nothing arrives from the security module for `timeout`
+    /// while fetch or update requests are pending, the connection is considered dead.
+    ///
+    /// Returns `Self`.
+    pub fn liveness_timeout(mut self, timeout: Duration) -> Self {
+        self.liveness_timeout = Some(timeout);
+        self
+    }
+
+    /// Sets how long updates queued by [`Context::queue_update`] are collected before they are
+    /// sent to the security module, 1 ms by default.
+    ///
+    /// Returns `Self`.
+    ///
+    /// [`Context::queue_update`]: crate::medusa::Context::queue_update
+    pub fn update_flush_interval(mut self, interval: Duration) -> Self {
+        self.update_flush_interval = Some(interval);
+        self
+    }
+
+    /// Skips updates of an object which are identical to the last update sent to it within
+    /// `window`, answering them as successful. Objects are identified by their class and primary
+    /// key attributes. An object whose update failed is updated again regardless of the window.
+    ///
+    /// Returns `Self`.
+    pub fn deduplicate_updates(mut self, window: Duration) -> Self {
+        self.update_dedup_window = Some(window);
+        self
+    }
+
+    /// Sets how long an update or fetch request may wait for an answer, 60 s by default. Older
+    /// requests, as well as those nobody waits for anymore, are periodically removed and
+    /// counted as abandoned, see [`PipelineStats::abandoned_requests`]. Abandoned requests are
+    /// answered as timed out, see [`FetchStatus::TimedOut`] and [`UpdateStatus::TimedOut`], and
+    /// abandoned update requests are retried, see [`ConfigBuilder::update_retries`].
+    ///
+    /// Returns `Self`.
+    ///
+    /// [`PipelineStats::abandoned_requests`]: crate::medusa::PipelineStats::abandoned_requests
+    /// [`FetchStatus::TimedOut`]: crate::medusa::FetchStatus::TimedOut
+    /// [`UpdateStatus::TimedOut`]: crate::medusa::UpdateStatus::TimedOut
+    pub fn pending_request_max_age(mut self, max_age: Duration) -> Self {
+        self.pending_request_max_age = Some(max_age);
+        self
+    }
+
+    /// Sends update requests again, up to `retries` times, if they fail in the security module
+    /// or are abandoned by [`ConfigBuilder::pending_request_max_age`]. The first retry is made
+    /// after `backoff`, which doubles with every further one. Updates are not retried by
+    /// default, nor are those queued by [`Context::queue_update`].
+    ///
+    /// A failed update of labels leaves the entity with wrong permissions, see
+    /// [`ConfigBuilder::on_update_failure`] to react once no retries are left.
+    ///
+    /// Returns `Self`.
+    ///
+    /// [`Context::queue_update`]: crate::medusa::Context::queue_update
+    pub fn update_retries(mut self, retries: u32, backoff: Duration) -> Self {
+        self.update_retries = retries;
+        self.update_backoff = backoff;
+        self
+    }
+
+    /// Sets a callback which is called when an update request has failed and no retries are
+    /// left, see [`ConfigBuilder::update_retries`].
+    ///
+    /// Returns `Self`.
+    pub fn on_update_failure<F>(mut self, callback: F) -> Self
+    where
+        F: Fn(&UpdateError) + Send + Sync + 'static,
+    {
+        self.update_escalation = Some(Arc::new(callback));
+        self
+    }
+
+    /// Enables a watchdog of handlers. If handlers of a request run for longer than
+    /// `threshold`, the event, the subject and the elapsed time are logged once, and again when
+    /// they finish. The answer is not affected, the handlers keep running.
+    ///
+    /// Returns `Self`.
+    pub fn handler_watchdog(mut self, threshold: Duration) -> Self {
+        self.handler_watchdog = Some(threshold);
+        self
+    }
+
+    /// Sets an objective for the time between receiving an authorization request and answering
+    /// it. Handlers designated as non-critical are degraded when it is exceeded repeatedly, see
+    /// [`LatencySlo`].
+    ///
+    /// Returns `Self` or `ConfigError` on build if a designated handler does not exist.
+    pub fn latency_slo(mut self, slo: LatencySlo) -> Self {
+        self.latency_slo = Some(slo);
+        self
+    }
+
+    /// Enables detection of unusual access patterns of domains, see [`AnomalyDetection`]. It is
+    /// a setting of the connection, learning starts when the connection is established.
+    ///
+    /// Returns `Self`.
+    pub fn anomaly_detection(mut self, detection: AnomalyDetection) -> Self {
+        self.anomaly_detection = Some(detection);
+        self
+    }
+
+    /// Enables counting of the operations granted to each domain, see [`ResourceAccounting`].
+    /// It is a setting of the connection, counting starts when the connection is established.
+    ///
+    /// Returns `Self`.
+    pub fn resource_accounting(mut self, accounting: ResourceAccounting) -> Self {
+        self.resource_accounting = Some(accounting);
+        self
+    }
+
+    /// Sets a hook which is called with the state of a connection which has been silent for the
+    /// liveness timeout, or which has been closed.
+    ///
+    /// Returns `Self`.
+    pub fn on_liveness<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(Liveness) + Send + Sync + 'static,
+    {
+        self.liveness_hook = Some(Arc::new(hook));
+        self
+    }
+
+    /// Sets a hook which is called when the security module registers a class with an attribute
+    /// layout different from the one it was registered with before, by this or an earlier
+    /// connection of the process, e.g. after the kernel was upgraded and a supervisor
+    /// reconnected. The changes are logged and the queued updates of the class, packed in the
+    /// previous layout, are dropped. [`AttributeHandle`]s of the previous layout fail with
+    /// [`AttributeError::StaleHandleError`], the hook is meant for resetting caches of handlers
+    /// relying on the previous layout in other ways.
+    ///
+    /// Returns `Self`.
+    ///
+    /// [`AttributeHandle`]: crate::medusa::AttributeHandle
+    /// [`AttributeError::StaleHandleError`]: crate::medusa::AttributeError::StaleHandleError
+    pub fn on_layout_change<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(&[LayoutChange]) + Send + Sync + 'static,
+    {
+        self.layout_hook = Some(Arc::new(hook));
+        self
+    }
+
+    /// Adds a hook which is called every time an authorization request has been answered.
+    ///
+    /// Returns `Self`.
+    pub fn on_completion<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(&CompletedRequest) + Send + Sync + 'static,
+    {
+        self.completion_hooks.push(Arc::new(hook));
+        self
+    }
+
+    /// Builds this config representation into usable form.
+    ///
+    /// Returns `Config` or `ConfigError` on error, e.g. `UnknownSpaceError` if a handler, a
+    /// relation or another space refers to a space which is not defined.
+    pub fn build(mut self) -> Result<Config, ConfigError> {
+        let start = Instant::now();
+        let (mut def, retired_space_bits) = match self.replaced_spaces.take() {
+            Some((previous, retired)) => {
+                let reserved = retired.bits();
+                (SpaceDef::with_previous(previous, reserved), retired)
+            }
+            None => (SpaceDef::new(), Default::default()),
+        };
+        let mut cinfo = FastHashMap::default();
+
+        for (space, includes) in self.include_space.clone() {
+            for include in includes {
+                let &(path, recursive, priority) = self
+                    .space_to_path
+                    .get(include)
+                    .ok_or_else(|| ConfigError::UnknownSpaceError(include.to_owned()))?;
+                let parsed_path = ParsedPath::new(path);
+                self.update_or_create_tree_by_path(parsed_path, recursive, priority, space, true);
+            }
+        }
+
+        for (space, excludes) in self.exclude_space.clone() {
+            for exclude in excludes {
+                let &(path, recursive, priority) = self
+                    .space_to_path
+                    .get(exclude)
+                    .ok_or_else(|| ConfigError::UnknownSpaceError(exclude.to_owned()))?;
+                let parsed_path = ParsedPath::new(path);
+                self.update_or_create_tree_by_path(parsed_path, recursive, priority, space, false);
+            }
+        }
+
+        let user_domains_path = self
+            .user_domains
+            .as_ref()
+            .map(|x| ParsedPath::new(x.path()));
+        if let Some(path) = &user_domains_path {
+            self.get_or_create_node(path, NODE_HIGHEST_PRIORITY);
+        }
+
+        for path in &self.lazy_subtrees {
+            let parsed_path = ParsedPath::new(path);
+            self.trees
+                .get_mut(parsed_path.tree_name)
+                .and_then(|x| x.node_mut(&parsed_path.items))
+                .ok_or_else(|| ConfigError::UnknownNodeError(path.to_string()))?
+                .set_lazy();
+        }
+
+        // the quarantine keeps the access of the node of its space, but no other membership
+        let quarantine_spaces = match self.quarantine_space {
+            Some(name) => {
+                let (path, _, _) = self
+                    .space_to_path
+                    .get(name)
+                    .ok_or_else(|| ConfigError::UnknownSpaceError(name.to_owned()))?;
+                let parsed_path = ParsedPath::new(path);
+                let node = self
+                    .trees
+                    .get_mut(parsed_path.tree_name)
+                    .and_then(|x| x.node_mut(&parsed_path.items))
+                    .expect("node of a space exists");
+
+                let mut spaces: [Vec<Space>; AccessType::Length as usize] = Default::default();
+                spaces[AccessType::Member as usize] = vec![Space::ByName(name)];
+                for at in [AccessType::Read, AccessType::Write, AccessType::See] {
+                    spaces[at as usize] = node.space_names(at).map(Space::ByName).collect();
+                }
+                let denied = node.denied_names().map(Space::ByName).collect::<Vec<_>>();
+                Some((spaces, denied))
+            }
+            None => None,
+        };
+
+        // regular expressions are compiled up front, so that they can be compiled in parallel
+        // while spaces are still assigned their bits in the order of the nodes
+        let compilation_start = Instant::now();
+        let mut patterns = HashSet::new();
+        for tree in self.trees.values() {
+            tree.collect_patterns(&mut patterns);
+        }
+        let regexes = tree::compile_regexes(patterns)?;
+        let regex_compilation = compilation_start.elapsed();
+
+        let lazy_nodes = Arc::new(LazyNodes::default());
+        let mut state = TreeBuildState {
+            def: &mut def,
+            cinfo: &mut cinfo,
+            regexes: &regexes,
+            lazy_nodes: &lazy_nodes,
+        };
+        let trees: Box<[Tree]> = self
+            .trees
+            .into_values()
+            .map(|x| x.build(&mut state))
+            .collect::<Result<_, _>>()?;
+
+        let user_domains = match (self.user_domains, user_domains_path) {
+            (Some(domains), Some(path)) => {
+                let names = (0..domains.capacity())
+                    .map(|n| policy::intern(&format!("user_domain_{}", n)))
+                    .collect::<Vec<_>>();
+                def.define_consecutive_spaces(&names);
+                let first_bit = def.space_id("user_domain_0").unwrap_or_default();
+                let tree = trees
+                    .iter()
+                    .find(|x| x.name() == path.tree_name)
+                    .expect("tree of user domains exists");
+
+                domains.build(tree, &path.items[1..], first_bit)
+            }
+            _ => None,
+        };
+        lazy_nodes.set_def(&def);
+
+        let quarantine = match quarantine_spaces {
+            Some((spaces, denied)) => {
+                let mut vs = VirtualSpace::new();
+                vs.set_access_types(&def, &spaces)?;
+                vs.set_denied(&def, &denied)?;
+                Some(vs)
+            }
+            None => None,
+        };
+
+        let space_access = self
+            .space_access
+            .into_iter()
+            .map(|(name, (at_names, denied))| {
+                let mut spaces = at_names.map(|x| x.into_iter().map(Space::ByName).collect());
+                spaces[AccessType::Member as usize] = vec![Space::ByName(name)];
+                let denied = denied.into_iter().map(Space::ByName).collect::<Vec<_>>();
+
+                let mut vs = VirtualSpace::new();
+                vs.set_access_types(&def, &spaces)?;
+                vs.set_denied(&def, &denied)?;
+                Ok((name.to_owned(), vs))
+            })
+            .collect::<Result<_, ConfigError>>()?;
+
+        let mut redactions = redact::DEFAULT_REDACTIONS
+            .iter()
+            .map(|(attribute, redaction)| (attribute.to_string(), *redaction))
+            .collect::<FastHashMap<_, _>>();
+        redactions.extend(self.redactions);
+
+        if let Some(tripwire) = self
+            .tripwires
+            .iter()
+            .find(|x| !self.space_to_path.contains_key(x.space))
+        {
+            return Err(ConfigError::UnknownSpaceError(tripwire.space.to_owned()));
+        }
+        // tripwires run first for all monitored events, including those having only relations
+        if !self.tripwires.is_empty() {
+            let events = self
+                .event_handlers
+                .values()
+                .filter_map(|x| x.first())
+                .map(|x| x.event)
+                .chain(self.relations.iter().map(|x| x.event))
+                .collect::<HashSet<_>>();
+            for event in events {
+                let tripwires = self.tripwires.iter().map(|tripwire| {
+                    EventHandlerBuilder::new()
+                        .event(Event::from_name(event))
+                        .with_tripwire_handler(tripwire.clone())
+                });
+                self.event_handlers
+                    .entry(event.to_owned())
+                    .or_default()
+                    .splice(0..0, tripwires);
+            }
+        }
+
+        let mut event_ids = FastHashMap::default();
+        let mut event_handlers: Vec<Box<[EventHandler]>> = Vec::new();
+        for (event, handlers) in self.event_handlers {
+            event_ids.insert(event, event_handlers.len());
+            let handlers = handlers.into_iter().map(|x| x.build(&def));
+            event_handlers.push(handlers.collect::<Result<_, _>>()?);
+        }
+        // events having only relations are interned without handlers
+        for relation in &self.relations {
+            if !event_ids.contains_key(relation.event) {
+                event_ids.insert(relation.event.to_owned(), event_handlers.len());
+                event_handlers.push(Box::new([]));
+            }
+        }
+        if let Some(slo) = &self.latency_slo {
+            let handlers = event_handlers.iter().flat_map(|x| x.iter());
+            let names = handlers.map(|x| x.name()).collect::<HashSet<_>>();
+            if let Some(name) = slo.degraded.keys().find(|x| !names.contains(x.as_str())) {
+                return Err(ConfigError::UnknownHandlerError(name.clone()));
+            }
+        }
+
+        let fast_events = event_handlers
+            .iter()
+            .map(|handlers| handlers.iter().all(|x| x.is_fast()))
+            .collect();
+        let decision_table = match self.relations.is_empty() {
+            true => None,
+            false => Some(DecisionTable::build(&self.relations, &event_ids, &def)?),
+        };
+
+        let plugins = PluginRegistry::default();
+        for plugin in self.plugins {
+            plugins.insert(plugin);
+        }
+
+        let name_to_space_bit = def.name_to_id_owned();
         let space_bit_to_name = def.id_to_name_owned();
 
-        Ok(Config {
+        let mut warm_up_paths = self.warm_up_paths;
+        for (tree, path) in self.warm_up_files {
+            let paths = fs::read_to_string(&path)
+                .map_err(|e| ConfigError::WarmUpFileError(path.clone(), e))?;
+            warm_up_paths.extend(
+                paths
+                    .lines()
+                    .map(str::trim)
+                    .filter(|x| !x.is_empty() && !x.starts_with('#'))
+                    .map(|x| (tree.clone(), x.to_owned())),
+            );
+        }
+
+        let build_report = BuildReport {
+            nodes: cinfo.len(),
+            regexes: regexes.len(),
+            regex_compilation,
+            total: Duration::ZERO,
+        };
+
+        let mut config = Config {
+            generation: NEXT_GENERATION.fetch_add(1, Ordering::Relaxed),
+            build_report,
             trees,
             cinfo_nodes: cinfo,
-            event_handlers,
+            lazy_nodes,
+            event_ids,
+            event_handlers: event_handlers.into_boxed_slice(),
+            fast_events,
+            decision_table,
             name_to_space_bit,
             space_bit_to_name,
+            retired_space_bits,
             covered_events_mask: AtomicU64::new(0),
-        })
+            covered_events: self.covered_events.map(Vec::into_boxed_slice),
+            dispatch_mode: self.dispatch_mode,
+            dispatch_queue_capacity: self
+                .dispatch_queue_capacity
+                .unwrap_or(DISPATCH_QUEUE_DEFAULT_CAPACITY),
+            io_scheduling: self.io_scheduling,
+            runtime_mode: self.runtime_mode,
+            recovery_strategy: self.recovery_strategy,
+            liveness_timeout: self.liveness_timeout,
+            update_flush_interval: self
+                .update_flush_interval
+                .unwrap_or(UPDATE_FLUSH_DEFAULT_INTERVAL),
+            update_dedup_window: self.update_dedup_window,
+            pending_request_max_age: self
+                .pending_request_max_age
+                .unwrap_or(PENDING_REQUEST_DEFAULT_MAX_AGE),
+            update_retries: self.update_retries,
+            update_backoff: self.update_backoff,
+            handler_watchdog: self.handler_watchdog,
+            latency_slo: self.latency_slo,
+            anomaly_detection: self.anomaly_detection,
+            resource_accounting: self.resource_accounting,
+            assumed_capabilities: self.assumed_capabilities,
+            required_capabilities: self.required_capabilities,
+            policy_hash: self.policy_hash,
+            enforce: self.enforce,
+            expected_events: self.expected_events.into_boxed_slice(),
+            critical_events: self.critical_events.into_boxed_slice(),
+            latency_critical_events: self.latency_critical_events.into_boxed_slice(),
+            expected_attributes: self.expected_attributes.into_boxed_slice(),
+            default_attributes: self.default_attributes,
+            redactions,
+            generation_attributes: self.generation_attributes,
+            shadow: self.shadow.map(Box::new),
+            observe: self.observe,
+            track_processes: self.track_processes,
+            separate_tree_positions: self.separate_tree_positions,
+            label_migration: self.label_migration,
+            control_socket: self.control_socket,
+            snapshot_path: self.snapshot_path,
+            #[cfg(feature = "signing")]
+            policy_verifying_key: self.policy_verifying_key,
+            #[cfg(feature = "recording")]
+            session_recording: self.session_recording,
+            user_domains,
+            quarantine,
+            space_access,
+            audit_sinks: self.audit_sinks.into_boxed_slice(),
+            executor: self.executor.unwrap_or_else(|| Arc::new(TokioExecutor)),
+            plugins,
+            liveness_hook: self.liveness_hook,
+            layout_hook: self.layout_hook,
+            update_escalation: self.update_escalation,
+            completion_hooks: self.completion_hooks.into_boxed_slice(),
+        };
+
+        for (tree, path) in &warm_up_paths {
+            config.warm_up(tree, [path]);
+        }
+        config.build_report.total = start.elapsed();
+
+        Ok(config)
     }
 
     fn update_or_create_tree_by_path(
         &mut self,
         path: ParsedPath,
         recursive: bool,
+        priority: u16,
         space: &'static str,
         include: bool,
     ) -> &mut NodeBuilder {
+        let node = self.get_or_create_node(&path, priority);
+
+        node.member_of_include_or_exclude(space, include);
+
+        node.set_recursive(recursive);
+
+        node
+    }
+
+    /// Returns the node at `path`, which has `priority` among its siblings. Nodes on the way
+    /// have the highest priority.
+    fn get_or_create_node(&mut self, path: &ParsedPath, priority: u16) -> &mut NodeBuilder {
         let tree = self.get_or_create_tree(path.tree_name);
-        let mut iter = path.items.into_iter();
+        let mut iter = path.items.iter().peekable();
 
         let root_path = iter.next().expect("Root is missing.");
 
         let mut node = tree.get_or_create_root(root_path);
-        for item in iter {
-            node = node.get_or_create_child(NODE_HIGHEST_PRIORITY, item);
+        while let Some(item) = iter.next() {
+            let priority = match iter.peek() {
+                Some(_) => NODE_HIGHEST_PRIORITY,
+                None => priority,
+            };
+            node = node.get_or_create_child(priority, item);
         }
 
-        node.member_of_include_or_exclude(space, include);
-
-        node.set_recursive(recursive);
-
         node
     }
 
@@ -0,0 +1,179 @@
+//! Confinement of a single program without writing spaces and handlers, see [`Policy`].
+
+use crate::medusa::policy::intern;
+use crate::medusa::{
+    Config, ConfigBuilder, ConfigError, Event, ExecutableMap, HandlerFlags, SpaceBuilder,
+};
+
+/// Tree of files.
+const FILES_TREE: &str = "fs";
+
+/// Tree of processes.
+const DOMAINS_TREE: &str = "domains";
+
+/// Simple confinement policy in the style of Landlock, a program may access only the files it
+/// is allowed to:
+///
+/// ```text
+/// let config = Policy::new()
+///     .allow_read("/usr")?
+///     .allow_write("/var/lib/myapp")?
+///     .for_exe("/usr/bin/myapp")
+///     .build()?;
+/// ```
+///
+/// The policy compiles to a [`ConfigBuilder`] with the trees `fs` and `domains`, a space of the
+/// confined domain and one space per allowed path. Processes of the given executables are
+/// entered into the confined domain by an [`ExecutableMap`], which may read and see allowed
+/// paths, write paths allowed for writing and interact with processes of its own domain. All
+/// other processes stay unconfined and may access everything.
+///
+/// Paths are allowed together with their subtrees. Where an allowed path is nested in another
+/// one, the deeper path decides.
+#[derive(Debug, Clone)]
+pub struct Policy {
+    name: &'static str,
+    reads: Vec<String>,
+    writes: Vec<String>,
+    executables: Vec<String>,
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Self {
+            name: "confined",
+            reads: Vec::new(),
+            writes: Vec::new(),
+            executables: Vec::new(),
+        }
+    }
+}
+
+impl Policy {
+    /// Creates new `Policy` allowing nothing.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Sets the name of the space of the confined domain, `confined` by default. Spaces of
+    /// allowed paths are named after it.
+    ///
+    /// Returns `Self`.
+    pub fn with_name(mut self, name: &'static str) -> Self {
+        self.name = name;
+        self
+    }
+
+    /// Allows reading files under `path`.
+    ///
+    /// Returns `Self` or `ConfigError` if the path is not absolute.
+    pub fn allow_read(mut self, path: &str) -> Result<Self, ConfigError> {
+        self.reads.push(tree_path(FILES_TREE, path)?);
+        Ok(self)
+    }
+
+    /// Allows reading and writing files under `path`.
+    ///
+    /// Returns `Self` or `ConfigError` if the path is not absolute.
+    pub fn allow_write(mut self, path: &str) -> Result<Self, ConfigError> {
+        self.writes.push(tree_path(FILES_TREE, path)?);
+        Ok(self)
+    }
+
+    /// Confines processes executing `exe`, matched at the start of their `cmdline`. May be
+    /// given multiple times, all executables share the confined domain.
+    ///
+    /// Returns `Self`.
+    pub fn for_exe(mut self, exe: &str) -> Self {
+        self.executables.push(exe.to_owned());
+        self
+    }
+
+    /// Compiles the policy into a [`ConfigBuilder`], so that other settings can still be added
+    /// before building the [`Config`].
+    ///
+    /// Returns `ConfigBuilder` or `ConfigError` if no executable is confined.
+    pub fn compile(self) -> Result<ConfigBuilder, ConfigError> {
+        if self.executables.is_empty() {
+            return Err(ConfigError::NoExecutableError);
+        }
+
+        let name = self.name;
+        let domain = format!("/{}", name);
+        let mut map = ExecutableMap::new(DOMAINS_TREE);
+        for exe in &self.executables {
+            // the cmdline holds the arguments separated by NULs or spaces
+            map = map.map(&format!(r"^{}(?:[\x00 ]|$)", regex::escape(exe)), &domain)?;
+        }
+        map = map.map("^", "/")?;
+
+        let reads = self.reads.iter().enumerate().map(|(i, path)| {
+            SpaceBuilder::new()
+                .with_name(intern(&format!("{}_read_{}", name, i)))
+                .with_path_recursive(intern(path))
+        });
+        let writes = self.writes.iter().enumerate().map(|(i, path)| {
+            SpaceBuilder::new()
+                .with_name(intern(&format!("{}_write_{}", name, i)))
+                .with_path_recursive(intern(path))
+        });
+        let reads = reads.collect::<Vec<_>>();
+        let writes = writes.collect::<Vec<_>>();
+        let read_names = reads.iter().chain(&writes).map(|x| x.name());
+        let write_names = writes.iter().map(|x| x.name());
+
+        let all_files = SpaceBuilder::new()
+            .with_name("all_files")
+            .with_path_recursive(intern(&format!("{}/", FILES_TREE)));
+
+        let confined = SpaceBuilder::new()
+            .with_name(name)
+            .with_path(intern(&tree_path(DOMAINS_TREE, &domain)?))
+            .reads(read_names.clone().chain([name]))
+            .writes(write_names.chain([name]))
+            .sees(read_names.clone().chain([name]));
+
+        let everything = read_names
+            .chain(["all_files", "unconfined", name])
+            .collect::<Vec<_>>();
+        let unconfined = SpaceBuilder::new()
+            .with_name("unconfined")
+            .with_path_recursive(intern(&format!("{}/", DOMAINS_TREE)))
+            .reads(everything.clone())
+            .writes(everything.clone())
+            .sees(everything);
+
+        Ok(Config::builder()
+            .add_spaces([all_files, unconfined, confined])
+            .add_spaces(reads)
+            .add_spaces(writes)
+            .add_hierarchy_event_handler(
+                Event::GetFile,
+                FILES_TREE,
+                Some("filename"),
+                HandlerFlags::FROM_OBJECT,
+            )
+            .add_executable_map(map))
+    }
+
+    /// Compiles and builds the policy, see [`Policy::compile`].
+    pub fn build(self) -> Result<Config, ConfigError> {
+        self.compile()?.build()
+    }
+}
+
+/// Returns the path of the node for absolute `path` in `tree`. Components of `path` are
+/// matched literally.
+fn tree_path(tree: &str, path: &str) -> Result<String, ConfigError> {
+    if !path.starts_with('/') {
+        return Err(ConfigError::InvalidPathError(path.to_owned()));
+    }
+
+    let components = path
+        .split('/')
+        .filter(|x| !x.is_empty())
+        .map(regex::escape)
+        .collect::<Vec<_>>();
+
+    Ok(format!("{}/{}", tree, components.join("/")))
+}
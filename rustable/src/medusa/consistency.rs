@@ -0,0 +1,110 @@
+//! Detection of objects changed between fetch and update, see [`FetchedObject`].
+
+use crate::medusa::error::ConsistencyError;
+use crate::medusa::{executor, Context, MedusaClass};
+
+/// Object fetched from the security module for a read-modify-write, remembering the state it
+/// was fetched in.
+///
+/// Before the modified object is written back by [`FetchedObject::update`], it is fetched
+/// again and compared with that state. If a concurrent handler or the kernel changed it
+/// meanwhile, the update is refused with [`ConsistencyError::ChangedError`] instead of
+/// clobbering the change. The states are compared by the generation attribute of the class
+/// if the config names one, see [`ConfigBuilder::generation_attribute`], and byte by byte
+/// otherwise.
+///
+/// The security module has no compare-and-swap, so a change in the short time between the
+/// comparison and the update is still lost. [`MedusaClass::update_with`] retries the whole
+/// read-modify-write on a detected change.
+///
+/// [`ConfigBuilder::generation_attribute`]: crate::medusa::ConfigBuilder::generation_attribute
+#[derive(Debug, Clone)]
+pub struct FetchedObject {
+    object: MedusaClass,
+    observed: Vec<u8>,
+}
+
+impl FetchedObject {
+    /// Fetches the current state of `object`.
+    ///
+    /// Returns `ConsistencyError` if the security module does not know the object.
+    pub async fn fetch(ctx: &Context, object: &MedusaClass) -> Result<Self, ConsistencyError> {
+        let fetched = object
+            .fetch(ctx)
+            .await
+            .ok_or_else(|| ConsistencyError::NotFoundError(object.subject_id()))?;
+        let observed = observed_state(ctx, &fetched);
+
+        Ok(Self {
+            object: fetched,
+            observed,
+        })
+    }
+
+    /// Same as [`FetchedObject::fetch`], but blocks the calling thread until the answer
+    /// arrives.
+    pub fn fetch_blocking(ctx: &Context, object: &MedusaClass) -> Result<Self, ConsistencyError> {
+        executor::block_on(Self::fetch(ctx, object))
+    }
+
+    /// Returns the fetched object, including the modifications made so far.
+    pub fn object(&self) -> &MedusaClass {
+        &self.object
+    }
+
+    /// Returns the fetched object for modification.
+    pub fn object_mut(&mut self) -> &mut MedusaClass {
+        &mut self.object
+    }
+
+    /// Returns the fetched object, including the modifications made so far.
+    pub fn into_inner(self) -> MedusaClass {
+        self.object
+    }
+
+    /// Fetches the object again and returns `true` if it differs from the state it was fetched
+    /// in.
+    ///
+    /// Returns `ConsistencyError` if the security module no longer knows the object.
+    pub async fn is_changed(&self, ctx: &Context) -> Result<bool, ConsistencyError> {
+        let current = self
+            .object
+            .fetch(ctx)
+            .await
+            .ok_or_else(|| ConsistencyError::NotFoundError(self.object.subject_id()))?;
+
+        Ok(observed_state(ctx, &current) != self.observed)
+    }
+
+    /// Writes the modified object back if it did not change since it was fetched.
+    ///
+    /// Returns `ConsistencyError` if the object changed meanwhile or if the security module did
+    /// not update it.
+    pub async fn update(&self, ctx: &Context) -> Result<(), ConsistencyError> {
+        if self.is_changed(ctx).await? {
+            return Err(ConsistencyError::ChangedError(self.object.subject_id()));
+        }
+
+        Ok(self.object.update(ctx).await?)
+    }
+
+    /// Same as [`FetchedObject::update`], but blocks the calling thread until the answers
+    /// arrive.
+    pub fn update_blocking(&self, ctx: &Context) -> Result<(), ConsistencyError> {
+        executor::block_on(self.update(ctx))
+    }
+}
+
+/// Returns the part of the state of `object` which is compared, its generation attribute or
+/// all of its attributes.
+fn observed_state(ctx: &Context, object: &MedusaClass) -> Vec<u8> {
+    let config = ctx.config.load();
+    let generation = config
+        .generation_attribute(object.name())
+        .and_then(|x| object.get_attribute_bytes_trimmed(x).ok());
+
+    match generation {
+        Some(generation) => generation.to_vec(),
+        None => object.pack_attributes(),
+    }
+}
@@ -0,0 +1,29 @@
+//! Inspection of live tasks with [tokio-console], available with the `console` feature.
+//!
+//! Tasks are instrumented only if the crate is also built with `--cfg tokio_unstable`:
+//!
+//! ```text
+//! RUSTFLAGS="--cfg tokio_unstable" cargo build --features console
+//! ```
+//!
+//! Tasks answering authorization requests are then named `<event> #<request id>`, e.g.
+//! `getfile #42`, tasks of the connection are named `medusa-<purpose>`. Only tasks spawned by
+//! [`TokioExecutor`] are named, see [`Executor::spawn_named`].
+//!
+//! [tokio-console]: https://github.com/tokio-rs/console
+//! [`TokioExecutor`]: crate::medusa::executor::TokioExecutor
+//! [`Executor::spawn_named`]: crate::medusa::executor::Executor::spawn_named
+
+/// Installs the global subscriber serving state of tasks to tokio-console, on
+/// `127.0.0.1:6669` unless changed by the `TOKIO_CONSOLE_BIND` environment variable. Should be
+/// called once, before the connection is established.
+///
+/// # Panics
+///
+/// Panics if a global tracing subscriber is already installed.
+pub fn init() {
+    #[cfg(not(tokio_unstable))]
+    eprintln!("tasks are not instrumented, build with `--cfg tokio_unstable` to inspect them");
+
+    console_subscriber::init();
+}
@@ -42,8 +42,8 @@ pub const MEDUSA_EVTYPE_TRIGGEREDBYOBJECTBIT: u16 = 0x4000;
 pub const MEDUSA_ACCTYPE_TRIGGEREDATOBJECT: u16 =
     MEDUSA_EVTYPE_TRIGGEREDATOBJECT | MEDUSA_EVTYPE_TRIGGEREDBYOBJECTBIT;
 
-pub const NODE_HIGHEST_PRIORITY: u16 = std::u16::MIN;
-pub const NODE_LOWEST_PRIORITY: u16 = std::u16::MAX;
+pub const NODE_HIGHEST_PRIORITY: u16 = u16::MIN;
+pub const NODE_LOWEST_PRIORITY: u16 = u16::MAX;
 
 bitflags! {
     #[derive(Default)]
@@ -83,6 +83,9 @@ pub enum AttributeDataType {
     String,
     Bitmap,
     Bytes,
+
+    /// Data type not known to this implementation. The attribute is treated as raw bytes.
+    Unknown = 0xff,
 }
 
 impl TryFrom<u8> for AttributeDataType {
@@ -124,5 +127,31 @@ bitflags! {
     #[derive(Default)]
     pub struct HandlerFlags: u8 {
         const FROM_OBJECT = 0x01;
+        /// The classified entity is fetched from the security module and its labels are
+        /// applied to the fetched copy, so that the update persists them without overwriting
+        /// changes made by the kernel in the meantime.
+        const PIN_LABELS = 0x02;
+    }
+}
+
+bitflags! {
+    /// Features of the security module which may be missing in some kernel builds.
+    #[derive(Default)]
+    pub struct KernelCapabilities: u64 {
+        /// The security module answers `fetch` requests.
+        const FETCH = 0x01;
+        /// The security module answers `update` requests.
+        const UPDATE = 0x02;
+    }
+}
+
+impl KernelCapabilities {
+    /// Returns capabilities implied by the protocol version. Protocol version 2 has no way of
+    /// announcing capabilities, so every known capability is assumed.
+    pub fn from_protocol_version(version: u64) -> Self {
+        match version {
+            2 => Self::FETCH | Self::UPDATE,
+            _ => Self::empty(),
+        }
     }
 }
@@ -1,71 +1,365 @@
+use crate::medusa::anomaly::AnomalyDetector;
+use crate::medusa::batch::{RecentUpdates, UpdateQueue};
 use crate::medusa::config::Config;
+use crate::medusa::executor::{self, Executor};
+use crate::medusa::health::HealthState;
+use crate::medusa::outstanding::OutstandingRequests;
+use crate::medusa::overrides::Overrides;
+use crate::medusa::pending::{PendingRequests, SentFetch};
+use crate::medusa::positions::TreePositions;
+use crate::medusa::process::ProcessTree;
+use crate::medusa::proto::Registry;
+use crate::medusa::retry::UpdateSender;
+use crate::medusa::slo::SloState;
+use crate::medusa::usage::UsageAccountant;
+use crate::medusa::watchdog::RunningHandlers;
 use crate::medusa::{
-    FetchAnswer, MedusaClass, MedusaEvtype, MedusaRequest, RequestType, UpdateAnswer, Writer,
+    enforcement, AccessType, AnswerOverride, ConfigError, FetchAnswer, Health, KernelCapabilities,
+    MedusaAnswer, MedusaClass, MedusaEvtype, MedusaRequest, Node, PipelineStats, ProcessInfo,
+    RequestType, Snapshot, SubjectId, UpdateAnswer, UpdateError, UpdateStatus, Usage, Writer,
 };
-use dashmap::DashMap;
-use std::sync::atomic::{AtomicU64, Ordering};
-use std::sync::Arc;
-use tokio::sync::mpsc::{self, UnboundedSender};
+use arc_swap::ArcSwap;
+use dashmap::DashSet;
+use std::collections::VecDeque;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Callback receiving the answer to an update request sent by
+/// [`Context::update_request_no_wait`].
+pub type UpdateCallback = Box<dyn FnOnce(UpdateAnswer) + Send>;
 
 /// Shared context between various asynchronous tasks.
 pub struct Context {
-    pub(crate) classes: DashMap<u64, MedusaClass>,
-    pub(crate) evtypes: DashMap<u64, MedusaEvtype>,
+    // classes and events registered by the security module
+    pub(crate) registry: Arc<Registry>,
 
-    pub(crate) fetch_requests: DashMap<u64, UnboundedSender<FetchAnswer>>,
-    pub(crate) update_requests: DashMap<u64, UnboundedSender<UpdateAnswer>>,
+    pub(crate) pending: Arc<PendingRequests>,
 
-    pub(crate) class_id: DashMap<String, u64>,
-    pub(crate) evtype_id: DashMap<String, u64>,
+    // authorization requests received and not answered yet
+    pub(crate) outstanding: Arc<OutstandingRequests>,
 
     pub(crate) writer: Writer,
 
-    pub(crate) config: Config,
+    // replaced as a whole, so that readers need no lock, see `Context::replace_config`
+    pub(crate) config: ArcSwap<Config>,
+    // serializes registration of events with replacement of the config
+    registering: Mutex<()>,
+
+    pub(crate) kernel_capabilities: KernelCapabilities,
+
+    // context evaluating the candidate config, see `ConfigBuilder::shadow`
+    pub(crate) shadow: Option<Arc<Context>>,
+
+    // see `ConfigBuilder::enforce`
+    pub(crate) enforcing: Arc<AtomicBool>,
+
+    // answer to all requests, see `ConfigBuilder::observe`
+    pub(crate) observing: Option<MedusaAnswer>,
+
+    // timings of request handling stages, shared with the writer
+    pub(crate) stats: Arc<PipelineStats>,
+
+    // see `ConfigBuilder::executor`
+    pub(crate) executor: Arc<dyn Executor>,
+
+    // see `ConfigBuilder::handler_watchdog`
+    pub(crate) running_handlers: Option<Arc<RunningHandlers>>,
+
+    // see `ConfigBuilder::track_processes`
+    pub(crate) processes: Option<Arc<ProcessTree>>,
 
-    request_id_cn: AtomicU64,
+    // see `ConfigBuilder::separate_tree_positions`
+    pub(crate) positions: Option<Arc<TreePositions>>,
+
+    // see `ConfigBuilder::latency_slo`
+    pub(crate) slo: SloState,
+
+    // see `Context::quarantine`
+    quarantined: Arc<DashSet<SubjectId>>,
+
+    // see `ConfigBuilder::anomaly_detection`
+    pub(crate) anomalies: Option<Arc<AnomalyDetector>>,
+
+    // see `ConfigBuilder::resource_accounting`
+    pub(crate) usage: Option<Arc<UsageAccountant>>,
+
+    // see `Context::add_override`
+    pub(crate) overrides: Arc<Overrides>,
+
+    // see `Context::health`
+    pub(crate) health: Arc<HealthState>,
+
+    debug_handlers: Arc<DashSet<String>>,
+    update_queue: Arc<UpdateQueue>,
+    recent_updates: Arc<RecentUpdates>,
+    dry_run: bool,
 }
 
 impl Context {
-    pub(crate) fn new(writer: Writer, config: Config) -> Self {
+    pub(crate) fn new(
+        registry: Arc<Registry>,
+        writer: Writer,
+        config: Config,
+        stats: Arc<PipelineStats>,
+    ) -> Self {
+        let pending: Arc<PendingRequests> = Default::default();
+        let recent_updates = Arc::new(RecentUpdates::new(config.update_dedup_window));
+        let executor = Arc::clone(&config.executor);
+        let update_queue = Arc::new(UpdateQueue::new(
+            config.update_flush_interval,
+            writer.clone(),
+            Arc::clone(&pending),
+            Arc::clone(&recent_updates),
+            Arc::clone(&executor),
+        ));
+        let running_handlers = config.handler_watchdog.map(|_| Default::default());
+        let observing = config.observe;
+        let processes = config.track_processes.then(Default::default);
+        let positions = config.separate_tree_positions.then(Default::default);
+        let anomalies = config
+            .anomaly_detection
+            .clone()
+            .map(|x| Arc::new(AnomalyDetector::new(x)));
+        let usage = config
+            .resource_accounting
+            .clone()
+            .map(|x| Arc::new(UsageAccountant::new(x)));
+
         Self {
-            classes: DashMap::new(),
-            evtypes: DashMap::new(),
-            fetch_requests: DashMap::new(),
-            update_requests: DashMap::new(),
-            class_id: DashMap::new(),
-            evtype_id: DashMap::new(),
+            registry,
+            pending,
+            outstanding: Default::default(),
             writer,
-            config,
-            request_id_cn: AtomicU64::new(111),
+            config: ArcSwap::from_pointee(config),
+            registering: Mutex::new(()),
+            kernel_capabilities: KernelCapabilities::empty(),
+            shadow: None,
+            enforcing: Default::default(),
+            observing,
+            stats,
+            executor,
+            running_handlers,
+            processes,
+            positions,
+            slo: Default::default(),
+            quarantined: Default::default(),
+            anomalies,
+            usage,
+            overrides: Default::default(),
+            health: Default::default(),
+            debug_handlers: Default::default(),
+            update_queue,
+            recent_updates,
+            // an observer never updates kernel objects
+            dry_run: observing.is_some(),
+        }
+    }
+
+    /// Creates a context sharing the connection with this one, but using a different `config`.
+    /// Update requests made through the new context are not sent to the security module.
+    pub(crate) fn dry_run_with(&self, config: Config) -> Self {
+        Self {
+            registry: Arc::clone(&self.registry),
+            pending: Arc::clone(&self.pending),
+            outstanding: Arc::clone(&self.outstanding),
+            writer: self.writer.clone(),
+            config: ArcSwap::from_pointee(config),
+            registering: Mutex::new(()),
+            kernel_capabilities: self.kernel_capabilities,
+            shadow: None,
+            enforcing: Arc::clone(&self.enforcing),
+            observing: None,
+            stats: Arc::clone(&self.stats),
+            executor: Arc::clone(&self.executor),
+            running_handlers: None,
+            processes: self.processes.clone(),
+            positions: self.positions.clone(),
+            slo: Default::default(),
+            quarantined: Arc::clone(&self.quarantined),
+            anomalies: None,
+            // handlers of the dry run read the usage of the connection
+            usage: self.usage.clone(),
+            // the dry run shows what the config alone decides
+            overrides: Default::default(),
+            health: Arc::clone(&self.health),
+            debug_handlers: Arc::clone(&self.debug_handlers),
+            update_queue: Arc::clone(&self.update_queue),
+            recent_updates: Arc::clone(&self.recent_updates),
+            dry_run: true,
+        }
+    }
+
+    /// Returns the current configuration. It stays valid for as long as it is held, even if the
+    /// configuration is replaced meanwhile, see [`Context::replace_config`].
+    pub fn config(&self) -> Arc<Config> {
+        self.config.load_full()
+    }
+
+    /// Replaces the configuration. Requests being handled finish with the handlers of the
+    /// previous configuration, requests read afterwards are handled according to `config`.
+    /// Events registered by the security module are registered with `config` before it is
+    /// published, readers are never blocked.
+    ///
+    /// Settings of the connection itself, such as its runtime, the control socket or the
+    /// candidate of [`ConfigBuilder::shadow`], keep the values of the configuration the
+    /// connection was established with. Entities entered into trees keep the virtual spaces
+    /// assigned according to the previous configuration until they are entered again, so
+    /// `config` should keep the spaces of the previous one. Their nodes are looked up in `config`
+    /// by the position in the tree, hierarchy handlers fail for entities whose node has no
+    /// counterpart in `config`. Users keep their user
+    /// domains, see [`ConfigBuilder::user_domains`]. Plugins loaded through the control socket
+    /// are not carried over.
+    ///
+    /// [`ConfigBuilder::shadow`]: crate::medusa::ConfigBuilder::shadow
+    /// [`ConfigBuilder::user_domains`]: crate::medusa::ConfigBuilder::user_domains
+    pub fn replace_config(&self, mut config: Config) {
+        {
+            let _registering = self.registering.lock().unwrap();
+            for mut evtype in self.registry.evtypes.iter_mut() {
+                config.register_evtype(&mut evtype.header);
+            }
+
+            let previous = self.config.load();
+            if let (Some(domains), Some(previous)) =
+                (&mut config.user_domains, &previous.user_domains)
+            {
+                domains.adopt(previous);
+            }
+            config
+                .retired_space_bits
+                .replace(&previous.space_bit_to_name, &config.space_bit_to_name);
+
+            self.config.store(Arc::new(config));
+        }
+
+        enforcement::update(self);
+    }
+
+    /// Releases the bits of virtual spaces removed by config replacements, so that new spaces of
+    /// the next config prepared by [`ConfigBuilder::replacing`] may be given them. Entities
+    /// carrying a retired bit have it cleared once they take part in a request, but the others
+    /// keep it and would become members of the new space, so the caller must make sure that no
+    /// such entity is left, e.g. by having all of them entered into trees again.
+    ///
+    /// Returns the released bits.
+    ///
+    /// [`ConfigBuilder::replacing`]: crate::medusa::ConfigBuilder::replacing
+    pub fn release_retired_space_bits(&self) -> Vec<usize> {
+        let config = self.config.load();
+        config.retired_space_bits.release().into_iter().collect()
+    }
+
+    /// Registers `evtype` defined by the security module with the current configuration.
+    pub(crate) fn register_evtype(&self, mut evtype: MedusaEvtype) {
+        {
+            let _registering = self.registering.lock().unwrap();
+            self.config.load().register_evtype(&mut evtype.header);
+            self.registry.define_evtype(evtype);
+        }
+
+        enforcement::update(self);
+    }
+
+    /// Runs `future` in the background on the executor of the connection, see
+    /// [`ConfigBuilder::executor`].
+    ///
+    /// [`ConfigBuilder::executor`]: crate::medusa::ConfigBuilder::executor
+    pub fn spawn<F>(&self, future: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        self.executor.spawn(Box::pin(future));
+    }
+
+    /// Returns capabilities of the connected security module.
+    pub fn kernel_capabilities(&self) -> KernelCapabilities {
+        self.kernel_capabilities
+    }
+
+    /// Returns `true` if deny-by-default enforcement is enabled and all readiness checks have
+    /// passed, see [`ConfigBuilder::enforce`].
+    ///
+    /// [`ConfigBuilder::enforce`]: crate::medusa::ConfigBuilder::enforce
+    pub fn is_enforcing(&self) -> bool {
+        self.enforcing.load(Ordering::SeqCst)
+    }
+
+    /// Returns timings of the stages authorization requests pass through since the connection
+    /// was established.
+    pub fn pipeline_stats(&self) -> &PipelineStats {
+        &self.stats
+    }
+
+    /// Enables or disables verbose output of handlers named `name`, see
+    /// [`EventHandlerBuilder::name`]. The change takes effect for the next request.
+    ///
+    /// Returns `false` if there is no such handler.
+    ///
+    /// [`EventHandlerBuilder::name`]: crate::medusa::EventHandlerBuilder::name
+    pub fn set_handler_debug(&self, name: &str, enabled: bool) -> bool {
+        if !self.config.load().has_handler_named(name) {
+            return false;
+        }
+
+        if enabled {
+            self.debug_handlers.insert(name.to_owned());
+        } else {
+            self.debug_handlers.remove(name);
         }
+
+        true
     }
 
-    /// Returns configuration.
-    pub fn config(&self) -> &Config {
-        &self.config
+    /// Returns names of the handlers with verbose output enabled.
+    pub(crate) fn debugged_handlers(&self) -> Vec<String> {
+        let mut names = self
+            .debug_handlers
+            .iter()
+            .map(|x| x.key().clone())
+            .collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+
+    /// Returns `true` if verbose output of handlers named `name` is enabled.
+    pub fn is_handler_debugged(&self, name: &str) -> bool {
+        !self.debug_handlers.is_empty() && self.debug_handlers.contains(name)
+    }
+
+    /// Returns classes and events registered by the security module.
+    pub fn registry(&self) -> &Registry {
+        &self.registry
     }
 
     /// Returns identification of a class having the given name.
     pub fn class_id_from_name(&self, class_name: &str) -> Option<u64> {
-        self.class_id.get(class_name).map(|x| *x)
+        self.registry.class_id_from_name(class_name)
     }
 
     /// Returns identification of an event having the given name.
     pub fn evtype_id_from_name(&self, evtype_name: &str) -> Option<u64> {
-        self.evtype_id.get(evtype_name).map(|x| *x)
+        self.registry.evtype_id_from_name(evtype_name)
     }
 
-    /// Returns an empty class having the given id with no attribute data.
+    /// Returns an empty class having the given id with no attribute data besides the defaults
+    /// of [`ConfigBuilder::default_attribute`].
+    ///
+    /// [`ConfigBuilder::default_attribute`]: crate::medusa::ConfigBuilder::default_attribute
     pub fn empty_class_from_id(&self, class_id: &u64) -> Option<MedusaClass> {
-        self.classes.get(class_id).map(|x| x.value().clone())
+        let mut class = self.registry.empty_class_from_id(class_id)?;
+        // errors are reported when the class is registered
+        let _ = self.config.load().apply_default_attributes(&mut class);
+        Some(class)
     }
 
     /// Returns an empty event having the given id with no attribute data.
     pub fn empty_evtype_from_id(&self, evtype_id: &u64) -> Option<MedusaEvtype> {
-        self.evtypes.get(evtype_id).map(|x| x.value().clone())
+        self.registry.empty_evtype_from_id(evtype_id)
     }
 
-    /// Returns an empty class having the given name with no attribute data.
+    /// Returns an empty class having the given name, see [`Context::empty_class_from_id`].
     pub fn empty_class(&self, class_name: &str) -> Option<MedusaClass> {
         let class_id = self.class_id_from_name(class_name)?;
         self.empty_class_from_id(&class_id)
@@ -77,41 +371,425 @@ impl Context {
         self.empty_evtype_from_id(&evtype_id)
     }
 
-    /// Performs `update` request.
-    pub async fn update_request(&self, class_id: u64, data: &[u8]) -> UpdateAnswer {
-        let req = MedusaRequest {
-            req_type: RequestType::Update,
-            class_id,
-            id: self.get_new_request_id(),
-            data,
+    /// Returns a snapshot of the state of the connection, e.g. to find out why requests are
+    /// stuck, see also [`ConfigBuilder::snapshot_on_signal`].
+    ///
+    /// [`ConfigBuilder::snapshot_on_signal`]: crate::medusa::ConfigBuilder::snapshot_on_signal
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot::new(self)
+    }
+
+    /// Returns the operations granted to the domain of `subject` within the window of
+    /// [`ConfigBuilder::resource_accounting`], or `None` if the accounting is not enabled.
+    ///
+    /// [`ConfigBuilder::resource_accounting`]: crate::medusa::ConfigBuilder::resource_accounting
+    pub fn usage(&self, subject: &MedusaClass) -> Option<Usage> {
+        let usage = self.usage.as_ref()?;
+        Some(usage.usage(&self.config(), subject))
+    }
+
+    /// Returns the health of the connection, for liveness and readiness checks.
+    pub fn health(&self) -> Health {
+        Health::new(self)
+    }
+
+    /// Returns the known ancestors of process `subject`, its parent first. The list is empty if
+    /// processes are not tracked, see [`ConfigBuilder::track_processes`].
+    ///
+    /// [`ConfigBuilder::track_processes`]: crate::medusa::ConfigBuilder::track_processes
+    pub fn ancestors(&self, subject: &MedusaClass) -> Vec<ProcessInfo> {
+        match &self.processes {
+            Some(processes) => processes.ancestors(subject),
+            None => Vec::new(),
+        }
+    }
+
+    /// Returns `true` if any known ancestor of process `subject` was a member of virtual space
+    /// `space`, see [`Context::ancestors`].
+    pub fn has_ancestor_in(&self, subject: &MedusaClass, space: &str) -> bool {
+        let config = self.config();
+        let bit = match config.name_to_space_bit(space) {
+            Some(bit) => *bit,
+            None => return false,
         };
 
-        let (sender, mut receiver) = mpsc::unbounded_channel();
-        self.update_requests.insert(req.id, sender);
+        self.ancestors(subject).iter().any(|x| x.is_member_of(bit))
+    }
 
-        self.writer.write(Arc::from(req.to_vec()));
+    /// Returns whether a subject in virtual spaces `subject_spaces` would be allowed access `at`
+    /// to an object in virtual spaces `object_spaces` by the current config, with the semantics
+    /// of the security module: a space the subject reads, writes or sees by
+    /// [`SpaceBuilder::reads`], [`SpaceBuilder::writes`] or [`SpaceBuilder::sees`] and which
+    /// none of its spaces denies by [`SpaceBuilder::denies`] must be a space of the object. For
+    /// [`AccessType::Member`], the subject and the object must share a space.
+    ///
+    /// Returns `UnknownSpaceError` if a space is not defined in the current config.
+    ///
+    /// [`SpaceBuilder::reads`]: crate::medusa::SpaceBuilder::reads
+    /// [`SpaceBuilder::writes`]: crate::medusa::SpaceBuilder::writes
+    /// [`SpaceBuilder::sees`]: crate::medusa::SpaceBuilder::sees
+    /// [`SpaceBuilder::denies`]: crate::medusa::SpaceBuilder::denies
+    pub fn check(
+        &self,
+        subject_spaces: &[&str],
+        object_spaces: &[&str],
+        at: AccessType,
+    ) -> Result<bool, ConfigError> {
+        self.config().check(subject_spaces, object_spaces, at)
+    }
+
+    /// Returns the node of tree `tree` `entity` is in, or `None` if it was not entered into the
+    /// tree. Without [`ConfigBuilder::separate_tree_positions`], only the node of the tree the
+    /// entity was entered into last is known.
+    ///
+    /// [`ConfigBuilder::separate_tree_positions`]: crate::medusa::ConfigBuilder::separate_tree_positions
+    pub fn node_in_tree(&self, entity: &MedusaClass, tree: &str) -> Option<Arc<Node>> {
+        let config = self.config();
+        let tree = config.tree_by_name(tree)?;
+        let cinfo = match &self.positions {
+            Some(positions) => positions.cinfo(&config, tree, entity),
+            None => entity.get_object_cinfo().ok()?,
+        };
+        if config.tree_name_of(cinfo) != Some(tree.name()) {
+            return None;
+        }
 
-        receiver.recv().await.expect("channel is disconnected")
+        config.node_by_cinfo(&cinfo).cloned()
     }
 
-    /// Performs `fetch` request.
+    /// Confines `subject` to the space of [`ConfigBuilder::quarantine_space`] and updates it
+    /// in the security module. It stays quarantined when it is entered into a tree again,
+    /// until it is released by [`Context::release`].
+    ///
+    /// Returns `UpdateError` if the security module did not update `subject`.
+    ///
+    /// [`ConfigBuilder::quarantine_space`]: crate::medusa::ConfigBuilder::quarantine_space
+    pub async fn quarantine(&self, subject: &mut MedusaClass) -> Result<(), UpdateError> {
+        self.quarantined.insert(subject.subject_id());
+        self.set_quarantine(subject);
+        subject.update(self).await
+    }
+
+    /// Same as [`Context::quarantine`], but does not wait for the update.
+    pub fn quarantine_no_wait(&self, subject: &mut MedusaClass) {
+        self.quarantined.insert(subject.subject_id());
+        self.set_quarantine(subject);
+        subject.update_no_wait(self, None);
+    }
+
+    /// Releases subject `id` from quarantine. It regains its access when it is entered into a
+    /// tree again.
+    ///
+    /// Returns `false` if it was not quarantined.
+    pub fn release(&self, id: &SubjectId) -> bool {
+        self.quarantined.remove(id).is_some()
+    }
+
+    /// Forces the answer of requests matching `value` until it expires, see
+    /// [`AnswerOverride`]. If several active overrides match a request, the one added last
+    /// decides.
+    ///
+    /// Returns the id of the override.
+    pub fn add_override(&self, value: AnswerOverride) -> u64 {
+        self.overrides.add(value)
+    }
+
+    /// Removes override `id` before it expires.
+    ///
+    /// Returns `false` if there is no such active override.
+    pub fn remove_override(&self, id: u64) -> bool {
+        self.overrides.remove(id)
+    }
+
+    /// Returns the active overrides with their ids and the time left until they expire.
+    pub fn overrides(&self) -> Vec<(u64, AnswerOverride, Duration)> {
+        self.overrides.list()
+    }
+
+    /// Returns `true` if `subject` is quarantined, see [`Context::quarantine`].
+    pub fn is_quarantined(&self, subject: &MedusaClass) -> bool {
+        !self.quarantined.is_empty() && self.quarantined.contains(&subject.subject_id())
+    }
+
+    /// Sets the virtual spaces of quarantined `subject` without updating it.
+    pub(crate) fn set_quarantine(&self, subject: &mut MedusaClass) {
+        match &self.config.load().quarantine {
+            Some(vs) => subject.set_access_types(vs),
+            None => subject.clear_node(),
+        }
+    }
+
+    /// Returns the numbers of queued updates and of updates remembered for deduplication.
+    pub(crate) fn update_lens(&self) -> (usize, usize) {
+        (self.update_queue.len(), self.recent_updates.len())
+    }
+
+    /// Returns `true` if this context does not send updates to the security module.
+    pub fn is_dry_run(&self) -> bool {
+        self.dry_run
+    }
+
+    /// Performs `update` request, which is retried if it fails, see
+    /// [`ConfigBuilder::update_retries`]. In a dry run context, the request is not sent and a
+    /// successful answer is returned immediately.
+    ///
+    /// [`ConfigBuilder::update_retries`]: crate::medusa::ConfigBuilder::update_retries
+    pub async fn update_request(&self, class_id: u64, data: &[u8]) -> UpdateAnswer {
+        let key = self.update_key(class_id, data);
+        if self.dry_run || self.is_duplicate_update(key.as_ref(), data) {
+            return UpdateAnswer {
+                class_id,
+                msg_seq: self.pending.next_id(),
+                status: UpdateStatus::Ok,
+            };
+        }
+
+        let sender = self.update_sender();
+        let sent = sender.send(class_id, data);
+        let answer = sender.answer(class_id, data, sent).await;
+        if let Some(key) = key.filter(|_| answer.status != UpdateStatus::Ok) {
+            self.recent_updates.forget(&key);
+        }
+
+        answer
+    }
+
+    /// Same as [`Context::update_request`], but blocks the calling thread until the answer
+    /// arrives, see [`EventHandlerBuilder::with_blocking_handler`].
+    ///
+    /// [`EventHandlerBuilder::with_blocking_handler`]: crate::medusa::EventHandlerBuilder::with_blocking_handler
+    pub fn update_request_blocking(&self, class_id: u64, data: &[u8]) -> UpdateAnswer {
+        executor::block_on(self.update_request(class_id, data))
+    }
+
+    /// Sends `update` request without waiting for the answer, which is passed to `callback` once
+    /// it arrives. Requests and answers to authorization requests are written in order, so the
+    /// update is applied before a later answer. In a dry run context, the request is not sent
+    /// and `callback` receives a successful answer immediately.
+    pub fn update_request_no_wait(
+        &self,
+        class_id: u64,
+        data: &[u8],
+        callback: Option<UpdateCallback>,
+    ) {
+        let key = self.update_key(class_id, data);
+        if self.dry_run || self.is_duplicate_update(key.as_ref(), data) {
+            if let Some(callback) = callback {
+                callback(UpdateAnswer {
+                    class_id,
+                    msg_seq: self.pending.next_id(),
+                    status: UpdateStatus::Ok,
+                });
+            }
+            return;
+        }
+
+        let sender = self.update_sender();
+        let sent = sender.send(class_id, data);
+
+        // the answer is awaited even without callback, so that the request is not abandoned
+        let data = data.to_vec();
+        let recent_updates = Arc::clone(&self.recent_updates);
+        self.spawn(async move {
+            let answer = sender.answer(class_id, &data, sent).await;
+            if let Some(key) = key.filter(|_| answer.status != UpdateStatus::Ok) {
+                recent_updates.forget(&key);
+            }
+            if let Some(callback) = callback {
+                callback(answer);
+            }
+        });
+    }
+
+    fn update_sender(&self) -> UpdateSender {
+        UpdateSender::new(
+            &self.config(),
+            self.writer.clone(),
+            Arc::clone(&self.pending),
+            Arc::clone(&self.executor),
+        )
+    }
+
+    /// Queues update of `object`. Queued updates are sent together once the flush interval
+    /// passes, see [`ConfigBuilder::update_flush_interval`]. If the same object, identified by
+    /// its class and primary key attributes, is queued again before that, only its latest state
+    /// is sent. Failed updates are logged. In a dry run context, nothing is queued.
+    ///
+    /// [`ConfigBuilder::update_flush_interval`]: crate::medusa::ConfigBuilder::update_flush_interval
+    pub fn queue_update(&self, object: &MedusaClass) {
+        if self.dry_run {
+            return;
+        }
+
+        let data = object.pack_attributes();
+        let key = SubjectId::from_raw(object, &data);
+        self.update_queue.push(key, data);
+    }
+
+    /// Forgets everything remembered about `object`: its last update kept for deduplication,
+    /// its queued update which has not been sent yet and its positions in trees, see
+    /// [`ConfigBuilder::separate_tree_positions`]. Meant for deleted objects, whose
+    /// identification, e.g. an inode number, may be reused by an unrelated object, see
+    /// [`ConfigBuilder::add_invalidation_event_handler`].
+    ///
+    /// [`ConfigBuilder::add_invalidation_event_handler`]: crate::medusa::ConfigBuilder::add_invalidation_event_handler
+    /// [`ConfigBuilder::separate_tree_positions`]: crate::medusa::ConfigBuilder::separate_tree_positions
+    pub fn forget_object(&self, object: &MedusaClass) {
+        let key = object.subject_id();
+        self.recent_updates.forget(&key);
+        self.update_queue.discard(&key);
+        if let Some(positions) = &self.positions {
+            positions.forget(&key);
+        }
+    }
+
+    /// Forgets everything remembered about objects of class `class_id` in its previous layout,
+    /// see [`ConfigBuilder::on_layout_change`]: their last updates kept for deduplication and
+    /// their queued updates, which would be misread by the security module.
+    ///
+    /// [`ConfigBuilder::on_layout_change`]: crate::medusa::ConfigBuilder::on_layout_change
+    pub(crate) fn forget_class(&self, class_id: u64) {
+        self.recent_updates.forget_class(class_id);
+        let discarded = self.update_queue.discard_class(class_id);
+        if discarded > 0 {
+            eprintln!(
+                "{} queued update(s) of class 0x{:x} dropped",
+                discarded, class_id
+            );
+        }
+    }
+
+    /// Reclassifies `object` whose labels are stale, e.g. after a domain transition or a config
+    /// replacement. Its node and virtual spaces are cleared and it is entered into the node of
+    /// `path` in `primary_tree` again, like by [`MedusaClass::enter_tree`]. If `path` is not
+    /// covered by the tree, `object` is left without any virtual spaces.
+    ///
+    /// Returns `UpdateError` if the security module did not update `object`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `path` is not absolute or if `primary_tree` does not exist.
+    pub async fn reclassify(
+        &self,
+        object: &mut MedusaClass,
+        evtype: &MedusaEvtype,
+        primary_tree: &str,
+        path: &str,
+    ) -> Result<(), UpdateError> {
+        assert!(path.starts_with('/'));
+
+        let config = self.config();
+        let tree = config
+            .tree_by_name(primary_tree)
+            .unwrap_or_else(|| panic!("primary tree `{}` not found", primary_tree));
+
+        object.clear_node();
+        match tree.resolve(path) {
+            Some((node, recursed)) => {
+                println!(
+                    "{}: \"{}\" reclassified -> \"{}\"{}",
+                    evtype.header.name,
+                    path,
+                    node.path(),
+                    if recursed { " (recursion)" } else { "" }
+                );
+                object.set_node(self, evtype, node, recursed);
+            }
+            None => println!(
+                "{}: \"{}\" not covered by tree {}, labels cleared",
+                evtype.header.name, path, primary_tree
+            ),
+        }
+
+        object.update(self).await
+    }
+
+    // Returns the object updated by `data` if updates are deduplicated.
+    fn update_key(&self, class_id: u64, data: &[u8]) -> Option<SubjectId> {
+        if !self.recent_updates.is_enabled() {
+            return None;
+        }
+
+        let class = self.registry.classes.get(&class_id)?;
+        Some(SubjectId::from_raw(&class, data))
+    }
+
+    fn is_duplicate_update(&self, key: Option<&SubjectId>, data: &[u8]) -> bool {
+        match key {
+            Some(key) => self.recent_updates.is_duplicate(key, data),
+            None => false,
+        }
+    }
+
+    /// Performs `fetch` request. A request abandoned before its answer arrived is answered as
+    /// [`FetchStatus::TimedOut`], see [`ConfigBuilder::pending_request_max_age`].
+    ///
+    /// [`FetchStatus::TimedOut`]: crate::medusa::FetchStatus::TimedOut
+    /// [`ConfigBuilder::pending_request_max_age`]: crate::medusa::ConfigBuilder::pending_request_max_age
     pub async fn fetch_request(&self, class_id: u64, data: &[u8]) -> FetchAnswer {
+        self.send_fetch(class_id, data).answer().await
+    }
+
+    /// Performs `fetch` requests of `requests`, pairs of a class and the data identifying the
+    /// object, without waiting for each answer before sending the next request. At most `limit`
+    /// requests are awaiting their answers at a time, so that a handler inspecting many objects
+    /// does not flood the security module.
+    ///
+    /// Returns the answers in the order of `requests`.
+    pub async fn fetch_many<'a, I>(&self, requests: I, limit: usize) -> Vec<FetchAnswer>
+    where
+        I: IntoIterator<Item = (u64, &'a [u8])>,
+    {
+        let limit = limit.max(1);
+        let mut answers = Vec::new();
+        let mut in_flight: VecDeque<SentFetch> = VecDeque::with_capacity(limit);
+
+        for (class_id, data) in requests {
+            if in_flight.len() == limit {
+                if let Some(sent) = in_flight.pop_front() {
+                    answers.push(sent.answer().await);
+                }
+            }
+            in_flight.push_back(self.send_fetch(class_id, data));
+        }
+        for sent in in_flight {
+            answers.push(sent.answer().await);
+        }
+
+        answers
+    }
+
+    /// Same as [`Context::fetch_many`], but blocks the calling thread until the answers arrive,
+    /// see [`EventHandlerBuilder::with_blocking_handler`].
+    ///
+    /// [`EventHandlerBuilder::with_blocking_handler`]: crate::medusa::EventHandlerBuilder::with_blocking_handler
+    pub fn fetch_many_blocking<'a, I>(&self, requests: I, limit: usize) -> Vec<FetchAnswer>
+    where
+        I: IntoIterator<Item = (u64, &'a [u8])>,
+    {
+        executor::block_on(self.fetch_many(requests, limit))
+    }
+
+    /// Sends `fetch` request and returns it to wait for its answer.
+    pub(crate) fn send_fetch(&self, class_id: u64, data: &[u8]) -> SentFetch {
+        let (id, receiver) = self.pending.register_fetch();
         let req = MedusaRequest {
             req_type: RequestType::Fetch,
             class_id,
-            id: self.get_new_request_id(),
+            id,
             data,
         };
 
-        let (sender, mut receiver) = mpsc::unbounded_channel();
-        self.fetch_requests.insert(req.id, sender);
-
         self.writer.write(Arc::from(req.to_vec()));
 
-        receiver.recv().await.expect("channel is disconnected")
+        SentFetch::new(class_id, id, receiver)
     }
 
-    fn get_new_request_id(&self) -> u64 {
-        self.request_id_cn.fetch_add(1, Ordering::SeqCst)
+    /// Same as [`Context::fetch_request`], but blocks the calling thread until the answer
+    /// arrives, see [`EventHandlerBuilder::with_blocking_handler`].
+    ///
+    /// [`EventHandlerBuilder::with_blocking_handler`]: crate::medusa::EventHandlerBuilder::with_blocking_handler
+    pub fn fetch_request_blocking(&self, class_id: u64, data: &[u8]) -> FetchAnswer {
+        executor::block_on(self.fetch_request(class_id, data))
     }
 }
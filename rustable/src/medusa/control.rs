@@ -0,0 +1,397 @@
+//! Control socket for administration of a running server, see [`ConfigBuilder::control_socket`].
+//!
+//! Every command is a single line of text. The answer starts with `ok` or `error: <reason>`,
+//! may continue with further lines and is always terminated by an empty line, so the socket can
+//! be used both interactively (e.g. `socat - UNIX-CONNECT:<path>`) and from scripts.
+//!
+//! [`ConfigBuilder::control_socket`]: crate::medusa::ConfigBuilder::control_socket
+
+use crate::medusa::{
+    enforcement, AccessType, AnswerOverride, Config, Context, MedusaAnswer, PolicyLoader, Stage,
+};
+use std::fs;
+use std::io;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::sync::{Arc, Mutex, PoisonError, Weak};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::task::JoinHandle;
+
+#[cfg(feature = "plugins")]
+use crate::medusa::{PluginHandler, SharedLibrary};
+
+#[cfg(feature = "scripting")]
+use crate::medusa::ScriptHandler;
+
+#[cfg(feature = "wasm")]
+use crate::medusa::WasmModule;
+
+const HELP: &str = "\
+help                       show this help
+handlers                   list handlers and whether their debug output is enabled
+debug <handler> on|off     toggle verbose output of a handler
+readiness                  show the enforcement mode and unmet readiness checks
+health [--ready|--json]    show the health of the connection, an error if it is lost or, with
+                           `--ready`, its answers are not enforced
+stats                      show timings of request handling stages, abandoned requests,
+                           anomalies, degradation by the latency objective, decisions by
+                           virtual space and operations granted to domains
+snapshot                   dump the state of the connection as JSON
+reload <path>              replace the running config by the policy file at <path> and show
+                           the changes, handlers added in code are not carried over, the
+                           file must be signed if `verify_policies_with` is configured
+reload --dry-run <path>    show how the policy file at <path> differs from the running config
+check <subject> <object> read|write|see|member
+                           show whether a subject in the comma-separated spaces <subject> is
+                           allowed the access to an object in the spaces <object>
+override <subject> <event> <object> allow|deny <duration> [reason]
+                           force the answer of matching requests for <duration>, e.g. `30m`,
+                           see `AnswerOverride`
+overrides                  list active overrides with their ids and remaining time
+override remove <id>       remove an active override
+plugins                    list registered plugins
+plugin unload <name>       unregister a plugin
+plugin load <path>         load a plugin from a shared library (`plugins` feature)
+script load <name> <path>  load plugin <name> from a Rhai script (`scripting` feature)
+wasm load <name> <path>    load plugin <name> from a WebAssembly module (`wasm` feature)";
+
+lazy_static! {
+    // held while a reloaded config is checked and published, see `reload`
+    static ref RELOADING: Mutex<()> = Default::default();
+}
+
+/// Binds the control socket at `path` and serves it until the returned task is aborted.
+pub(crate) fn spawn(path: &Path, ctx: &Arc<Context>) -> io::Result<JoinHandle<()>> {
+    // a socket left behind by a previous run would make bind fail
+    let _ = fs::remove_file(path);
+    let listener = UnixListener::bind(path)?;
+    fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+
+    let ctx = Arc::downgrade(ctx);
+    Ok(tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    tokio::spawn(serve_client(stream, Weak::clone(&ctx)));
+                }
+                Err(e) => eprintln!("control socket: {}", e),
+            }
+        }
+    }))
+}
+
+async fn serve_client(stream: UnixStream, ctx: Weak<Context>) {
+    let (read, mut write) = stream.into_split();
+    let mut lines = BufReader::new(read).lines();
+
+    while let Ok(Some(line)) = lines.next_line().await {
+        let ctx = match ctx.upgrade() {
+            Some(ctx) => ctx,
+            None => break,
+        };
+
+        let response = match execute(&ctx, line.trim()).await {
+            Ok(output) if output.is_empty() => "ok\n\n".to_owned(),
+            Ok(output) => format!("ok\n{}\n\n", output),
+            Err(reason) => format!("error: {}\n\n", reason),
+        };
+
+        if write.write_all(response.as_bytes()).await.is_err() {
+            break;
+        }
+    }
+}
+
+/// Executes a single command and returns its output.
+async fn execute(ctx: &Context, line: &str) -> Result<String, String> {
+    let args = line.split_whitespace().collect::<Vec<_>>();
+
+    match args[..] {
+        [] => Ok(String::new()),
+        ["help"] => Ok(HELP.to_owned()),
+        ["handlers"] => {
+            let mut handlers = ctx
+                .config()
+                .handlers()
+                .map(|x| {
+                    let debug = if ctx.is_handler_debugged(x.name()) {
+                        " (debug)"
+                    } else {
+                        ""
+                    };
+                    format!("{}{}", x.name(), debug)
+                })
+                .collect::<Vec<_>>();
+            handlers.sort();
+            handlers.dedup();
+
+            Ok(handlers.join("\n"))
+        }
+        ["debug", name, state @ ("on" | "off")] => {
+            if !ctx.set_handler_debug(name, state == "on") {
+                return Err(format!("no handler named `{}`", name));
+            }
+
+            println!("control: debug output of {} turned {}", name, state);
+            Ok(String::new())
+        }
+        ["readiness"] => {
+            if !ctx.config().enforce {
+                return Err("enforcement is not enabled".to_owned());
+            }
+
+            if ctx.is_enforcing() {
+                return Ok("enforcing".to_owned());
+            }
+
+            let mut output = vec!["permissive".to_owned()];
+            output.extend(enforcement::readiness_issues(ctx));
+            Ok(output.join("\n"))
+        }
+        ["health"] => {
+            let health = ctx.health();
+            if !health.is_live() {
+                return Err("not connected to the security module".to_owned());
+            }
+
+            Ok(health.to_string())
+        }
+        ["health", "--ready"] => {
+            let health = ctx.health();
+            if !health.is_live() {
+                return Err("not connected to the security module".to_owned());
+            }
+            if !health.is_ready() {
+                return Err(format!("answers are not enforced, mode is {}", health.mode));
+            }
+
+            Ok(health.to_string())
+        }
+        ["health", "--json"] => Ok(ctx.health().to_json()),
+        ["stats"] => {
+            let stats = ctx.pipeline_stats();
+            let mut output = Stage::ALL
+                .iter()
+                .map(|stage| format!("{:<14}{}", stage.name(), stats.timings(*stage)))
+                .collect::<Vec<_>>();
+            output.push(format!("abandoned     {}", stats.abandoned_requests()));
+            if let Some(anomalies) = &ctx.anomalies {
+                output.push(format!("anomalies     {}", anomalies.deviations()));
+            }
+            if ctx.config().latency_slo.is_some() {
+                output.push(format!("degraded      {}", ctx.slo.is_degraded()));
+            }
+            if let Some(usage) = &ctx.usage {
+                output.extend(usage.usages().iter().map(|x| format!("usage {}", x)));
+            }
+            output.extend(
+                stats
+                    .space_counts()
+                    .iter()
+                    .map(|(space, counts)| format!("space {} {}", space, counts)),
+            );
+
+            Ok(output.join("\n"))
+        }
+        ["snapshot"] => Ok(ctx.snapshot().to_json()),
+        ["reload", "--dry-run", path] => {
+            // handlers added in code are not part of the policy file
+            let previous = ctx.config();
+            let config = load_policy(&previous, path).await?;
+
+            Ok(previous.diff(&config).to_string())
+        }
+        ["reload", path] => reload(ctx, path).await,
+        ["check", subject, object, at] => {
+            let at = match at {
+                "read" => AccessType::Read,
+                "write" => AccessType::Write,
+                "see" => AccessType::See,
+                "member" => AccessType::Member,
+                _ => return Err(format!("invalid access type `{}`", at)),
+            };
+            let subject = subject.split(',').collect::<Vec<_>>();
+            let object = object.split(',').collect::<Vec<_>>();
+
+            match ctx.check(&subject, &object, at) {
+                Ok(true) => Ok("allowed".to_owned()),
+                Ok(false) => Ok("denied".to_owned()),
+                Err(e) => Err(e.to_string()),
+            }
+        }
+        ["override", "remove", id] => {
+            let id = id
+                .parse()
+                .map_err(|_| format!("invalid override id `{}`", id))?;
+            if !ctx.remove_override(id) {
+                return Err(format!("no active override {}", id));
+            }
+
+            Ok(String::new())
+        }
+        ["override", subject, event, object, answer, duration, ref reason @ ..] => {
+            let answer = match answer {
+                "allow" => MedusaAnswer::Allow,
+                "deny" => MedusaAnswer::Deny,
+                _ => return Err(format!("invalid answer `{}`", answer)),
+            };
+            let duration = parse_duration(duration)
+                .ok_or_else(|| format!("invalid duration `{}`", duration))?;
+
+            let mut value = AnswerOverride::new(subject, event, object, answer, duration);
+            if !reason.is_empty() {
+                value = value.reason(&reason.join(" "));
+            }
+
+            Ok(ctx.add_override(value).to_string())
+        }
+        ["overrides"] => {
+            let overrides = ctx
+                .overrides()
+                .into_iter()
+                .map(|(id, value, left)| {
+                    format!("{:<4}{:<8}{}", id, format!("{}s", left.as_secs()), value)
+                })
+                .collect::<Vec<_>>();
+
+            Ok(overrides.join("\n"))
+        }
+        ["plugins"] => Ok(ctx.config().plugins().names().join("\n")),
+        ["plugin", "unload", name] => {
+            if !ctx.config().plugins().remove(name) {
+                return Err(format!("no plugin named `{}`", name));
+            }
+
+            Ok(String::new())
+        }
+        #[cfg(feature = "plugins")]
+        ["plugin", "load", path] => {
+            let plugin = SharedLibrary::load(path).map_err(|e| e.to_string())?;
+            let name = plugin.name().to_owned();
+            ctx.config().plugins().insert(Arc::new(plugin));
+
+            Ok(name)
+        }
+        #[cfg(feature = "scripting")]
+        ["script", "load", name, path] => {
+            let plugin = ScriptHandler::load(name, path).map_err(|e| e.to_string())?;
+            ctx.config().plugins().insert(Arc::new(plugin));
+
+            Ok(String::new())
+        }
+        #[cfg(feature = "wasm")]
+        ["wasm", "load", name, path] => {
+            let plugin = WasmModule::load(name, path).map_err(|e| e.to_string())?;
+            ctx.config().plugins().insert(Arc::new(plugin));
+
+            Ok(String::new())
+        }
+        _ => Err(format!("unknown command `{}`, try `help`", line)),
+    }
+}
+
+/// Replaces the running config by the policy file at `path` and returns the changes.
+async fn reload(ctx: &Context, path: &str) -> Result<String, String> {
+    let previous = ctx.config();
+    let config = load_policy(&previous, path).await?;
+
+    // the config replaced must be the one the new config was prepared for
+    let _reloading = RELOADING.lock().unwrap_or_else(PoisonError::into_inner);
+    if ctx.config().generation != previous.generation {
+        return Err("the config was replaced while the policy was loaded, try again".to_owned());
+    }
+    let diff = previous.diff(&config);
+    ctx.replace_config(config);
+
+    Ok(diff.to_string())
+}
+
+/// Loads the policy file at `path` as the replacement of `previous`, see
+/// [`ConfigBuilder::replacing`]. The policy must be signed if `previous` requires it, see
+/// [`ConfigBuilder::verify_policies_with`]. Loading runs on a blocking thread, so that reading
+/// and compiling a large policy does not stall the runtime.
+///
+/// [`ConfigBuilder::replacing`]: crate::medusa::ConfigBuilder::replacing
+/// [`ConfigBuilder::verify_policies_with`]: crate::medusa::ConfigBuilder::verify_policies_with
+async fn load_policy(previous: &Arc<Config>, path: &str) -> Result<Config, String> {
+    let previous = Arc::clone(previous);
+    let path = path.to_owned();
+
+    tokio::task::spawn_blocking(move || {
+        let loader = PolicyLoader::new();
+        #[cfg(feature = "signing")]
+        let loader = match &previous.policy_verifying_key {
+            Some(key) => loader.verify_with(key).map_err(|e| e.to_string())?,
+            None => loader,
+        };
+
+        loader
+            .load(&path)
+            .map_err(|e| e.to_string())?
+            .replacing(&previous)
+            .build()
+            .map_err(|e| e.to_string())
+    })
+    .await
+    .map_err(|e| format!("loading the policy failed: {}", e))?
+}
+
+/// Parses a duration such as `90s`, `30m`, `2h` or `1d`.
+fn parse_duration(text: &str) -> Option<Duration> {
+    let split = text.find(|c: char| !c.is_ascii_digit())?;
+    let value = text[..split].parse::<u64>().ok()?;
+    let unit = match &text[split..] {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => return None,
+    };
+
+    Some(Duration::from_secs(value.checked_mul(unit)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_durations() {
+        assert_eq!(parse_duration("90s"), Some(Duration::from_secs(90)));
+        assert_eq!(parse_duration("30m"), Some(Duration::from_secs(30 * 60)));
+        assert_eq!(parse_duration("2h"), Some(Duration::from_secs(2 * 60 * 60)));
+        assert_eq!(
+            parse_duration("1d"),
+            Some(Duration::from_secs(24 * 60 * 60))
+        );
+        assert_eq!(parse_duration("0s"), Some(Duration::ZERO));
+
+        for text in ["90", "", "m", "1.5h", "10 m", "3w", "-1s", "1hh"] {
+            assert_eq!(parse_duration(text), None, "{}", text);
+        }
+        assert_eq!(parse_duration(&format!("{}d", u64::MAX / 1000)), None);
+        assert_eq!(parse_duration(&format!("{}0s", u64::MAX)), None);
+    }
+
+    #[test]
+    fn rejects_invalid_policies_on_every_load() {
+        let dir = std::env::temp_dir().join(format!("rustable-control-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("invalid.policy");
+        fs::write(&path, "space a fs/\n    include_space missing\n").unwrap();
+        let path = path.to_str().unwrap();
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        let previous = Arc::new(Config::builder().build().unwrap());
+        for _ in 0..2 {
+            let res = runtime.block_on(load_policy(&previous, path));
+            assert!(res.unwrap_err().contains("missing"));
+        }
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
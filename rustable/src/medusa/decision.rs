@@ -0,0 +1,310 @@
+//! Static relations between virtual spaces compiled into a lookup table, see
+//! [`ConfigBuilder::allow_relation`].
+//!
+//! [`ConfigBuilder::allow_relation`]: crate::medusa::ConfigBuilder::allow_relation
+
+use crate::bitmap;
+use crate::medusa::space::{Space, SpaceDef};
+use crate::medusa::{AuthRequestData, ConfigError, FastHashMap, MedusaAnswer};
+
+/// Relation answering `event` of subjects in the `subject` space with objects in the `object`
+/// space, `None` meaning any object, including a missing one.
+#[derive(Debug, Clone)]
+pub(crate) struct Relation {
+    pub(crate) answer: MedusaAnswer,
+    pub(crate) event: &'static str,
+    pub(crate) subject: Space,
+    pub(crate) object: Option<Space>,
+}
+
+// index of a bitmap within a row
+const DENY: usize = 0;
+const ALLOW: usize = 1;
+
+/// Relations compiled into object bitmaps indexed by event, subject space and answer.
+#[derive(Debug)]
+pub(crate) struct DecisionTable {
+    // rows of an event start with one row per subject space followed by the row of relations
+    // of any subject, every row holds a deny and an allow bitmap of object spaces; the last
+    // byte of a bitmap marks relations of any object
+    bits: Box<[u8]>,
+    // whether an event has any relation, indexed by event id
+    events: Box<[bool]>,
+    nspaces: usize,
+    nbytes: usize,
+}
+
+impl DecisionTable {
+    /// Compiles `relations` of events interned in `event_ids`. Every event of a relation must
+    /// have an id.
+    ///
+    /// Returns `UnknownSpaceError` if a relation refers to an unknown space.
+    pub(crate) fn build(
+        relations: &[Relation],
+        event_ids: &FastHashMap<String, usize>,
+        def: &SpaceDef,
+    ) -> Result<Self, ConfigError> {
+        let nspaces = def.bitmap_nbytes() * 8;
+        let nbytes = def.bitmap_nbytes();
+        let mut table = Self {
+            bits: vec![0; event_ids.len() * (nspaces + 1) * 2 * (nbytes + 1)].into_boxed_slice(),
+            events: vec![false; event_ids.len()].into_boxed_slice(),
+            nspaces,
+            nbytes,
+        };
+
+        let space_id = |name: &str| {
+            def.space_id(name)
+                .ok_or_else(|| ConfigError::UnknownSpaceError(name.to_owned()))
+        };
+
+        for relation in relations {
+            let event = event_ids[relation.event];
+            table.events[event] = true;
+
+            let kind = match relation.answer {
+                MedusaAnswer::Deny => DENY,
+                _ => ALLOW,
+            };
+            let row = match relation.subject {
+                Space::All => nspaces,
+                Space::ByName(name) => space_id(name)?,
+            };
+
+            let bitmap = table.bitmap_mut(event, row, kind);
+            match relation.object {
+                None | Some(Space::All) => bitmap[nbytes] = 1,
+                Some(Space::ByName(name)) => bitmap::set_bit(bitmap, space_id(name)?),
+            }
+        }
+
+        Ok(table)
+    }
+
+    /// Returns the answer of the relations of `event` matching `auth_data`, `None` if there is no
+    /// such relation or the request lacks virtual spaces. Denying relations take precedence.
+    pub(crate) fn lookup(&self, event: usize, auth_data: &AuthRequestData) -> Option<MedusaAnswer> {
+        if !self.events[event] {
+            return None;
+        }
+
+        let svs = auth_data.subject.get_vs().ok()?;
+        let ovs = match &auth_data.object {
+            Some(object) => Some(object.get_vs().ok()?),
+            None => None,
+        };
+
+        self.lookup_vs(event, svs, ovs)
+    }
+
+    /// Same as [`DecisionTable::lookup`] for `event` of a subject in virtual spaces `svs` and an
+    /// object in `ovs`.
+    pub(crate) fn lookup_vs(
+        &self,
+        event: usize,
+        svs: &[u8],
+        ovs: Option<&[u8]>,
+    ) -> Option<MedusaAnswer> {
+        if !self.events[event] {
+            return None;
+        }
+
+        let subject_rows =
+            (0..self.nspaces.min(svs.len() * 8)).filter(|&x| bitmap::test_bit(svs, x));
+        let mut allowed = false;
+        for row in subject_rows.chain([self.nspaces]) {
+            if self.matches(event, row, DENY, ovs) {
+                return Some(MedusaAnswer::Deny);
+            }
+            allowed = allowed || self.matches(event, row, ALLOW, ovs);
+        }
+
+        allowed.then_some(MedusaAnswer::Allow)
+    }
+
+    fn matches(&self, event: usize, row: usize, kind: usize, ovs: Option<&[u8]>) -> bool {
+        let bitmap = &self.bits[self.offset(event, row, kind)..][..self.nbytes + 1];
+        bitmap[self.nbytes] != 0
+            || ovs.is_some_and(|ovs| {
+                bitmap[..self.nbytes]
+                    .iter()
+                    .zip(ovs)
+                    .any(|(x, y)| x & y != 0)
+            })
+    }
+
+    fn bitmap_mut(&mut self, event: usize, row: usize, kind: usize) -> &mut [u8] {
+        let offset = self.offset(event, row, kind);
+        &mut self.bits[offset..][..self.nbytes + 1]
+    }
+
+    fn offset(&self, event: usize, row: usize, kind: usize) -> usize {
+        ((event * (self.nspaces + 1) + row) * 2 + kind) * (self.nbytes + 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // ten spaces, so that bitmaps span two bytes
+    const SPACES: [&str; 10] = [
+        "s0", "s1", "s2", "s3", "s4", "s5", "s6", "s7", "s8", "confined",
+    ];
+    const KILL: usize = 0;
+    const FORK: usize = 1;
+    const OPEN: usize = 2;
+
+    fn relation(
+        answer: MedusaAnswer,
+        event: &'static str,
+        subject: Space,
+        object: Option<Space>,
+    ) -> Relation {
+        Relation {
+            answer,
+            event,
+            subject,
+            object,
+        }
+    }
+
+    fn table(relations: &[Relation]) -> (DecisionTable, SpaceDef) {
+        let (table, def) = try_table(relations);
+        (table.unwrap(), def)
+    }
+
+    fn try_table(relations: &[Relation]) -> (Result<DecisionTable, ConfigError>, SpaceDef) {
+        let mut def = SpaceDef::new();
+        for name in SPACES {
+            def.define_space(name);
+        }
+        let event_ids = ["kill", "fork", "open"]
+            .into_iter()
+            .enumerate()
+            .map(|(id, name)| (name.to_owned(), id))
+            .collect();
+
+        (DecisionTable::build(relations, &event_ids, &def), def)
+    }
+
+    fn vs(def: &SpaceDef, names: &[&str]) -> Vec<u8> {
+        let mut vs = vec![0; def.bitmap_nbytes()];
+        for name in names {
+            bitmap::set_bit(&mut vs, def.space_id(name).unwrap());
+        }
+        vs
+    }
+
+    #[test]
+    fn looks_up_relations_between_spaces() {
+        let (table, def) = table(&[relation(
+            MedusaAnswer::Allow,
+            "kill",
+            Space::ByName("s1"),
+            Some(Space::ByName("confined")),
+        )]);
+        let lookup = |svs: &[&str], ovs: Option<&[&str]>| {
+            table.lookup_vs(KILL, &vs(&def, svs), ovs.map(|x| vs(&def, x)).as_deref())
+        };
+
+        assert_eq!(
+            lookup(&["s0", "s1"], Some(&["s8", "confined"])),
+            Some(MedusaAnswer::Allow)
+        );
+        assert_eq!(lookup(&["s0"], Some(&["confined"])), None);
+        assert_eq!(lookup(&["s1"], Some(&["s8"])), None);
+        // a relation with an object space never matches a request without an object
+        assert_eq!(lookup(&["s1"], None), None);
+    }
+
+    #[test]
+    fn prefers_denying_relations() {
+        let (table, def) = table(&[
+            relation(MedusaAnswer::Allow, "kill", Space::All, None),
+            relation(
+                MedusaAnswer::Deny,
+                "kill",
+                Space::ByName("confined"),
+                Some(Space::ByName("s0")),
+            ),
+        ]);
+
+        let s0 = vs(&def, &["s0"]);
+        let s2 = vs(&def, &["s2"]);
+        let confined = vs(&def, &["confined", "s2"]);
+        assert_eq!(
+            table.lookup_vs(KILL, &confined, Some(&s0)),
+            Some(MedusaAnswer::Deny)
+        );
+        assert_eq!(
+            table.lookup_vs(KILL, &confined, Some(&s2)),
+            Some(MedusaAnswer::Allow)
+        );
+        assert_eq!(
+            table.lookup_vs(KILL, &s2, Some(&s0)),
+            Some(MedusaAnswer::Allow)
+        );
+        assert_eq!(table.lookup_vs(KILL, &[], None), Some(MedusaAnswer::Allow));
+    }
+
+    #[test]
+    fn matches_any_object_and_missing_objects() {
+        let (table, def) = table(&[
+            relation(MedusaAnswer::Deny, "fork", Space::ByName("s3"), None),
+            relation(
+                MedusaAnswer::Allow,
+                "fork",
+                Space::ByName("s4"),
+                Some(Space::All),
+            ),
+        ]);
+
+        assert_eq!(
+            table.lookup_vs(FORK, &vs(&def, &["s3"]), None),
+            Some(MedusaAnswer::Deny)
+        );
+        assert_eq!(
+            table.lookup_vs(FORK, &vs(&def, &["s4"]), Some(&vs(&def, &[]))),
+            Some(MedusaAnswer::Allow)
+        );
+        assert_eq!(table.lookup_vs(KILL, &vs(&def, &["s3"]), None), None);
+        assert_eq!(table.lookup_vs(OPEN, &vs(&def, &["s3"]), None), None);
+    }
+
+    #[test]
+    fn ignores_bits_beyond_the_table() {
+        let (table, def) = table(&[relation(
+            MedusaAnswer::Allow,
+            "open",
+            Space::ByName("confined"),
+            Some(Space::ByName("s0")),
+        )]);
+
+        let mut svs = vs(&def, &["confined"]);
+        svs.extend([0xff; 6]);
+        assert_eq!(
+            table.lookup_vs(OPEN, &svs, Some(&[1])),
+            Some(MedusaAnswer::Allow)
+        );
+        assert_eq!(table.lookup_vs(OPEN, &[0xff], Some(&[1])), None);
+    }
+
+    #[test]
+    fn rejects_unknown_spaces() {
+        for relation in [
+            relation(MedusaAnswer::Allow, "kill", Space::ByName("missing"), None),
+            relation(
+                MedusaAnswer::Deny,
+                "kill",
+                Space::All,
+                Some(Space::ByName("missing")),
+            ),
+        ] {
+            assert!(matches!(
+                try_table(&[relation]).0,
+                Err(ConfigError::UnknownSpaceError(name)) if name == "missing"
+            ));
+        }
+    }
+}
@@ -0,0 +1,327 @@
+//! Differences between two configs, see [`Config::diff`].
+
+use crate::bitmap;
+use crate::medusa::{AccessType, Config, Node};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::sync::Arc;
+
+/// Access types compared by [`ConfigDiff`], named like the methods of [`SpaceBuilder`].
+///
+/// [`SpaceBuilder`]: crate::medusa::SpaceBuilder
+const ACCESS_TYPES: [(AccessType, &str); 4] = [
+    (AccessType::Member, "member"),
+    (AccessType::Read, "reads"),
+    (AccessType::Write, "writes"),
+    (AccessType::See, "sees"),
+];
+
+/// Virtual spaces of a node by access type, indexed like [`ACCESS_TYPES`].
+type NodeSpaces = [BTreeSet<String>; 4];
+
+/// Change of the virtual spaces a node has for one access type.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NodeChange {
+    /// Location of the node as `<tree>/<path>`.
+    pub node: String,
+
+    /// Changed access type.
+    pub access_type: AccessType,
+
+    /// Virtual spaces only the new config assigns.
+    pub added: Vec<String>,
+
+    /// Virtual spaces only the old config assigns.
+    pub removed: Vec<String>,
+}
+
+/// Structured report of the differences between two configs, e.g. for review before
+/// [`Context::replace_config`]. Spaces, nodes and handlers are compared by their names and
+/// locations. Relations, hooks and connection settings are not compared.
+///
+/// Its [`Display`](fmt::Display) implementation prints one change per line:
+///
+/// ```text
+/// + space sshd
+/// + node domains/usr/sbin/sshd
+/// ~ domains/usr/sbin/sshd member +sshd
+/// ~ domains/usr/sbin/sshd reads +all_files +sshd
+/// - handler rules_kill
+/// ```
+///
+/// [`Context::replace_config`]: crate::medusa::Context::replace_config
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ConfigDiff {
+    /// Virtual spaces only the new config defines.
+    pub added_spaces: Vec<String>,
+
+    /// Virtual spaces only the old config defines.
+    pub removed_spaces: Vec<String>,
+
+    /// Nodes only the new config has, as `<tree>/<path>`.
+    pub added_nodes: Vec<String>,
+
+    /// Nodes only the old config has, as `<tree>/<path>`.
+    pub removed_nodes: Vec<String>,
+
+    /// Changes of the virtual spaces nodes of both configs are members of.
+    pub changed_coverage: Vec<NodeChange>,
+
+    /// Changes of the virtual spaces nodes of both configs may read, write or see.
+    pub changed_grants: Vec<NodeChange>,
+
+    /// Names of handlers only the new config has.
+    pub added_handlers: Vec<String>,
+
+    /// Names of handlers only the old config has.
+    pub removed_handlers: Vec<String>,
+}
+
+impl ConfigDiff {
+    pub(crate) fn new(old: &Config, new: &Config) -> Self {
+        let mut diff = ConfigDiff::default();
+
+        let old_spaces = old.space_names().map(|(x, _)| x).collect::<BTreeSet<_>>();
+        let new_spaces = new.space_names().map(|(x, _)| x).collect::<BTreeSet<_>>();
+        (diff.added_spaces, diff.removed_spaces) = difference(&old_spaces, &new_spaces);
+
+        let old_nodes = node_spaces(old);
+        let new_nodes = node_spaces(new);
+        let old_locations = old_nodes.keys().collect::<BTreeSet<_>>();
+        let new_locations = new_nodes.keys().collect::<BTreeSet<_>>();
+        (diff.added_nodes, diff.removed_nodes) = difference(&old_locations, &new_locations);
+
+        for (location, old_spaces) in &old_nodes {
+            let new_spaces = match new_nodes.get(location) {
+                Some(spaces) => spaces,
+                None => continue,
+            };
+
+            for (i, (at, _)) in ACCESS_TYPES.iter().enumerate() {
+                let (added, removed) = difference(&old_spaces[i], &new_spaces[i]);
+                if added.is_empty() && removed.is_empty() {
+                    continue;
+                }
+
+                let change = NodeChange {
+                    node: location.clone(),
+                    access_type: *at,
+                    added,
+                    removed,
+                };
+                match at {
+                    AccessType::Member => diff.changed_coverage.push(change),
+                    _ => diff.changed_grants.push(change),
+                }
+            }
+        }
+
+        let old_handlers = old.handlers().map(|x| x.name()).collect::<BTreeSet<_>>();
+        let new_handlers = new.handlers().map(|x| x.name()).collect::<BTreeSet<_>>();
+        (diff.added_handlers, diff.removed_handlers) = difference(&old_handlers, &new_handlers);
+
+        diff
+    }
+
+    /// Returns `true` if the configs do not differ in anything compared.
+    pub fn is_empty(&self) -> bool {
+        *self == ConfigDiff::default()
+    }
+}
+
+impl fmt::Display for ConfigDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut lines = Vec::new();
+
+        lines.extend(self.added_spaces.iter().map(|x| format!("+ space {}", x)));
+        lines.extend(self.removed_spaces.iter().map(|x| format!("- space {}", x)));
+        lines.extend(self.added_nodes.iter().map(|x| format!("+ node {}", x)));
+        lines.extend(self.removed_nodes.iter().map(|x| format!("- node {}", x)));
+
+        for change in self.changed_coverage.iter().chain(&self.changed_grants) {
+            let name = ACCESS_TYPES
+                .iter()
+                .find(|(at, _)| *at == change.access_type)
+                .map_or("", |(_, name)| name);
+            let spaces = change
+                .added
+                .iter()
+                .map(|x| format!("+{}", x))
+                .chain(change.removed.iter().map(|x| format!("-{}", x)))
+                .collect::<Vec<_>>();
+            lines.push(format!("~ {} {} {}", change.node, name, spaces.join(" ")));
+        }
+
+        lines.extend(
+            self.added_handlers
+                .iter()
+                .map(|x| format!("+ handler {}", x)),
+        );
+        lines.extend(
+            self.removed_handlers
+                .iter()
+                .map(|x| format!("- handler {}", x)),
+        );
+
+        write!(f, "{}", lines.join("\n"))
+    }
+}
+
+/// Returns items only in `new` and items only in `old`.
+fn difference<T: ToString + Ord>(
+    old: &BTreeSet<T>,
+    new: &BTreeSet<T>,
+) -> (Vec<String>, Vec<String>) {
+    let added = new.difference(old).map(T::to_string).collect();
+    let removed = old.difference(new).map(T::to_string).collect();
+
+    (added, removed)
+}
+
+/// Returns names of the virtual spaces of every node of `config` by its location.
+fn node_spaces(config: &Config) -> BTreeMap<String, NodeSpaces> {
+    config
+        .nodes()
+        .map(|node| {
+            let vs = node.virtual_space();
+            let spaces = ACCESS_TYPES.map(|(at, _)| {
+                let bits = vs.to_at_bytes(at);
+                (0..bits.len() * 8)
+                    .filter(|&bit| bitmap::test_bit(&bits, bit))
+                    .filter_map(|bit| config.space_bit_to_name(&bit).cloned())
+                    .collect()
+            });
+
+            (location(config, node), spaces)
+        })
+        .collect()
+}
+
+/// Returns the location of `node` as `<tree>/<path>`.
+pub(crate) fn location(config: &Config, node: &Arc<Node>) -> String {
+    let cinfo = node.cinfo();
+    let (tree, paths) = match config.node_location(&cinfo) {
+        Some(location) => location,
+        None => return node.path().to_owned(),
+    };
+
+    // the root is `/`, regular expressions of the other nodes are anchored
+    let components = paths
+        .iter()
+        .skip(1)
+        .map(|x| x.strip_prefix('^').unwrap_or(x))
+        .map(|x| x.strip_suffix('$').unwrap_or(x))
+        .collect::<Vec<_>>();
+
+    format!("{}/{}", tree, components.join("/"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::medusa::{ConfigBuilder, Event, Rule, SpaceBuilder};
+
+    fn space(name: &'static str, path: &'static str) -> SpaceBuilder {
+        SpaceBuilder::new()
+            .with_name(name)
+            .with_path_recursive(path)
+    }
+
+    fn rules(config: ConfigBuilder, event: Event) -> ConfigBuilder {
+        let rule = Rule::parse("deny if subject.uid == 0").unwrap();
+        config.add_rule_event_handler(event, vec![rule])
+    }
+
+    #[test]
+    fn compares_spaces_and_nodes() {
+        let old = Config::builder()
+            .add_space(space("etc", "fs/etc"))
+            .add_space(space("tmp", "fs/tmp"))
+            .build()
+            .unwrap();
+        let new = Config::builder()
+            .add_space(space("etc", "fs/etc"))
+            .add_space(space("logs", "fs/var/log"))
+            .build()
+            .unwrap();
+
+        let diff = old.diff(&new);
+        assert_eq!(diff.added_spaces, ["logs"]);
+        assert_eq!(diff.removed_spaces, ["tmp"]);
+        assert_eq!(diff.added_nodes, ["fs/var", "fs/var/log"]);
+        assert_eq!(diff.removed_nodes, ["fs/tmp"]);
+        assert!(diff.changed_coverage.is_empty() && diff.changed_grants.is_empty());
+
+        let reverse = new.diff(&old);
+        assert_eq!(reverse.added_spaces, diff.removed_spaces);
+        assert_eq!(reverse.removed_nodes, diff.added_nodes);
+    }
+
+    #[test]
+    fn compares_grants_and_coverage() {
+        let old = Config::builder()
+            .add_space(space("etc", "fs/etc"))
+            .add_space(space("logs", "fs/var/log"))
+            .add_space(space("app", "fs/srv/app").reads(["etc"]))
+            .build()
+            .unwrap();
+        let new = Config::builder()
+            .add_space(space("etc", "fs/etc"))
+            .add_space(space("logs", "fs/var/log").include_path("fs/srv/app"))
+            .add_space(space("app", "fs/srv/app").writes(["logs"]))
+            .build()
+            .unwrap();
+
+        let diff = old.diff(&new);
+        assert!(diff.added_spaces.is_empty() && diff.removed_spaces.is_empty());
+        assert_eq!(
+            diff.changed_grants,
+            [
+                NodeChange {
+                    node: "fs/srv/app".into(),
+                    access_type: AccessType::Read,
+                    added: vec![],
+                    removed: vec!["etc".into()],
+                },
+                NodeChange {
+                    node: "fs/srv/app".into(),
+                    access_type: AccessType::Write,
+                    added: vec!["logs".into()],
+                    removed: vec![],
+                },
+            ]
+        );
+        assert!(diff.added_nodes.is_empty() && diff.removed_nodes.is_empty());
+        assert_eq!(
+            diff.changed_coverage,
+            [NodeChange {
+                node: "fs/srv/app".into(),
+                access_type: AccessType::Member,
+                added: vec!["logs".into()],
+                removed: vec![],
+            }]
+        );
+        assert_eq!(
+            diff.to_string(),
+            "~ fs/srv/app member +logs\n~ fs/srv/app reads -etc\n~ fs/srv/app writes +logs"
+        );
+    }
+
+    #[test]
+    fn compares_handlers() {
+        let spaces = || Config::builder().add_space(space("etc", "fs/etc"));
+        let old = rules(spaces(), Event::Kill).build().unwrap();
+        let new = rules(spaces(), Event::GetFile).build().unwrap();
+
+        let diff = old.diff(&new);
+        assert_eq!(diff.added_handlers, ["rules_getfile"]);
+        assert_eq!(diff.removed_handlers, ["rules_kill"]);
+        assert_eq!(
+            diff.to_string(),
+            "+ handler rules_getfile\n- handler rules_kill"
+        );
+
+        assert!(old.diff(&old).is_empty());
+        assert_eq!(old.diff(&old).to_string(), "");
+    }
+}
@@ -0,0 +1,227 @@
+//! Per-user domains created on the fly, see [`ConfigBuilder::user_domains`].
+//!
+//! [`ConfigBuilder::user_domains`]: crate::medusa::ConfigBuilder::user_domains
+
+use crate::medusa::tree::Node;
+use crate::medusa::{Context, HandlerArgs, MedusaAnswer, Tree};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, OnceLock};
+
+/// Default path of the node under which user domains are created.
+pub const USER_DOMAINS_DEFAULT_PATH: &str = "domains/user";
+
+/// Default number of users which get their own domain.
+pub const USER_DOMAINS_DEFAULT_CAPACITY: usize = 64;
+
+/// Default lowest uid which gets its own domain, lower ones belong to system users.
+pub const USER_DOMAINS_DEFAULT_MIN_UID: u32 = 1000;
+
+/// Builder of per-user domains.
+///
+/// Processes are entered into node `<path>/<uid>` by a `getprocess` handler. The node is
+/// created the first time its uid is seen and it has the access of the node at `path`, so the
+/// rights shared by all users are given to a space at `path`, e.g. `domains/user recursive`.
+/// In addition, each user domain is a member of its own virtual space named
+/// `user_domain_<n>`, which only processes of the same user can read, write and see.
+///
+/// Space bits are reserved for at most `capacity` users. Processes of further users, as well
+/// as those whose uid could not be read, are entered into the node at `path` itself.
+#[derive(Debug, Clone)]
+pub struct UserDomainsBuilder {
+    path: &'static str,
+    capacity: usize,
+    min_uid: u32,
+    uid_attribute: &'static str,
+}
+
+impl Default for UserDomainsBuilder {
+    fn default() -> Self {
+        Self {
+            path: USER_DOMAINS_DEFAULT_PATH,
+            capacity: USER_DOMAINS_DEFAULT_CAPACITY,
+            min_uid: USER_DOMAINS_DEFAULT_MIN_UID,
+            uid_attribute: "uid",
+        }
+    }
+}
+
+impl UserDomainsBuilder {
+    /// Creates new `UserDomainsBuilder`.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Sets path of the node under which user domains are created, including the tree name.
+    ///
+    /// Returns `Self`.
+    pub fn with_path(mut self, path: &'static str) -> Self {
+        self.path = path;
+        self
+    }
+
+    /// Sets the number of users which get their own domain.
+    ///
+    /// Returns `Self`.
+    pub fn with_capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity;
+        self
+    }
+
+    /// Sets the lowest uid which gets its own domain. Processes of lower uids are left to other
+    /// `getprocess` handlers.
+    ///
+    /// Returns `Self`.
+    pub fn with_min_uid(mut self, uid: u32) -> Self {
+        self.min_uid = uid;
+        self
+    }
+
+    /// Sets the process attribute holding the uid, `uid` by default.
+    ///
+    /// Returns `Self`.
+    pub fn with_uid_attribute(mut self, attribute: &'static str) -> Self {
+        self.uid_attribute = attribute;
+        self
+    }
+
+    pub(crate) fn path(&self) -> &'static str {
+        self.path
+    }
+
+    pub(crate) fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Builds user domains under the node at `path` of `tree`, `first_bit` being the bit of
+    /// virtual space `user_domain_0`.
+    ///
+    /// Returns `None` if the node does not exist.
+    pub(crate) fn build(self, tree: &Tree, path: &[&str], first_bit: usize) -> Option<UserDomains> {
+        let mut base = tree.root();
+        for part in path {
+            base = base.child_by_path(part)?;
+        }
+
+        Some(UserDomains {
+            path: self.path,
+            base: Arc::clone(base),
+            first_bit,
+            min_uid: self.min_uid,
+            uid_attribute: self.uid_attribute,
+            slots: (0..self.capacity).map(|_| OnceLock::new()).collect(),
+            uids: Default::default(),
+        })
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct UserDomains {
+    path: &'static str,
+    base: Arc<Node>,
+    first_bit: usize,
+    min_uid: u32,
+    uid_attribute: &'static str,
+
+    // nodes of user domains of this config by slot, the index is relative to `first_bit`
+    slots: Box<[OnceLock<Arc<Node>>]>,
+
+    // shared by the configs replacing one another, see `UserDomains::adopt`
+    uids: Arc<Mutex<UidSlots>>,
+}
+
+/// Slots of uids in the order they were seen, `None` if there was no free slot left.
+#[derive(Debug, Default)]
+struct UidSlots {
+    slots: HashMap<u32, Option<usize>>,
+    next: usize,
+}
+
+impl UserDomains {
+    /// Returns whether virtual space `bit` is reserved for a user domain.
+    pub(crate) fn reserves(&self, bit: usize) -> bool {
+        (self.first_bit..self.first_bit + self.slots.len()).contains(&bit)
+    }
+
+    /// Returns nodes of the user domains created so far.
+    pub(crate) fn nodes(&self) -> impl Iterator<Item = &Arc<Node>> {
+        self.slots.iter().filter_map(|x| x.get())
+    }
+
+    /// Takes over the slots given to uids by `previous`, which this replaces, so that the space
+    /// bit of a user domain is never given to another user while processes may still carry it.
+    pub(crate) fn adopt(&mut self, previous: &UserDomains) {
+        self.uids = Arc::clone(&previous.uids);
+    }
+
+    pub(crate) fn node_by_cinfo(&self, cinfo: &usize) -> Option<&Arc<Node>> {
+        self.nodes().find(|x| x.cinfo() == *cinfo)
+    }
+
+    /// Returns the domain of `uid`, creating it if this is the first time the uid is seen.
+    fn node(&self, uid: u32) -> &Arc<Node> {
+        let slot = {
+            let mut uids = self.uids.lock().unwrap();
+            match uids.slots.get(&uid).copied() {
+                Some(Some(slot)) => Some(slot),
+                _ if uids.next < self.slots.len() => {
+                    let slot = uids.next;
+                    uids.next += 1;
+                    uids.slots.insert(uid, Some(slot));
+                    println!("created user domain {}/{}", self.path, uid);
+
+                    Some(slot)
+                }
+                seen => {
+                    if seen.is_none() {
+                        eprintln!("no free user domain for uid {}, using {}", uid, self.path);
+                        uids.slots.insert(uid, None);
+                    }
+
+                    None
+                }
+            }
+        };
+
+        // a slot given by a previous config may be beyond the capacity of this one
+        match slot.and_then(|x| Some((x, self.slots.get(x)?))) {
+            Some((slot, node)) => node.get_or_init(|| {
+                Node::new_child(&self.base, &uid.to_string(), self.first_bit + slot)
+            }),
+            None => &self.base,
+        }
+    }
+}
+
+pub(crate) async fn user_domain_handler(
+    ctx: &Context,
+    args: HandlerArgs<'_>,
+) -> anyhow::Result<MedusaAnswer> {
+    let config = ctx.config();
+    let domains = config
+        .user_domains
+        .as_ref()
+        .expect("user domains are not configured");
+    let HandlerArgs {
+        evtype,
+        mut subject,
+        ..
+    } = args;
+
+    let node = match subject.get_attribute::<u32>(domains.uid_attribute) {
+        Ok(uid) if uid < domains.min_uid => return Ok(MedusaAnswer::Allow),
+        Ok(uid) => domains.node(uid),
+        Err(e) => {
+            eprintln!("{}", e);
+            &domains.base
+        }
+    };
+
+    if let Err(e) = subject
+        .enter_tree_with_node(ctx, &evtype, node, false)
+        .await
+    {
+        eprintln!("{}", e);
+    }
+
+    Ok(MedusaAnswer::Allow)
+}
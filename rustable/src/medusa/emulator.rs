@@ -0,0 +1,796 @@
+//! Emulation of the security module driven by a YAML script, so that [`Connection::run`] can be
+//! tested end to end on machines without the Medusa LSM.
+//!
+//! Available with the `testing` feature. The `medusa-emulator` binary runs a script against an
+//! authorization server connecting to a Unix socket instead of `/dev/medusa`.
+//!
+//! ```text
+//! classes:
+//!   - name: process
+//!     id: 0x1000
+//!     size: 72
+//!     attributes:
+//!       - { name: pid, offset: 0, length: 4, type: signed, primary_key: true }
+//!       - { name: vs, offset: 8, length: 8, type: bitmap }
+//!       - { name: vsr, offset: 16, length: 8, type: bitmap }
+//!       - { name: vsw, offset: 24, length: 8, type: bitmap }
+//!       - { name: vss, offset: 32, length: 8, type: bitmap }
+//!       - { name: med_oact, offset: 40, length: 8, type: bitmap }
+//!       - { name: med_sact, offset: 48, length: 8, type: bitmap }
+//!       - { name: o_cinfo, offset: 56, length: 8, type: unsigned }
+//!       - { name: cmdline, offset: 64, length: 8, type: string, read_only: true }
+//! evtypes:
+//!   - name: getprocess
+//!     id: 0x2000
+//!     subject: process
+//! entities:
+//!   init: { class: process, attributes: { pid: 1, cmdline: /sbin/init } }
+//! events:
+//!   - { event: getprocess, subject: init, expect: allow }
+//! ```
+//!
+//! Classes and events are registered in the order of the script, then the authorization
+//! requests of `events` are sent one by one. Each request waits for its answer, while update
+//! and fetch requests of the authorization server are answered. Updated attributes, except
+//! read-only ones, are stored into the entity with the same class and primary key attributes,
+//! so later requests of that entity carry them.
+//!
+//! [`Connection::run`]: crate::medusa::Connection::run
+
+use crate::medusa::constants::*;
+use crate::medusa::{EmulatorError, MedusaAnswer};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{Read, Write};
+use std::path::Path;
+
+/// Script of the emulated security module, see [`emulator`](crate::medusa::emulator).
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Script {
+    #[serde(default)]
+    classes: Vec<ClassDef>,
+    #[serde(default)]
+    evtypes: Vec<EvtypeDef>,
+    #[serde(default)]
+    entities: BTreeMap<String, EntityDef>,
+    #[serde(default)]
+    events: Vec<EventDef>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ClassDef {
+    name: String,
+    id: u64,
+    size: i16,
+    #[serde(default)]
+    attributes: Vec<AttributeDef>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct EvtypeDef {
+    name: String,
+    id: u64,
+    #[serde(default)]
+    size: u16,
+    #[serde(default)]
+    monitoring_bit: u16,
+    // whether the event is monitored at the object
+    #[serde(default)]
+    at_object: bool,
+    subject: String,
+    object: Option<String>,
+    #[serde(default)]
+    attributes: Vec<AttributeDef>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct AttributeDef {
+    name: String,
+    offset: i16,
+    length: i16,
+    #[serde(rename = "type")]
+    data_type: DataType,
+    #[serde(default)]
+    primary_key: bool,
+    #[serde(default)]
+    read_only: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum DataType {
+    Unsigned,
+    Signed,
+    String,
+    Bitmap,
+    Bytes,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct EntityDef {
+    class: String,
+    #[serde(default)]
+    attributes: BTreeMap<String, Value>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct EventDef {
+    event: String,
+    subject: String,
+    object: Option<String>,
+    #[serde(default)]
+    attributes: BTreeMap<String, Value>,
+    expect: Option<ExpectedAnswer>,
+}
+
+/// Value of an attribute: a number, a string or a list of set bits of a bitmap.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+enum Value {
+    Unsigned(u64),
+    Signed(i64),
+    String(String),
+    Bits(Vec<usize>),
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum ExpectedAnswer {
+    Yes,
+    Deny,
+    Skip,
+    Allow,
+    Err,
+}
+
+impl From<ExpectedAnswer> for MedusaAnswer {
+    fn from(answer: ExpectedAnswer) -> Self {
+        match answer {
+            ExpectedAnswer::Yes => MedusaAnswer::Yes,
+            ExpectedAnswer::Deny => MedusaAnswer::Deny,
+            ExpectedAnswer::Skip => MedusaAnswer::Skip,
+            ExpectedAnswer::Allow => MedusaAnswer::Allow,
+            ExpectedAnswer::Err => MedusaAnswer::Err,
+        }
+    }
+}
+
+impl Script {
+    /// Parses a script from YAML `text`.
+    pub fn from_yaml(text: &str) -> Result<Self, EmulatorError> {
+        Ok(serde_yaml::from_str(text)?)
+    }
+
+    /// Reads and parses the script at `path`.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, EmulatorError> {
+        Self::from_yaml(&fs::read_to_string(path)?)
+    }
+}
+
+/// Answer of a single authorization request of the script.
+#[derive(Debug, Clone)]
+pub struct Outcome {
+    /// Identification of the request, its position in the script starting with 1.
+    pub request_id: u64,
+
+    /// Name of the event.
+    pub event: String,
+
+    /// Answer of the authorization server.
+    pub answer: MedusaAnswer,
+
+    /// Answer expected by the script, if any.
+    pub expected: Option<MedusaAnswer>,
+}
+
+impl Outcome {
+    /// Returns `true` if the answer is the expected one or nothing was expected.
+    pub fn is_expected(&self) -> bool {
+        self.expected.is_none_or(|x| x == self.answer)
+    }
+}
+
+/// Result of a run of the script.
+#[derive(Debug, Clone, Default)]
+pub struct Report {
+    /// Answers of the authorization requests in the order of the script.
+    pub outcomes: Vec<Outcome>,
+
+    /// Number of update requests received.
+    pub updates: usize,
+
+    /// Number of fetch requests received.
+    pub fetches: usize,
+}
+
+impl Report {
+    /// Returns `true` if every request got its expected answer.
+    pub fn is_success(&self) -> bool {
+        self.outcomes.iter().all(Outcome::is_expected)
+    }
+}
+
+/// Emulated security module playing a [`Script`].
+#[derive(Debug)]
+pub struct Emulator {
+    script: Script,
+    // current attribute data of entities
+    entities: BTreeMap<String, Vec<u8>>,
+}
+
+impl Emulator {
+    /// Creates new `Emulator` playing `script`.
+    ///
+    /// Returns `Emulator` or `EmulatorError` if the script refers to undefined classes,
+    /// events, entities or attributes, or if a value does not fit its attribute.
+    pub fn new(script: Script) -> Result<Self, EmulatorError> {
+        let mut entities = BTreeMap::new();
+        for (name, entity) in &script.entities {
+            let class = class_by_name(&script, &entity.class)?;
+            let data = encode(
+                &class.name,
+                class.size.max(0) as usize,
+                &class.attributes,
+                &entity.attributes,
+            )?;
+            entities.insert(name.clone(), data);
+        }
+
+        for event in &script.events {
+            let evtype = evtype_by_name(&script, &event.event)?;
+            for (name, expected) in [
+                (Some(&event.subject), Some(&evtype.subject)),
+                (event.object.as_ref(), evtype.object.as_ref()),
+            ] {
+                let (name, expected) = match (name, expected) {
+                    (Some(name), Some(expected)) => (name, expected),
+                    (None, None) => continue,
+                    _ => return Err(EmulatorError::ObjectMismatchError(event.event.clone())),
+                };
+                let entity = script
+                    .entities
+                    .get(name)
+                    .ok_or_else(|| EmulatorError::UnknownEntityError(name.clone()))?;
+                if entity.class != *expected {
+                    return Err(EmulatorError::ClassMismatchError(
+                        name.clone(),
+                        expected.clone(),
+                    ));
+                }
+            }
+            class_by_name(&script, &evtype.subject)?;
+            encode(
+                &evtype.name,
+                evtype.size as usize,
+                &evtype.attributes,
+                &event.attributes,
+            )?;
+        }
+
+        Ok(Self { script, entities })
+    }
+
+    /// Plays the script over `stream` connected to the authorization server, which must read
+    /// from and write to the other end of it.
+    ///
+    /// Returns the answers of the requests, or `EmulatorError` if the stream fails or the
+    /// authorization server sends something unexpected. To avoid waiting forever for a missing
+    /// answer, set a read timeout on the stream.
+    pub fn run<S: Read + Write>(&mut self, stream: &mut S) -> Result<Report, EmulatorError> {
+        let mut buf = Vec::new();
+        buf.extend(GREETING_NATIVE_BYTE_ORDER.to_ne_bytes());
+        buf.extend(PROTOCOL_VERSION.to_ne_bytes());
+
+        for class in &self.script.classes {
+            command(&mut buf, MEDUSA_COMM_KCLASSDEF);
+            buf.extend(class.id.to_le_bytes());
+            buf.extend(class.size.to_le_bytes());
+            buf.extend(padded(&class.name, MEDUSA_COMM_KCLASSNAME_MAX));
+            attribute_headers(&mut buf, &class.attributes);
+        }
+
+        for evtype in &self.script.evtypes {
+            let subject = class_by_name(&self.script, &evtype.subject)?.id;
+            // an event without an object has the subject as its object under the same name
+            let (object, names) = match &evtype.object {
+                Some(object) => (
+                    class_by_name(&self.script, object)?.id,
+                    ["subject", "object"],
+                ),
+                None => (subject, ["subject", "subject"]),
+            };
+            let mut actbit = evtype.monitoring_bit & !ACTBIT_FLAGS_MASK;
+            if evtype.at_object {
+                actbit |= MEDUSA_ACCTYPE_TRIGGEREDATOBJECT;
+            }
+
+            command(&mut buf, MEDUSA_COMM_EVTYPEDEF);
+            buf.extend(evtype.id.to_le_bytes());
+            buf.extend(evtype.size.to_le_bytes());
+            buf.extend(actbit.to_le_bytes());
+            buf.extend(subject.to_le_bytes());
+            buf.extend(object.to_le_bytes());
+            buf.extend(padded(&evtype.name, MEDUSA_COMM_EVNAME_MAX));
+            buf.extend(padded(names[0], MEDUSA_COMM_ATTRNAME_MAX));
+            buf.extend(padded(names[1], MEDUSA_COMM_ATTRNAME_MAX));
+            attribute_headers(&mut buf, &evtype.attributes);
+        }
+
+        stream.write_all(&buf)?;
+        stream.flush()?;
+
+        let mut report = Report::default();
+        // entities are updated while the events are played
+        let events = self.script.events.clone();
+        for (i, event) in events.iter().enumerate() {
+            let request_id = i as u64 + 1;
+            let evtype = evtype_by_name(&self.script, &event.event)?;
+
+            let mut buf = Vec::new();
+            buf.extend(evtype.id.to_le_bytes());
+            buf.extend(request_id.to_le_bytes());
+            buf.extend(encode(
+                &evtype.name,
+                evtype.size as usize,
+                &evtype.attributes,
+                &event.attributes,
+            )?);
+            buf.extend(&self.entities[&event.subject]);
+            if let Some(object) = &event.object {
+                buf.extend(&self.entities[object]);
+            }
+            stream.write_all(&buf)?;
+            stream.flush()?;
+
+            let answer = self.wait_for_answer(stream, request_id, &mut report)?;
+            report.outcomes.push(Outcome {
+                request_id,
+                event: event.event.clone(),
+                answer,
+                expected: event.expect.map(MedusaAnswer::from),
+            });
+        }
+
+        Ok(report)
+    }
+
+    /// Answers update and fetch requests until the answer to `request_id` arrives.
+    fn wait_for_answer<S: Read + Write>(
+        &mut self,
+        stream: &mut S,
+        request_id: u64,
+        report: &mut Report,
+    ) -> Result<MedusaAnswer, EmulatorError> {
+        loop {
+            match read_u64(stream)? {
+                MEDUSA_COMM_AUTHANSWER => {
+                    let id = read_u64(stream)?;
+                    let mut status = [0; 2];
+                    stream.read_exact(&mut status)?;
+                    let status = u16::from_le_bytes(status);
+
+                    if id != request_id {
+                        return Err(EmulatorError::UnexpectedAnswerError(id));
+                    }
+                    return answer_from_status(status);
+                }
+                kind @ (MEDUSA_COMM_UPDATE_REQUEST | MEDUSA_COMM_FETCH_REQUEST) => {
+                    let class_id = read_u64(stream)?;
+                    let msg_seq = read_u64(stream)?;
+                    let class = self
+                        .script
+                        .classes
+                        .iter()
+                        .find(|x| x.id == class_id)
+                        .ok_or(EmulatorError::UnknownClassIdError(class_id))?;
+                    let mut data = vec![0; class.size.max(0) as usize];
+                    stream.read_exact(&mut data)?;
+
+                    let mut buf = Vec::new();
+                    if kind == MEDUSA_COMM_UPDATE_REQUEST {
+                        report.updates += 1;
+                        self.apply_update(class_id, &data);
+                        command(&mut buf, MEDUSA_COMM_UPDATE_ANSWER);
+                        buf.extend(class_id.to_le_bytes());
+                        buf.extend(msg_seq.to_le_bytes());
+                        buf.extend(0i32.to_le_bytes());
+                    } else {
+                        report.fetches += 1;
+                        // unknown entities are answered with the requested data
+                        let data = self.entity_by_key(class_id, &data).unwrap_or(data);
+                        command(&mut buf, MEDUSA_COMM_FETCH_ANSWER);
+                        buf.extend(class_id.to_le_bytes());
+                        buf.extend(msg_seq.to_le_bytes());
+                        buf.extend(data);
+                    }
+                    stream.write_all(&buf)?;
+                    stream.flush()?;
+                }
+                kind => return Err(EmulatorError::UnexpectedMessageError(kind)),
+            }
+        }
+    }
+
+    /// Returns names of entities of class `class_id` with the primary key of `data`.
+    fn matching_entities(&self, class_id: u64, data: &[u8]) -> Vec<String> {
+        let class = match self.script.classes.iter().find(|x| x.id == class_id) {
+            Some(class) => class,
+            None => return Vec::new(),
+        };
+        let key = primary_key(&class.attributes, data);
+
+        self.script
+            .entities
+            .iter()
+            .filter(|(_, entity)| entity.class == class.name)
+            .map(|(name, _)| name)
+            .filter(|name| primary_key(&class.attributes, &self.entities[*name]) == key)
+            .cloned()
+            .collect()
+    }
+
+    fn entity_by_key(&self, class_id: u64, data: &[u8]) -> Option<Vec<u8>> {
+        match &self.matching_entities(class_id, data)[..] {
+            [name] => Some(self.entities[name].clone()),
+            _ => None,
+        }
+    }
+
+    /// Stores writable attributes of `data` into the single entity it identifies.
+    fn apply_update(&mut self, class_id: u64, data: &[u8]) {
+        let name = match &self.matching_entities(class_id, data)[..] {
+            [name] => name.clone(),
+            _ => return,
+        };
+        let class = self
+            .script
+            .classes
+            .iter()
+            .find(|x| x.id == class_id)
+            .expect("class of the entity is defined");
+        let entity = self.entities.get_mut(&name).expect("entity is defined");
+
+        for attr in class.attributes.iter().filter(|x| !x.read_only) {
+            if let Some(range) = attribute_range(attr, data.len()) {
+                entity[range.clone()].copy_from_slice(&data[range]);
+            }
+        }
+    }
+}
+
+fn class_by_name<'a>(script: &'a Script, name: &str) -> Result<&'a ClassDef, EmulatorError> {
+    script
+        .classes
+        .iter()
+        .find(|x| x.name == name)
+        .ok_or_else(|| EmulatorError::UnknownClassError(name.to_owned()))
+}
+
+fn evtype_by_name<'a>(script: &'a Script, name: &str) -> Result<&'a EvtypeDef, EmulatorError> {
+    script
+        .evtypes
+        .iter()
+        .find(|x| x.name == name)
+        .ok_or_else(|| EmulatorError::UnknownEventError(name.to_owned()))
+}
+
+fn answer_from_status(status: u16) -> Result<MedusaAnswer, EmulatorError> {
+    [
+        MedusaAnswer::Yes,
+        MedusaAnswer::Deny,
+        MedusaAnswer::Skip,
+        MedusaAnswer::Allow,
+        MedusaAnswer::Err,
+    ]
+    .into_iter()
+    .find(|x| *x as u16 == status)
+    .ok_or(EmulatorError::UnknownAnswerError(status))
+}
+
+fn read_u64<S: Read>(stream: &mut S) -> Result<u64, EmulatorError> {
+    let mut buf = [0; 8];
+    stream.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+fn command(buf: &mut Vec<u8>, cmd: u32) {
+    buf.extend(0u64.to_le_bytes());
+    buf.extend(cmd.to_le_bytes());
+}
+
+fn padded(name: &str, len: usize) -> Vec<u8> {
+    let mut bytes = name.as_bytes().to_vec();
+    bytes.resize(len, 0);
+    bytes
+}
+
+fn attribute_headers(buf: &mut Vec<u8>, attributes: &[AttributeDef]) {
+    for attr in attributes {
+        let data_type = match attr.data_type {
+            DataType::Unsigned => AttributeDataType::Unsigned,
+            DataType::Signed => AttributeDataType::Signed,
+            DataType::String => AttributeDataType::String,
+            DataType::Bitmap => AttributeDataType::Bitmap,
+            DataType::Bytes => AttributeDataType::Bytes,
+        };
+        let mut mods = AttributeMods::empty();
+        mods.set(AttributeMods::PRIMARY_KEY, attr.primary_key);
+        mods.set(AttributeMods::READ_ONLY, attr.read_only);
+
+        buf.extend(attr.offset.to_le_bytes());
+        buf.extend(attr.length.to_le_bytes());
+        buf.push(data_type as u8 | mods.bits());
+        buf.extend(padded(&attr.name, MEDUSA_COMM_ATTRNAME_MAX));
+    }
+
+    // terminated by an attribute of the end type
+    buf.extend([0; 5]);
+    buf.extend([0; MEDUSA_COMM_ATTRNAME_MAX]);
+}
+
+fn attribute_range(attr: &AttributeDef, size: usize) -> Option<std::ops::Range<usize>> {
+    let start = usize::try_from(attr.offset).ok()?;
+    let end = start + usize::try_from(attr.length).ok()?;
+    (end <= size).then_some(start..end)
+}
+
+fn primary_key(attributes: &[AttributeDef], data: &[u8]) -> Vec<u8> {
+    attributes
+        .iter()
+        .filter(|x| x.primary_key)
+        .filter_map(|x| attribute_range(x, data.len()))
+        .flat_map(|range| data[range].to_vec())
+        .collect()
+}
+
+/// Returns attribute data of `size` bytes with `values` set. `owner` names the class or event
+/// in errors.
+fn encode(
+    owner: &str,
+    size: usize,
+    attributes: &[AttributeDef],
+    values: &BTreeMap<String, Value>,
+) -> Result<Vec<u8>, EmulatorError> {
+    let mut data = vec![0; size];
+
+    for (name, value) in values {
+        let invalid = || EmulatorError::InvalidValueError(owner.to_owned(), name.clone());
+        let attr = attributes
+            .iter()
+            .find(|x| x.name == *name)
+            .ok_or_else(|| EmulatorError::UnknownAttributeError(owner.to_owned(), name.clone()))?;
+        let range = attribute_range(attr, size).ok_or_else(invalid)?;
+        let slot = &mut data[range];
+
+        match (attr.data_type, value) {
+            (DataType::Unsigned | DataType::Signed | DataType::Bitmap, Value::Unsigned(x)) => {
+                let bytes = x.to_le_bytes();
+                if bytes[slot.len().min(8)..].iter().any(|&b| b != 0) {
+                    return Err(invalid());
+                }
+                let len = slot.len().min(8);
+                slot[..len].copy_from_slice(&bytes[..len]);
+            }
+            (DataType::Signed, Value::Signed(x)) => {
+                let bytes = x.to_le_bytes();
+                let len = slot.len().min(8);
+                slot[..len].copy_from_slice(&bytes[..len]);
+                if len < 8 && i64::from_le_bytes(sign_extend(&bytes[..len])) != *x {
+                    return Err(invalid());
+                }
+            }
+            (DataType::String | DataType::Bytes, Value::String(x)) => {
+                let bytes = x.as_bytes();
+                if bytes.len() > slot.len() {
+                    return Err(invalid());
+                }
+                slot[..bytes.len()].copy_from_slice(bytes);
+            }
+            (DataType::Bitmap, Value::Bits(bits)) => {
+                for &bit in bits {
+                    if bit >= slot.len() * 8 {
+                        return Err(invalid());
+                    }
+                    crate::bitmap::set_bit(slot, bit);
+                }
+            }
+            _ => return Err(invalid()),
+        }
+    }
+
+    Ok(data)
+}
+
+fn sign_extend(bytes: &[u8]) -> [u8; 8] {
+    let negative = bytes.last().is_some_and(|&b| b & 0x80 != 0);
+    let mut res = if negative { [0xff; 8] } else { [0; 8] };
+    res[..bytes.len()].copy_from_slice(bytes);
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::medusa::{Connection, Event, Policy, Space};
+    use std::os::unix::net::UnixStream;
+    use std::time::Duration;
+
+    const CLASSES: &str = "
+classes:
+  - name: process
+    id: 0x1000
+    size: 72
+    attributes:
+      - { name: pid, offset: 0, length: 4, type: signed, primary_key: true }
+      - { name: vs, offset: 8, length: 8, type: bitmap }
+      - { name: vsr, offset: 16, length: 8, type: bitmap }
+      - { name: vsw, offset: 24, length: 8, type: bitmap }
+      - { name: vss, offset: 32, length: 8, type: bitmap }
+      - { name: med_oact, offset: 40, length: 8, type: bitmap }
+      - { name: med_sact, offset: 48, length: 8, type: bitmap }
+      - { name: o_cinfo, offset: 56, length: 8, type: unsigned }
+      - { name: cmdline, offset: 64, length: 8, type: string, read_only: true }
+evtypes:
+  - name: getprocess
+    id: 0x2000
+    subject: process
+  - name: kill
+    id: 0x2001
+    monitoring_bit: 1
+    subject: process
+    object: process
+entities:
+  init: { class: process, attributes: { pid: 1, cmdline: /sbin/in } }
+  app: { class: process, attributes: { pid: -2, cmdline: /app, vs: [0, 63] } }
+";
+
+    fn script(events: &str) -> Script {
+        Script::from_yaml(&format!("{}events:\n{}", CLASSES, events)).unwrap()
+    }
+
+    /// Plays `script` against a connection confining `/app`.
+    fn play(script: Script) -> Report {
+        let mut emulator = Emulator::new(script).unwrap();
+        let (mut module, server) = UnixStream::pair().unwrap();
+        module
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let emulator = std::thread::spawn(move || emulator.run(&mut module));
+
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        runtime.block_on(async {
+            let config = Policy::new()
+                .for_exe("/app")
+                .compile()
+                .unwrap()
+                .deny_relation(
+                    Event::Kill,
+                    Space::ByName("confined"),
+                    Some(Space::ByName("unconfined")),
+                )
+                .allow_relation(Event::Kill, Space::ByName("unconfined"), None)
+                .build()
+                .unwrap();
+            let mut connection = Connection::new(server.try_clone().unwrap(), server, config)
+                .await
+                .unwrap();
+            let _ = tokio::time::timeout(Duration::from_secs(5), connection.run()).await;
+        });
+
+        emulator.join().unwrap().unwrap()
+    }
+
+    #[test]
+    fn plays_decisions_of_a_connection() {
+        let report = play(script(
+            "
+  - { event: getprocess, subject: init, expect: allow }
+  - { event: getprocess, subject: app, expect: allow }
+  - { event: kill, subject: app, object: init, expect: deny }
+  - { event: kill, subject: init, object: app }
+",
+        ));
+
+        let answers = report
+            .outcomes
+            .iter()
+            .map(|x| (x.request_id, x.event.as_str(), x.answer))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            answers,
+            [
+                (1, "getprocess", MedusaAnswer::Allow),
+                (2, "getprocess", MedusaAnswer::Allow),
+                (3, "kill", MedusaAnswer::Deny),
+                (4, "kill", MedusaAnswer::Allow),
+            ]
+        );
+        // the labels of both processes were written back
+        assert_eq!(report.updates, 2);
+        assert!(report.is_success());
+    }
+
+    #[test]
+    fn reports_unexpected_answers() {
+        let report = play(script(
+            "
+  - { event: getprocess, subject: init }
+  - { event: getprocess, subject: app }
+  - { event: kill, subject: init, object: app, expect: deny }
+",
+        ));
+
+        assert!(report.outcomes[0].is_expected());
+        assert!(!report.outcomes[2].is_expected());
+        assert!(!report.is_success());
+    }
+
+    #[test]
+    fn times_out_without_answer() {
+        let mut emulator =
+            Emulator::new(script("  - { event: getprocess, subject: init }")).unwrap();
+        let (mut module, _server) = UnixStream::pair().unwrap();
+        module
+            .set_read_timeout(Some(Duration::from_millis(10)))
+            .unwrap();
+
+        assert!(matches!(
+            emulator.run(&mut module),
+            Err(EmulatorError::IOError(_))
+        ));
+    }
+
+    #[test]
+    fn encodes_entities() {
+        let emulator = Emulator::new(script("  []")).unwrap();
+        let app = &emulator.entities["app"];
+        assert_eq!(app[..4], (-2i32).to_le_bytes());
+        assert_eq!(app[8..16], (1u64 | 1 << 63).to_le_bytes());
+        assert_eq!(&app[64..72], b"/app\0\0\0\0");
+    }
+
+    #[test]
+    fn rejects_invalid_scripts() {
+        for (events, error) in [
+            ("  - { event: open, subject: init }", "unknown event `open`"),
+            (
+                "  - { event: getprocess, subject: nobody }",
+                "unknown entity `nobody`",
+            ),
+            (
+                "  - { event: kill, subject: init }",
+                "object of event `kill` does not match its definition",
+            ),
+            (
+                "  - { event: getprocess, subject: init, object: app }",
+                "object of event `getprocess` does not match its definition",
+            ),
+        ] {
+            assert_eq!(
+                Emulator::new(script(events)).unwrap_err().to_string(),
+                error
+            );
+        }
+
+        let script = Script::from_yaml(
+            "classes: [{name: c, id: 1, size: 4, attributes: [{name: a, offset: 0, length: 1, type: unsigned}]}]
+entities: {e: {class: c, attributes: {a: 300}}}",
+        )
+        .unwrap();
+        assert_eq!(
+            Emulator::new(script).unwrap_err().to_string(),
+            "value of attribute `a` of `c` does not fit the attribute"
+        );
+        assert!(Script::from_yaml("foo: 1").is_err());
+    }
+}
@@ -0,0 +1,149 @@
+//! Switching from permissive to enforcing mode, see [`ConfigBuilder::enforce`].
+//!
+//! [`ConfigBuilder::enforce`]: crate::medusa::ConfigBuilder::enforce
+
+use crate::bitmap;
+use crate::medusa::constants::DEFAULT_ANSWER;
+use crate::medusa::{AccessType, Config, Context, MedusaAnswer};
+use std::collections::BTreeSet;
+use std::sync::atomic::Ordering;
+
+/// Events whose handlers enter processes and files into trees. Without them, no entity would be
+/// a member of any virtual space and everything would be denied.
+pub(crate) const REQUIRED_HANDLERS: [&str; 2] = ["getprocess", "getfile"];
+
+/// Returns problems found in `config` which do not depend on the security module: missing
+/// handlers of [`REQUIRED_HANDLERS`], hierarchy handlers referring to unknown trees and virtual
+/// spaces without members, which are usually misspelled names. Spaces reserved for user domains
+/// are not reported.
+pub(crate) fn lint(config: &Config) -> Vec<String> {
+    let mut issues = Vec::new();
+
+    for event in REQUIRED_HANDLERS {
+        if !config.has_handler(event) {
+            issues.push(format!("no handler for `{}`", event));
+        }
+    }
+
+    for handler in config.handlers() {
+        let tree = &handler.data().primary_tree;
+        if !tree.is_empty() && config.tree_by_name(tree).is_none() {
+            issues.push(format!(
+                "handler `{}` refers to unknown tree `{}`",
+                handler.name(),
+                tree
+            ));
+        }
+    }
+
+    let mut members = Vec::new();
+    for node in config.nodes() {
+        let vs = node.virtual_space().to_at_bytes(AccessType::Member);
+        members.resize(members.len().max(vs.len()), 0);
+        bitmap::or(&mut members, &vs);
+    }
+    let mut empty_spaces = config
+        .space_names()
+        .filter(|&(_, bit)| bit >= members.len() * 8 || !bitmap::test_bit(&members, bit))
+        .filter(|&(_, bit)| {
+            !config
+                .user_domains
+                .as_ref()
+                .is_some_and(|x| x.reserves(bit))
+        })
+        .map(|(name, _)| name)
+        .collect::<Vec<_>>();
+    empty_spaces.sort();
+    for space in empty_spaces {
+        issues.push(format!("virtual space `{}` has no members", space));
+    }
+
+    issues
+}
+
+/// Returns security-critical events registered by the security module which are left
+/// unhandled: events of [`REQUIRED_HANDLERS`] without a handler and events added by
+/// [`ConfigBuilder::require_handler`] without handlers and relations.
+///
+/// [`ConfigBuilder::require_handler`]: crate::medusa::ConfigBuilder::require_handler
+pub(crate) fn unhandled_critical_events(ctx: &Context) -> Vec<String> {
+    let config = ctx.config();
+
+    let required = REQUIRED_HANDLERS
+        .into_iter()
+        .filter(|&event| !config.has_handler(event));
+    let critical = config
+        .critical_events
+        .iter()
+        .map(|x| x.as_str())
+        .filter(|&event| config.event_id(event).is_none());
+
+    required
+        .chain(critical)
+        .filter(|&event| ctx.evtype_id_from_name(event).is_some())
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .map(str::to_owned)
+        .collect()
+}
+
+/// Returns preconditions of enforcing mode which are not met yet.
+pub(crate) fn readiness_issues(ctx: &Context) -> Vec<String> {
+    let config = ctx.config();
+    let mut issues = lint(&config);
+
+    // missing handlers of `REQUIRED_HANDLERS` are reported by `lint`
+    for event in unhandled_critical_events(ctx) {
+        if !REQUIRED_HANDLERS.contains(&event.as_str()) {
+            issues.push(format!("event `{}` has no handler", event));
+        }
+    }
+
+    let expected = config
+        .handlers()
+        .map(|x| x.data().event.as_str())
+        .chain(config.expected_events.iter().map(|x| x.as_str()))
+        .chain(config.covered_events.iter().flatten().map(|x| x.as_str()))
+        .collect::<BTreeSet<_>>();
+    for event in expected {
+        if ctx.evtype_id_from_name(event).is_none() {
+            issues.push(format!("event `{}` is not registered", event));
+        }
+    }
+
+    issues
+}
+
+/// Switches `ctx` to enforcing mode once all readiness checks pass. Once enforcing, the mode is
+/// never left. An observer never enforces, see [`ConfigBuilder::observe`].
+///
+/// [`ConfigBuilder::observe`]: crate::medusa::ConfigBuilder::observe
+pub(crate) fn update(ctx: &Context) {
+    if !ctx.config().enforce || ctx.is_enforcing() || ctx.observing.is_some() {
+        return;
+    }
+
+    if readiness_issues(ctx).is_empty() && !ctx.enforcing.swap(true, Ordering::SeqCst) {
+        println!("readiness checks passed, switching to enforcing mode");
+    }
+}
+
+/// Applies the current mode to the answer of the handlers, `None` meaning that no handler was
+/// applicable.
+pub(crate) fn apply(ctx: &Context, request_id: u64, answer: Option<MedusaAnswer>) -> MedusaAnswer {
+    if !ctx.config().enforce {
+        return answer.unwrap_or(DEFAULT_ANSWER);
+    }
+
+    if ctx.is_enforcing() {
+        return answer.unwrap_or(MedusaAnswer::Deny);
+    }
+
+    match answer {
+        Some(MedusaAnswer::Deny) | None => {
+            println!("permissive mode: request {} would be denied", request_id);
+            MedusaAnswer::Allow
+        }
+        Some(answer) => answer,
+    }
+}
@@ -1,4 +1,5 @@
-use crate::medusa::Command;
+use crate::medusa::{AttributeMismatch, Command, KernelCapabilities, SubjectId};
+use std::path::PathBuf;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -6,6 +7,22 @@ use thiserror::Error;
 pub enum ConfigError {
     #[error(transparent)]
     InvalidRegexError(#[from] regex::Error),
+    #[error("path `{0}` is not absolute")]
+    InvalidPathError(String),
+    #[error("unknown event `{0}`")]
+    UnknownEventError(String),
+    #[error("policy confines no executable")]
+    NoExecutableError,
+    #[error("no handler named `{0}`")]
+    UnknownHandlerError(String),
+    #[error("no space named `{0}`")]
+    UnknownSpaceError(String),
+    #[error("no node at `{0}`")]
+    UnknownNodeError(String),
+    #[error("no node with cinfo 0x{0:x}, it may have been removed by a config replacement")]
+    UnknownCinfoError(usize),
+    #[error("cannot read paths to warm up from `{0}`: {1}")]
+    WarmUpFileError(PathBuf, #[source] std::io::Error),
 }
 
 #[derive(Error, Debug)]
@@ -17,6 +34,8 @@ pub enum ReaderError {
     ParseError(String),
     #[error("unknown class with id 0x{0:x}")]
     UnknownClassError(u64),
+    #[error("connection closed by the security module")]
+    Disconnected,
 }
 
 #[derive(Error, Debug)]
@@ -30,6 +49,8 @@ pub enum ConnectionError {
     UnknownByteOrder(u64),
     #[error("protocol version {0} is not supported")]
     UnsupportedVersionError(u64),
+    #[error("security module lacks required capabilities: {0:?}")]
+    MissingCapabilitiesError(KernelCapabilities),
 }
 
 #[derive(Error, Debug)]
@@ -47,6 +68,29 @@ pub enum CommunicationError {
     UnknownSubjectTypeError(u64),
     #[error("unknown object type: 0x{0:x}")]
     UnknownObjectTypeError(u64),
+    #[error("security module did not answer {0} pending request(s)")]
+    KernelGoneError(usize),
+    #[error(
+        "class `{0}` does not have the attributes the config expects: {}",
+        .1.iter().map(ToString::to_string).collect::<Vec<_>>().join("; ")
+    )]
+    AttributeMismatchError(String, Vec<AttributeMismatch>),
+    #[error("dispatch of authorization requests stopped")]
+    DispatchStoppedError,
+}
+
+impl CommunicationError {
+    /// Returns `true` if the error means that the security module is no longer reachable, as
+    /// opposed to an error in the received data. A supervisor should reconnect in this case.
+    pub fn is_kernel_gone(&self) -> bool {
+        matches!(
+            self,
+            Self::IOError(_)
+                | Self::ReaderError(ReaderError::IOError(_))
+                | Self::ReaderError(ReaderError::Disconnected)
+                | Self::KernelGoneError(_)
+        )
+    }
 }
 
 #[derive(Error, Debug)]
@@ -56,4 +100,142 @@ pub enum AttributeError {
     UnknownAttributeError(String),
     #[error("cannot modify read-only attribute: \"{0}\"")]
     ModifyReadOnlyError(String),
+    #[error("handle of attribute \"{0}\" belongs to a different class")]
+    StaleHandleError(String),
+}
+
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum UpdateError {
+    #[error("update of class 0x{0:x} failed in the security module")]
+    FailedError(u64),
+    #[error("update of class 0x{0:x} was not answered in time")]
+    TimeoutError(u64),
+    #[error("update of class 0x{0:x} answered with unknown status {1}")]
+    UnknownStatusError(u64, i32),
+}
+
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum AnswerError {
+    #[error("request {0} was already answered")]
+    AlreadyAnsweredError(u64),
+    #[error("request {0} was never received from the security module")]
+    UnknownRequestError(u64),
+}
+
+#[derive(Error, Debug)]
+#[non_exhaustive]
+pub enum ConsistencyError {
+    #[error("object {0} is not known to the security module")]
+    NotFoundError(SubjectId),
+    #[error("object {0} changed since it was fetched")]
+    ChangedError(SubjectId),
+    #[error(transparent)]
+    AttributeError(#[from] AttributeError),
+    #[error(transparent)]
+    UpdateError(#[from] UpdateError),
+}
+
+#[cfg(any(feature = "plugins", feature = "scripting", feature = "wasm"))]
+#[derive(Error, Debug)]
+#[non_exhaustive]
+pub enum PluginError {
+    #[cfg(feature = "plugins")]
+    #[error("{0}: {1}")]
+    LoadError(PathBuf, #[source] libloading::Error),
+    #[error("{0}: unsupported plugin interface version {1}")]
+    AbiVersionError(PathBuf, u32),
+    #[error("{0}: invalid plugin name")]
+    InvalidNameError(PathBuf),
+    #[cfg(feature = "scripting")]
+    #[error("{0}: {1}")]
+    ScriptError(PathBuf, #[source] Box<rhai::EvalAltResult>),
+    #[cfg(feature = "wasm")]
+    #[error("{0}: {1:#}")]
+    WasmError(PathBuf, #[source] anyhow::Error),
+}
+
+#[cfg(feature = "testing")]
+#[derive(Error, Debug)]
+#[non_exhaustive]
+pub enum EmulatorError {
+    #[error(transparent)]
+    IOError(#[from] std::io::Error),
+    #[error(transparent)]
+    YamlError(#[from] serde_yaml::Error),
+    #[error("unknown class `{0}`")]
+    UnknownClassError(String),
+    #[error("unknown class with id 0x{0:x}")]
+    UnknownClassIdError(u64),
+    #[error("unknown event `{0}`")]
+    UnknownEventError(String),
+    #[error("unknown entity `{0}`")]
+    UnknownEntityError(String),
+    #[error("unknown attribute `{1}` of `{0}`")]
+    UnknownAttributeError(String, String),
+    #[error("value of attribute `{1}` of `{0}` does not fit the attribute")]
+    InvalidValueError(String, String),
+    #[error("entity `{0}` is not of class `{1}`")]
+    ClassMismatchError(String, String),
+    #[error("object of event `{0}` does not match its definition")]
+    ObjectMismatchError(String),
+    #[error("answer to unexpected request {0}")]
+    UnexpectedAnswerError(u64),
+    #[error("unknown answer status {0}")]
+    UnknownAnswerError(u16),
+    #[error("unexpected message 0x{0:x}")]
+    UnexpectedMessageError(u64),
+}
+
+#[derive(Error, Debug)]
+#[non_exhaustive]
+pub enum AuditFormatError {
+    #[error(transparent)]
+    IOError(#[from] std::io::Error),
+    #[error("not a stream of compact audit records")]
+    InvalidHeaderError,
+    #[error("unsupported version {0} of compact audit records")]
+    UnsupportedVersionError(u16),
+    #[error("unknown frame 0x{0:02x}")]
+    UnknownFrameError(u8),
+    #[error("reference to undefined string {0}")]
+    UnknownStringError(u32),
+    #[error("string is not valid UTF-8")]
+    InvalidStringError,
+}
+
+#[derive(Error, Debug)]
+#[non_exhaustive]
+pub enum RuleError {
+    #[error("column {0}: {1}")]
+    SyntaxError(usize, String),
+    #[error(transparent)]
+    InvalidRegexError(#[from] regex::Error),
+}
+
+#[derive(Error, Debug)]
+#[non_exhaustive]
+pub enum TeError {
+    #[error("line {0}: {1}")]
+    SyntaxError(usize, String),
+}
+
+#[derive(Error, Debug)]
+#[non_exhaustive]
+pub enum PolicyError {
+    #[error("{0}: {1}")]
+    IOError(PathBuf, #[source] std::io::Error),
+    #[error("{path}:{line}: {message}")]
+    ParseError {
+        path: PathBuf,
+        line: usize,
+        message: String,
+    },
+    #[error("invalid public key")]
+    InvalidPublicKey,
+    #[error("policy {0} is not signed")]
+    UnsignedError(PathBuf),
+    #[error("policy {0} has an invalid signature")]
+    InvalidSignatureError(PathBuf),
 }
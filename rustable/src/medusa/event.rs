@@ -1,21 +1,19 @@
 use crate::medusa::constants::*;
-use crate::medusa::error::AttributeError;
+use crate::medusa::error::{AttributeError, ConfigError};
 use crate::medusa::MedusaAttributes;
+use std::fmt;
 use std::mem;
 use std::num::NonZeroU64;
+use std::str::FromStr;
+use std::sync::Arc;
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
 pub enum Monitoring {
+    #[default]
     Subject,
     Object,
 }
 
-impl Default for Monitoring {
-    fn default() -> Self {
-        Self::Subject
-    }
-}
-
 #[derive(Debug, Default, Clone)]
 pub struct MedusaEvtypeHeader {
     pub(crate) evid: u64,
@@ -29,8 +27,14 @@ pub struct MedusaEvtypeHeader {
     pub(crate) ev_sub: u64,
     pub(crate) ev_obj: Option<NonZeroU64>,
 
-    pub(crate) name: String,
-    pub(crate) ev_name: [String; 2],
+    // shared, so that copies made for each request do not allocate
+    pub(crate) name: Arc<str>,
+    pub(crate) ev_name: [Arc<str>; 2],
+
+    // interned name of the event, see `Config::event_id`, `None` if it has no handlers
+    pub(crate) event_id: Option<usize>,
+    // generation of the config which interned `event_id`, see `Config::event_id_of`
+    pub(crate) config_generation: u64,
 }
 
 impl MedusaEvtypeHeader {
@@ -38,6 +42,47 @@ impl MedusaEvtypeHeader {
         &self.name
     }
 
+    /// Returns identification of the event.
+    pub fn id(&self) -> u64 {
+        self.evid
+    }
+
+    /// Returns size of the attribute data of the event in bytes. Not to be confused with
+    /// [`MedusaEvtypeHeader::size`], the size of the header itself.
+    pub fn data_size(&self) -> usize {
+        self.size as usize
+    }
+
+    /// Returns whether the event is monitored at its subject or object.
+    pub fn monitoring(&self) -> Monitoring {
+        self.monitoring
+    }
+
+    /// Returns the bit of the event in the monitoring bitmaps of subjects and objects.
+    pub fn monitoring_bit(&self) -> u16 {
+        self.monitoring_bit
+    }
+
+    /// Returns identification of the class of the subject.
+    pub fn subject_class(&self) -> u64 {
+        self.ev_sub
+    }
+
+    /// Returns identification of the class of the object, `None` if the event has no object.
+    pub fn object_class(&self) -> Option<u64> {
+        self.ev_obj.map(NonZeroU64::get)
+    }
+
+    /// Returns name of the subject argument of the event.
+    pub fn subject_name(&self) -> &str {
+        &self.ev_name[0]
+    }
+
+    /// Returns name of the object argument of the event, `None` if the event has no object.
+    pub fn object_name(&self) -> Option<&str> {
+        self.ev_obj.map(|_| &*self.ev_name[1])
+    }
+
     pub const fn size() -> usize {
         mem::size_of::<u64>()
             + mem::size_of::<u16>()
@@ -49,6 +94,192 @@ impl MedusaEvtypeHeader {
     }
 }
 
+/// Event of the security module, such as [`Event::GetFile`]. Used in place of event names by
+/// the builders of handlers and relations, so that a misspelled event is a compile error.
+///
+/// Events this enum does not know are given as [`Event::Custom`] with their name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Event {
+    /// Entering a file into the trees, on its first access.
+    GetFile,
+    /// Entering a process into the trees, on its first action.
+    GetProcess,
+    /// Entering an IPC object into the trees, on its first access.
+    GetIpc,
+    /// Creation of a process.
+    Fork,
+    /// Execution of a program by a process.
+    Exec,
+    /// Execution of a program changing the credentials of a process.
+    Sexec,
+    /// Sending of a signal to a process.
+    Kill,
+    /// Change of the user ids of a process.
+    Setresuid,
+    /// Use of a capability by a process.
+    Capable,
+    /// Tracing of a process.
+    Ptrace,
+    /// Creation of a directory.
+    Mkdir,
+    /// Removal of a directory.
+    Rmdir,
+    /// Creation of a special file.
+    Mknod,
+    /// Creation of a hard link.
+    Link,
+    /// Removal of a file.
+    Unlink,
+    /// Creation of a symbolic link.
+    Symlink,
+    /// Renaming of a file.
+    Rename,
+    /// Reading of a symbolic link.
+    Readlink,
+    /// Truncation of a file.
+    Truncate,
+    /// Change of the permissions of a file.
+    Chmod,
+    /// Change of the owner of a file.
+    Chown,
+    /// Association with an existing IPC object.
+    IpcAssociate,
+    /// Control operation on an IPC object.
+    IpcCtl,
+    /// Sending of a message to a message queue.
+    IpcMsgsnd,
+    /// Receiving of a message from a message queue.
+    IpcMsgrcv,
+    /// Operation on a semaphore set.
+    IpcSemop,
+    /// Attachment of a shared memory segment.
+    IpcShmat,
+    /// Event not listed above, by its name.
+    Custom(&'static str),
+}
+
+impl Event {
+    /// Events other than [`Event::Custom`].
+    pub const KNOWN: [Self; 27] = [
+        Self::GetFile,
+        Self::GetProcess,
+        Self::GetIpc,
+        Self::Fork,
+        Self::Exec,
+        Self::Sexec,
+        Self::Kill,
+        Self::Setresuid,
+        Self::Capable,
+        Self::Ptrace,
+        Self::Mkdir,
+        Self::Rmdir,
+        Self::Mknod,
+        Self::Link,
+        Self::Unlink,
+        Self::Symlink,
+        Self::Rename,
+        Self::Readlink,
+        Self::Truncate,
+        Self::Chmod,
+        Self::Chown,
+        Self::IpcAssociate,
+        Self::IpcCtl,
+        Self::IpcMsgsnd,
+        Self::IpcMsgrcv,
+        Self::IpcSemop,
+        Self::IpcShmat,
+    ];
+
+    /// Returns the event named `name`, [`Event::Custom`] if it is not known.
+    pub const fn from_name(name: &'static str) -> Self {
+        let mut i = 0;
+        while i < Self::KNOWN.len() {
+            if str_eq(Self::KNOWN[i].name(), name) {
+                return Self::KNOWN[i];
+            }
+            i += 1;
+        }
+
+        Self::Custom(name)
+    }
+
+    /// Returns name of the event as registered by the security module.
+    pub const fn name(&self) -> &'static str {
+        match self {
+            Self::GetFile => "getfile",
+            Self::GetProcess => "getprocess",
+            Self::GetIpc => "getipc",
+            Self::Fork => "fork",
+            Self::Exec => "exec",
+            Self::Sexec => "sexec",
+            Self::Kill => "kill",
+            Self::Setresuid => "setresuid",
+            Self::Capable => "capable",
+            Self::Ptrace => "ptrace",
+            Self::Mkdir => "mkdir",
+            Self::Rmdir => "rmdir",
+            Self::Mknod => "mknod",
+            Self::Link => "link",
+            Self::Unlink => "unlink",
+            Self::Symlink => "symlink",
+            Self::Rename => "rename",
+            Self::Readlink => "readlink",
+            Self::Truncate => "truncate",
+            Self::Chmod => "chmod",
+            Self::Chown => "chown",
+            Self::IpcAssociate => "ipc_associate",
+            Self::IpcCtl => "ipc_ctl",
+            Self::IpcMsgsnd => "ipc_msgsnd",
+            Self::IpcMsgrcv => "ipc_msgrcv",
+            Self::IpcSemop => "ipc_semop",
+            Self::IpcShmat => "ipc_shmat",
+            Self::Custom(name) => name,
+        }
+    }
+
+    /// Returns whether the event is other than [`Event::Custom`].
+    pub const fn is_known(&self) -> bool {
+        !matches!(self, Self::Custom(_))
+    }
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Event {
+    type Err = ConfigError;
+
+    /// Parses name of a known event. Unknown events are not accepted, as they cannot borrow
+    /// their name, see [`Event::from_name`].
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        Self::KNOWN
+            .into_iter()
+            .find(|x| x.name() == name)
+            .ok_or_else(|| ConfigError::UnknownEventError(name.to_owned()))
+    }
+}
+
+const fn str_eq(a: &str, b: &str) -> bool {
+    let (a, b) = (a.as_bytes(), b.as_bytes());
+    if a.len() != b.len() {
+        return false;
+    }
+
+    let mut i = 0;
+    while i < a.len() {
+        if a[i] != b[i] {
+            return false;
+        }
+        i += 1;
+    }
+
+    true
+}
+
 /// Event, such as `getfile` or `getprocess`.
 #[derive(Default, Clone, Debug)]
 pub struct MedusaEvtype {
@@ -62,8 +293,38 @@ impl MedusaEvtype {
         self.attributes.get(attr_name)
     }
 
+    /// Returns bytes of attribute `attr_name` up to its NUL padding, see
+    /// [`MedusaClass::get_attribute_bytes_trimmed`].
+    ///
+    /// [`MedusaClass::get_attribute_bytes_trimmed`]: crate::medusa::MedusaClass::get_attribute_bytes_trimmed
+    pub fn get_attribute_bytes_trimmed(&self, attr_name: &str) -> Result<&[u8], AttributeError> {
+        self.attributes.get_bytes_trimmed(attr_name)
+    }
+
+    /// Returns header of this event.
+    pub fn header(&self) -> &MedusaEvtypeHeader {
+        &self.header
+    }
+
     /// Returns name of this event.
     pub fn name(&self) -> &str {
         self.header.name()
     }
+
+    /// Returns identification of this event.
+    pub fn id(&self) -> u64 {
+        self.header.evid
+    }
+
+    /// Returns attributes of this event.
+    pub fn attributes(&self) -> &MedusaAttributes {
+        &self.attributes
+    }
+
+    /// Packs attributes into vector of bytes.
+    pub fn pack_attributes(&self) -> Vec<u8> {
+        let mut res = vec![0; self.header.size as usize];
+        self.attributes.pack(&mut res);
+        res
+    }
 }
@@ -0,0 +1,118 @@
+//! Classification of processes into domains by their executable, see
+//! [`ConfigBuilder::add_executable_map`].
+//!
+//! [`ConfigBuilder::add_executable_map`]: crate::medusa::ConfigBuilder::add_executable_map
+
+use crate::medusa::{ConfigError, Context, HandlerArgs, MedusaAnswer};
+use regex::bytes::Regex;
+
+/// Map from executable patterns to domain paths, a `getprocess` handler without custom code.
+///
+/// Patterns are regular expressions matched anywhere in the bytes of the `cmdline` attribute of
+/// the process, or of another attribute set by [`ExecutableMap::with_attribute`]. The process is
+/// entered into the path of the first matching pattern, processes matching no pattern are left to
+/// other handlers. A pattern like `^` matching everything serves as a default:
+///
+/// ```text
+/// let map = ExecutableMap::new("domains")
+///     .map("/usr/sbin/sshd", "/usr/sbin/sshd")?
+///     .map("/usr/bin/passwd", "/usr/bin/passwd")?
+///     .map("^", "/")?;
+/// ```
+#[derive(Debug, Clone)]
+pub struct ExecutableMap {
+    tree: String,
+    attribute: String,
+    entries: Vec<(Regex, String)>,
+}
+
+impl ExecutableMap {
+    /// Creates new `ExecutableMap` entering processes into `tree`.
+    pub fn new(tree: &str) -> Self {
+        Self {
+            tree: tree.to_owned(),
+            attribute: "cmdline".to_owned(),
+            entries: Vec::new(),
+        }
+    }
+
+    /// Sets the string attribute of the process matched against the patterns.
+    ///
+    /// Returns `Self`.
+    pub fn with_attribute(mut self, attribute: &str) -> Self {
+        self.attribute = attribute.to_owned();
+        self
+    }
+
+    /// Adds a mapping of processes matching `pattern` to the domain at `path`.
+    ///
+    /// Returns `Self` or `ConfigError` if the pattern is not a valid regular expression or the
+    /// path is not absolute.
+    pub fn map(mut self, pattern: &str, path: &str) -> Result<Self, ConfigError> {
+        if !path.starts_with('/') {
+            return Err(ConfigError::InvalidPathError(path.to_owned()));
+        }
+
+        self.entries.push((Regex::new(pattern)?, path.to_owned()));
+        Ok(self)
+    }
+
+    /// Returns the tree processes are entered into.
+    pub fn tree(&self) -> &str {
+        &self.tree
+    }
+
+    /// Returns the domain path of the first pattern matching `value`.
+    pub(crate) fn domain(&self, value: &[u8]) -> Option<&str> {
+        self.entries
+            .iter()
+            .find(|(pattern, _)| pattern.is_match(value))
+            .map(|(_, path)| path.as_str())
+    }
+}
+
+pub(crate) async fn executable_map_handler(
+    ctx: &Context,
+    args: HandlerArgs<'_>,
+) -> anyhow::Result<MedusaAnswer> {
+    let HandlerArgs {
+        evtype,
+        mut subject,
+        handler_data,
+        ..
+    } = args;
+    let map = handler_data
+        .executable_map
+        .as_ref()
+        .expect("handler has no executable map");
+
+    // matched as raw bytes, so that a NUL or invalid UTF-8 does not hide the rest of the value
+    let value = subject.get_attribute_bytes_trimmed(&map.attribute)?;
+    let path = match map.domain(value) {
+        Some(path) => path,
+        None => return Ok(MedusaAnswer::Allow),
+    };
+
+    let config = ctx.config();
+    let tree = config
+        .tree_by_name(&map.tree)
+        .unwrap_or_else(|| panic!("primary tree `{}` not found", map.tree));
+    if !tree.root().covers(path) {
+        println!("{path} not covered by tree {}", map.tree);
+        return Ok(MedusaAnswer::Deny);
+    }
+
+    if ctx.is_handler_debugged(&handler_data.name) {
+        let value = config
+            .redaction(&map.attribute)
+            .apply(value)
+            .unwrap_or_else(|| "<omitted>".to_owned());
+        println!("[{}] `{}` -> {}", handler_data.name, value, path);
+    }
+
+    if let Err(e) = subject.enter_tree(ctx, &evtype, &map.tree, path).await {
+        eprintln!("{}", e);
+    }
+
+    Ok(MedusaAnswer::Allow)
+}
@@ -0,0 +1,150 @@
+//! Spawning of tasks and timers, see [`ConfigBuilder::executor`].
+//!
+//! The connection and its tasks use only what [`Executor`] provides, channels of `tokio::sync`
+//! work with any executor. Exceptions are the control socket and snapshots on signals, which are
+//! always served by tokio, and [`RuntimeMode`] other than [`RuntimeMode::Caller`].
+//!
+//! [`ConfigBuilder::executor`]: crate::medusa::ConfigBuilder::executor
+//! [`RuntimeMode`]: crate::medusa::RuntimeMode
+//! [`RuntimeMode::Caller`]: crate::medusa::RuntimeMode::Caller
+
+use std::any::Any;
+use std::future::Future;
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll, Wake, Waker};
+use std::thread::{self, Thread};
+use std::time::Duration;
+use tokio::runtime::{Handle, RuntimeFlavor};
+use tokio::sync::oneshot;
+
+/// Future run by an [`Executor`].
+pub type BoxFuture = Pin<Box<dyn Future<Output = ()> + Send + 'static>>;
+
+/// Executor running tasks of a connection.
+///
+/// ```text
+/// struct SmolExecutor;
+///
+/// impl Executor for SmolExecutor {
+///     fn spawn(&self, future: BoxFuture) {
+///         smol::spawn(future).detach();
+///     }
+///
+///     fn sleep(&self, duration: Duration) -> BoxFuture {
+///         Box::pin(async move {
+///             smol::Timer::after(duration).await;
+///         })
+///     }
+/// }
+/// ```
+pub trait Executor: Send + Sync {
+    /// Runs `future` to completion in the background.
+    fn spawn(&self, future: BoxFuture);
+
+    /// Runs `future` to completion in the background as a task named `_name`, which is shown by
+    /// tools inspecting tasks. Same as [`Executor::spawn`] by default.
+    fn spawn_named(&self, _name: &str, future: BoxFuture) {
+        self.spawn(future);
+    }
+
+    /// Returns a future which completes after `duration`.
+    fn sleep(&self, duration: Duration) -> BoxFuture;
+}
+
+/// Executor spawning tasks on the current tokio runtime, used by default.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct TokioExecutor;
+
+impl Executor for TokioExecutor {
+    fn spawn(&self, future: BoxFuture) {
+        tokio::spawn(future);
+    }
+
+    // names are seen by tokio-console only with instrumentation enabled, see `console`
+    #[cfg(all(feature = "console", tokio_unstable))]
+    fn spawn_named(&self, name: &str, future: BoxFuture) {
+        tokio::task::Builder::new()
+            .name(name)
+            .spawn(future)
+            .expect("task is spawned");
+    }
+
+    fn sleep(&self, duration: Duration) -> BoxFuture {
+        Box::pin(tokio::time::sleep(duration))
+    }
+}
+
+/// Spawns `future` on `executor`.
+///
+/// Returns a receiver of its output, which fails if the future panicked.
+pub(crate) fn spawn_with_output<F>(
+    executor: &dyn Executor,
+    future: F,
+) -> oneshot::Receiver<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send,
+{
+    let (sender, receiver) = oneshot::channel();
+    executor.spawn(Box::pin(async move {
+        let _ = sender.send(future.await);
+    }));
+
+    receiver
+}
+
+/// Runs `future` to completion on the calling thread, which is blocked while the future is
+/// pending. Unlike `Handle::block_on`, this may be called from within a runtime, e.g. by
+/// blocking handlers. Within a multi-threaded runtime, other tasks are moved off the thread
+/// first, so that they, as well as the timers the future may wait for, keep running. A
+/// current-thread runtime is blocked entirely.
+pub(crate) fn block_on<F: Future>(future: F) -> F::Output {
+    if let Ok(handle) = Handle::try_current() {
+        if handle.runtime_flavor() == RuntimeFlavor::MultiThread {
+            return tokio::task::block_in_place(|| handle.block_on(future));
+        }
+    }
+
+    struct Unparker(Thread);
+
+    impl Wake for Unparker {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    let waker = Waker::from(Arc::new(Unparker(thread::current())));
+    let mut cx = Context::from_waker(&waker);
+    let mut future = Box::pin(future);
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+            return output;
+        }
+        thread::park();
+    }
+}
+
+/// Future catching a panic of the wrapped future, so that handlers need no task of their own
+/// to be isolated.
+pub(crate) struct CatchUnwind<F>(Pin<Box<F>>);
+
+impl<F: Future> CatchUnwind<F> {
+    pub(crate) fn new(future: F) -> Self {
+        Self(Box::pin(future))
+    }
+}
+
+impl<F: Future> Future for CatchUnwind<F> {
+    type Output = Result<F::Output, Box<dyn Any + Send>>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let future = self.0.as_mut();
+        match panic::catch_unwind(AssertUnwindSafe(|| future.poll(cx))) {
+            Ok(Poll::Ready(output)) => Poll::Ready(Ok(output)),
+            Ok(Poll::Pending) => Poll::Pending,
+            Err(payload) => Poll::Ready(Err(payload)),
+        }
+    }
+}
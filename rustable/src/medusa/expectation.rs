@@ -0,0 +1,70 @@
+//! Attributes the config requires of classes, see [`ConfigBuilder::expect_attribute`].
+//!
+//! [`ConfigBuilder::expect_attribute`]: crate::medusa::ConfigBuilder::expect_attribute
+
+use crate::medusa::{AttributeDataType, MedusaClass};
+use std::fmt;
+
+/// Attribute of a class the config requires, with the data type handlers read it as.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AttributeExpectation {
+    /// Name of the class.
+    pub class: String,
+
+    /// Name of the attribute.
+    pub attribute: String,
+
+    /// Data type of the attribute.
+    pub data_type: AttributeDataType,
+}
+
+impl AttributeExpectation {
+    /// Returns the mismatch of `class` with this expectation, if it applies to `class`.
+    pub(crate) fn check(&self, class: &MedusaClass) -> Option<AttributeMismatch> {
+        if self.class != class.name() {
+            return None;
+        }
+
+        let found = class
+            .attributes()
+            .iter()
+            .find(|x| x.name() == self.attribute)
+            .map(|x| x.header.data_type.clone());
+        if found.as_ref() == Some(&self.data_type) {
+            return None;
+        }
+
+        Some(AttributeMismatch {
+            attribute: self.attribute.clone(),
+            expected: self.data_type.clone(),
+            found,
+        })
+    }
+}
+
+/// Attribute of a class registered by the security module which does not meet an
+/// [`AttributeExpectation`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AttributeMismatch {
+    /// Name of the attribute.
+    pub attribute: String,
+
+    /// Data type the config requires.
+    pub expected: AttributeDataType,
+
+    /// Data type the security module registered, `None` if the class lacks the attribute.
+    pub found: Option<AttributeDataType>,
+}
+
+impl fmt::Display for AttributeMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.found {
+            Some(found) => write!(
+                f,
+                "`{}` is {:?}, expected {:?}",
+                self.attribute, found, self.expected
+            ),
+            None => write!(f, "`{}` is missing", self.attribute),
+        }
+    }
+}
@@ -0,0 +1,256 @@
+//! Tracing of how an authorization request is decided, see [`Config::explain`].
+
+use crate::medusa::constants::DEFAULT_ANSWER;
+use crate::medusa::diff::location;
+use crate::medusa::handler::{hierarchy_node, hierarchy_path, HierarchyNode};
+use crate::medusa::{AuthRequestData, Config, EventHandler, MedusaAnswer};
+use std::fmt;
+
+/// Node the path of a request resolved to in the primary tree of a hierarchy handler.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NodeResolution {
+    /// The subject is entered into the node at `location`, given as `<tree>/<path>`.
+    /// `recursed` is `true` if a recursive ancestor stands for the path.
+    Node { location: String, recursed: bool },
+
+    /// `path` is not covered by the children of the node at `parent`, the request is denied.
+    NotCovered { path: String, parent: String },
+
+    /// The node could not be resolved, e.g. because the request lacks an attribute.
+    Unresolved(String),
+}
+
+/// How a single handler of the event treats a request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HandlerExplanation {
+    /// Name of the handler.
+    pub name: String,
+
+    /// `true` if the handler would be run.
+    pub applicable: bool,
+
+    /// Virtual spaces the handler requires of the subject which it is not a member of.
+    pub missing_subject_spaces: Vec<String>,
+
+    /// Virtual spaces the handler requires of the object which it is not a member of.
+    pub missing_object_spaces: Vec<String>,
+
+    /// Node the path of the request resolved to, for applicable hierarchy handlers.
+    pub node: Option<NodeResolution>,
+}
+
+/// Trace of how a [`Config`] decides an authorization request: the static relation which
+/// answers it, otherwise the handlers of its event and why each of them would or would not
+/// be run. Handlers are not run, so their answers are not known.
+///
+/// Its [`Display`](fmt::Display) implementation prints one line per step:
+///
+/// ```text
+/// event getfile
+/// relation none
+/// handler hierarchy_getfile applicable, node fs/etc/shadow
+/// handler check_shadow not applicable, subject not in sshd, object not in shadow
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Explanation {
+    /// Name of the event.
+    pub event: String,
+
+    /// Answer of the static relations, see [`ConfigBuilder::allow_relation`]. Handlers are not
+    /// run if there is one.
+    ///
+    /// [`ConfigBuilder::allow_relation`]: crate::medusa::ConfigBuilder::allow_relation
+    pub relation: Option<MedusaAnswer>,
+
+    /// Handlers of the event in the order they are run.
+    pub handlers: Vec<HandlerExplanation>,
+
+    /// Answer given if no handler is applicable, or with deny-by-default enforcement if none
+    /// answers.
+    pub default_answer: MedusaAnswer,
+}
+
+impl Explanation {
+    /// Explains `event` of a subject in virtual spaces `svs` and an object in `ovs`. Nodes are
+    /// not resolved.
+    pub(crate) fn new_vs(config: &Config, event: &str, svs: &[u8], ovs: Option<&[u8]>) -> Self {
+        let default_answer = if config.enforce {
+            MedusaAnswer::Deny
+        } else {
+            DEFAULT_ANSWER
+        };
+        let mut explanation = Self {
+            event: event.to_owned(),
+            relation: None,
+            handlers: Vec::new(),
+            default_answer,
+        };
+
+        let id = match config.event_id(event) {
+            Some(id) => id,
+            None => return explanation,
+        };
+
+        explanation.relation = config
+            .decision_table
+            .as_ref()
+            .and_then(|table| table.lookup_vs(id, svs, ovs));
+        if explanation.relation.is_some() {
+            return explanation;
+        }
+
+        explanation.handlers = config
+            .handlers_by_event_id(id)
+            .iter()
+            .map(|handler| explain_handler(config, handler, svs, ovs))
+            .collect();
+
+        explanation
+    }
+
+    /// Explains `request`, resolving its path for applicable hierarchy handlers.
+    pub(crate) fn new(config: &Config, request: &AuthRequestData) -> Self {
+        let svs = request.subject.get_vs().unwrap_or_default();
+        let ovs = request
+            .object
+            .as_ref()
+            .map(|x| x.get_vs().unwrap_or_default());
+        let mut explanation = Self::new_vs(config, request.evtype.name(), svs, ovs);
+
+        let id = match config.event_id_of(&request.evtype.header) {
+            Some(id) => id,
+            None => return explanation,
+        };
+
+        for (explained, handler) in explanation
+            .handlers
+            .iter_mut()
+            .zip(config.handlers_by_event_id(id))
+        {
+            if explained.applicable && handler.data().hierarchy {
+                explained.node = Some(resolve(config, handler, request));
+            }
+        }
+
+        explanation
+    }
+
+    /// Returns the answer to the request, or `None` if it depends on the answers of handlers.
+    pub fn answer(&self) -> Option<MedusaAnswer> {
+        match self.relation {
+            Some(answer) => Some(answer),
+            None if self.handlers.iter().all(|x| !x.applicable) => Some(self.default_answer),
+            None => None,
+        }
+    }
+}
+
+impl fmt::Display for Explanation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut lines = vec![format!("event {}", self.event)];
+
+        match self.relation {
+            Some(answer) => lines.push(format!("relation {:?}", answer)),
+            None => lines.push("relation none".to_owned()),
+        }
+
+        for handler in &self.handlers {
+            let mut parts = vec![format!(
+                "handler {} {}",
+                handler.name,
+                if handler.applicable {
+                    "applicable"
+                } else {
+                    "not applicable"
+                }
+            )];
+            if !handler.missing_subject_spaces.is_empty() {
+                parts.push(format!(
+                    "subject not in {}",
+                    handler.missing_subject_spaces.join(" ")
+                ));
+            }
+            if !handler.missing_object_spaces.is_empty() {
+                parts.push(format!(
+                    "object not in {}",
+                    handler.missing_object_spaces.join(" ")
+                ));
+            }
+            match &handler.node {
+                Some(NodeResolution::Node { location, recursed }) => parts.push(format!(
+                    "node {}{}",
+                    location,
+                    if *recursed { " (recursion)" } else { "" }
+                )),
+                Some(NodeResolution::NotCovered { path, parent }) => {
+                    parts.push(format!("{} not covered, parent {}", path, parent))
+                }
+                Some(NodeResolution::Unresolved(reason)) => {
+                    parts.push(format!("node unresolved: {}", reason))
+                }
+                None => (),
+            }
+            lines.push(parts.join(", "));
+        }
+
+        match self.answer() {
+            Some(answer) => lines.push(format!("answer {:?}", answer)),
+            None => lines.push("answer decided by handlers".to_owned()),
+        }
+
+        write!(f, "{}", lines.join("\n"))
+    }
+}
+
+/// Explains whether `handler` would be run for a subject in virtual spaces `svs` and an
+/// object in `ovs`.
+fn explain_handler(
+    config: &Config,
+    handler: &EventHandler,
+    svs: &[u8],
+    ovs: Option<&[u8]>,
+) -> HandlerExplanation {
+    let (subject, object) = handler.missing_spaces_vs(svs, ovs);
+    let names = |bits: Vec<usize>| {
+        bits.iter()
+            .map(|bit| match config.space_bit_to_name(bit) {
+                Some(name) => name.clone(),
+                None => format!("#{}", bit),
+            })
+            .collect()
+    };
+
+    HandlerExplanation {
+        name: handler.name().to_owned(),
+        applicable: handler.is_applicable_vs(svs, ovs),
+        missing_subject_spaces: names(subject),
+        missing_object_spaces: names(object),
+        node: None,
+    }
+}
+
+/// Resolves the node hierarchy `handler` enters the subject of `request` into.
+fn resolve(config: &Config, handler: &EventHandler, request: &AuthRequestData) -> NodeResolution {
+    let data = handler.data();
+    let tree = match config.tree_by_name(&data.primary_tree) {
+        Some(tree) => tree,
+        None => {
+            let reason = format!("primary tree `{}` not found", data.primary_tree);
+            return NodeResolution::Unresolved(reason);
+        }
+    };
+
+    let path = hierarchy_path(data, &request.evtype);
+    let object = request.object.as_ref();
+    match hierarchy_node(config, tree, data, &path, &request.subject, object, None) {
+        Ok(HierarchyNode::Node(node, recursed)) => NodeResolution::Node {
+            location: location(config, node),
+            recursed,
+        },
+        Ok(HierarchyNode::NotCovered(parent)) => NodeResolution::NotCovered {
+            path,
+            parent: location(config, parent),
+        },
+        Err(e) => NodeResolution::Unresolved(e.to_string()),
+    }
+}
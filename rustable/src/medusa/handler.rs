@@ -1,12 +1,24 @@
 use crate::bitmap;
 use crate::cstr_to_string;
+use crate::medusa::domains::user_domain_handler;
+use crate::medusa::executable::executable_map_handler;
+use crate::medusa::invalidate::invalidation_handler;
+use crate::medusa::plugin::plugin_handler;
+use crate::medusa::positions::TreePositions;
+use crate::medusa::redact;
+use crate::medusa::rename::rename_handler;
+use crate::medusa::rule::rule_handler;
 use crate::medusa::space::{spaces_to_bitmap, Space, SpaceDef};
+use crate::medusa::tripwire::{tripwire_handler, Tripwire};
 use crate::medusa::{
-    AuthRequestData, Context, HandlerFlags, MedusaAnswer, MedusaClass, MedusaEvtype,
+    AuthRequestData, Config, ConfigError, Context, Event, ExecutableMap, HandlerFlags,
+    KernelCapabilities, MedusaAnswer, MedusaClass, MedusaEvtype, Node, Rule, Tree,
 };
 use derivative::Derivative;
 use std::future::Future;
 use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Instant;
 
 pub struct HandlerArgs<'a> {
     pub evtype: MedusaEvtype,
@@ -22,8 +34,29 @@ pub type Handler =
         args: HandlerArgs<'a>,
     ) -> Pin<Box<dyn Future<Output = anyhow::Result<MedusaAnswer>> + Send + 'a>>;
 
+/// Handler deciding without waiting for anything, see
+/// [`EventHandlerBuilder::with_fast_handler`].
+pub type FastHandler = fn(
+    ctx: &Context,
+    request: &AuthRequestData,
+    handler_data: &HandlerData,
+) -> anyhow::Result<MedusaAnswer>;
+
+/// Handler which may block the calling thread, see
+/// [`EventHandlerBuilder::with_blocking_handler`].
+pub type BlockingHandler =
+    for<'a> fn(ctx: &'a Context, args: HandlerArgs<'a>) -> anyhow::Result<MedusaAnswer>;
+
+#[derive(Clone, Copy)]
+enum HandlerFn {
+    Async(Handler),
+    Fast(FastHandler),
+    Blocking(BlockingHandler),
+}
+
 #[derive(Debug, Clone)]
 pub struct HandlerData {
+    pub name: String,
     pub event: String,
     pub attribute: Option<String>,
     pub flags: HandlerFlags,
@@ -33,6 +66,11 @@ pub struct HandlerData {
     pub subject_vs: Vec<u8>,
     pub object_vs: Vec<u8>,
 
+    pub(crate) rules: Arc<[Rule]>,
+    pub(crate) executable_map: Option<Arc<ExecutableMap>>,
+    pub(crate) tripwire: Option<Arc<Tripwire>>,
+    pub(crate) hierarchy: bool,
+
     bitmap_nbytes: usize,
 }
 
@@ -56,7 +94,8 @@ macro_rules! force_boxed {
 }
 
 pub struct CustomHandlerDef {
-    pub event: &'static str,
+    pub name: &'static str,
+    pub event: Event,
     pub handler: Handler,
     pub subject: Space,
     pub object: Option<Space>,
@@ -70,15 +109,22 @@ pub trait CustomHandler {
 #[derivative(Debug, Default)]
 pub struct EventHandlerBuilder {
     pub(crate) event: &'static str,
+    name: Option<String>,
     attribute: Option<String>,
     flags: HandlerFlags,
     primary_tree: String,
+    rules: Vec<Rule>,
+    executable_map: Option<ExecutableMap>,
+    tripwire: Option<Tripwire>,
+    hierarchy: bool,
+    renames: bool,
+    invalidates: bool,
 
     subject: Option<Space>,
     object: Option<Space>,
 
     #[derivative(Debug = "ignore")]
-    handler: Option<Handler>,
+    handler: Option<HandlerFn>,
 }
 
 impl EventHandlerBuilder {
@@ -86,8 +132,18 @@ impl EventHandlerBuilder {
         Default::default()
     }
 
-    pub fn event(mut self, event: &'static str) -> Self {
-        self.event = event;
+    pub fn event(mut self, event: Event) -> Self {
+        self.event = event.name();
+        self
+    }
+
+    /// Sets the name used to refer to this handler, for example when toggling debug output.
+    /// Custom handlers are named after their function, hierarchy handlers are named
+    /// `hierarchy_<event>`, rename handlers `rename_<event>`, invalidation handlers
+    /// `invalidate_<event>`, rule handlers `rules_<event>` and executable map handlers
+    /// `executable_map_<event>` by default.
+    pub fn name(mut self, name: &str) -> Self {
+        self.name = Some(name.to_owned());
         self
     }
 
@@ -106,7 +162,173 @@ impl EventHandlerBuilder {
         self.subject = Some(Space::All);
         self.object = Some(Space::All);
         self.primary_tree = primary_tree.to_owned();
-        self.handler = Some(force_boxed!(hierarchy_handler));
+        self.hierarchy = true;
+        self.handler = Some(HandlerFn::Async(force_boxed!(hierarchy_handler)));
+        self
+    }
+
+    /// Sets the handler to move the renamed subject of the event to the node of its new name in
+    /// `primary_tree`, see [`ConfigBuilder::add_rename_event_handler`].
+    ///
+    /// [`ConfigBuilder::add_rename_event_handler`]: crate::medusa::ConfigBuilder::add_rename_event_handler
+    pub fn with_rename_handler(
+        mut self,
+        primary_tree: &str,
+        attribute: &str,
+        flags: HandlerFlags,
+    ) -> Self {
+        if self.handler.is_some() {
+            panic!("handler already set");
+        }
+
+        self.attribute = Some(attribute.to_owned());
+        self.flags = flags;
+        self.renames = true;
+        self.subject = Some(Space::All);
+        self.object = Some(Space::All);
+        self.primary_tree = primary_tree.to_owned();
+        self.handler = Some(HandlerFn::Async(force_boxed!(rename_handler)));
+        self
+    }
+
+    /// Sets the handler to forget the cached state of the deleted subject of the event, or of its
+    /// object with [`HandlerFlags::FROM_OBJECT`], see
+    /// [`ConfigBuilder::add_invalidation_event_handler`].
+    ///
+    /// [`ConfigBuilder::add_invalidation_event_handler`]: crate::medusa::ConfigBuilder::add_invalidation_event_handler
+    pub fn with_invalidation_handler(mut self, flags: HandlerFlags) -> Self {
+        if self.handler.is_some() {
+            panic!("handler already set");
+        }
+
+        self.flags = flags;
+        self.invalidates = true;
+        self.subject = Some(Space::All);
+        self.object = Some(Space::All);
+        self.handler = Some(HandlerFn::Fast(invalidation_handler));
+        self
+    }
+
+    /// Sets the handler to a plugin slot named `name`, see [`plugin`](crate::medusa::plugin).
+    pub fn with_plugin_handler(
+        mut self,
+        name: &str,
+        subject: Space,
+        object: Option<Space>,
+    ) -> Self {
+        if self.handler.is_some() {
+            panic!("handler already set");
+        }
+
+        self.name = Some(name.to_owned());
+        self.subject = Some(subject);
+        self.object = object;
+        self.handler = Some(HandlerFn::Async(force_boxed!(plugin_handler)));
+        self
+    }
+
+    /// Sets the handler to evaluate `rules` in order, the first rule whose condition holds
+    /// decides the answer. If there is no such rule, the answer is [`MedusaAnswer::Allow`].
+    pub fn with_rule_handler(mut self, rules: Vec<Rule>) -> Self {
+        if self.handler.is_some() {
+            panic!("handler already set");
+        }
+
+        self.rules = rules;
+        self.subject = Some(Space::All);
+        self.object = Some(Space::All);
+        self.handler = Some(HandlerFn::Async(force_boxed!(rule_handler)));
+        self
+    }
+
+    /// Sets the handler to enter processes into domains by their executable, see
+    /// [`ExecutableMap`].
+    pub fn with_executable_map_handler(mut self, map: ExecutableMap) -> Self {
+        if self.handler.is_some() {
+            panic!("handler already set");
+        }
+
+        self.primary_tree = map.tree().to_owned();
+        self.executable_map = Some(map);
+        self.subject = Some(Space::All);
+        self.object = Some(Space::All);
+        self.handler = Some(HandlerFn::Async(force_boxed!(executable_map_handler)));
+        self
+    }
+
+    /// Sets the handler to enter processes into per-user domains, see
+    /// [`ConfigBuilder::user_domains`](crate::medusa::ConfigBuilder::user_domains).
+    pub(crate) fn with_user_domain_handler(mut self) -> Self {
+        if self.handler.is_some() {
+            panic!("handler already set");
+        }
+
+        self.subject = Some(Space::All);
+        self.object = Some(Space::All);
+        self.handler = Some(HandlerFn::Async(force_boxed!(user_domain_handler)));
+        self
+    }
+
+    /// Sets the handler to answer accesses to objects in the space of `tripwire`, see
+    /// [`ConfigBuilder::tripwire`](crate::medusa::ConfigBuilder::tripwire).
+    pub(crate) fn with_tripwire_handler(mut self, tripwire: Tripwire) -> Self {
+        if self.handler.is_some() {
+            panic!("handler already set");
+        }
+
+        self.name = Some(format!("tripwire_{}_{}", tripwire.space, self.event));
+        self.subject = Some(Space::All);
+        self.object = Some(Space::ByName(tripwire.space));
+        self.tripwire = Some(tripwire);
+        self.handler = Some(HandlerFn::Fast(tripwire_handler));
+        self
+    }
+
+    /// Sets a handler which decides without waiting for anything, e.g. by checking virtual
+    /// spaces of the subject and object. It must neither block nor update the entities.
+    ///
+    /// In [`DispatchMode::Concurrent`], requests of an event whose handlers are all fast are
+    /// answered right after they are read, without spawning a task.
+    ///
+    /// [`DispatchMode::Concurrent`]: crate::medusa::DispatchMode::Concurrent
+    pub fn with_fast_handler(
+        mut self,
+        handler: FastHandler,
+        subject: Space,
+        object: Option<Space>,
+    ) -> Self {
+        if self.handler.is_some() {
+            panic!("handler already set");
+        }
+
+        self.subject = Some(subject);
+        self.object = object;
+        self.handler = Some(HandlerFn::Fast(handler));
+        self
+    }
+
+    /// Sets a synchronous handler, e.g. for small utilities not using asynchronous programming
+    /// otherwise. It may wait for the security module with [`Context::update_request_blocking`]
+    /// and [`Context::fetch_request_blocking`], which blocks the task running it, so it is
+    /// meant for connections made by [`Connection::new_blocking`]. On a current-thread runtime,
+    /// which would be blocked entirely, it must not wait for requests retried after a backoff,
+    /// see [`ConfigBuilder::update_retries`].
+    ///
+    /// [`Connection::new_blocking`]: crate::medusa::Connection::new_blocking
+    /// [`ConfigBuilder::update_retries`]: crate::medusa::ConfigBuilder::update_retries
+    pub fn with_blocking_handler(
+        mut self,
+        handler: BlockingHandler,
+        subject: Space,
+        object: Option<Space>,
+    ) -> Self {
+        if self.handler.is_some() {
+            panic!("handler already set");
+        }
+
+        self.subject = Some(subject);
+        self.object = object;
+        self.handler = Some(HandlerFn::Blocking(handler));
         self
     }
 
@@ -116,43 +338,69 @@ impl EventHandlerBuilder {
         }
 
         let CustomHandlerDef {
+            name,
             event,
             handler,
             subject,
             object,
         } = custom_handler.define();
 
-        self.event = event;
+        self.name.get_or_insert_with(|| name.to_owned());
+        self.event = event.name();
         self.subject = Some(subject);
         self.object = object;
-        self.handler = Some(handler);
+        self.handler = Some(HandlerFn::Async(handler));
         self
     }
 
-    pub(crate) fn build(self, def: &SpaceDef) -> EventHandler {
+    pub(crate) fn build(self, def: &SpaceDef) -> Result<EventHandler, ConfigError> {
         let handler = self
             .handler
             .unwrap_or_else(|| panic!("no handler specified for event: {}", self.event));
 
         let bitmap_nbytes = def.bitmap_nbytes();
-        let subject_vs = spaces_to_bitmap(&[self.subject.unwrap()], def);
+        let subject_vs = spaces_to_bitmap(&[self.subject.unwrap()], def)?;
         let object_vs = match self.object {
-            Some(object) => spaces_to_bitmap(&[object], def),
+            Some(object) => spaces_to_bitmap(&[object], def)?,
             None => vec![0xff; bitmap_nbytes],
         };
 
-        EventHandler {
+        let kind = if !self.rules.is_empty() {
+            "rules"
+        } else if self.invalidates {
+            "invalidate"
+        } else if matches!(handler, HandlerFn::Fast(_)) {
+            "fast"
+        } else if matches!(handler, HandlerFn::Blocking(_)) {
+            "blocking"
+        } else if self.executable_map.is_some() {
+            "executable_map"
+        } else if self.renames {
+            "rename"
+        } else {
+            "hierarchy"
+        };
+        let name = self
+            .name
+            .unwrap_or_else(|| format!("{}_{}", kind, self.event));
+
+        Ok(EventHandler {
             data: HandlerData {
+                name,
                 event: self.event.to_string(),
                 attribute: self.attribute,
                 flags: self.flags,
                 primary_tree: self.primary_tree,
                 subject_vs,
                 object_vs,
+                rules: self.rules.into(),
+                executable_map: self.executable_map.map(Arc::new),
+                tripwire: self.tripwire.map(Arc::new),
+                hierarchy: self.hierarchy,
                 bitmap_nbytes,
             },
             handler,
-        }
+        })
     }
 }
 
@@ -162,7 +410,7 @@ pub struct EventHandler {
     data: HandlerData,
 
     #[derivative(Debug = "ignore")]
-    handler: Handler,
+    handler: HandlerFn,
 }
 
 impl EventHandler {
@@ -170,14 +418,126 @@ impl EventHandler {
         EventHandlerBuilder::new()
     }
 
+    /// Returns the name of this handler.
+    pub fn name(&self) -> &str {
+        &self.data.name
+    }
+
+    pub(crate) fn data(&self) -> &HandlerData {
+        &self.data
+    }
+
+    /// Returns whether this handler decides without waiting, see
+    /// [`EventHandlerBuilder::with_fast_handler`].
+    pub fn is_fast(&self) -> bool {
+        matches!(self.handler, HandlerFn::Fast(_))
+    }
+
+    /// Returns whether this handler checks a tripwire, see
+    /// [`ConfigBuilder::tripwire`](crate::medusa::ConfigBuilder::tripwire).
+    pub(crate) fn is_tripwire(&self) -> bool {
+        self.data.tripwire.is_some()
+    }
+
     pub(crate) async fn handle(&self, ctx: &Context, auth_data: AuthRequestData) -> MedusaAnswer {
+        if self.is_fast() {
+            return self.handle_fast(ctx, &auth_data);
+        }
+
+        let debug = ctx.is_handler_debugged(&self.data.name);
+        let request_id = auth_data.request_id;
+        if debug {
+            self.debug_request(ctx, &auth_data);
+        }
+
         let args = HandlerArgs {
             evtype: auth_data.evtype,
             subject: auth_data.subject,
             object: auth_data.object,
             handler_data: &self.data,
         };
-        (self.handler)(ctx, args).await.unwrap_or(MedusaAnswer::Err)
+
+        let start = Instant::now();
+        let res = match self.handler {
+            HandlerFn::Async(handler) => handler(ctx, args).await,
+            HandlerFn::Blocking(handler) => handler(ctx, args),
+            HandlerFn::Fast(_) => unreachable!("fast handlers are run by `handle_fast`"),
+        };
+
+        if debug {
+            self.debug_result(request_id, start, &res);
+        }
+
+        res.unwrap_or(MedusaAnswer::Err)
+    }
+
+    /// Runs a fast handler, see [`EventHandlerBuilder::with_fast_handler`].
+    pub(crate) fn handle_fast(&self, ctx: &Context, auth_data: &AuthRequestData) -> MedusaAnswer {
+        let handler = match self.handler {
+            HandlerFn::Fast(handler) => handler,
+            _ => panic!("handler `{}` is not fast", self.data.name),
+        };
+
+        let debug = ctx.is_handler_debugged(&self.data.name);
+        if debug {
+            self.debug_request(ctx, auth_data);
+        }
+
+        let start = Instant::now();
+        let res = handler(ctx, auth_data, &self.data);
+
+        if debug {
+            self.debug_result(auth_data.request_id, start, &res);
+        }
+
+        res.unwrap_or(MedusaAnswer::Err)
+    }
+
+    fn debug_request(&self, ctx: &Context, auth_data: &AuthRequestData) {
+        let config = ctx.config();
+        println!(
+            "[{}] request {}: {} subject {} vs {:x?} [{}] object {} vs {:x?} [{}]",
+            self.data.name,
+            auth_data.request_id,
+            auth_data.evtype.name(),
+            auth_data.subject.header.name(),
+            auth_data.subject.get_vs().unwrap_or_default(),
+            redact::format_attributes(&config, &auth_data.subject),
+            auth_data
+                .object
+                .as_ref()
+                .map(|x| x.header.name())
+                .unwrap_or("-"),
+            auth_data
+                .object
+                .as_ref()
+                .and_then(|x| x.get_vs().ok())
+                .unwrap_or_default(),
+            auth_data
+                .object
+                .as_ref()
+                .map(|x| redact::format_attributes(&config, x))
+                .unwrap_or_default(),
+        );
+    }
+
+    fn debug_result(&self, request_id: u64, start: Instant, res: &anyhow::Result<MedusaAnswer>) {
+        match res {
+            Ok(answer) => println!(
+                "[{}] request {}: {:?} after {:?}",
+                self.data.name,
+                request_id,
+                answer,
+                start.elapsed()
+            ),
+            Err(e) => println!(
+                "[{}] request {}: failed after {:?}: {:#}",
+                self.data.name,
+                request_id,
+                start.elapsed(),
+                e
+            ),
+        }
     }
 
     pub(crate) fn is_applicable(
@@ -185,16 +545,57 @@ impl EventHandler {
         subject: &MedusaClass,
         object: Option<&MedusaClass>,
     ) -> bool {
+        let svs = subject.get_vs().expect("subject has no vs");
+        let ovs = object.map(|x| x.get_vs().expect("object has no vs"));
+
+        self.is_applicable_vs(svs, ovs)
+    }
+
+    /// Returns virtual spaces required by this handler which a subject in virtual spaces `svs`
+    /// and an object in `ovs` are not members of, as bits of the subject and of the object.
+    pub(crate) fn missing_spaces_vs(
+        &self,
+        svs: &[u8],
+        ovs: Option<&[u8]>,
+    ) -> (Vec<usize>, Vec<usize>) {
+        let missing = |required: &[u8], vs: &[u8]| {
+            if bitmap::all(required) {
+                return Vec::new();
+            }
+
+            (0..self.data.bitmap_nbytes * 8)
+                .filter(|&bit| bitmap::test_bit(required, bit))
+                .filter(|&bit| bit >= vs.len() * 8 || !bitmap::test_bit(vs, bit))
+                .collect()
+        };
+
+        let subject = missing(&self.data.subject_vs, svs);
+        let object = match ovs {
+            Some(ovs) => missing(&self.data.object_vs, ovs),
+            None => Vec::new(),
+        };
+
+        (subject, object)
+    }
+
+    /// Same as [`EventHandler::is_applicable`] for a subject in virtual spaces `svs` and an
+    /// object in `ovs`.
+    pub(crate) fn is_applicable_vs(&self, svs: &[u8], ovs: Option<&[u8]>) -> bool {
         if !bitmap::all(&self.data.subject_vs) {
-            let svs = &subject.get_vs().expect("subject has no vs")[..self.data.bitmap_nbytes];
+            let svs = &svs[..self.data.bitmap_nbytes];
             if bitmap::and(&mut self.data.subject_vs.clone(), svs) != self.data.subject_vs {
                 return false;
             }
         }
 
+        // a tripwire is only touched by an object in its space
+        if self.data.tripwire.is_some() && ovs.is_none() {
+            return false;
+        }
+
         if !bitmap::all(&self.data.object_vs) {
-            if let Some(object) = object {
-                let ovs = &object.get_vs().expect("object has no vs")[..self.data.bitmap_nbytes];
+            if let Some(ovs) = ovs {
+                let ovs = &ovs[..self.data.bitmap_nbytes];
                 if bitmap::and(&mut self.data.object_vs.clone(), ovs) != self.data.object_vs {
                     return false;
                 }
@@ -218,52 +619,22 @@ async fn hierarchy_handler(ctx: &Context, args: HandlerArgs<'_>) -> anyhow::Resu
         .tree_by_name(&handler_data.primary_tree)
         .unwrap_or_else(|| panic!("primary tree `{}` not found", handler_data.primary_tree));
 
-    let mut cinfo = subject.get_object_cinfo()?;
-    let mut node;
-
-    let path_attr = handler_data.attribute.as_deref().unwrap_or("");
-    let path = cstr_to_string(evtype.get_attribute(path_attr).unwrap_or(b"\0"));
-
-    if cinfo == 0 {
-        if handler_data.flags.contains(HandlerFlags::FROM_OBJECT)
-            && subject.header.id == object.as_ref().expect("No object.").header.id
-            && path != "/"
-        // ignore root's possible parent
-        {
-            let parent_cinfo = object.as_ref().expect("No object.").get_object_cinfo()?;
-            cinfo = parent_cinfo;
-        }
-
-        if cinfo == 0 {
-            node = tree.root();
-        } else {
-            node = config.node_by_cinfo(&cinfo).expect("node not found");
-        }
-    } else {
-        node = config.node_by_cinfo(&cinfo).expect("node not found");
-    }
-
-    let mut recursed = false;
-
-    // is not root?
-    if cinfo != 0 {
-        if let Some(child) = node.child_by_path(&path) {
-            node = child;
-        } else {
-            // find first recursive ancestor
-            while !node.is_recursive() {
-                match node.parent_cinfo() {
-                    Some(pcinfo) => node = config.node_by_cinfo(&pcinfo).expect("node not found"),
-                    None => {
-                        println!("{path} not covered by tree, parent = {}", node.path());
-                        return Ok(MedusaAnswer::Deny);
-                    }
-                }
-            }
-
-            recursed = true;
+    let path = hierarchy_path(handler_data, &evtype);
+    let (node, recursed) = match hierarchy_node(
+        &config,
+        tree,
+        handler_data,
+        &path,
+        &subject,
+        object.as_ref(),
+        ctx.positions.as_deref(),
+    )? {
+        HierarchyNode::Node(node, recursed) => (node, recursed),
+        HierarchyNode::NotCovered(parent) => {
+            println!("{path} not covered by tree, parent = {}", parent.path());
+            return Ok(MedusaAnswer::Deny);
         }
-    }
+    };
 
     println!(
         "{}: \"{}\" -> \"{}\"{}",
@@ -273,9 +644,103 @@ async fn hierarchy_handler(ctx: &Context, args: HandlerArgs<'_>) -> anyhow::Resu
         if recursed { " (recursion)" } else { "" }
     );
 
-    subject
-        .enter_tree_with_node(ctx, &evtype, node, recursed)
-        .await;
+    if handler_data.flags.contains(HandlerFlags::PIN_LABELS) {
+        subject = pinned(ctx, subject).await;
+    }
+
+    // the answer is written after the update, so there is no need to wait for it
+    subject.set_node(ctx, &evtype, node, recursed);
+    let name = subject.header.name().to_owned();
+    subject.update_no_wait(
+        ctx,
+        Some(Box::new(move |answer| {
+            if let Err(e) = answer.result() {
+                eprintln!("{}: {}", name, e);
+            }
+        })),
+    );
 
     Ok(MedusaAnswer::Allow)
 }
+
+/// Node of its primary tree a hierarchy handler enters the subject into.
+pub(crate) enum HierarchyNode<'a> {
+    /// Node of the path, `true` if a recursive ancestor stands for it.
+    Node(&'a Arc<Node>, bool),
+
+    /// The path is not covered by the children of the parent node.
+    NotCovered(&'a Arc<Node>),
+}
+
+/// Returns the path a hierarchy handler resolves, the value of its attribute of `evtype`.
+pub(crate) fn hierarchy_path(handler_data: &HandlerData, evtype: &MedusaEvtype) -> String {
+    let path_attr = handler_data.attribute.as_deref().unwrap_or("");
+    cstr_to_string(evtype.get_attribute(path_attr).unwrap_or(b"\0"))
+}
+
+/// Returns the node of `tree` a hierarchy handler enters `subject` with `path` into. The node
+/// is looked up among the children of the node of the subject's parent, or of the parent of
+/// `object` with [`HandlerFlags::FROM_OBJECT`]. Entities without a parent are entered into
+/// the root. With `positions`, the parent is looked up in `tree`, see
+/// [`ConfigBuilder::separate_tree_positions`].
+///
+/// [`ConfigBuilder::separate_tree_positions`]: crate::medusa::ConfigBuilder::separate_tree_positions
+pub(crate) fn hierarchy_node<'a>(
+    config: &'a Config,
+    tree: &'a Tree,
+    handler_data: &HandlerData,
+    path: &str,
+    subject: &MedusaClass,
+    object: Option<&MedusaClass>,
+    positions: Option<&TreePositions>,
+) -> anyhow::Result<HierarchyNode<'a>> {
+    let position = |entity: &MedusaClass| match positions {
+        Some(positions) => Ok(positions.cinfo(config, tree, entity)),
+        None => entity.get_object_cinfo(),
+    };
+    let mut cinfo = position(subject)?;
+
+    if cinfo == 0
+        && handler_data.flags.contains(HandlerFlags::FROM_OBJECT)
+        && subject.header.id == object.expect("No object.").header.id
+        // ignore root's possible parent
+        && path != "/"
+    {
+        cinfo = position(object.expect("No object."))?;
+    }
+
+    if cinfo == 0 {
+        return Ok(HierarchyNode::Node(tree.root(), false));
+    }
+
+    let parent = config
+        .node_by_cinfo(&cinfo)
+        .ok_or(ConfigError::UnknownCinfoError(cinfo))?;
+    Ok(match config.child_node(parent, path) {
+        Some((child, recursed)) => HierarchyNode::Node(child, recursed),
+        None => HierarchyNode::NotCovered(parent),
+    })
+}
+
+/// Returns the current state of `entity` in the security module, or `entity` itself if it
+/// cannot be fetched.
+async fn pinned(ctx: &Context, entity: MedusaClass) -> MedusaClass {
+    if ctx.is_dry_run()
+        || !ctx
+            .kernel_capabilities()
+            .contains(KernelCapabilities::FETCH)
+    {
+        return entity;
+    }
+
+    match entity.fetch(ctx).await {
+        Some(fetched) => fetched,
+        None => {
+            eprintln!(
+                "{} could not be fetched, labels are not pinned",
+                entity.header.name()
+            );
+            entity
+        }
+    }
+}
@@ -0,0 +1,118 @@
+//! Ready-made handlers for common policy fragments, added with
+//! [`ConfigBuilder::add_event_handler`] or [`ConfigBuilder::add_event_handlers`]:
+//!
+//! ```text
+//! let config = Config::builder()
+//!     .add_spaces(spaces)
+//!     .add_event_handlers(handlers::read_only_paths([Space::ByName("system_files")]))
+//!     .add_event_handler(handlers::no_exec_under(Space::ByName("tmp")))
+//!     .add_event_handler(handlers::deny_event_for(Space::ByName("sandbox"), Event::Ptrace))
+//!     .add_event_handler(handlers::log_only(Event::Kill))
+//!     .build()?;
+//! ```
+//!
+//! All of them are fast handlers, see [`EventHandlerBuilder::with_fast_handler`], deciding only
+//! by the virtual spaces of the subject and object.
+//!
+//! [`ConfigBuilder::add_event_handler`]: crate::medusa::ConfigBuilder::add_event_handler
+//! [`ConfigBuilder::add_event_handlers`]: crate::medusa::ConfigBuilder::add_event_handlers
+
+use crate::medusa::{AuthRequestData, Context, Event, EventHandlerBuilder, HandlerData};
+use crate::medusa::{MedusaAnswer, Space};
+
+/// Events modifying the object or the directory entries within it.
+const WRITE_EVENTS: [Event; 10] = [
+    Event::Mkdir,
+    Event::Rmdir,
+    Event::Mknod,
+    Event::Link,
+    Event::Unlink,
+    Event::Symlink,
+    Event::Rename,
+    Event::Truncate,
+    Event::Chmod,
+    Event::Chown,
+];
+
+/// Returns handlers denying modifications of objects in any of `spaces`: creating, removing,
+/// linking and renaming entries, truncating and changing ownership or permissions. The
+/// handlers are named `read_only_<space>_<event>`.
+pub fn read_only_paths<I>(spaces: I) -> Vec<EventHandlerBuilder>
+where
+    I: IntoIterator<Item = Space>,
+{
+    spaces
+        .into_iter()
+        .flat_map(|space| {
+            WRITE_EVENTS.into_iter().map(move |event| {
+                EventHandlerBuilder::new()
+                    .event(event)
+                    .name(&format!("read_only_{}_{}", space_name(space), event))
+                    .with_fast_handler(deny_handler, Space::All, Some(space))
+            })
+        })
+        .collect()
+}
+
+/// Returns a handler denying execution of files in `space`, named
+/// `no_exec_under_<space>`.
+pub fn no_exec_under(space: Space) -> EventHandlerBuilder {
+    EventHandlerBuilder::new()
+        .event(Event::Exec)
+        .name(&format!("no_exec_under_{}", space_name(space)))
+        .with_fast_handler(deny_handler, Space::All, Some(space))
+}
+
+/// Returns a handler denying `event` to subjects in `space`, named `deny_<event>_for_<space>`.
+pub fn deny_event_for(space: Space, event: Event) -> EventHandlerBuilder {
+    EventHandlerBuilder::new()
+        .event(event)
+        .name(&format!("deny_{}_for_{}", event, space_name(space)))
+        .with_fast_handler(deny_handler, space, None)
+}
+
+/// Returns a handler printing every request of `event` and allowing it, named
+/// `log_only_<event>`. It is meant for observing an event before writing its policy, as it
+/// overrides the answers of handlers run before it.
+pub fn log_only(event: Event) -> EventHandlerBuilder {
+    EventHandlerBuilder::new()
+        .event(event)
+        .name(&format!("log_only_{}", event))
+        .with_fast_handler(log_handler, Space::All, None)
+}
+
+fn space_name(space: Space) -> &'static str {
+    match space {
+        Space::All => "all",
+        Space::ByName(name) => name,
+    }
+}
+
+fn deny_handler(
+    _ctx: &Context,
+    _request: &AuthRequestData,
+    _handler_data: &HandlerData,
+) -> anyhow::Result<MedusaAnswer> {
+    Ok(MedusaAnswer::Deny)
+}
+
+fn log_handler(
+    _ctx: &Context,
+    request: &AuthRequestData,
+    handler_data: &HandlerData,
+) -> anyhow::Result<MedusaAnswer> {
+    println!(
+        "[{}] request {}: {} subject {} object {}",
+        handler_data.name,
+        request.request_id,
+        request.evtype.name(),
+        request.subject.header.name(),
+        request
+            .object
+            .as_ref()
+            .map(|x| x.header.name())
+            .unwrap_or("-")
+    );
+
+    Ok(MedusaAnswer::Allow)
+}
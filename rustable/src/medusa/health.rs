@@ -0,0 +1,157 @@
+//! Summary of the enforcement state for health checks, see [`Context::health`].
+//!
+//! [`Context::health`]: crate::medusa::Context::health
+
+use crate::medusa::Context;
+use serde::Serialize;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Health of a connection, e.g. for liveness and readiness probes of a container.
+///
+/// The control socket serves it by the `health` command, see
+/// [`control`](crate::medusa::control).
+#[derive(Clone, Debug, Serialize)]
+pub struct Health {
+    /// Milliseconds since the Unix epoch at which the health was checked.
+    pub timestamp_ms: u64,
+
+    /// Whether the connection with the security module is alive, `false` once
+    /// [`Connection::run`] finished.
+    ///
+    /// [`Connection::run`]: crate::medusa::Connection::run
+    pub connected: bool,
+
+    /// Protocol version the security module greeted with.
+    pub protocol_version: u64,
+
+    /// How requests are answered.
+    pub mode: EnforcementMode,
+
+    /// Fetch and update requests waiting for an answer.
+    pub pending_requests: usize,
+
+    /// Authorization requests received from the security module and not answered yet.
+    pub unanswered_requests: usize,
+
+    /// Milliseconds since the Unix epoch at which the last authorization request was answered,
+    /// `None` if none was answered yet.
+    pub last_decision_ms: Option<u64>,
+}
+
+/// How a connection answers authorization requests.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EnforcementMode {
+    /// Answers of the handlers are sent to the security module.
+    Enforcing,
+
+    /// Readiness checks of deny-by-default enforcement have not passed yet, see
+    /// [`ConfigBuilder::enforce`].
+    ///
+    /// [`ConfigBuilder::enforce`]: crate::medusa::ConfigBuilder::enforce
+    Permissive,
+
+    /// Every request gets the same answer, see [`ConfigBuilder::observe`].
+    ///
+    /// [`ConfigBuilder::observe`]: crate::medusa::ConfigBuilder::observe
+    Observing,
+}
+
+impl fmt::Display for EnforcementMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Self::Enforcing => "enforcing",
+            Self::Permissive => "permissive",
+            Self::Observing => "observing",
+        };
+
+        f.write_str(name)
+    }
+}
+
+impl Health {
+    /// Checks the health of `ctx`.
+    pub(crate) fn new(ctx: &Context) -> Self {
+        let mode = if ctx.observing.is_some() {
+            EnforcementMode::Observing
+        } else if ctx.config().enforce && !ctx.is_enforcing() {
+            EnforcementMode::Permissive
+        } else {
+            EnforcementMode::Enforcing
+        };
+
+        let last_decision_ms = match ctx.health.last_decision_ms.load(Ordering::Relaxed) {
+            0 => None,
+            x => Some(x),
+        };
+
+        Self {
+            timestamp_ms: now_ms(),
+            connected: ctx.health.connected.load(Ordering::Relaxed),
+            protocol_version: ctx.health.protocol_version.load(Ordering::Relaxed),
+            mode,
+            pending_requests: ctx.pending.len(),
+            unanswered_requests: ctx.outstanding.len(),
+            last_decision_ms,
+        }
+    }
+
+    /// Returns `true` if the connection is alive, i.e. the server is live.
+    pub fn is_live(&self) -> bool {
+        self.connected
+    }
+
+    /// Returns `true` if the connection is alive and its answers are enforced, i.e. the server
+    /// is ready.
+    pub fn is_ready(&self) -> bool {
+        self.connected && self.mode == EnforcementMode::Enforcing
+    }
+
+    /// Returns the health as pretty-printed JSON.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("health is serializable")
+    }
+}
+
+impl fmt::Display for Health {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "connected         {}", self.connected)?;
+        writeln!(f, "protocol version  {}", self.protocol_version)?;
+        writeln!(f, "mode              {}", self.mode)?;
+        writeln!(f, "pending requests  {}", self.pending_requests)?;
+        writeln!(f, "unanswered        {}", self.unanswered_requests)?;
+        match self.last_decision_ms {
+            Some(last) => write!(
+                f,
+                "last decision     {}ms ago",
+                self.timestamp_ms.saturating_sub(last)
+            ),
+            None => write!(f, "last decision     never"),
+        }
+    }
+}
+
+/// Parts of the health of a connection which are not kept elsewhere in its context.
+#[derive(Default)]
+pub(crate) struct HealthState {
+    pub(crate) connected: AtomicBool,
+    pub(crate) protocol_version: AtomicU64,
+    // 0 until the first request is answered
+    last_decision_ms: AtomicU64,
+}
+
+impl HealthState {
+    /// Records that an authorization request was answered.
+    pub(crate) fn record_decision(&self) {
+        self.last_decision_ms.store(now_ms(), Ordering::Relaxed);
+    }
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|x| x.as_millis() as u64)
+        .unwrap_or_default()
+}
@@ -0,0 +1,47 @@
+//! Identity of kernel entities shared by components keeping per-entity state.
+
+use crate::medusa::audit::to_hex;
+use crate::medusa::MedusaClass;
+use std::fmt;
+
+/// Identity of a subject or an object of the security module: its class and the values of the
+/// primary key attributes of the class, e.g. the pid and the start time of a process. Unlike
+/// virtual spaces or the class name, it tells apart entities of the same class, so caches of
+/// updates and audit records key entities by it.
+///
+/// Identities of entities whose primary key is reused, e.g. the inode number of a deleted file,
+/// are equal, see [`Context::forget_object`].
+///
+/// [`Context::forget_object`]: crate::medusa::Context::forget_object
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct SubjectId {
+    class_id: u64,
+    key: Box<[u8]>,
+}
+
+impl SubjectId {
+    /// Returns the identity of the entity of `class` whose attributes are packed in `data`.
+    pub(crate) fn from_raw(class: &MedusaClass, data: &[u8]) -> Self {
+        Self {
+            class_id: class.header.id,
+            key: class.attributes.primary_key_from_raw(data).into(),
+        }
+    }
+
+    /// Returns the identification of the class of the entity.
+    pub fn class_id(&self) -> u64 {
+        self.class_id
+    }
+
+    /// Returns the packed values of the primary key attributes, empty if the class has none.
+    pub fn key(&self) -> &[u8] {
+        &self.key
+    }
+}
+
+impl fmt::Display for SubjectId {
+    /// Formats the identity as `<class id>:<primary key>`, both hexadecimal.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:x}:{}", self.class_id, to_hex(&self.key))
+    }
+}
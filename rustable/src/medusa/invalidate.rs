@@ -0,0 +1,24 @@
+//! Forgetting of deleted entities, see [`ConfigBuilder::add_invalidation_event_handler`].
+//!
+//! [`ConfigBuilder::add_invalidation_event_handler`]: crate::medusa::ConfigBuilder::add_invalidation_event_handler
+
+use crate::medusa::{AuthRequestData, Context, HandlerData, HandlerFlags, MedusaAnswer};
+use anyhow::Context as _;
+
+/// Forgets the cached state of the subject of a delete or close event, or of its object with
+/// [`HandlerFlags::FROM_OBJECT`], see [`Context::forget_object`].
+pub(crate) fn invalidation_handler(
+    ctx: &Context,
+    request: &AuthRequestData,
+    handler_data: &HandlerData,
+) -> anyhow::Result<MedusaAnswer> {
+    let entity = if handler_data.flags.contains(HandlerFlags::FROM_OBJECT) {
+        request.object.as_ref().context("event has no object")?
+    } else {
+        &request.subject
+    };
+
+    ctx.forget_object(entity);
+
+    Ok(MedusaAnswer::Allow)
+}
@@ -0,0 +1,119 @@
+//! Detection of changed attribute layouts of classes, see [`ConfigBuilder::on_layout_change`].
+//!
+//! [`ConfigBuilder::on_layout_change`]: crate::medusa::ConfigBuilder::on_layout_change
+
+use crate::medusa::{AttributeDataType, MedusaClass};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::sync::Mutex;
+
+lazy_static! {
+    // layouts of classes registered by any connection of this process, by class name, so that
+    // they are compared across reconnects as well
+    static ref CLASS_LAYOUTS: Mutex<HashMap<String, BTreeMap<String, AttributeLayout>>> =
+        Default::default();
+}
+
+/// Placement of an attribute within the attribute data of its class.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AttributeLayout {
+    /// Offset in bytes.
+    pub offset: usize,
+
+    /// Length in bytes.
+    pub length: usize,
+
+    /// Data type of the attribute.
+    pub data_type: AttributeDataType,
+}
+
+impl fmt::Display for AttributeLayout {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:?} at {}..{}",
+            self.data_type,
+            self.offset,
+            self.offset + self.length
+        )
+    }
+}
+
+/// Attribute of a class whose layout differs from the one the class was registered with
+/// before, by this or an earlier connection.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LayoutChange {
+    /// Name of the class.
+    pub class: String,
+
+    /// Name of the attribute.
+    pub attribute: String,
+
+    /// Previous layout, `None` if the attribute was added.
+    pub before: Option<AttributeLayout>,
+
+    /// New layout, `None` if the attribute was removed.
+    pub after: Option<AttributeLayout>,
+}
+
+impl fmt::Display for LayoutChange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (&self.before, &self.after) {
+            (Some(before), Some(after)) => write!(
+                f,
+                "`{}.{}` changed from {} to {}",
+                self.class, self.attribute, before, after
+            ),
+            (None, Some(after)) => {
+                write!(f, "`{}.{}` added as {}", self.class, self.attribute, after)
+            }
+            (Some(before), None) => write!(
+                f,
+                "`{}.{}` removed, was {}",
+                self.class, self.attribute, before
+            ),
+            (None, None) => write!(f, "`{}.{}` unchanged", self.class, self.attribute),
+        }
+    }
+}
+
+/// Remembers the layout of `class` and returns how it differs from the layout it was
+/// registered with before. A class registered for the first time has no changes.
+pub(crate) fn record(class: &MedusaClass) -> Vec<LayoutChange> {
+    let layout = class
+        .attributes()
+        .iter()
+        .map(|x| {
+            let layout = AttributeLayout {
+                offset: x.header.offset as usize,
+                length: x.header.length as usize,
+                data_type: x.header.data_type.clone(),
+            };
+            (x.name().to_owned(), layout)
+        })
+        .collect::<BTreeMap<_, _>>();
+
+    let previous = CLASS_LAYOUTS
+        .lock()
+        .unwrap()
+        .insert(class.name().to_owned(), layout.clone());
+    let previous = match previous {
+        Some(previous) => previous,
+        None => return Vec::new(),
+    };
+
+    let mut attributes = previous.keys().chain(layout.keys()).collect::<Vec<_>>();
+    attributes.sort();
+    attributes.dedup();
+
+    attributes
+        .into_iter()
+        .filter(|x| previous.get(*x) != layout.get(*x))
+        .map(|x| LayoutChange {
+            class: class.name().to_owned(),
+            attribute: x.clone(),
+            before: previous.get(x).cloned(),
+            after: layout.get(x).cloned(),
+        })
+        .collect()
+}
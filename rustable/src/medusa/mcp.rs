@@ -1,34 +1,55 @@
-use crate::medusa::constants::*;
+use crate::medusa::audit::{self, AuditRecord};
+use crate::medusa::executor::CatchUnwind;
+use crate::medusa::proto::{Client, Frame, Message, RawAuthRequest};
+#[cfg(feature = "recording")]
+use crate::medusa::recording::SessionRecorder;
 use crate::medusa::{
-    AsyncReader, AuthRequestData, Command, CommunicationError, Config, ConnectionError, Context,
-    DecisionAnswer, MedusaAnswer, NativeByteOrderReader, Writer,
+    control, enforcement, layout, migrate, pending, shadow, slo, snapshot, watchdog,
 };
-use std::collections::HashMap;
+use crate::medusa::{
+    AuthRequestData, CommunicationError, CompletedRequest, Config, ConnectionError, Context,
+    DecisionAnswer, DispatchMode, EventHandler, KernelCapabilities, Liveness, MedusaAnswer,
+    PipelineStats, RecoveryStrategy, RuntimeMode, Stage, ThreadScheduling, Writer,
+};
+use std::collections::BTreeSet;
 use std::io::{Read, Write};
+use std::iter;
 use std::os::unix::io::AsRawFd;
+use std::panic::{self, AssertUnwindSafe};
 use std::sync::atomic::Ordering;
 use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use tokio::runtime::{Handle, RuntimeFlavor};
+use tokio::sync::mpsc::{self, UnboundedSender};
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
 
-lazy_static! {
-    static ref COMMS: HashMap<Command, &'static str> = {
-        let mut map = HashMap::new();
-        map.insert(MEDUSA_COMM_AUTHREQUEST, "MEDUSA_COMM_AUTHREQUEST");
-        map.insert(MEDUSA_COMM_KCLASSDEF, "MEDUSA_COMM_KCLASSDEF");
-        map.insert(MEDUSA_COMM_KCLASSUNDEF, "MEDUSA_COMM_KCLASSUNDEF");
-        map.insert(MEDUSA_COMM_EVTYPEDEF, "MEDUSA_COMM_EVTYPEDEF");
-        map.insert(MEDUSA_COMM_EVTYPEUNDEF, "MEDUSA_COMM_EVTYPEUNDEF");
-        map.insert(MEDUSA_COMM_FETCH_ANSWER, "MEDUSA_COMM_FETCH_ANSWER");
-        map.insert(MEDUSA_COMM_FETCH_ERROR, "MEDUSA_COMM_FETCH_ERROR");
-        map.insert(MEDUSA_COMM_UPDATE_ANSWER, "MEDUSA_COMM_UPDATE_ANSWER");
-        map
-    };
-}
+/// Default number of read authorization requests waiting to be dispatched.
+pub(crate) const DISPATCH_QUEUE_DEFAULT_CAPACITY: usize = 1024;
 
 /// Connection to Medusa security module.
 pub struct Connection<R: Read + Unpin> {
-    // TODO endian based reader
-    reader: NativeByteOrderReader<R>,
+    client: Client<R>,
     context: Arc<Context>,
+
+    // authorization requests read from the security module, parsed and dispatched by a task
+    dispatch: mpsc::Sender<RawAuthRequest>,
+
+    control: Option<JoinHandle<()>>,
+    snapshot_signal: Option<JoinHandle<()>>,
+    // stops the sweeper of abandoned requests when dropped
+    _sweeper: oneshot::Sender<()>,
+    // stops the watchdog of stuck handlers when dropped, see `ConfigBuilder::handler_watchdog`
+    _watchdog: Option<std::sync::mpsc::Sender<()>>,
+
+    // runtime of the connection loop and its tasks, `None` for the calling one
+    handle: Option<Handle>,
+    // stops the thread driving the runtime owned by the connection when dropped
+    _runtime_shutdown: Option<oneshot::Sender<()>>,
+
+    // whether registration of covered events was checked
+    coverage_checked: bool,
 }
 
 impl<R: Read + AsRawFd + Unpin + Send> Connection<R> {
@@ -42,240 +63,878 @@ impl<R: Read + AsRawFd + Unpin + Send> Connection<R> {
     where
         W: Write + Unpin + Send + 'static,
     {
-        let mut reader = NativeByteOrderReader::new(read_handle)?;
-
-        let writer = Writer::new(write_handle);
+        let (handle, runtime_shutdown) = connection_runtime(&config)?;
+        Self::connect(write_handle, read_handle, config, handle, runtime_shutdown).await
+    }
 
-        let context = Arc::new(Context::new(writer, config));
+    /// Same as [`Connection::new`], but for callers without an asynchronous runtime, see
+    /// [`Connection::run_blocking`]. Unless a handle is set by [`ConfigBuilder::runtime`], the
+    /// connection runs on a multi-threaded runtime of its own with a single worker. Reading from
+    /// and writing to the security module is done by dedicated threads, so that handlers made by
+    /// [`EventHandlerBuilder::with_blocking_handler`] can wait for answers of the security
+    /// module.
+    ///
+    /// [`ConfigBuilder::runtime`]: crate::medusa::ConfigBuilder::runtime
+    /// [`EventHandlerBuilder::with_blocking_handler`]: crate::medusa::EventHandlerBuilder::with_blocking_handler
+    pub fn new_blocking<W>(
+        write_handle: W,
+        read_handle: R,
+        mut config: Config,
+    ) -> Result<Self, ConnectionError>
+    where
+        W: Write + Unpin + Send + 'static,
+    {
+        config
+            .io_scheduling
+            .get_or_insert_with(ThreadScheduling::new);
+
+        // a blocked handler moves the worker off its thread, so that timers keep running
+        let (handle, runtime_shutdown) = match &config.runtime_mode {
+            RuntimeMode::Handle(handle) => (handle.clone(), None),
+            RuntimeMode::Caller | RuntimeMode::CurrentThread => {
+                let mut builder = tokio::runtime::Builder::new_multi_thread();
+                builder.worker_threads(1);
+                let (handle, shutdown) = spawn_runtime(builder)?;
+                (handle, Some(shutdown))
+            }
+        };
+        handle.block_on(Self::connect(
+            write_handle,
+            read_handle,
+            config,
+            Some(handle.clone()),
+            runtime_shutdown,
+        ))
+    }
 
-        let greeting = reader.read_u64().await?;
-        println!("greeting = 0x{:016x}", greeting);
-        if greeting == GREETING_NATIVE_BYTE_ORDER {
-            println!("native byte order");
-        } else if greeting == GREETING_REVERSED_BYTE_ORDER {
-            unimplemented!("reversed byte order");
-        } else {
-            return Err(ConnectionError::UnknownByteOrder(greeting));
+    async fn connect<W>(
+        write_handle: W,
+        read_handle: R,
+        mut config: Config,
+        handle: Option<Handle>,
+        runtime_shutdown: Option<oneshot::Sender<()>>,
+    ) -> Result<Self, ConnectionError>
+    where
+        W: Write + Unpin + Send + 'static,
+    {
+        let mut client = Client::new(read_handle)?;
+        #[cfg(feature = "recording")]
+        if let Some(recording) = &config.session_recording {
+            client.record(SessionRecorder::start(recording)?);
         }
 
-        let version = reader.read_u64().await?;
+        let stats = Arc::new(PipelineStats::default());
+        let writer = {
+            let _runtime = handle.as_ref().map(Handle::enter);
+            Writer::new(
+                write_handle,
+                Arc::clone(&stats),
+                config.io_scheduling.clone(),
+                &*config.executor,
+            )?
+        };
+
+        let version = client.handshake().await?;
         println!("protocol version {}", version);
 
-        if version != PROTOCOL_VERSION {
-            return Err(ConnectionError::UnsupportedVersionError(version));
+        let capabilities = config
+            .assumed_capabilities
+            .unwrap_or_else(|| KernelCapabilities::from_protocol_version(version));
+        println!("kernel capabilities {:?}", capabilities);
+        println!("{}", config.build_report());
+
+        let missing = config.required_capabilities - capabilities;
+        if !missing.is_empty() {
+            return Err(ConnectionError::MissingCapabilitiesError(missing));
+        }
+
+        if let Some(answer) = config.observe {
+            println!("observer mode, answering every request {:?}", answer);
+        } else if config.enforce {
+            println!("permissive mode until readiness checks pass");
+            for issue in enforcement::lint(&config) {
+                println!("  {}", issue);
+            }
+        }
+
+        let candidate = config.shadow.take();
+
+        let registry = Arc::clone(client.registry());
+        let mut context = Context::new(registry, writer, config, stats);
+        context.kernel_capabilities = capabilities;
+        context
+            .health
+            .protocol_version
+            .store(version, Ordering::Relaxed);
+        context.health.connected.store(true, Ordering::Relaxed);
+
+        if let Some(candidate) = candidate {
+            println!("evaluating candidate config in shadow mode");
+            context.shadow = Some(Arc::new(context.dry_run_with(*candidate)));
         }
 
         println!();
 
-        Ok(Self { reader, context })
+        let context = Arc::new(context);
+
+        // tasks of the connection are spawned on its runtime
+        let (dispatch, control, snapshot_signal, sweeper, watchdog) = {
+            let _runtime = handle.as_ref().map(Handle::enter);
+
+            let dispatch = spawn_dispatch(Arc::clone(&context));
+            let config = context.config();
+
+            let control = match &config.control_socket {
+                Some(path) => Some(control::spawn(path, &context)?),
+                None => None,
+            };
+
+            let snapshot_signal = match &config.snapshot_path {
+                Some(path) => Some(snapshot::spawn_on_signal(path, &context)?),
+                None => None,
+            };
+
+            let sweeper = pending::spawn_sweeper(&context);
+            let watchdog = watchdog::spawn(&context)?;
+
+            (dispatch, control, snapshot_signal, sweeper, watchdog)
+        };
+
+        Ok(Self {
+            client,
+            context,
+            dispatch,
+            control,
+            snapshot_signal,
+            _sweeper: sweeper,
+            _watchdog: watchdog,
+            handle,
+            _runtime_shutdown: runtime_shutdown,
+            coverage_checked: false,
+        })
+    }
+
+    /// Returns the context shared with event handlers.
+    pub fn context(&self) -> &Arc<Context> {
+        &self.context
     }
 
     /// Runs the main connection loop.
+    ///
+    /// Use [`CommunicationError::is_kernel_gone`] to distinguish a lost connection from an error
+    /// in the received data.
+    ///
+    /// If [`ConfigBuilder::io_scheduling`] or [`ConfigBuilder::runtime`] is set, the loop runs
+    /// on a dedicated thread and this blocks the calling one.
+    ///
+    /// [`ConfigBuilder::io_scheduling`]: crate::medusa::ConfigBuilder::io_scheduling
+    /// [`ConfigBuilder::runtime`]: crate::medusa::ConfigBuilder::runtime
     pub async fn run(&mut self) -> Result<(), CommunicationError> {
-        self.run_loop().await
+        let scheduling = self.context.config.load().io_scheduling.clone();
+        let res = if self.handle.is_none() && scheduling.is_none() {
+            self.run_loop().await
+        } else {
+            self.run_dedicated(scheduling.as_ref())
+        };
+
+        self.finish(res)
+    }
+
+    /// Same as [`Connection::run`], but for callers without an asynchronous runtime. The loop
+    /// always runs on a dedicated thread and this blocks the calling one, see
+    /// [`Connection::new_blocking`].
+    pub fn run_blocking(&mut self) -> Result<(), CommunicationError> {
+        let scheduling = self.context.config.load().io_scheduling.clone();
+        let res = self.run_dedicated(scheduling.as_ref());
+
+        self.finish(res)
+    }
+
+    fn finish(&self, res: Result<(), CommunicationError>) -> Result<(), CommunicationError> {
+        self.context
+            .health
+            .connected
+            .store(false, Ordering::Relaxed);
+        if let Err(error) = &res {
+            if error.is_kernel_gone() {
+                self.notify_liveness(Liveness::KernelGone);
+            }
+        }
+
+        res
+    }
+
+    /// Runs the main connection loop on a thread with `scheduling`. The loop runs on the
+    /// runtime of the connection, or on a runtime of its own so that reading is not delayed by
+    /// other tasks.
+    fn run_dedicated(
+        &mut self,
+        scheduling: Option<&ThreadScheduling>,
+    ) -> Result<(), CommunicationError> {
+        let handle = self.handle.clone();
+
+        block_caller(|| {
+            thread::scope(|scope| {
+                let reader = thread::Builder::new()
+                    .name("medusa-reader".to_owned())
+                    .spawn_scoped(scope, || {
+                        if let Some(scheduling) = scheduling {
+                            scheduling.apply()?;
+                        }
+
+                        match handle {
+                            Some(handle) => handle.block_on(self.run_loop()),
+                            None => tokio::runtime::Builder::new_current_thread()
+                                .enable_all()
+                                .build()?
+                                .block_on(self.run_loop()),
+                        }
+                    })?;
+
+                reader.join().unwrap_or_else(|e| panic::resume_unwind(e))
+            })
+        })
     }
 
     async fn run_loop(&mut self) -> Result<(), CommunicationError> {
+        let mut next_frame = None;
+
         loop {
-            let id = self.reader.read_u64().await?;
-
-            if id == 0 {
-                let cmd = self.reader.read_command().await?;
-                /*println!(
-                    "cmd(0x{:x}) = {}",
-                    cmd,
-                    COMMS
-                        .get(&cmd)
-                        .ok_or(CommunicationError::UnknownCommand(cmd))?
-                );*/
-                match cmd {
-                    MEDUSA_COMM_KCLASSDEF => {
-                        self.register_class().await?;
+            let frame = match next_frame.take() {
+                Some(frame) => frame,
+                None => {
+                    self.wait_for_traffic()?;
+                    self.client.read_frame().await?
+                }
+            };
+
+            if let Err(error) = self.handle_frame(frame).await {
+                if !self.is_recoverable(&error) {
+                    return Err(error);
+                }
+
+                eprintln!("{}, resynchronizing", error);
+                next_frame = Some(self.client.resynchronize().await?);
+            }
+        }
+    }
+
+    fn wait_for_traffic(&mut self) -> Result<(), CommunicationError> {
+        let timeout = match self.context.config.load().liveness_timeout {
+            Some(timeout) => timeout,
+            None => return Ok(()),
+        };
+
+        let mut idle = Duration::ZERO;
+        while !self.client.wait_readable(timeout)? {
+            let pending = self.context.pending.len();
+            if pending > 0 {
+                return Err(CommunicationError::KernelGoneError(pending));
+            }
+
+            idle += timeout;
+            self.notify_liveness(Liveness::Idle(idle));
+        }
+
+        Ok(())
+    }
+
+    fn notify_liveness(&self, liveness: Liveness) {
+        if let Some(hook) = &self.context.config.load().liveness_hook {
+            hook(liveness);
+        }
+    }
+
+    async fn handle_frame(&mut self, frame: Frame) -> Result<(), CommunicationError> {
+        if let Frame::AuthRequest { .. } = frame {
+            if !self.coverage_checked {
+                self.coverage_checked = true;
+                self.check_coverage();
+                migrate::spawn(&self.context);
+            }
+        }
+
+        let decode_start = Instant::now();
+        let message = match self.client.read_message(frame).await {
+            Ok(message) => message,
+            Err(error) => {
+                // do not leave the security module waiting for an answer
+                if let Frame::AuthRequest { request_id, .. } = frame {
+                    if self.is_recoverable(&error) {
+                        self.context.outstanding.receive(request_id);
+                        write_answer(&self.context, request_id, MedusaAnswer::Err, false);
                     }
-                    MEDUSA_COMM_EVTYPEDEF => {
-                        self.register_evtype().await?;
+                }
+                return Err(error);
+            }
+        };
+
+        match message {
+            Message::ClassDef(class) => {
+                let config = self.context.config.load();
+                let mismatches = config.attribute_mismatches(&class);
+                if !mismatches.is_empty() {
+                    let name = class.name().to_owned();
+                    return Err(CommunicationError::AttributeMismatchError(name, mismatches));
+                }
+                for error in config.apply_default_attributes(&mut class.clone()) {
+                    eprintln!("default attribute of class `{}`: {}", class.name(), error);
+                }
+
+                let changes = layout::record(&class);
+                if !changes.is_empty() {
+                    for change in &changes {
+                        eprintln!("class layout changed: {}", change);
                     }
-                    MEDUSA_COMM_UPDATE_ANSWER => {
-                        self.handle_update_answer().await?;
+                    // caches of this connection may hold data of the previous layout
+                    let previous_id = self
+                        .context
+                        .registry
+                        .class_id_from_name(class.name())
+                        .filter(|&x| x != class.id());
+                    for id in previous_id.into_iter().chain([class.id()]) {
+                        self.context.forget_class(id);
                     }
-                    MEDUSA_COMM_FETCH_ANSWER => {
-                        self.handle_fetch_answer().await?;
+                    if let Some(hook) = &config.layout_hook {
+                        hook(&changes);
                     }
-                    MEDUSA_COMM_FETCH_ERROR => {
-                        eprintln!("MEDUSA_COMM_FETCH_ERROR");
+                }
+                self.context.registry.define_class(class)
+            }
+            Message::EvtypeDef(evtype) => self.context.register_evtype(evtype),
+            Message::UpdateAnswer(answer) => self.context.pending.answer_update(answer),
+            Message::FetchAnswer(answer) => self.context.pending.answer_fetch(answer),
+            Message::FetchError => eprintln!("MEDUSA_COMM_FETCH_ERROR"),
+            Message::AuthRequest(request) => {
+                self.context
+                    .stats
+                    .record(Stage::Decode, request.received - decode_start);
+                self.context.outstanding.receive(request.request_id);
+
+                let config = self.context.config.load();
+                let event_id = config.event_id_of(&request.evtype.0.header);
+                if self.context.observing.is_some() || config.is_fast_path(event_id) {
+                    answer_fast(&self.context, *request);
+                } else {
+                    // waits while the dispatch queue is full
+                    if let Err(e) = self.dispatch.send(*request).await {
+                        write_answer(&self.context, e.0.request_id, MedusaAnswer::Err, false);
+                        return Err(CommunicationError::DispatchStoppedError);
                     }
-                    _ => unimplemented!("0x{:x}", cmd),
                 }
-            } else {
-                let auth_data = self.acquire_auth_req_data(id).await?;
-                self.spawn_event_handler(auth_data);
             }
         }
+
+        Ok(())
     }
 
-    fn spawn_event_handler(&self, auth_data: AuthRequestData) {
-        let ctx = Arc::clone(&self.context);
+    /// Compares events registered by the security module with the config. Reported are covered
+    /// and expected events, events having handlers and classes having expected attributes which
+    /// are not registered, as well as registered security-critical events without handlers, see
+    /// [`ConfigBuilder::require_handler`]. The security module registers its events before
+    /// sending authorization requests, so this is done when the first one arrives.
+    ///
+    /// [`ConfigBuilder::require_handler`]: crate::medusa::ConfigBuilder::require_handler
+    fn check_coverage(&self) {
+        let config = self.context.config.load();
+
+        for event in config.covered_events.iter().flatten() {
+            if self.context.evtype_id_from_name(event).is_none() {
+                eprintln!("covered event `{}` is not registered", event);
+            }
+        }
 
-        tokio::spawn(async move {
-            let request_id = auth_data.request_id;
+        for event in config.expected_events.iter() {
+            if self.context.evtype_id_from_name(event).is_none() {
+                eprintln!("expected event `{}` is not registered", event);
+            }
+        }
 
-            let ctx = Arc::clone(&ctx);
-            let join_handle = tokio::spawn(get_answer(Arc::clone(&ctx), auth_data)).await;
+        let classes = config
+            .expected_attributes
+            .iter()
+            .map(|x| x.class.as_str())
+            .collect::<BTreeSet<_>>();
+        for class in classes {
+            if self.context.registry.class_id_from_name(class).is_none() {
+                eprintln!(
+                    "class `{}` with expected attributes is not registered",
+                    class
+                );
+            }
+        }
 
-            let answer = match join_handle {
-                Ok(answer) => answer,
-                Err(error) => {
-                    eprintln!("{}", error);
-                    MedusaAnswer::Err
-                }
-            };
+        let handled = config
+            .handlers()
+            .map(|x| x.data().event.as_str())
+            .collect::<BTreeSet<_>>();
+        for event in handled {
+            if self.context.evtype_id_from_name(event).is_none() {
+                eprintln!(
+                    "event `{}` is not registered, its handlers never run",
+                    event
+                );
+            }
+        }
 
-            let status = answer as u16;
-            let decision = DecisionAnswer { request_id, status };
-            ctx.writer.write(Arc::from(decision.to_vec()));
-        });
+        for event in enforcement::unhandled_critical_events(&self.context) {
+            eprintln!("security-critical event `{}` has no handler", event);
+        }
     }
 
-    async fn acquire_auth_req_data(
-        &mut self,
-        id: u64,
-    ) -> Result<AuthRequestData, CommunicationError> {
-        //println!("Medusa auth request, id = 0x{:x}", id);
-
-        let mut evtype = self
-            .context
-            .empty_evtype_from_id(&id)
-            .ok_or(CommunicationError::UnknownAccessTypeError(id))?;
-
-        let request_id = self.reader.read_u64().await?;
-
-        let mut ev_attrs_raw = vec![0; evtype.header.size as usize];
-        self.reader.read_exact(&mut ev_attrs_raw).await?;
-        evtype.attributes.set_from_raw(&ev_attrs_raw);
-
-        let ev_sub = evtype.header.ev_sub;
-        let ev_obj = evtype.header.ev_obj;
-
-        // subject type
-        let mut subject = self
-            .context
-            .empty_class_from_id(&ev_sub)
-            .ok_or(CommunicationError::UnknownSubjectTypeError(ev_sub))?;
-
-        // there seems to be padding so store into buffer first
-        let mut sub_attrs_raw = vec![0; subject.header.size as usize];
-        self.reader.read_exact(&mut sub_attrs_raw).await?;
-        subject.attributes.set_from_raw(&sub_attrs_raw);
-
-        // object type
-        let object = match ev_obj.map(|x| x.get()) {
-            Some(ev_obj) => {
-                let mut object = self
-                    .context
-                    .empty_class_from_id(&ev_obj)
-                    .ok_or(CommunicationError::UnknownObjectTypeError(ev_obj))?;
-
-                let mut obj_attrs_raw = vec![0; object.header.size as usize];
-                self.reader.read_exact(&mut obj_attrs_raw).await?;
-                object.attributes.set_from_raw(&obj_attrs_raw);
-
-                Some(object)
-            }
-            None => None,
-        };
+    fn is_recoverable(&self, error: &CommunicationError) -> bool {
+        if self.context.config.load().recovery_strategy != RecoveryStrategy::Resynchronize {
+            return false;
+        }
 
-        Ok(AuthRequestData {
-            request_id,
-            evtype,
-            subject,
-            object,
-        })
+        // resynchronizing would only skip the class the config cannot work with, or requests
+        // nobody dispatches
+        !error.is_kernel_gone()
+            && !matches!(
+                error,
+                CommunicationError::AttributeMismatchError(..)
+                    | CommunicationError::DispatchStoppedError
+            )
     }
+}
 
-    async fn register_class(&mut self) -> Result<(), CommunicationError> {
-        let mut class = self.reader.read_class().await?;
-        let name = class.header.name().to_owned();
+impl<R: Read + Unpin> Drop for Connection<R> {
+    fn drop(&mut self) {
+        if let Some(control) = &self.control {
+            control.abort();
+        }
+        if let Some(snapshot_signal) = &self.snapshot_signal {
+            snapshot_signal.abort();
+        }
+    }
+}
 
-        let attrs = self.reader.read_attributes().await?;
-        for attr in attrs {
-            class.attributes.push(attr);
+/// Returns the handle of the runtime `config` asks for, `None` for the calling one, and a sender
+/// stopping the runtime owned by the connection when dropped.
+fn connection_runtime(
+    config: &Config,
+) -> std::io::Result<(Option<Handle>, Option<oneshot::Sender<()>>)> {
+    Ok(match &config.runtime_mode {
+        RuntimeMode::Caller => (None, None),
+        RuntimeMode::Handle(handle) => (Some(handle.clone()), None),
+        RuntimeMode::CurrentThread => {
+            let (handle, shutdown) = spawn_runtime(tokio::runtime::Builder::new_current_thread())?;
+            (Some(handle), Some(shutdown))
         }
+    })
+}
 
-        self.context.class_id.insert(name, class.header.id);
-        self.context.classes.insert(class.header.id, class);
+/// Spawns a thread driving the runtime made by `builder`, see [`RuntimeMode::CurrentThread`].
+///
+/// Returns the handle of the runtime and a sender which stops the thread when dropped.
+fn spawn_runtime(
+    mut builder: tokio::runtime::Builder,
+) -> std::io::Result<(Handle, oneshot::Sender<()>)> {
+    let runtime = builder.enable_all().build()?;
+    let handle = runtime.handle().clone();
+
+    let (shutdown, stopped) = oneshot::channel();
+    thread::Builder::new()
+        .name("medusa-runtime".to_owned())
+        .spawn(move || {
+            let _ = runtime.block_on(stopped);
+        })?;
+
+    Ok((handle, shutdown))
+}
 
-        Ok(())
+/// Runs `f` blocking the calling thread. Other tasks of a multi-threaded runtime are moved off
+/// the thread first, a current-thread runtime is blocked entirely.
+fn block_caller<T>(f: impl FnOnce() -> T) -> T {
+    match Handle::try_current().map(|x| x.runtime_flavor()) {
+        Ok(RuntimeFlavor::MultiThread) => tokio::task::block_in_place(f),
+        _ => f(),
     }
+}
 
-    async fn register_evtype(&mut self) -> Result<(), CommunicationError> {
-        let mut evtype = self.reader.read_evtype().await?;
-        let ev_sub = evtype.header.ev_sub;
-        let ev_obj = evtype.header.ev_obj.expect("ev_obj is 0").get(); // should always be non-zero from medusa
-        let name = evtype.header.name().to_owned();
+/// Spawns the task parsing authorization requests and dispatching them to event handlers, see
+/// [`ConfigBuilder::dispatch_queue_capacity`].
+///
+/// [`ConfigBuilder::dispatch_queue_capacity`]: crate::medusa::ConfigBuilder::dispatch_queue_capacity
+fn spawn_dispatch(ctx: Arc<Context>) -> mpsc::Sender<RawAuthRequest> {
+    let config = ctx.config();
+    let (sender, mut receiver) = mpsc::channel::<RawAuthRequest>(config.dispatch_queue_capacity);
+
+    // present only in sequential dispatch mode, unbounded so that a running handler cannot
+    // stop reading of the fetch and update answers it waits for
+    let dispatcher = match config.dispatch_mode {
+        DispatchMode::Concurrent => None,
+        DispatchMode::Sequential => Some(spawn_dispatcher(Arc::clone(&ctx))),
+    };
 
-        if ev_sub == ev_obj && evtype.header.ev_name[0] == evtype.header.ev_name[1] {
-            evtype.header.ev_obj = None;
-            evtype.header.ev_name[1] = String::new();
-        }
+    let executor = Arc::clone(&ctx.executor);
+    executor.spawn_named(
+        "medusa-dispatch",
+        Box::pin(async move {
+            while let Some(request) = receiver.recv().await {
+                let received = request.received;
+
+                let parse_start = Instant::now();
+                let mut auth_data = request.parse();
+                clear_retired_bits(&ctx, &mut auth_data);
+                record_processes(&ctx, &auth_data);
+                ctx.stats.record(Stage::Parse, parse_start.elapsed());
+
+                match &dispatcher {
+                    Some(dispatcher) => {
+                        // the read loop stops once this task is gone
+                        if let Err(e) = dispatcher.send((auth_data, received)) {
+                            let request_id = (e.0).0.request_id;
+                            write_answer(&ctx, request_id, MedusaAnswer::Err, false);
+                            eprintln!("sequential dispatcher is gone");
+                            break;
+                        }
+                    }
+                    None => match static_answer(&ctx, &auth_data) {
+                        // answered without spawning any task
+                        Some(answer) => {
+                            let start = Instant::now();
+                            ctx.stats.record(Stage::DispatchWait, start - received);
+
+                            let shadow_evaluation = ctx
+                                .shadow
+                                .as_ref()
+                                .map(|shadow| shadow::spawn_evaluation(&ctx, shadow, &auth_data));
+                            ctx.stats.record(Stage::Handler, start.elapsed());
+                            complete_request(&ctx, auth_data, answer, shadow_evaluation);
+                        }
+                        None => spawn_answer(&ctx, auth_data, received),
+                    },
+                }
+            }
+        }),
+    );
+
+    sender
+}
+
+fn spawn_dispatcher(ctx: Arc<Context>) -> UnboundedSender<(AuthRequestData, Instant)> {
+    let (sender, mut receiver) = mpsc::unbounded_channel();
+
+    let executor = Arc::clone(&ctx.executor);
+    executor.spawn_named(
+        "medusa-dispatcher",
+        Box::pin(async move {
+            while let Some((auth_data, decoded)) = receiver.recv().await {
+                answer_request(Arc::clone(&ctx), auth_data, decoded).await;
+            }
+        }),
+    );
 
-        let attrs = self.reader.read_attributes().await?;
-        for attr in attrs {
-            evtype.attributes.push(attr);
+    sender
+}
+
+/// Spawns a task answering the request received at `received`. With the `console` feature, the
+/// task is named after the event and the id of the request.
+fn spawn_answer(ctx: &Arc<Context>, auth_data: AuthRequestData, received: Instant) {
+    #[cfg(feature = "console")]
+    let name = format!(
+        "{} #{}",
+        auth_data.evtype.header.name(),
+        auth_data.request_id
+    );
+
+    let future = Box::pin(answer_request(Arc::clone(ctx), auth_data, received));
+
+    #[cfg(feature = "console")]
+    ctx.executor.spawn_named(&name, future);
+    #[cfg(not(feature = "console"))]
+    ctx.executor.spawn(future);
+}
+
+/// Answers the request received at `received`.
+async fn answer_request(ctx: Arc<Context>, auth_data: AuthRequestData, received: Instant) {
+    ctx.stats.record(Stage::DispatchWait, received.elapsed());
+
+    let shadow_evaluation = ctx
+        .shadow
+        .as_ref()
+        .map(|shadow| shadow::spawn_evaluation(&ctx, shadow, &auth_data));
+
+    // a panic of a handler results in an error answer
+    let start = Instant::now();
+    let running = ctx.running_handlers.as_ref().map(|x| x.start(&auth_data));
+    let answer = match CatchUnwind::new(get_answer(&ctx, &auth_data)).await {
+        Ok(answer) => answer,
+        Err(_) => {
+            eprintln!("handlers of request {} panicked", auth_data.request_id);
+            MedusaAnswer::Err
         }
+    };
+    drop(running);
+    ctx.stats.record(Stage::Handler, start.elapsed());
+    slo::record(&ctx, received);
+
+    complete_request(&ctx, auth_data, answer, shadow_evaluation);
+}
+
+/// Answers a request whose handlers are all fast on the calling task, see
+/// [`Config::is_fast_path`]. An observer answers all requests this way, without running
+/// handlers, see [`ConfigBuilder::observe`].
+///
+/// [`ConfigBuilder::observe`]: crate::medusa::ConfigBuilder::observe
+fn answer_fast(ctx: &Context, request: RawAuthRequest) {
+    let received = request.received;
+    let parse_start = Instant::now();
+    let mut auth_data = request.parse();
+    clear_retired_bits(ctx, &mut auth_data);
+    record_processes(ctx, &auth_data);
+
+    let start = Instant::now();
+    ctx.stats.record(Stage::Parse, start - parse_start);
+    ctx.stats.record(Stage::DispatchWait, start - received);
+
+    let shadow_evaluation = ctx
+        .shadow
+        .as_ref()
+        .map(|shadow| shadow::spawn_evaluation(ctx, shadow, &auth_data));
+
+    // a panic results in an error answer, as in `answer_request`
+    let answer = match ctx.observing {
+        Some(answer) => answer,
+        None => panic::catch_unwind(AssertUnwindSafe(|| get_answer_fast(ctx, &auth_data)))
+            .unwrap_or(MedusaAnswer::Err),
+    };
+    ctx.stats.record(Stage::Handler, start.elapsed());
+    slo::record(ctx, received);
 
-        if self.context.config.has_handler(&name) {
-            let mask = 1 << evtype.header.monitoring_bit;
-            self.context
-                .config
-                .covered_events_mask
-                .fetch_or(mask, Ordering::SeqCst);
+    complete_request(ctx, auth_data, answer, shadow_evaluation);
+}
+
+/// Counts the decision for the virtual spaces of the subject and of the object of a request,
+/// see [`PipelineStats::space_counts`].
+fn record_space_counts(
+    ctx: &Context,
+    config: &Config,
+    auth_data: &AuthRequestData,
+    answer: MedusaAnswer,
+) {
+    let denied = match answer {
+        MedusaAnswer::Err => return,
+        MedusaAnswer::Deny => true,
+        MedusaAnswer::Yes | MedusaAnswer::Skip | MedusaAnswer::Allow => false,
+    };
+
+    for space in audit::space_names(config, &auth_data.subject) {
+        ctx.stats.record_space(&space, false, denied);
+    }
+    if let Some(object) = &auth_data.object {
+        for space in audit::space_names(config, object) {
+            ctx.stats.record_space(&space, true, denied);
         }
+    }
+}
 
-        self.context.evtype_id.insert(name, evtype.header.evid);
-        self.context.evtypes.insert(evtype.header.evid, evtype);
+/// Remembers processes of a request, see [`ConfigBuilder::track_processes`].
+///
+/// [`ConfigBuilder::track_processes`]: crate::medusa::ConfigBuilder::track_processes
+fn record_processes(ctx: &Context, auth_data: &AuthRequestData) {
+    if let Some(processes) = &ctx.processes {
+        processes.record(&auth_data.subject);
+        if let Some(object) = &auth_data.object {
+            processes.record(object);
+        }
+    }
+}
 
-        Ok(())
+/// Clears bits of removed virtual spaces from the entities of a request and updates the ones
+/// which carried any, see [`ConfigBuilder::replacing`].
+///
+/// [`ConfigBuilder::replacing`]: crate::medusa::ConfigBuilder::replacing
+fn clear_retired_bits(ctx: &Context, auth_data: &mut AuthRequestData) {
+    let config = ctx.config.load();
+    let retired = &config.retired_space_bits;
+    if retired.is_empty() {
+        return;
     }
 
-    async fn handle_update_answer(&mut self) -> Result<(), CommunicationError> {
-        let ans = self.reader.read_update_answer().await?;
-        if let Some((_, sender)) = self.context.update_requests.remove(&{ ans.msg_seq }) {
-            sender.send(ans).expect("channel is disconnected");
+    for class in iter::once(&mut auth_data.subject).chain(auth_data.object.as_mut()) {
+        if retired.clear(class) {
+            ctx.update_request_no_wait(class.header.id, &class.pack_attributes(), None);
         }
+    }
+}
 
-        Ok(())
+/// Writes `answer` to the security module and passes the request to the shadow comparison,
+/// completion hooks and audit sinks.
+fn complete_request(
+    ctx: &Context,
+    auth_data: AuthRequestData,
+    answer: MedusaAnswer,
+    shadow_evaluation: Option<oneshot::Receiver<MedusaAnswer>>,
+) {
+    let config = ctx.config.load();
+    let answer = ctx.overrides.apply(&config, &auth_data, answer);
+
+    let critical = config.is_latency_critical(auth_data.evtype.header.name());
+    write_answer(ctx, auth_data.request_id, answer, critical);
+
+    if let Some(evaluation) = shadow_evaluation {
+        let auth_data = auth_data.clone();
+        ctx.spawn(async move { shadow::compare(evaluation, &auth_data, answer).await });
+    }
+
+    record_space_counts(ctx, &config, &auth_data, answer);
+    if let Some(usage) = &ctx.usage {
+        usage.record(&config, &auth_data, answer);
     }
 
-    async fn handle_fetch_answer(&mut self) -> Result<(), CommunicationError> {
-        let ans = self.reader.read_fetch_answer(&self.context.classes).await?;
-        if let Some((_, sender)) = self.context.fetch_requests.remove(&ans.msg_seq) {
-            sender.send(ans).expect("channel is disconnected");
+    if !config.completion_hooks.is_empty() || !config.audit_sinks.is_empty() {
+        let completed = CompletedRequest {
+            data: auth_data,
+            answer,
+        };
+        for hook in config.completion_hooks.iter() {
+            hook(&completed);
         }
 
-        Ok(())
+        if !config.audit_sinks.is_empty() {
+            let record = AuditRecord::new(&config, &completed);
+            for sink in config.audit_sinks.iter() {
+                sink.record_on(ctx, &record, &completed);
+            }
+        }
     }
 }
 
-async fn get_answer(ctx: Arc<Context>, auth_data: AuthRequestData) -> MedusaAnswer {
-    let event = auth_data.evtype.name();
-    let event_handlers = ctx.config.handlers_by_event(event);
+/// Writes `answer` to request `request_id`, before the decisions of other events waiting in the
+/// queue if `critical`. An answer to a request which was already answered or never received is
+/// reported instead of written.
+fn write_answer(ctx: &Context, request_id: u64, answer: MedusaAnswer, critical: bool) {
+    if let Err(e) = ctx.outstanding.answer(request_id) {
+        eprintln!("answer {:?} not written: {}", answer, e);
+        return;
+    }
+
+    let status = answer as u16;
+    let decision = DecisionAnswer { request_id, status };
+    if critical {
+        ctx.writer.write_critical(decision);
+    } else {
+        ctx.writer.write(decision);
+    }
+    ctx.health.record_decision();
+}
 
-    let subject = &auth_data.subject;
-    let object = &auth_data.object;
+pub(crate) async fn get_answer(ctx: &Context, auth_data: &AuthRequestData) -> MedusaAnswer {
+    if let Some(answer) = static_answer(ctx, auth_data) {
+        return answer;
+    }
 
-    let mut answer = DEFAULT_ANSWER;
-    if let Some(event_handlers) = event_handlers {
-        for event_handler in event_handlers {
-            if event_handler.is_applicable(subject, object.as_ref()) {
-                answer = event_handler.handle(&ctx, auth_data.clone()).await;
+    // handlers of the config are kept even if it is replaced meanwhile
+    let config = ctx.config();
+    let event_id = config.event_id_of(&auth_data.evtype.header);
 
-                // premature exit of handlers on Deny
-                if answer == MedusaAnswer::Deny {
-                    break;
-                }
-            }
+    let mut answer = None;
+    for event_handler in applicable_handlers(ctx, &config, event_id, auth_data) {
+        let handler_answer = match slo::degraded_answer(ctx, event_handler, auth_data) {
+            Some(answer) => answer,
+            None => event_handler.handle(ctx, auth_data.clone()).await,
+        };
+        answer = Some(handler_answer);
+
+        // premature exit of handlers on Deny
+        if handler_answer == MedusaAnswer::Deny {
+            break;
+        }
+    }
+
+    let answer = answer.map(|x| judge_access(ctx, &config, auth_data, x));
+    enforcement::apply(ctx, auth_data.request_id, answer)
+}
+
+/// Same as [`get_answer`] for requests whose handlers are all fast.
+fn get_answer_fast(ctx: &Context, auth_data: &AuthRequestData) -> MedusaAnswer {
+    if let Some(answer) = static_answer(ctx, auth_data) {
+        return answer;
+    }
+
+    let config = ctx.config.load();
+    let event_id = config.event_id_of(&auth_data.evtype.header);
+
+    let mut answer = None;
+    for event_handler in applicable_handlers(ctx, &config, event_id, auth_data) {
+        let handler_answer = slo::degraded_answer(ctx, event_handler, auth_data)
+            .unwrap_or_else(|| event_handler.handle_fast(ctx, auth_data));
+        answer = Some(handler_answer);
+
+        // premature exit of handlers on Deny
+        if handler_answer == MedusaAnswer::Deny {
+            break;
         }
     }
 
-    answer
+    let answer = answer.map(|x| judge_access(ctx, &config, auth_data, x));
+    enforcement::apply(ctx, auth_data.request_id, answer)
+}
+
+/// Returns the answer of relations matching the request, see
+/// [`ConfigBuilder::allow_relation`].
+///
+/// [`ConfigBuilder::allow_relation`]: crate::medusa::ConfigBuilder::allow_relation
+fn static_answer(ctx: &Context, auth_data: &AuthRequestData) -> Option<MedusaAnswer> {
+    let config = ctx.config.load();
+    let event_id = config.event_id_of(&auth_data.evtype.header)?;
+    // tripwires run before relations
+    let tripped = config
+        .handlers_by_event_id(event_id)
+        .iter()
+        .take_while(|x| x.is_tripwire())
+        .any(|x| x.is_applicable(&auth_data.subject, auth_data.object.as_ref()));
+    if tripped {
+        return None;
+    }
+
+    let answer = config
+        .decision_table
+        .as_ref()?
+        .lookup(event_id, auth_data)?;
+    let answer = judge_access(ctx, &config, auth_data, answer);
+    Some(enforcement::apply(ctx, auth_data.request_id, Some(answer)))
+}
+
+/// Returns `answer` of a request, denied if the access deviates from the learned ones, see
+/// [`ConfigBuilder::anomaly_detection`].
+///
+/// [`ConfigBuilder::anomaly_detection`]: crate::medusa::ConfigBuilder::anomaly_detection
+fn judge_access(
+    ctx: &Context,
+    config: &Config,
+    auth_data: &AuthRequestData,
+    answer: MedusaAnswer,
+) -> MedusaAnswer {
+    match &ctx.anomalies {
+        Some(anomalies) => anomalies.check(config, auth_data, answer),
+        None => answer,
+    }
+}
+
+fn applicable_handlers<'a>(
+    ctx: &'a Context,
+    config: &'a Config,
+    event_id: Option<usize>,
+    auth_data: &'a AuthRequestData,
+) -> impl Iterator<Item = &'a EventHandler> {
+    let event_handlers = match event_id {
+        Some(id) => config.handlers_by_event_id(id),
+        None => &[],
+    };
+
+    event_handlers.iter().filter(move |event_handler| {
+        let applicable = event_handler.is_applicable(&auth_data.subject, auth_data.object.as_ref());
+        if !applicable && ctx.is_handler_debugged(event_handler.name()) {
+            println!(
+                "[{}] request {}: not applicable",
+                event_handler.name(),
+                auth_data.request_id
+            );
+        }
+
+        applicable
+    })
 }
@@ -0,0 +1,260 @@
+//! Classification of processes running before the connection, see
+//! [`ConfigBuilder::migrate_labels`].
+//!
+//! [`ConfigBuilder::migrate_labels`]: crate::medusa::ConfigBuilder::migrate_labels
+
+use crate::medusa::executor::CatchUnwind;
+use crate::medusa::process::{pid_attribute, PARENT_PID_ATTR_NAME, PID_ATTR_NAME};
+use crate::medusa::{
+    AuthRequestData, Config, Context, Event, FetchStatus, KernelCapabilities, MedusaAnswer,
+    MedusaClass, MedusaEvtype,
+};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::ops::RangeInclusive;
+use std::sync::Arc;
+use std::time::Instant;
+
+/// Name of the class of processes.
+const PROCESS_CLASS_NAME: &str = "process";
+
+/// Default number of fetch requests waiting for an answer at once, see
+/// [`LabelMigration::concurrency`].
+const DEFAULT_CONCURRENCY: usize = 64;
+
+/// Migration of the labels of processes which were running before the connection was
+/// established, so that a restarted server does not wait for the next monitored event of each
+/// process to classify it.
+///
+/// Once the security module sends the first authorization request, all of its classes and
+/// events are registered. The processes are then fetched by their ids and the `getprocess`
+/// handlers run on each of them as on a `getprocess` request without event attributes, parents
+/// before their children. Each process starts in the node its parent was entered into, as a
+/// newly started process does, so hierarchy handlers and [`ExecutableMap`]s place it as if it
+/// was started under this server. Answers of the handlers are ignored and tripwires are not
+/// checked.
+///
+/// [`ExecutableMap`]: crate::medusa::ExecutableMap
+#[derive(Debug, Clone)]
+pub struct LabelMigration {
+    pids: Option<RangeInclusive<i32>>,
+    concurrency: usize,
+}
+
+impl LabelMigration {
+    /// Creates new `LabelMigration` of the processes listed in `/proc`.
+    pub fn new() -> Self {
+        Self {
+            pids: None,
+            concurrency: DEFAULT_CONCURRENCY,
+        }
+    }
+
+    /// Fetches the processes with ids in `pids` instead of the ones listed in `/proc`, e.g.
+    /// when the server runs in a different pid namespace than the processes.
+    ///
+    /// Returns `Self`.
+    pub fn pid_range(mut self, pids: RangeInclusive<i32>) -> Self {
+        self.pids = Some(pids);
+        self
+    }
+
+    /// Sets the number of fetch requests waiting for an answer at once, 64 by default.
+    ///
+    /// Returns `Self`.
+    pub fn concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+}
+
+impl Default for LabelMigration {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Spawns the migration of `ctx` if its config has one.
+pub(crate) fn spawn(ctx: &Arc<Context>) {
+    let settings = match &ctx.config().label_migration {
+        Some(settings) => settings.clone(),
+        None => return,
+    };
+    if !ctx
+        .kernel_capabilities()
+        .contains(KernelCapabilities::FETCH)
+    {
+        eprintln!("label migration: the security module does not support fetching");
+        return;
+    }
+
+    let migrated = Arc::clone(ctx);
+    ctx.spawn(async move { migrate(&migrated, &settings).await });
+}
+
+async fn migrate(ctx: &Context, settings: &LabelMigration) {
+    let start = Instant::now();
+    let config = ctx.config();
+    let template = ctx.empty_class(PROCESS_CLASS_NAME);
+    let evtype = ctx.empty_evtype(Event::GetProcess.name());
+    let event_id = config.event_id(Event::GetProcess.name());
+    let (template, evtype, event_id) = match (template, evtype, event_id) {
+        (Some(template), Some(evtype), Some(event_id)) => (template, evtype, event_id),
+        _ => {
+            eprintln!("label migration: no `process` class, `getprocess` event or its handlers");
+            return;
+        }
+    };
+
+    let pids = match &settings.pids {
+        Some(pids) => pids.clone().collect(),
+        None => running_pids(),
+    };
+    let processes = fetch(ctx, &template, &pids, settings.concurrency).await;
+
+    let mut children = HashMap::<i32, Vec<MedusaClass>>::new();
+    let known = processes
+        .iter()
+        .map(|(pid, _)| *pid)
+        .collect::<HashSet<_>>();
+    let mut layer = Vec::new();
+    for (pid, process) in processes {
+        match pid_attribute(&process, PARENT_PID_ATTR_NAME) {
+            Some(parent) if parent != pid && known.contains(&parent) => {
+                children.entry(parent).or_default().push(process)
+            }
+            _ => layer.push(process),
+        }
+    }
+
+    // nodes the processes were entered into, by their ids
+    let mut cinfos = HashMap::new();
+    let mut migrated = 0;
+    while !layer.is_empty() {
+        let mut parents = Vec::new();
+        for mut process in layer {
+            let pid = pid_attribute(&process, PID_ATTR_NAME).unwrap_or_default();
+            let parent = pid_attribute(&process, PARENT_PID_ATTR_NAME).unwrap_or_default();
+            let _ = process.set_object_cinfo(cinfos.get(&parent).copied().unwrap_or_default());
+
+            classify(ctx, &config, event_id, &evtype, process).await;
+            migrated += 1;
+            if children.contains_key(&pid) {
+                parents.push(pid);
+            }
+        }
+
+        // updates are answered in order, so the fetches see the nodes set by the handlers
+        for (pid, process) in fetch(ctx, &template, &parents, settings.concurrency).await {
+            let cinfo = process.get_object_cinfo().unwrap_or_default();
+            if config.node_by_cinfo(&cinfo).is_some() {
+                cinfos.insert(pid, cinfo);
+            }
+        }
+
+        layer = parents
+            .iter()
+            .flat_map(|x| children.remove(x).unwrap_or_default())
+            .collect();
+    }
+
+    println!(
+        "label migration: classified {} of {} processes in {:?}",
+        migrated,
+        pids.len(),
+        start.elapsed()
+    );
+}
+
+/// Returns the ids of the processes listed in `/proc`.
+fn running_pids() -> Vec<i32> {
+    let entries = match fs::read_dir("/proc") {
+        Ok(entries) => entries,
+        Err(e) => {
+            eprintln!("label migration: /proc: {}", e);
+            return Vec::new();
+        }
+    };
+
+    let mut pids = entries
+        .filter_map(|x| x.ok()?.file_name().to_str()?.parse().ok())
+        .collect::<Vec<_>>();
+    pids.sort_unstable();
+
+    pids
+}
+
+/// Fetches processes `pids` with at most `limit` fetch requests waiting for an answer at once.
+/// Processes which do not exist or whose fetch is abandoned are left out.
+async fn fetch(
+    ctx: &Context,
+    template: &MedusaClass,
+    pids: &[i32],
+    limit: usize,
+) -> Vec<(i32, MedusaClass)> {
+    let mut processes = Vec::new();
+
+    for chunk in pids.chunks(limit) {
+        let receivers = chunk
+            .iter()
+            .filter_map(|&pid| {
+                let mut process = template.clone();
+                process.set_attribute(PID_ATTR_NAME, pid).ok()?;
+                let sent = ctx.send_fetch(process.header.id, &process.pack_attributes());
+                Some((pid, sent))
+            })
+            .collect::<Vec<_>>();
+
+        for (pid, sent) in receivers {
+            let answer = sent.answer().await;
+            if answer.status != FetchStatus::Ok {
+                continue;
+            }
+            let mut process = match ctx.empty_class_from_id(&answer.class_id) {
+                Some(process) => process,
+                None => continue,
+            };
+            process.attributes.set_from_raw(&answer.data);
+
+            if pid_attribute(&process, PID_ATTR_NAME) == Some(pid) {
+                processes.push((pid, process));
+            }
+        }
+    }
+
+    processes
+}
+
+/// Runs the handlers of event `event_id` on `process` as the subject of a request.
+async fn classify(
+    ctx: &Context,
+    config: &Config,
+    event_id: usize,
+    evtype: &MedusaEvtype,
+    process: MedusaClass,
+) {
+    let subject_raw = Arc::from(process.pack_attributes());
+    let auth_data = AuthRequestData {
+        request_id: 0,
+        evtype: evtype.clone(),
+        subject: process,
+        object: None,
+        evtype_raw: Arc::from([]),
+        subject_raw,
+        object_raw: None,
+    };
+
+    let handlers = config
+        .handlers_by_event_id(event_id)
+        .iter()
+        .filter(|x| !x.is_tripwire() && x.is_applicable(&auth_data.subject, None));
+    for handler in handlers {
+        // a panic of a handler stops the handlers of this process only
+        let answer = CatchUnwind::new(handler.handle(ctx, auth_data.clone()))
+            .await
+            .unwrap_or(MedusaAnswer::Err);
+        if answer == MedusaAnswer::Deny {
+            break;
+        }
+    }
+}
@@ -0,0 +1,169 @@
+//! Streaming of decisions to an external analyzer.
+
+use crate::medusa::audit::{to_hex, AuditRecord, AuditSink};
+use crate::medusa::CompletedRequest;
+use serde::Serialize;
+use std::io::{self, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone)]
+enum Endpoint {
+    Tcp(Vec<SocketAddr>),
+    Unix(PathBuf),
+}
+
+impl Endpoint {
+    fn connect(&self) -> io::Result<Box<dyn Write + Send>> {
+        match self {
+            Endpoint::Tcp(addrs) => Ok(Box::new(TcpStream::connect(&addrs[..])?)),
+            Endpoint::Unix(path) => Ok(Box::new(UnixStream::connect(path)?)),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct RawRequest {
+    event: String,
+    subject: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    object: Option<String>,
+}
+
+#[derive(Serialize)]
+struct MirrorRecord<'a> {
+    #[serde(flatten)]
+    record: &'a AuditRecord,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    raw: Option<RawRequest>,
+}
+
+/// Audit sink which streams every decision as a line of JSON to an external analyzer over TCP
+/// or a Unix domain socket.
+///
+/// Records are queued and sent from a background thread. When the queue is full or the analyzer
+/// is not connected, records are dropped, so the enforcement is never slowed down. The
+/// connection is reestablished automatically.
+pub struct MirrorSink {
+    sender: SyncSender<Vec<u8>>,
+    dropped: Arc<AtomicU64>,
+    raw_data: bool,
+}
+
+impl MirrorSink {
+    /// Creates new `MirrorSink` connecting to the TCP address `addr` and holding at most
+    /// `capacity` records in the queue.
+    ///
+    /// Returns `MirrorSink` or `io::Error` if `addr` could not be resolved.
+    pub fn tcp<A: ToSocketAddrs>(addr: A, capacity: usize) -> io::Result<Self> {
+        let addrs = addr.to_socket_addrs()?.collect();
+        Ok(Self::new(Endpoint::Tcp(addrs), capacity))
+    }
+
+    /// Creates new `MirrorSink` connecting to the Unix domain socket at `path` and holding at
+    /// most `capacity` records in the queue.
+    pub fn unix<P: AsRef<Path>>(path: P, capacity: usize) -> Self {
+        Self::new(Endpoint::Unix(path.as_ref().to_owned()), capacity)
+    }
+
+    /// Includes raw attributes of the event, subject and object in hexadecimal form.
+    ///
+    /// Returns `Self`.
+    pub fn with_raw_data(mut self) -> Self {
+        self.raw_data = true;
+        self
+    }
+
+    /// Returns the number of records which were dropped so far.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    fn new(endpoint: Endpoint, capacity: usize) -> Self {
+        let (sender, receiver) = mpsc::sync_channel(capacity);
+        let dropped = Arc::new(AtomicU64::new(0));
+
+        let thread_dropped = Arc::clone(&dropped);
+        thread::spawn(move || forward(endpoint, receiver, thread_dropped));
+
+        Self {
+            sender,
+            dropped,
+            raw_data: false,
+        }
+    }
+
+    fn send(&self, record: &MirrorRecord) {
+        let mut line = match serde_json::to_vec(record) {
+            Ok(line) => line,
+            Err(e) => {
+                eprintln!("failed to encode mirrored record: {}", e);
+                return;
+            }
+        };
+        line.push(b'\n');
+
+        match self.sender.try_send(line) {
+            Ok(()) => (),
+            Err(TrySendError::Full(_)) | Err(TrySendError::Disconnected(_)) => {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+impl AuditSink for MirrorSink {
+    fn record(&self, record: &AuditRecord) {
+        self.send(&MirrorRecord { record, raw: None });
+    }
+
+    fn record_completed(&self, record: &AuditRecord, completed: &CompletedRequest) {
+        let raw = self.raw_data.then(|| RawRequest {
+            event: to_hex(&completed.data.evtype.pack_attributes()),
+            subject: to_hex(&completed.data.subject.pack_attributes()),
+            object: completed
+                .data
+                .object
+                .as_ref()
+                .map(|x| to_hex(&x.pack_attributes())),
+        });
+
+        self.send(&MirrorRecord { record, raw });
+    }
+}
+
+fn forward(endpoint: Endpoint, receiver: Receiver<Vec<u8>>, dropped: Arc<AtomicU64>) {
+    let mut stream = None;
+
+    while let Ok(line) = receiver.recv() {
+        if stream.is_none() {
+            match endpoint.connect() {
+                Ok(connected) => stream = Some(connected),
+                Err(e) => {
+                    eprintln!("mirror: cannot connect to {:?}: {}", endpoint, e);
+                    dropped.fetch_add(1, Ordering::Relaxed);
+                    // records arriving in the meantime fill the queue and are dropped
+                    thread::sleep(RECONNECT_DELAY);
+                    continue;
+                }
+            }
+        }
+
+        if let Some(writer) = &mut stream {
+            if let Err(e) = writer.write_all(&line) {
+                eprintln!("mirror: connection lost: {}", e);
+                dropped.fetch_add(1, Ordering::Relaxed);
+                stream = None;
+            }
+        }
+    }
+}
@@ -1,53 +1,231 @@
 //! Everything related to Medusa communication protocol.
 
+pub mod alert;
+pub use alert::{AlertAction, AlertKey, AlertSink, AlertSummary};
+
+pub mod anomaly;
+pub use anomaly::AnomalyDetection;
+
 pub mod attribute;
-pub use attribute::{AttributeBytes, MedusaAttribute, MedusaAttributeHeader, MedusaAttributes};
+pub use attribute::{
+    AttributeBytes, AttributeHandle, AttributeValue, MedusaAttribute, MedusaAttributeHeader,
+    MedusaAttributes,
+};
+
+pub mod audit;
+pub use audit::{AuditRecord, AuditSink, JsonSink};
+
+mod batch;
 
 pub mod config;
-pub use config::{Config, ConfigBuilder};
+pub use config::{
+    BuildReport, CompletionHook, Config, ConfigBuilder, DispatchMode, LayoutHook, Liveness,
+    LivenessHook, RecoveryStrategy, RuntimeMode, UpdateEscalation,
+};
+
+pub mod confine;
+pub use confine::Policy;
+
+#[cfg(feature = "console")]
+pub mod console;
 
 mod constants;
-pub use constants::{AccessType, HandlerFlags};
+pub use constants::{AccessType, AttributeDataType, HandlerFlags, KernelCapabilities};
 
 pub mod class;
 pub use class::{MedusaClass, MedusaClassHeader};
 
+pub mod compact;
+pub use compact::{CompactReader, CompactSink, ConvertFormat};
+
+pub mod consistency;
+pub use consistency::FetchedObject;
+
 pub mod context;
-pub use context::Context;
+pub use context::{Context, UpdateCallback};
+
+pub mod control;
+
+mod decision;
+
+pub mod diff;
+pub use diff::{ConfigDiff, NodeChange};
+
+pub mod domains;
+pub use domains::UserDomainsBuilder;
+
+#[cfg(feature = "testing")]
+pub mod emulator;
+
+mod enforcement;
 
 pub mod event;
-pub use event::{MedusaEvtype, MedusaEvtypeHeader, Monitoring};
+pub use event::{Event, MedusaEvtype, MedusaEvtypeHeader, Monitoring};
 
 pub mod error;
-pub use error::{AttributeError, CommunicationError, ConfigError, ConnectionError, ReaderError};
+pub use error::{
+    AnswerError, AttributeError, AuditFormatError, CommunicationError, ConfigError,
+    ConnectionError, ConsistencyError, PolicyError, ReaderError, RuleError, TeError, UpdateError,
+};
+
+#[cfg(feature = "testing")]
+pub use error::EmulatorError;
+
+#[cfg(any(feature = "plugins", feature = "scripting", feature = "wasm"))]
+pub use error::PluginError;
+
+pub mod executable;
+pub use executable::ExecutableMap;
+
+pub mod executor;
+pub use executor::{BoxFuture, Executor, TokioExecutor};
+
+pub mod expectation;
+pub use expectation::{AttributeExpectation, AttributeMismatch};
+
+pub mod explain;
+pub use explain::{Explanation, HandlerExplanation, NodeResolution};
 
 pub mod handler;
 pub use handler::{
-    CustomHandler, EventHandler, EventHandlerBuilder, Handler, HandlerArgs, HandlerData,
+    BlockingHandler, CustomHandler, EventHandler, EventHandlerBuilder, FastHandler, Handler,
+    HandlerArgs, HandlerData,
 };
 
+pub mod handlers;
+
+pub mod health;
+pub use health::{EnforcementMode, Health};
+
+pub mod identity;
+pub use identity::SubjectId;
+
+mod invalidate;
+
+pub mod layout;
+pub use layout::{AttributeLayout, LayoutChange};
+
 pub mod mcp;
 pub use mcp::Connection;
 
+pub mod migrate;
+pub use migrate::LabelMigration;
+
+pub mod mirror;
+pub use mirror::MirrorSink;
+
+pub mod overrides;
+pub use overrides::AnswerOverride;
+
+mod outstanding;
+
 mod parser;
 
+mod pending;
+
+pub mod plugin;
+pub use plugin::{PluginHandler, PluginRegistry};
+
+#[cfg(feature = "plugins")]
+pub use plugin::SharedLibrary;
+
+#[cfg(feature = "wasm")]
+pub use plugin::WasmModule;
+
+pub mod policy;
+pub use policy::PolicyLoader;
+
+mod positions;
+
+pub mod process;
+pub use process::ProcessInfo;
+
+pub mod proto;
+
 mod reader;
 use reader::{AsyncReader, NativeByteOrderReader};
 
+#[cfg(feature = "recording")]
+pub mod recording;
+#[cfg(feature = "recording")]
+pub use recording::{SessionRecording, SessionReplay};
+
+pub mod redact;
+pub use redact::Redaction;
+
+mod rename;
+
+mod retry;
+
 pub mod request;
 pub use request::{
-    AuthRequestData, DecisionAnswer, FetchAnswer, MedusaAnswer, MedusaRequest, RequestType,
-    UpdateAnswer,
+    AuthRequestData, CompletedRequest, DecisionAnswer, FetchAnswer, FetchStatus, MedusaAnswer,
+    MedusaRequest, RequestType, UpdateAnswer, UpdateStatus,
 };
 
+#[cfg(feature = "repl")]
+pub mod repl;
+#[cfg(feature = "repl")]
+pub use repl::Repl;
+
+pub mod rule;
+pub use rule::Rule;
+
+#[cfg(feature = "scripting")]
+pub mod script;
+#[cfg(feature = "scripting")]
+pub use script::ScriptHandler;
+
+pub mod sched;
+pub use sched::ThreadScheduling;
+
+pub mod session;
+pub use session::Session;
+
+mod shadow;
+
+pub mod siem;
+pub use siem::{SiemFormat, SiemSink};
+
+pub mod slo;
+pub use slo::{Degraded, LatencySlo, SloAlert};
+
+pub mod snapshot;
+pub use snapshot::Snapshot;
+
 mod space;
 pub use space::{Space, SpaceBuilder, VirtualSpace};
 
+pub mod stats;
+pub use stats::{PipelineStats, SpaceCounts, Stage, StageTimings};
+
+pub mod suppress;
+pub use suppress::SuppressingSink;
+
+pub mod te;
+pub use te::TePolicy;
+
+#[cfg(feature = "testing")]
+pub mod testing;
+
 /// Anything related to tree structure including builders.
 pub mod tree;
 pub use tree::{Node, NodeBuilder, Tree, TreeBuilder};
 
+pub mod tripwire;
+pub use tripwire::{Tripwire, TripwireAlert};
+
+pub mod usage;
+pub use usage::{ResourceAccounting, Usage, UsageCategory};
+
+mod watchdog;
+
 mod writer;
 use writer::Writer;
 
 type Command = u32;
+
+// hasher of the lookup tables used by every request, keys are not attacker controlled
+type FastHasher = ahash::RandomState;
+type FastHashMap<K, V> = std::collections::HashMap<K, V, FastHasher>;
+type FastDashMap<K, V> = dashmap::DashMap<K, V, FastHasher>;
@@ -0,0 +1,163 @@
+//! Authorization requests waiting for an answer.
+
+use crate::medusa::{AnswerError, FastDashMap};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+/// Number of answered requests remembered, so that a second answer to one of them can be told
+/// apart from an answer to a request which was never received.
+const ANSWERED_HISTORY: usize = 4096;
+
+/// Authorization requests received from the security module and not answered yet. Each request
+/// must be answered exactly once; an answer to a request which was already answered or never
+/// received would be taken by the security module as the answer to a different request, or
+/// break the connection.
+pub struct OutstandingRequests {
+    requests: FastDashMap<u64, RequestState>,
+    waiting: AtomicUsize,
+
+    // ids of the most recently answered requests, each removed from `requests` once overwritten
+    answered: Box<[AtomicU64]>,
+    next_answered: AtomicUsize,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum RequestState {
+    Waiting,
+    Answered,
+}
+
+impl Default for OutstandingRequests {
+    fn default() -> Self {
+        Self {
+            requests: Default::default(),
+            waiting: AtomicUsize::new(0),
+            answered: (0..ANSWERED_HISTORY).map(|_| AtomicU64::new(0)).collect(),
+            next_answered: AtomicUsize::new(0),
+        }
+    }
+}
+
+impl OutstandingRequests {
+    /// Remembers that request `request_id` was received and waits for an answer.
+    pub fn receive(&self, request_id: u64) {
+        match self.requests.insert(request_id, RequestState::Waiting) {
+            Some(RequestState::Waiting) => eprintln!(
+                "request {} received again before it was answered",
+                request_id
+            ),
+            _ => {
+                self.waiting.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Marks request `request_id` as answered. Its answer may be written only if this succeeds.
+    ///
+    /// Returns `AnswerError` if the request was already answered or was never received.
+    pub fn answer(&self, request_id: u64) -> Result<(), AnswerError> {
+        {
+            // the entry is locked, so that a concurrent second answer is recognized
+            let mut state = self
+                .requests
+                .get_mut(&request_id)
+                .ok_or(AnswerError::UnknownRequestError(request_id))?;
+            if *state == RequestState::Answered {
+                return Err(AnswerError::AlreadyAnsweredError(request_id));
+            }
+            *state = RequestState::Answered;
+        }
+        self.waiting.fetch_sub(1, Ordering::Relaxed);
+
+        let slot = self.next_answered.fetch_add(1, Ordering::Relaxed) % ANSWERED_HISTORY;
+        let forgotten = self.answered[slot].swap(request_id, Ordering::Relaxed);
+        self.requests
+            .remove_if(&forgotten, |_, state| *state == RequestState::Answered);
+
+        Ok(())
+    }
+
+    /// Returns the number of requests waiting for an answer.
+    pub fn len(&self) -> usize {
+        self.waiting.load(Ordering::Relaxed)
+    }
+
+    /// Returns `true` if no request waits for an answer.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn answers_each_request_once() {
+        let requests = OutstandingRequests::default();
+        requests.receive(1);
+
+        assert!(requests.answer(1).is_ok());
+        assert!(matches!(
+            requests.answer(1),
+            Err(AnswerError::AlreadyAnsweredError(1))
+        ));
+        assert!(matches!(
+            requests.answer(2),
+            Err(AnswerError::UnknownRequestError(2))
+        ));
+    }
+
+    #[test]
+    fn forgets_answers_beyond_history() {
+        let requests = OutstandingRequests::default();
+        let last = ANSWERED_HISTORY as u64 + 1;
+        for request_id in 1..=last {
+            requests.receive(request_id);
+            requests.answer(request_id).unwrap();
+        }
+
+        // the oldest answer was overwritten, so the id may be reused by a new request
+        assert!(matches!(
+            requests.answer(1),
+            Err(AnswerError::UnknownRequestError(1))
+        ));
+        assert!(matches!(
+            requests.answer(2),
+            Err(AnswerError::AlreadyAnsweredError(2))
+        ));
+        assert!(matches!(
+            requests.answer(last),
+            Err(AnswerError::AlreadyAnsweredError(_))
+        ));
+
+        requests.receive(1);
+        assert_eq!(requests.len(), 1);
+        assert!(requests.answer(1).is_ok());
+        assert!(requests.is_empty());
+    }
+
+    #[test]
+    fn counts_waiting_requests() {
+        let requests = OutstandingRequests::default();
+        assert!(requests.is_empty());
+
+        requests.receive(1);
+        requests.receive(2);
+        // received again before it was answered, still one request
+        requests.receive(2);
+        assert_eq!(requests.len(), 2);
+
+        requests.answer(2).unwrap();
+        assert_eq!(requests.len(), 1);
+        assert!(requests.answer(2).is_err());
+        assert!(requests.answer(3).is_err());
+        assert_eq!(requests.len(), 1);
+
+        // an answered request may be received again
+        requests.receive(2);
+        assert_eq!(requests.len(), 2);
+        requests.answer(1).unwrap();
+        requests.answer(2).unwrap();
+        assert!(requests.is_empty());
+    }
+}
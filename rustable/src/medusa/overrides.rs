@@ -0,0 +1,361 @@
+//! Emergency overrides of answers managed at runtime, see [`AnswerOverride`].
+
+use crate::medusa::audit::{self, answer_name};
+use crate::medusa::{AuthRequestData, Config, MedusaAnswer};
+use std::fmt;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Answer forced on matching requests until it expires, for emergency interventions such as
+/// unblocking a service denied by a faulty policy without changing the policy.
+///
+/// A request matches if a virtual space of its subject matches the subject pattern, its event
+/// matches the event pattern and a virtual space of its object matches the object pattern.
+/// Patterns are names in which `*` stands for any sequence of characters, so `*` alone matches
+/// anything, including requests without an object. The handlers of a matching request still
+/// run, so that e.g. hierarchy handlers keep entities in their trees, only their answer is
+/// replaced.
+///
+/// Overrides are added by [`Context::add_override`] or by the `override` command of the
+/// control socket, see [`control`](crate::medusa::control). Adding, removal and expiry of an
+/// override as well as every answer it changes are written to the output.
+///
+/// [`Context::add_override`]: crate::medusa::Context::add_override
+#[derive(Debug, Clone)]
+pub struct AnswerOverride {
+    subject: String,
+    event: String,
+    object: String,
+    answer: MedusaAnswer,
+    duration: Duration,
+    reason: Option<String>,
+}
+
+impl AnswerOverride {
+    /// Creates new `AnswerOverride` forcing `answer` on requests of `event` by subjects in
+    /// spaces matching `subject` on objects in spaces matching `object` for `duration`.
+    pub fn new(
+        subject: &str,
+        event: &str,
+        object: &str,
+        answer: MedusaAnswer,
+        duration: Duration,
+    ) -> Self {
+        Self {
+            subject: subject.to_owned(),
+            event: event.to_owned(),
+            object: object.to_owned(),
+            answer,
+            duration,
+            reason: None,
+        }
+    }
+
+    /// Sets the reason of the override, written to the output with it.
+    ///
+    /// Returns `Self`.
+    pub fn reason(mut self, reason: &str) -> Self {
+        self.reason = Some(reason.to_owned());
+        self
+    }
+
+    fn matches(&self, config: &Config, auth_data: &AuthRequestData) -> bool {
+        if !matches_pattern(&self.event, auth_data.evtype.name()) {
+            return false;
+        }
+
+        let subject_spaces = audit::space_names(config, &auth_data.subject);
+        if self.subject != "*"
+            && !subject_spaces
+                .iter()
+                .any(|x| matches_pattern(&self.subject, x))
+        {
+            return false;
+        }
+
+        if self.object == "*" {
+            return true;
+        }
+        let object_spaces = auth_data
+            .object
+            .as_ref()
+            .map(|x| audit::space_names(config, x))
+            .unwrap_or_default();
+        object_spaces
+            .iter()
+            .any(|x| matches_pattern(&self.object, x))
+    }
+}
+
+impl fmt::Display for AnswerOverride {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {} {}",
+            self.subject,
+            self.event,
+            self.object,
+            answer_name(self.answer)
+        )?;
+        if let Some(reason) = &self.reason {
+            write!(f, " ({})", reason)?;
+        }
+
+        Ok(())
+    }
+}
+
+struct ActiveOverride {
+    id: u64,
+    value: AnswerOverride,
+    expires: Instant,
+}
+
+/// Overrides active on a connection.
+#[derive(Default)]
+pub(crate) struct Overrides {
+    next_id: AtomicU64,
+    // number of active overrides, so that requests do not lock while there are none
+    len: AtomicUsize,
+    active: Mutex<Vec<ActiveOverride>>,
+}
+
+impl Overrides {
+    /// Activates `value` and returns its id.
+    pub(crate) fn add(&self, value: AnswerOverride) -> u64 {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+        println!("override {} added for {:?}: {}", id, value.duration, value);
+
+        let mut active = self.active.lock().unwrap();
+        active.push(ActiveOverride {
+            id,
+            expires: Instant::now() + value.duration,
+            value,
+        });
+        self.len.store(active.len(), Ordering::Relaxed);
+
+        id
+    }
+
+    /// Deactivates override `id`. Returns `false` if there is no such active override.
+    pub(crate) fn remove(&self, id: u64) -> bool {
+        let mut active = self.active.lock().unwrap();
+        let removed = match active.iter().position(|x| x.id == id) {
+            Some(i) => active.remove(i),
+            None => return false,
+        };
+        self.len.store(active.len(), Ordering::Relaxed);
+        println!("override {} removed: {}", id, removed.value);
+
+        true
+    }
+
+    /// Returns the active overrides with their ids and the time left until they expire.
+    pub(crate) fn list(&self) -> Vec<(u64, AnswerOverride, Duration)> {
+        let mut active = self.active.lock().unwrap();
+        self.expire(&mut active);
+
+        let now = Instant::now();
+        active
+            .iter()
+            .map(|x| (x.id, x.value.clone(), x.expires - now))
+            .collect()
+    }
+
+    /// Returns the answer of the most recent active override matching the request, or
+    /// `answer` if there is none.
+    pub(crate) fn apply(
+        &self,
+        config: &Config,
+        auth_data: &AuthRequestData,
+        answer: MedusaAnswer,
+    ) -> MedusaAnswer {
+        if self.len.load(Ordering::Relaxed) == 0 {
+            return answer;
+        }
+
+        let mut active = self.active.lock().unwrap();
+        self.expire(&mut active);
+
+        let matching = active
+            .iter()
+            .rev()
+            .find(|x| x.value.matches(config, auth_data));
+        match matching {
+            Some(x) if x.value.answer != answer => {
+                println!(
+                    "override {}: request {} answered {} instead of {}",
+                    x.id,
+                    auth_data.request_id,
+                    answer_name(x.value.answer),
+                    answer_name(answer)
+                );
+                x.value.answer
+            }
+            _ => answer,
+        }
+    }
+
+    fn expire(&self, active: &mut Vec<ActiveOverride>) {
+        let now = Instant::now();
+        active.retain(|x| {
+            let expired = x.expires <= now;
+            if expired {
+                println!("override {} expired: {}", x.id, x.value);
+            }
+            !expired
+        });
+        self.len.store(active.len(), Ordering::Relaxed);
+    }
+}
+
+/// Returns whether `name` matches `pattern`, in which `*` stands for any sequence of
+/// characters.
+fn matches_pattern(pattern: &str, name: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let mut rest = match name.strip_prefix(first) {
+        Some(rest) => rest,
+        None => return false,
+    };
+
+    let parts = parts.collect::<Vec<_>>();
+    let (last, middle) = match parts.split_last() {
+        Some(split) => split,
+        // no `*` in the pattern
+        None => return rest.is_empty(),
+    };
+    for part in middle {
+        rest = match rest.find(part) {
+            Some(i) => &rest[i + part.len()..],
+            None => return false,
+        };
+    }
+
+    rest.ends_with(last)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::medusa::SpaceBuilder;
+
+    fn config() -> Config {
+        let space = |name: &'static str, path: &'static str| {
+            SpaceBuilder::new()
+                .with_name(name)
+                .with_path_recursive(path)
+        };
+        Config::builder()
+            .add_space(space("web", "fs/srv/www"))
+            .add_space(space("secrets", "fs/etc/secrets"))
+            .build()
+            .unwrap()
+    }
+
+    fn allow(subject: &str, event: &str, object: &str) -> AnswerOverride {
+        AnswerOverride::new(
+            subject,
+            event,
+            object,
+            MedusaAnswer::Allow,
+            Duration::from_secs(60),
+        )
+    }
+
+    #[test]
+    fn matches_patterns() {
+        assert!(matches_pattern("*", ""));
+        assert!(matches_pattern("*", "web"));
+
+        assert!(matches_pattern("w*", "web"));
+        assert!(matches_pattern("w*", "w"));
+        assert!(!matches_pattern("w*", "aw"));
+
+        assert!(matches_pattern("*b", "web"));
+        assert!(matches_pattern("*b", "b"));
+        assert!(!matches_pattern("*b", "bw"));
+
+        assert!(matches_pattern("a*b*c", "abc"));
+        assert!(matches_pattern("a*b*c", "axxbyyc"));
+        assert!(matches_pattern("a*b*c", "abcbc"));
+        assert!(!matches_pattern("a*b*c", "acb"));
+        assert!(!matches_pattern("a*b*c", "abcx"));
+
+        assert!(matches_pattern("web", "web"));
+        assert!(!matches_pattern("web", "webs"));
+        assert!(!matches_pattern("web", "we"));
+        assert!(!matches_pattern("", "web"));
+    }
+
+    #[test]
+    fn does_not_overlap_prefix_and_suffix() {
+        assert!(!matches_pattern("a*a", "a"));
+        assert!(matches_pattern("a*a", "aa"));
+        assert!(matches_pattern("a*a", "aba"));
+        assert!(!matches_pattern("ab*ba", "aba"));
+    }
+
+    #[test]
+    fn matches_requests() {
+        let config = config();
+        let request = AuthRequestData::for_test(&config, "open", &["web"], Some(&["secrets"]));
+
+        assert!(allow("*", "*", "*").matches(&config, &request));
+        assert!(allow("web", "open", "sec*").matches(&config, &request));
+        assert!(allow("w*", "o*", "*ets").matches(&config, &request));
+        assert!(!allow("secrets", "open", "*").matches(&config, &request));
+        assert!(!allow("web", "kill", "*").matches(&config, &request));
+        assert!(!allow("web", "open", "web").matches(&config, &request));
+
+        let unlabeled = AuthRequestData::for_test(&config, "open", &[], Some(&[]));
+        assert!(allow("*", "open", "*").matches(&config, &unlabeled));
+        assert!(!allow("w*", "open", "*").matches(&config, &unlabeled));
+    }
+
+    #[test]
+    fn matches_requests_without_object() {
+        let config = config();
+        let request = AuthRequestData::for_test(&config, "fork", &["web"], None);
+
+        assert!(allow("web", "fork", "*").matches(&config, &request));
+        assert!(!allow("web", "fork", "web").matches(&config, &request));
+        assert!(!allow("web", "fork", "s*").matches(&config, &request));
+    }
+
+    #[test]
+    fn removes_expired_overrides() {
+        let config = config();
+        let request = AuthRequestData::for_test(&config, "open", &["web"], Some(&["secrets"]));
+        let overrides = Overrides::default();
+
+        let active = overrides.add(allow("web", "open", "*"));
+        overrides.add(AnswerOverride::new(
+            "*",
+            "*",
+            "*",
+            MedusaAnswer::Deny,
+            Duration::ZERO,
+        ));
+        assert_eq!(overrides.len.load(Ordering::Relaxed), 2);
+
+        // the expired override is newer, but no longer applies
+        assert_eq!(
+            overrides.apply(&config, &request, MedusaAnswer::Deny),
+            MedusaAnswer::Allow
+        );
+        assert_eq!(overrides.len.load(Ordering::Relaxed), 1);
+        let listed = overrides.list();
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].0, active);
+
+        assert!(overrides.remove(active));
+        assert!(!overrides.remove(active));
+        assert_eq!(overrides.len.load(Ordering::Relaxed), 0);
+        assert_eq!(
+            overrides.apply(&config, &request, MedusaAnswer::Deny),
+            MedusaAnswer::Deny
+        );
+    }
+}
@@ -2,6 +2,7 @@ use crate::cstr_to_string;
 use crate::medusa::constants::*;
 use crate::medusa::*;
 use nom::bytes::complete::take;
+use nom::error::{Error, ErrorKind};
 use nom::number::complete::*;
 use nom::IResult;
 use std::num::NonZeroU64;
@@ -51,8 +52,13 @@ pub fn parse_evtype_header(i: &[u8]) -> IResult<&[u8], MedusaEvtypeHeader> {
             monitoring_bit,
             ev_sub,
             ev_obj: NonZeroU64::new(ev_obj),
-            name: cstr_to_string(name),
-            ev_name: [cstr_to_string(ev_name1), cstr_to_string(ev_name2)],
+            name: cstr_to_string(name).into(),
+            ev_name: [
+                cstr_to_string(ev_name1).into(),
+                cstr_to_string(ev_name2).into(),
+            ],
+            event_id: None,
+            config_generation: 0,
         },
     ))
 }
@@ -63,14 +69,12 @@ pub fn parse_attribute_header(i: &[u8]) -> IResult<&[u8], MedusaAttributeHeader>
     let (i, r#type) = le_u8(i)?;
     let (i, name) = take(MEDUSA_COMM_ATTRNAME_MAX)(i)?;
 
-    // TODO return error
-    let mods = AttributeMods::from_bits(r#type & 0xc0).expect("Unknown attribute mod");
-    let endianness = ((r#type & 0x30) >> 4)
-        .try_into()
-        .expect("Unknown attribute endianness");
-    let data_type = (r#type & 0x0f)
-        .try_into()
-        .expect("Unknown attribute data type");
+    let mods = AttributeMods::from_bits_truncate(r#type);
+    let endianness = AttributeEndianness::try_from((r#type & 0x30) >> 4)
+        .map_err(|_| nom::Err::Failure(Error::new(i, ErrorKind::Verify)))?;
+    // unknown data types are kept, so that newer kernels do not break the connection
+    let data_type =
+        AttributeDataType::try_from(r#type & 0x0f).unwrap_or(AttributeDataType::Unknown);
 
     Ok((
         i,
@@ -80,7 +84,8 @@ pub fn parse_attribute_header(i: &[u8]) -> IResult<&[u8], MedusaAttributeHeader>
             mods,
             endianness,
             data_type,
-            name: cstr_to_string(name),
+            raw_type: r#type,
+            name: attribute::intern_name(&cstr_to_string(name)),
         },
     ))
 }
@@ -94,7 +99,7 @@ pub fn parse_update_answer(i: &[u8]) -> IResult<&[u8], UpdateAnswer> {
         UpdateAnswer {
             class_id,
             msg_seq,
-            status,
+            status: status.into(),
         },
     ))
 }
@@ -117,6 +122,7 @@ pub fn parse_fetch_answer_stage1(
             class_id,
             msg_seq,
             data: data.to_vec(),
+            status: FetchStatus::Ok,
         },
     ))
 }
@@ -0,0 +1,181 @@
+//! Identification of update and fetch requests and routing of their answers.
+
+use crate::medusa::{Context, FastDashMap, FetchAnswer, UpdateAnswer};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Weak};
+use std::time::{Duration, Instant};
+use tokio::sync::oneshot::{self, error::TryRecvError};
+
+/// Id of the first request sent to the security module.
+const FIRST_REQUEST_ID: u64 = 111;
+
+/// Default time after which a request still waiting for an answer is abandoned.
+pub(crate) const PENDING_REQUEST_DEFAULT_MAX_AGE: Duration = Duration::from_secs(60);
+
+/// Longest time between two sweeps of abandoned requests.
+const PENDING_SWEEP_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Fetch request sent to the security module, see [`SentFetch::answer`].
+pub(crate) struct SentFetch {
+    class_id: u64,
+    id: u64,
+    receiver: oneshot::Receiver<FetchAnswer>,
+}
+
+impl SentFetch {
+    pub(crate) fn new(class_id: u64, id: u64, receiver: oneshot::Receiver<FetchAnswer>) -> Self {
+        Self {
+            class_id,
+            id,
+            receiver,
+        }
+    }
+
+    /// Waits for the answer. A request abandoned before its answer arrived is answered as
+    /// [`FetchStatus::TimedOut`](crate::medusa::FetchStatus::TimedOut).
+    pub(crate) async fn answer(self) -> FetchAnswer {
+        self.receiver
+            .await
+            .unwrap_or_else(|_| FetchAnswer::timed_out(self.class_id, self.id))
+    }
+}
+
+/// Update and fetch requests waiting for an answer from the security module.
+pub struct PendingRequests {
+    next_id: AtomicU64,
+
+    // senders of the answers with the time the request was made
+    updates: FastDashMap<u64, (Instant, oneshot::Sender<UpdateAnswer>)>,
+    fetches: FastDashMap<u64, (Instant, oneshot::Sender<FetchAnswer>)>,
+}
+
+impl Default for PendingRequests {
+    fn default() -> Self {
+        Self {
+            next_id: AtomicU64::new(FIRST_REQUEST_ID),
+            updates: Default::default(),
+            fetches: Default::default(),
+        }
+    }
+}
+
+impl PendingRequests {
+    /// Returns a new request id. Ids wrap around and those of requests which are still waiting
+    /// for an answer are skipped, so that an answer is never routed to a wrong request.
+    pub fn next_id(&self) -> u64 {
+        loop {
+            // wraps around on overflow
+            let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+            if !self.updates.contains_key(&id) && !self.fetches.contains_key(&id) {
+                return id;
+            }
+
+            eprintln!("request id {} is still pending, skipping it", id);
+        }
+    }
+
+    /// Returns a new id of an update request and the receiver of its answer.
+    pub fn register_update(&self) -> (u64, oneshot::Receiver<UpdateAnswer>) {
+        let (sender, receiver) = oneshot::channel();
+        let id = self.next_id();
+        self.updates.insert(id, (Instant::now(), sender));
+
+        (id, receiver)
+    }
+
+    /// Returns a new id of a fetch request and the receiver of its answer.
+    pub fn register_fetch(&self) -> (u64, oneshot::Receiver<FetchAnswer>) {
+        let (sender, receiver) = oneshot::channel();
+        let id = self.next_id();
+        self.fetches.insert(id, (Instant::now(), sender));
+
+        (id, receiver)
+    }
+
+    /// Passes `answer` to the update request it belongs to. The receiver may be gone, e.g. if
+    /// nobody waits for the answer.
+    pub fn answer_update(&self, answer: UpdateAnswer) {
+        match self.updates.remove(&answer.msg_seq) {
+            Some((_, (_, sender))) => {
+                let _ = sender.send(answer);
+            }
+            None => eprintln!(
+                "update answer to unknown request {} of class 0x{:x}",
+                answer.msg_seq, answer.class_id
+            ),
+        }
+    }
+
+    /// Passes `answer` to the fetch request it belongs to.
+    pub fn answer_fetch(&self, answer: FetchAnswer) {
+        match self.fetches.remove(&answer.msg_seq) {
+            Some((_, (_, sender))) => {
+                let _ = sender.send(answer);
+            }
+            None => eprintln!(
+                "fetch answer to unknown request {} of class 0x{:x}",
+                answer.msg_seq, answer.class_id
+            ),
+        }
+    }
+
+    /// Returns the number of requests waiting for an answer.
+    pub(crate) fn len(&self) -> usize {
+        self.updates.len() + self.fetches.len()
+    }
+
+    /// Returns the numbers of update and fetch requests waiting for an answer.
+    pub(crate) fn lens(&self) -> (usize, usize) {
+        (self.updates.len(), self.fetches.len())
+    }
+
+    /// Removes requests nobody waits for anymore and those older than `max_age`.
+    ///
+    /// Returns the number of removed requests.
+    fn sweep(&self, max_age: Duration) -> usize {
+        let before = self.len();
+        let now = Instant::now();
+        self.updates
+            .retain(|_, (sent, sender)| !sender.is_closed() && now.duration_since(*sent) < max_age);
+        self.fetches
+            .retain(|_, (sent, sender)| !sender.is_closed() && now.duration_since(*sent) < max_age);
+
+        before.saturating_sub(self.len())
+    }
+}
+
+/// Periodically removes abandoned requests of `ctx` until the returned sender is dropped, see
+/// [`ConfigBuilder::pending_request_max_age`].
+///
+/// [`ConfigBuilder::pending_request_max_age`]: crate::medusa::ConfigBuilder::pending_request_max_age
+pub(crate) fn spawn_sweeper(ctx: &Arc<Context>) -> oneshot::Sender<()> {
+    let max_age = ctx.config().pending_request_max_age;
+    let executor = Arc::clone(&ctx.executor);
+    let (stop, mut stopped) = oneshot::channel();
+    let ctx = Arc::downgrade(ctx);
+
+    executor.clone().spawn_named(
+        "medusa-sweeper",
+        Box::pin(async move {
+            loop {
+                executor.sleep(max_age.min(PENDING_SWEEP_INTERVAL)).await;
+                if !matches!(stopped.try_recv(), Err(TryRecvError::Empty)) {
+                    break;
+                }
+
+                let ctx = match Weak::upgrade(&ctx) {
+                    Some(ctx) => ctx,
+                    None => break,
+                };
+
+                let abandoned = ctx.pending.sweep(max_age);
+                if abandoned > 0 {
+                    eprintln!("removed {} abandoned pending requests", abandoned);
+                    ctx.stats.record_abandoned(abandoned as u64);
+                }
+            }
+        }),
+    );
+
+    stop
+}
@@ -0,0 +1,583 @@
+//! Handlers which can be loaded and unloaded while the server is running.
+//!
+//! The config declares plugin handler slots with [`ConfigBuilder::add_plugin_event_handler`],
+//! each identified by a name. Requests reaching a slot are passed to the plugin currently
+//! registered under that name in the [`PluginRegistry`]. If there is none, the slot answers
+//! [`MedusaAnswer::Err`].
+//!
+//! With the `plugins` feature, plugins can be shipped as shared libraries, see
+//! [`SharedLibrary`]. With the `wasm` feature, they can be shipped as sandboxed WebAssembly
+//! modules, see [`WasmModule`].
+//!
+//! [`ConfigBuilder::add_plugin_event_handler`]: crate::medusa::ConfigBuilder::add_plugin_event_handler
+
+use crate::medusa::{Context, HandlerArgs, MedusaAnswer};
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+#[cfg(feature = "plugins")]
+pub use shared_library::*;
+
+#[cfg(feature = "wasm")]
+pub use wasm_module::*;
+
+/// Handler logic which can be replaced at runtime.
+#[async_trait]
+pub trait PluginHandler: Send + Sync {
+    /// Returns the name of the slot this plugin fills.
+    fn name(&self) -> &str;
+
+    /// Decides the authorization request.
+    async fn handle(&self, ctx: &Context, args: HandlerArgs<'_>) -> anyhow::Result<MedusaAnswer>;
+}
+
+/// Plugins currently filling the plugin handler slots.
+#[derive(Default)]
+pub struct PluginRegistry {
+    plugins: RwLock<HashMap<String, Arc<dyn PluginHandler>>>,
+}
+
+impl PluginRegistry {
+    /// Registers `plugin`, replacing a previously registered plugin having the same name.
+    /// Requests which are already being handled by the old plugin finish with it.
+    pub fn insert(&self, plugin: Arc<dyn PluginHandler>) {
+        let name = plugin.name().to_owned();
+        println!("plugin {} registered", name);
+        self.plugins.write().unwrap().insert(name, plugin);
+    }
+
+    /// Unregisters the plugin having the given name.
+    ///
+    /// Returns `false` if there is no such plugin.
+    pub fn remove(&self, name: &str) -> bool {
+        let removed = self.plugins.write().unwrap().remove(name).is_some();
+        if removed {
+            println!("plugin {} unregistered", name);
+        }
+        removed
+    }
+
+    /// Returns the plugin having the given name.
+    pub fn get(&self, name: &str) -> Option<Arc<dyn PluginHandler>> {
+        self.plugins.read().unwrap().get(name).cloned()
+    }
+
+    /// Returns names of all registered plugins.
+    pub fn names(&self) -> Vec<String> {
+        let mut names = self
+            .plugins
+            .read()
+            .unwrap()
+            .keys()
+            .cloned()
+            .collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+}
+
+pub(crate) async fn plugin_handler(
+    ctx: &Context,
+    args: HandlerArgs<'_>,
+) -> anyhow::Result<MedusaAnswer> {
+    let name = &args.handler_data.name;
+    let plugin = ctx
+        .config()
+        .plugins()
+        .get(name)
+        .ok_or_else(|| anyhow::anyhow!("plugin `{}` is not loaded", name))?;
+
+    plugin.handle(ctx, args).await
+}
+
+#[cfg(feature = "plugins")]
+mod shared_library {
+    use super::PluginHandler;
+    use crate::medusa::{Context, HandlerArgs, MedusaAnswer, PluginError};
+    use async_trait::async_trait;
+    use libloading::Library;
+    use std::ffi::{c_char, c_void, CStr, CString};
+    use std::path::{Path, PathBuf};
+
+    /// Version of the C interface, see [`SharedLibrary`].
+    pub const PLUGIN_ABI_VERSION: u32 = 1;
+
+    /// Selects the event in [`PluginRequest::get_attribute`].
+    pub const PLUGIN_EVENT: u32 = 0;
+
+    /// Selects the subject in [`PluginRequest::get_attribute`].
+    pub const PLUGIN_SUBJECT: u32 = 1;
+
+    /// Selects the object in [`PluginRequest::get_attribute`].
+    pub const PLUGIN_OBJECT: u32 = 2;
+
+    /// Authorization request passed to `rustable_plugin_handle`. All pointers are valid only
+    /// during the call.
+    #[repr(C)]
+    pub struct PluginRequest {
+        /// Name of the event.
+        pub event: *const c_char,
+
+        /// Name of the subject class.
+        pub subject: *const c_char,
+
+        /// Name of the object class or null if the event has no object.
+        pub object: *const c_char,
+
+        /// Opaque pointer which has to be passed to `get_attribute`.
+        pub ctx: *const c_void,
+
+        /// Returns content of attribute `name` of the event, subject or object selected by
+        /// `entity` and stores its length in `len`, or returns null if there is no such
+        /// attribute.
+        pub get_attribute: unsafe extern "C" fn(
+            ctx: *const c_void,
+            entity: u32,
+            name: *const c_char,
+            len: *mut usize,
+        ) -> *const u8,
+    }
+
+    type AbiVersionFn = unsafe extern "C" fn() -> u32;
+    type NameFn = unsafe extern "C" fn() -> *const c_char;
+    type HandleFn = unsafe extern "C" fn(request: *const PluginRequest) -> i32;
+
+    /// Plugin loaded from a shared library with the following C interface:
+    ///
+    /// ```c
+    /// uint32_t rustable_plugin_abi_version(void);   /* returns PLUGIN_ABI_VERSION */
+    /// const char *rustable_plugin_name(void);        /* name of the handler slot */
+    /// int32_t rustable_plugin_handle(const struct plugin_request *request);
+    /// ```
+    ///
+    /// `rustable_plugin_handle` returns the numeric value of a [`MedusaAnswer`] or a negative
+    /// number on error. It is called from asynchronous tasks and must not block. The library is
+    /// unloaded once it is unregistered and no request is being handled by it.
+    pub struct SharedLibrary {
+        name: String,
+        path: PathBuf,
+        handle: HandleFn,
+
+        // keeps `handle` valid
+        _library: Library,
+    }
+
+    impl SharedLibrary {
+        /// Loads a plugin from the shared library at `path`.
+        ///
+        /// Returns `SharedLibrary` or `PluginError` if the library could not be loaded or
+        /// does not implement the expected interface.
+        pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, PluginError> {
+            let path = path.as_ref().to_owned();
+            let error = |e| PluginError::LoadError(path.clone(), e);
+
+            // SAFETY: initialization routines of the library are trusted, plugins are
+            // installed by the administrator
+            let library = unsafe { Library::new(&path) }.map_err(error)?;
+
+            // SAFETY: signatures of the symbols are part of the documented interface
+            let (version, name, handle) = unsafe {
+                let version = library
+                    .get::<AbiVersionFn>(b"rustable_plugin_abi_version\0")
+                    .map_err(error)?;
+                let name = library
+                    .get::<NameFn>(b"rustable_plugin_name\0")
+                    .map_err(error)?;
+                let handle = library
+                    .get::<HandleFn>(b"rustable_plugin_handle\0")
+                    .map_err(error)?;
+
+                (version(), name(), *handle)
+            };
+
+            if version != PLUGIN_ABI_VERSION {
+                return Err(PluginError::AbiVersionError(path, version));
+            }
+
+            if name.is_null() {
+                return Err(PluginError::InvalidNameError(path));
+            }
+            // SAFETY: checked for null, the string is static in the library
+            let name = unsafe { CStr::from_ptr(name) }
+                .to_str()
+                .map_err(|_| PluginError::InvalidNameError(path.clone()))?
+                .to_owned();
+
+            println!("loaded plugin {} from {}", name, path.display());
+
+            Ok(Self {
+                name,
+                path,
+                handle,
+                _library: library,
+            })
+        }
+
+        /// Returns the path the plugin was loaded from.
+        pub fn path(&self) -> &Path {
+            &self.path
+        }
+    }
+
+    #[async_trait]
+    impl PluginHandler for SharedLibrary {
+        fn name(&self) -> &str {
+            &self.name
+        }
+
+        async fn handle(
+            &self,
+            _ctx: &Context,
+            args: HandlerArgs<'_>,
+        ) -> anyhow::Result<MedusaAnswer> {
+            let event = CString::new(args.evtype.name())?;
+            let subject = CString::new(args.subject.header.name())?;
+            let object = args
+                .object
+                .as_ref()
+                .map(|x| CString::new(x.header.name()))
+                .transpose()?;
+
+            let request = PluginRequest {
+                event: event.as_ptr(),
+                subject: subject.as_ptr(),
+                object: object.as_ref().map_or(std::ptr::null(), |x| x.as_ptr()),
+                ctx: &args as *const HandlerArgs as *const c_void,
+                get_attribute,
+            };
+
+            // SAFETY: `request` and everything it points to outlive the call
+            let res = unsafe { (self.handle)(&request) };
+
+            match res {
+                0 => Ok(MedusaAnswer::Yes),
+                1 => Ok(MedusaAnswer::Deny),
+                2 => Ok(MedusaAnswer::Skip),
+                3 => Ok(MedusaAnswer::Allow),
+                _ => anyhow::bail!("plugin {} failed with {}", self.name, res),
+            }
+        }
+    }
+
+    unsafe extern "C" fn get_attribute(
+        ctx: *const c_void,
+        entity: u32,
+        name: *const c_char,
+        len: *mut usize,
+    ) -> *const u8 {
+        // SAFETY: `ctx` is the pointer set in `SharedLibrary::handle`, which is still running
+        let args = &*(ctx as *const HandlerArgs);
+        let name = match CStr::from_ptr(name).to_str() {
+            Ok(name) => name,
+            Err(_) => return std::ptr::null(),
+        };
+
+        let value = match entity {
+            PLUGIN_EVENT => args.evtype.get_attribute(name).ok(),
+            PLUGIN_SUBJECT => args.subject.attributes.get(name).ok(),
+            PLUGIN_OBJECT => args
+                .object
+                .as_ref()
+                .and_then(|x| x.attributes.get(name).ok()),
+            _ => None,
+        };
+
+        match value {
+            Some(value) => {
+                *len = value.len();
+                value.as_ptr()
+            }
+            None => std::ptr::null(),
+        }
+    }
+}
+
+#[cfg(feature = "wasm")]
+mod wasm_module {
+    use super::PluginHandler;
+    use crate::medusa::tree::Node;
+    use crate::medusa::PluginError;
+    use crate::medusa::{Context, HandlerArgs, MedusaAnswer, MedusaClass, MedusaEvtype};
+    use async_trait::async_trait;
+    use std::path::{Path, PathBuf};
+    use std::sync::Arc;
+    use wasmtime::{Caller, Engine, InstancePre, Linker, Module, Store};
+
+    /// Name of the module whose functions are imported by WebAssembly plugins.
+    pub const WASM_HOST_MODULE: &str = "rustable";
+
+    /// Default amount of fuel available to a single request, see [`WasmModule::with_fuel`].
+    pub const WASM_DEFAULT_FUEL: u64 = 10_000_000;
+
+    const ENTITY_EVENT: u32 = 0;
+    const ENTITY_SUBJECT: u32 = 1;
+    const ENTITY_OBJECT: u32 = 2;
+
+    struct HostState {
+        evtype: MedusaEvtype,
+        subject: MedusaClass,
+        object: Option<MedusaClass>,
+        // roots of the trees by name
+        trees: Vec<(String, Arc<Node>)>,
+
+        answer: Option<i32>,
+        enter: Vec<(u32, String, String)>,
+    }
+
+    /// Plugin implemented by a WebAssembly module. Each request is handled by a fresh instance
+    /// limited by an amount of fuel, so the module can neither keep state between requests nor
+    /// stall the server.
+    ///
+    /// The module exports its linear memory as `memory` and a function `handle: () -> ()`. It
+    /// may import the following functions from module `rustable`, where `entity` is 0 for the
+    /// event, 1 for the subject and 2 for the object:
+    ///
+    /// ```text
+    /// get_attribute(entity, name_ptr, name_len, out_ptr, out_cap: i32) -> i32
+    /// enter_tree(entity, tree_ptr, tree_len, path_ptr, path_len: i32) -> i32
+    /// answer(answer: i32)
+    /// log(ptr, len: i32)
+    /// ```
+    ///
+    /// `get_attribute` copies at most `out_cap` bytes of the attribute into memory at `out_ptr`
+    /// and returns its full length, or -1 if there is no such attribute. `enter_tree` enters
+    /// the entity into the node at `path` of the tree or returns -1 if the path is not covered
+    /// by the tree. Entities are entered once `handle` returns. `answer` sets the numeric value
+    /// of the [`MedusaAnswer`], the request fails if `handle` returns without setting it.
+    pub struct WasmModule {
+        name: String,
+        path: PathBuf,
+        fuel: u64,
+        engine: Engine,
+        instance: InstancePre<HostState>,
+    }
+
+    impl WasmModule {
+        /// Compiles a plugin filling the slot `name` from the WebAssembly module at `path`,
+        /// which may be either in the binary or in the text format.
+        ///
+        /// Returns `WasmModule` or `PluginError` if the module could not be compiled or does
+        /// not match the host interface.
+        pub fn load<P: AsRef<Path>>(name: &str, path: P) -> Result<Self, PluginError> {
+            let path = path.as_ref().to_owned();
+
+            let instance = (|| {
+                let mut config = wasmtime::Config::new();
+                config.consume_fuel(true);
+                let engine = Engine::new(&config)?;
+
+                let module = Module::from_file(&engine, &path)?;
+                let linker = linker(&engine)?;
+                let instance = linker.instantiate_pre(&module)?;
+                anyhow::Ok((engine, instance))
+            })();
+            let (engine, instance) =
+                instance.map_err(|e| PluginError::WasmError(path.clone(), e))?;
+
+            println!("loaded plugin {} from {}", name, path.display());
+
+            Ok(Self {
+                name: name.to_owned(),
+                path,
+                fuel: WASM_DEFAULT_FUEL,
+                engine,
+                instance,
+            })
+        }
+
+        /// Sets the amount of fuel available to a single request. Roughly, one unit of fuel is
+        /// consumed by one WebAssembly instruction.
+        ///
+        /// Returns `Self`.
+        pub fn with_fuel(mut self, fuel: u64) -> Self {
+            self.fuel = fuel;
+            self
+        }
+
+        /// Returns the path the plugin was loaded from.
+        pub fn path(&self) -> &Path {
+            &self.path
+        }
+    }
+
+    #[async_trait]
+    impl PluginHandler for WasmModule {
+        fn name(&self) -> &str {
+            &self.name
+        }
+
+        async fn handle(
+            &self,
+            ctx: &Context,
+            args: HandlerArgs<'_>,
+        ) -> anyhow::Result<MedusaAnswer> {
+            let state = HostState {
+                evtype: args.evtype,
+                subject: args.subject,
+                object: args.object,
+                trees: ctx
+                    .config()
+                    .trees()
+                    .iter()
+                    .map(|x| (x.name().to_owned(), Arc::clone(x.root())))
+                    .collect(),
+                answer: None,
+                enter: Vec::new(),
+            };
+
+            let mut store = Store::new(&self.engine, state);
+            store.set_fuel(self.fuel)?;
+            let instance = self.instance.instantiate(&mut store)?;
+            instance
+                .get_typed_func::<(), ()>(&mut store, "handle")?
+                .call(&mut store, ())?;
+
+            let HostState {
+                evtype,
+                mut subject,
+                mut object,
+                answer,
+                enter,
+                ..
+            } = store.into_data();
+
+            for (entity, tree, path) in enter {
+                let entity = match entity {
+                    ENTITY_SUBJECT => &mut subject,
+                    _ => object.as_mut().expect("checked in enter_tree"),
+                };
+                if let Err(e) = entity.enter_tree(ctx, &evtype, &tree, &path).await {
+                    eprintln!("plugin {}: {}", self.name, e);
+                }
+            }
+
+            match answer {
+                Some(0) => Ok(MedusaAnswer::Yes),
+                Some(1) => Ok(MedusaAnswer::Deny),
+                Some(2) => Ok(MedusaAnswer::Skip),
+                Some(3) => Ok(MedusaAnswer::Allow),
+                Some(res) => anyhow::bail!("plugin {} answered {}", self.name, res),
+                None => anyhow::bail!("plugin {} did not answer", self.name),
+            }
+        }
+    }
+
+    fn linker(engine: &Engine) -> anyhow::Result<Linker<HostState>> {
+        let mut linker = Linker::new(engine);
+
+        linker.func_wrap(
+            WASM_HOST_MODULE,
+            "get_attribute",
+            |mut caller: Caller<'_, HostState>,
+             entity: u32,
+             name_ptr: u32,
+             name_len: u32,
+             out_ptr: u32,
+             out_cap: u32|
+             -> anyhow::Result<i32> {
+                let (memory, state) = memory(&mut caller)?;
+                let name = read_str(memory, name_ptr, name_len)?;
+
+                let value = match entity {
+                    ENTITY_EVENT => state.evtype.get_attribute(name).ok(),
+                    ENTITY_SUBJECT => state.subject.attributes.get(name).ok(),
+                    ENTITY_OBJECT => state
+                        .object
+                        .as_ref()
+                        .and_then(|x| x.attributes.get(name).ok()),
+                    _ => None,
+                };
+                let value = match value {
+                    Some(value) => value,
+                    None => return Ok(-1),
+                };
+
+                let len = value.len().min(out_cap as usize);
+                memory
+                    .get_mut(out_ptr as usize..out_ptr as usize + len)
+                    .ok_or_else(|| anyhow::anyhow!("output out of bounds"))?
+                    .copy_from_slice(&value[..len]);
+
+                Ok(value.len() as i32)
+            },
+        )?;
+
+        linker.func_wrap(
+            WASM_HOST_MODULE,
+            "enter_tree",
+            |mut caller: Caller<'_, HostState>,
+             entity: u32,
+             tree_ptr: u32,
+             tree_len: u32,
+             path_ptr: u32,
+             path_len: u32|
+             -> anyhow::Result<i32> {
+                let (memory, state) = memory(&mut caller)?;
+                let tree = read_str(memory, tree_ptr, tree_len)?;
+                let path = read_str(memory, path_ptr, path_len)?;
+
+                let valid_entity = match entity {
+                    ENTITY_SUBJECT => true,
+                    ENTITY_OBJECT => state.object.is_some(),
+                    _ => false,
+                };
+                let covered = state
+                    .trees
+                    .iter()
+                    .any(|(name, root)| name == tree && root.covers(path));
+                if !valid_entity || !covered {
+                    return Ok(-1);
+                }
+
+                state.enter.push((entity, tree.to_owned(), path.to_owned()));
+                Ok(0)
+            },
+        )?;
+
+        linker.func_wrap(
+            WASM_HOST_MODULE,
+            "answer",
+            |mut caller: Caller<'_, HostState>, answer: i32| {
+                caller.data_mut().answer = Some(answer);
+            },
+        )?;
+
+        linker.func_wrap(
+            WASM_HOST_MODULE,
+            "log",
+            |mut caller: Caller<'_, HostState>, ptr: u32, len: u32| -> anyhow::Result<()> {
+                let (memory, state) = memory(&mut caller)?;
+                println!(
+                    "{}: {}",
+                    state.evtype.name(),
+                    String::from_utf8_lossy(read(memory, ptr, len)?)
+                );
+                Ok(())
+            },
+        )?;
+
+        Ok(linker)
+    }
+
+    fn memory<'a>(
+        caller: &'a mut Caller<'_, HostState>,
+    ) -> anyhow::Result<(&'a mut [u8], &'a mut HostState)> {
+        let memory = caller
+            .get_export("memory")
+            .and_then(|x| x.into_memory())
+            .ok_or_else(|| anyhow::anyhow!("module does not export memory"))?;
+
+        Ok(memory.data_and_store_mut(caller))
+    }
+
+    fn read(memory: &[u8], ptr: u32, len: u32) -> anyhow::Result<&[u8]> {
+        memory
+            .get(ptr as usize..ptr as usize + len as usize)
+            .ok_or_else(|| anyhow::anyhow!("input out of bounds"))
+    }
+
+    fn read_str(memory: &[u8], ptr: u32, len: u32) -> anyhow::Result<&str> {
+        Ok(std::str::from_utf8(read(memory, ptr, len)?)?)
+    }
+}
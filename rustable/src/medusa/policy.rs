@@ -0,0 +1,920 @@
+//! Loading of declarative policy files.
+//!
+//! A policy file describes virtual spaces and hierarchy handlers, one statement per line.
+//! Everything after `#`, unless it is within a quoted string, is a comment. Statements following
+//! a `space` statement extend that space.
+//!
+//! ```text
+//! space all_files fs/ recursive
+//!
+//! space all_domains domains/ recursive
+//!     reads all_files all_domains
+//!     writes all_files all_domains
+//!     sees all_files all_domains
+//!
+//! hierarchy getfile fs attribute=filename from_object
+//! rule getfile deny if event.filename ~= "^/etc/shadow$" && subject.uid != 0
+//! ```
+//!
+//! Supported statements are `space <name> <path> [recursive]`, `reads`, `writes`, `sees` and
+//! `denies`, see [`SpaceBuilder::denies`], followed by space names, `monitors` followed by
+//! event names, `include_space <name>`, `exclude_space <name>`,
+//! `include_path <path> [recursive]`, `exclude_path <path> [recursive]`,
+//! `exclude_matching <pattern>`, see [`SpaceBuilder::exclude_matching`],
+//! `hierarchy <event> <tree> [attribute=<name>] [from_object] [pin]`, `cover` followed by event
+//! names, see [`ConfigBuilder::cover_events`], `executable <tree> <pattern> <path>`, see
+//! [`ExecutableMap`], and `rule <event> <rule>`, see [`rule`](crate::medusa::rule). All rules of
+//! an event, as well as all executables of a tree, form a single handler placed where the first
+//! of them appears.
+//!
+//! Before the statements are parsed, the policy is expanded, so that one policy source can be
+//! deployed to different machines:
+//!
+//! ```text
+//! include hosts/${HOSTNAME}.policy optional
+//! include conf.d
+//!
+//! for user in ${ADMINS:-root}
+//!     space home_${user} fs/home/${user} recursive
+//!         reads home_${user}
+//! end
+//! ```
+//!
+//! `${NAME}` is replaced by the value of variable `NAME`, which is looked up in the loop
+//! variables, the variables set by [`PolicyLoader::define`], the environment and finally
+//! `HOSTNAME`, which defaults to the name of the machine. An undefined variable is an error
+//! unless a default is given as in `${NAME:-default}`. `include <path> [optional]` expands
+//! the policy at `path`, relative to the including policy, in place. If `path` is a directory,
+//! its files ending with `.policy` are expanded in the order of their names, so fragments
+//! named e.g. `10-base.policy` and `50-nginx.policy` are merged in a well-defined order.
+//! `for <name> in <values>` expands the lines up to the matching `end` once for each of the
+//! whitespace-separated values.
+//!
+//! Statements of all fragments are merged in the order of expansion. Rules of an event and
+//! executables of a tree from several fragments form a single handler, in which the first
+//! matching rule or pattern decides, so earlier fragments take precedence. Spaces and
+//! hierarchies, on the other hand, may be defined only once, a second definition, such as
+//! a fragment included twice, is an error pointing to the first one.
+//!
+//! Loading a policy results in a [`ConfigBuilder`], so custom handlers can still be added
+//! before building the [`Config`].
+//!
+//! [`Config`]: crate::medusa::Config
+
+use crate::medusa::audit::to_hex;
+use crate::medusa::{
+    Config, ConfigBuilder, Event, ExecutableMap, FastHasher, HandlerFlags, PolicyError, Rule,
+    SpaceBuilder,
+};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::{env, fs, io, mem};
+
+#[cfg(feature = "signing")]
+use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+
+/// Loader of policy files.
+#[derive(Debug, Default, Clone)]
+pub struct PolicyLoader {
+    variables: BTreeMap<String, String>,
+
+    #[cfg(feature = "signing")]
+    verifying_key: Option<VerifyingKey>,
+}
+
+impl PolicyLoader {
+    /// Creates new `PolicyLoader`.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Defines variable `name` substituted for `${name}` in loaded policies, overriding the
+    /// environment.
+    ///
+    /// Returns `Self`.
+    pub fn define(mut self, name: &str, value: &str) -> Self {
+        self.variables.insert(name.to_owned(), value.to_owned());
+        self
+    }
+
+    /// Requires every loaded policy, including the included ones, to have a valid detached
+    /// ed25519 signature made by the owner of `public_key`. The signature is read from a file
+    /// with `.sig` appended to the policy path and may be stored either as 64 raw bytes or as
+    /// hexadecimal text.
+    ///
+    /// Returns `Self` or `PolicyError` if the key is not a valid ed25519 public key.
+    #[cfg(feature = "signing")]
+    pub fn verify_with(mut self, public_key: &[u8; 32]) -> Result<Self, PolicyError> {
+        let key =
+            VerifyingKey::from_bytes(public_key).map_err(|_| PolicyError::InvalidPublicKey)?;
+        self.verifying_key = Some(key);
+        Ok(self)
+    }
+
+    /// Loads the policy file at `path` into a new [`ConfigBuilder`].
+    pub fn load<P: AsRef<Path>>(&self, path: P) -> Result<ConfigBuilder, PolicyError> {
+        self.load_into(Config::builder(), path)
+    }
+
+    /// Loads the policy file at `path` into an existing [`ConfigBuilder`]. The hash of the
+    /// policy, see [`ConfigBuilder::policy_hash`], is computed from the expanded policy, so it
+    /// differs between machines on which the policy expands differently.
+    pub fn load_into<P: AsRef<Path>>(
+        &self,
+        config: ConfigBuilder,
+        path: P,
+    ) -> Result<ConfigBuilder, PolicyError> {
+        let path = path.as_ref();
+        let mut lines = Vec::new();
+        self.expand_file(path, 0, &mut Vec::new(), &mut lines)?;
+
+        let expanded = lines.iter().map(|x| x.text.as_str()).collect::<Vec<_>>();
+        let hash = policy_hash(expanded.join("\n").as_bytes());
+        println!("loaded policy {} ({})", path.display(), hash);
+
+        let statements = parse(&lines, |name| config.has_space(name))?;
+        let config = statements.into_iter().fold(config, apply);
+
+        Ok(config.policy_hash(hash))
+    }
+
+    /// Reads the policy at `path`, included `depth` times deep, and appends its expanded lines
+    /// to `out`. `bindings` holds the loop variables bound by the including policies.
+    fn expand_file(
+        &self,
+        path: &Path,
+        depth: usize,
+        bindings: &mut Vec<(String, String)>,
+        out: &mut Vec<Line>,
+    ) -> Result<(), PolicyError> {
+        let data = fs::read(path).map_err(|e| PolicyError::IOError(path.to_owned(), e))?;
+
+        #[cfg(feature = "signing")]
+        if let Some(key) = &self.verifying_key {
+            verify_signature(key, path, &data)?;
+        }
+
+        let text = String::from_utf8(data).map_err(|_| PolicyError::ParseError {
+            path: path.to_owned(),
+            line: 0,
+            message: "policy is not valid UTF-8".to_owned(),
+        })?;
+
+        let lines = text
+            .lines()
+            .enumerate()
+            .map(|(n, line)| (n + 1, strip_comment(line)))
+            .collect::<Vec<_>>();
+
+        self.expand_lines(path, depth, &lines, bindings, out)
+    }
+
+    fn expand_lines(
+        &self,
+        path: &Path,
+        depth: usize,
+        lines: &[(usize, &str)],
+        bindings: &mut Vec<(String, String)>,
+        out: &mut Vec<Line>,
+    ) -> Result<(), PolicyError> {
+        let mut i = 0;
+        while i < lines.len() {
+            let (number, line) = lines[i];
+            let error = |message: String| PolicyError::ParseError {
+                path: path.to_owned(),
+                line: number,
+                message,
+            };
+            i += 1;
+
+            let text = self.substitute(line, bindings).map_err(error)?;
+            let args = text.split_whitespace().collect::<Vec<_>>();
+
+            match args[..] {
+                ["for", name, "in", ref values @ ..] => {
+                    let body = loop_body(&lines[i..])
+                        .ok_or_else(|| error("`for` without matching `end`".into()))?;
+                    for value in values {
+                        bindings.push((name.to_owned(), (*value).to_owned()));
+                        let res = self.expand_lines(path, depth, body, bindings, out);
+                        bindings.pop();
+                        res?;
+                    }
+                    // skip the body and its `end`
+                    i += body.len() + 1;
+                }
+                ["for", ..] => return Err(error("expected `for <name> in <values>`".into())),
+                ["end"] => return Err(error("`end` without `for`".into())),
+                ["include", included] | ["include", included, "optional"] => {
+                    if depth >= MAX_INCLUDE_DEPTH {
+                        return Err(error("includes are nested too deeply".into()));
+                    }
+
+                    let included = path.parent().unwrap_or(Path::new(".")).join(included);
+                    if args.len() == 3 && !included.exists() {
+                        continue;
+                    }
+                    if !included.is_dir() {
+                        self.expand_file(&included, depth + 1, bindings, out)?;
+                        continue;
+                    }
+
+                    let fragments = fragments(&included)
+                        .map_err(|e| PolicyError::IOError(included.clone(), e))?;
+                    for fragment in fragments {
+                        self.expand_file(&fragment, depth + 1, bindings, out)?;
+                    }
+                }
+                ["include", ..] => {
+                    return Err(error("expected `include <path> [optional]`".into()))
+                }
+                _ => out.push(Line {
+                    path: path.to_owned(),
+                    number,
+                    text,
+                }),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns `line` with variables replaced by their values.
+    fn substitute(&self, line: &str, bindings: &[(String, String)]) -> Result<String, String> {
+        let mut res = String::with_capacity(line.len());
+        let mut rest = line;
+
+        while let Some(start) = rest.find("${") {
+            res.push_str(&rest[..start]);
+            let end = rest[start..]
+                .find('}')
+                .ok_or_else(|| format!("unterminated variable in `{}`", line.trim()))?;
+            let reference = &rest[start + 2..start + end];
+            let (name, default) = match reference.split_once(":-") {
+                Some((name, default)) => (name, Some(default)),
+                None => (reference, None),
+            };
+
+            let value = bindings
+                .iter()
+                .rev()
+                .find(|(x, _)| x == name)
+                .map(|(_, value)| value.clone())
+                .or_else(|| self.variables.get(name).cloned())
+                .or_else(|| env::var(name).ok())
+                .or_else(|| (name == "HOSTNAME").then(hostname).flatten())
+                .or_else(|| default.map(str::to_owned))
+                .ok_or_else(|| format!("undefined variable `{}`", name))?;
+            res.push_str(&value);
+            rest = &rest[start + end + 1..];
+        }
+        res.push_str(rest);
+
+        Ok(res)
+    }
+}
+
+/// Maximum depth of nested includes, which stops include cycles.
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+/// Line of an expanded policy.
+struct Line {
+    path: PathBuf,
+    number: usize,
+    text: String,
+}
+
+/// Returns the lines of the loop starting at `lines` up to its matching `end`.
+fn loop_body<'a, 'b>(lines: &'a [(usize, &'b str)]) -> Option<&'a [(usize, &'b str)]> {
+    let mut nested = 0;
+    for (i, (_, line)) in lines.iter().enumerate() {
+        match line.split_whitespace().next() {
+            Some("for") => nested += 1,
+            Some("end") if nested == 0 => return Some(&lines[..i]),
+            Some("end") => nested -= 1,
+            _ => (),
+        }
+    }
+
+    None
+}
+
+/// Returns the policy files in directory `dir`, sorted by name.
+fn fragments(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut fragments = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let hidden = path
+            .file_name()
+            .is_some_and(|x| x.to_string_lossy().starts_with('.'));
+        if !hidden && path.is_file() && path.extension().is_some_and(|x| x == "policy") {
+            fragments.push(path);
+        }
+    }
+    fragments.sort();
+
+    Ok(fragments)
+}
+
+/// Returns the name of this machine.
+fn hostname() -> Option<String> {
+    fs::read_to_string("/proc/sys/kernel/hostname")
+        .ok()
+        .map(|x| x.trim().to_owned())
+}
+
+/// Returns hexadecimal SHA-256 digest of policy `data`, which identifies an exact policy version.
+pub fn policy_hash(data: &[u8]) -> String {
+    to_hex(&Sha256::digest(data))
+}
+
+#[cfg(feature = "signing")]
+fn verify_signature(key: &VerifyingKey, path: &Path, data: &[u8]) -> Result<(), PolicyError> {
+    let mut sig_path = path.as_os_str().to_owned();
+    sig_path.push(".sig");
+    let sig_path = std::path::PathBuf::from(sig_path);
+
+    let raw = match fs::read(&sig_path) {
+        Ok(raw) => raw,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return Err(PolicyError::UnsignedError(path.to_owned()));
+        }
+        Err(e) => return Err(PolicyError::IOError(sig_path, e)),
+    };
+
+    let bytes: [u8; 64] = match raw.len() {
+        64 => raw.try_into().unwrap(),
+        _ => decode_hex(&raw).ok_or_else(|| PolicyError::InvalidSignatureError(path.to_owned()))?,
+    };
+
+    key.verify(data, &Signature::from_bytes(&bytes))
+        .map_err(|_| PolicyError::InvalidSignatureError(path.to_owned()))
+}
+
+#[cfg(feature = "signing")]
+fn decode_hex(raw: &[u8]) -> Option<[u8; 64]> {
+    let text = std::str::from_utf8(raw).ok()?.trim();
+    if text.len() != 128 {
+        return None;
+    }
+
+    let mut res = [0; 64];
+    for (i, byte) in res.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(text.get(2 * i..2 * i + 2)?, 16).ok()?;
+    }
+
+    Some(res)
+}
+
+enum Statement {
+    Space(Box<SpaceBuilder>),
+    Hierarchy {
+        event: Event,
+        tree: String,
+        attribute: Option<String>,
+        flags: HandlerFlags,
+    },
+    Rules {
+        event: Event,
+        rules: Vec<Rule>,
+    },
+    Cover(Vec<Event>),
+    Executables(ExecutableMap),
+}
+
+fn apply(config: ConfigBuilder, statement: Statement) -> ConfigBuilder {
+    match statement {
+        Statement::Space(space) => config.add_space(*space),
+        Statement::Hierarchy {
+            event,
+            tree,
+            attribute,
+            flags,
+        } => config.add_hierarchy_event_handler(event, &tree, attribute.as_deref(), flags),
+        Statement::Rules { event, rules } => config.add_rule_event_handler(event, rules),
+        Statement::Executables(map) => config.add_executable_map(map),
+        Statement::Cover(events) => config.cover_events(events),
+    }
+}
+
+lazy_static! {
+    // names, paths and patterns of all loaded policies and names of user domains, see `intern`
+    static ref POLICY_STRINGS: Mutex<HashSet<&'static str, FastHasher>> = Default::default();
+}
+
+/// Returns the static copy of `s`, as builders only accept static strings. The copies are shared
+/// by all loads, so that reloading or previewing a policy again allocates nothing, and the memory
+/// held is bounded by the distinct strings of the policies loaded since the start.
+pub(crate) fn intern(s: &str) -> &'static str {
+    let mut strings = POLICY_STRINGS.lock().unwrap();
+    if let Some(s) = strings.get(s) {
+        return s;
+    }
+
+    let s: &'static str = Box::leak(s.to_owned().into_boxed_str());
+    strings.insert(s);
+    s
+}
+
+/// Returns `line` without its comment, which starts at the first `#` outside a quoted string.
+fn strip_comment(line: &str) -> &str {
+    let mut quoted = false;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            '#' if !quoted => return &line[..i],
+            _ => {}
+        }
+    }
+
+    line
+}
+
+/// Returns the event named `name`. Policies may name events unknown to [`Event`].
+fn event(name: &str) -> Event {
+    name.parse().unwrap_or_else(|_| Event::Custom(intern(name)))
+}
+
+/// Parses the expanded `lines` into statements. A space referred to by a space statement must be
+/// declared in the policy, or be `known` to the config the policy is loaded into.
+fn parse(lines: &[Line], known: impl Fn(&str) -> bool) -> Result<Vec<Statement>, PolicyError> {
+    let mut statements = Vec::new();
+    // spaces and hierarchies with the lines defining them
+    let mut defined = HashMap::new();
+    // spaces referred to by space statements with the lines referring to them, checked at the end
+    // because a space may be declared after it is referred to
+    let mut referred = Vec::new();
+
+    for line in lines {
+        let Line { path, number, text } = line;
+        let error = |message: String| PolicyError::ParseError {
+            path: path.clone(),
+            line: *number,
+            message,
+        };
+        let mut define = |what: String| match defined.insert(what.clone(), line) {
+            Some(first) => Err(error(format!(
+                "{} is already defined at {}:{}",
+                what,
+                first.path.display(),
+                first.number
+            ))),
+            None => Ok(()),
+        };
+
+        let line = text.as_str();
+        let mut tokens = line.split_whitespace();
+        let keyword = match tokens.next() {
+            Some(keyword) => keyword,
+            None => continue,
+        };
+        let args = tokens.collect::<Vec<_>>();
+
+        if keyword == "space" {
+            let space = match args[..] {
+                [name, path] => SpaceBuilder::new()
+                    .with_name(intern(name))
+                    .with_path(intern(path)),
+                [name, path, "recursive"] => SpaceBuilder::new()
+                    .with_name(intern(name))
+                    .with_path_recursive(intern(path)),
+                _ => return Err(error("expected `space <name> <path> [recursive]`".into())),
+            };
+            define(format!("space `{}`", space.name()))?;
+            statements.push(Statement::Space(Box::new(space)));
+            continue;
+        }
+
+        if keyword == "hierarchy" {
+            let (name, tree) = match args[..] {
+                [name, tree, ..] => (name, tree),
+                _ => return Err(error("expected `hierarchy <event> <tree> ...`".into())),
+            };
+
+            let mut attribute = None;
+            let mut flags = HandlerFlags::empty();
+            for arg in &args[2..] {
+                if *arg == "from_object" {
+                    flags |= HandlerFlags::FROM_OBJECT;
+                } else if *arg == "pin" {
+                    flags |= HandlerFlags::PIN_LABELS;
+                } else if let Some(attr) = arg.strip_prefix("attribute=") {
+                    attribute = Some(attr.to_owned());
+                } else {
+                    return Err(error(format!("unknown hierarchy option `{}`", arg)));
+                }
+            }
+
+            define(format!("hierarchy of `{}` in tree `{}`", name, tree))?;
+            statements.push(Statement::Hierarchy {
+                event: event(name),
+                tree: tree.to_owned(),
+                attribute,
+                flags,
+            });
+            continue;
+        }
+
+        if keyword == "cover" {
+            if args.is_empty() {
+                return Err(error("expected `cover <event>...`".into()));
+            }
+            statements.push(Statement::Cover(args.iter().map(|x| event(x)).collect()));
+            continue;
+        }
+
+        if keyword == "executable" {
+            let (tree, pattern, path) = match args[..] {
+                [tree, pattern, path] => (tree, pattern, path),
+                _ => {
+                    return Err(error(
+                        "expected `executable <tree> <pattern> <path>`".into(),
+                    ))
+                }
+            };
+
+            let existing = statements.iter_mut().find_map(|x| match x {
+                Statement::Executables(map) if map.tree() == tree => Some(map),
+                _ => None,
+            });
+            match existing {
+                Some(map) => {
+                    let previous = mem::replace(map, ExecutableMap::new(tree));
+                    *map = previous
+                        .map(pattern, path)
+                        .map_err(|e| error(e.to_string()))?;
+                }
+                None => statements.push(Statement::Executables(
+                    ExecutableMap::new(tree)
+                        .map(pattern, path)
+                        .map_err(|e| error(e.to_string()))?,
+                )),
+            }
+            continue;
+        }
+
+        if keyword == "rule" {
+            let (name, rule) = match line.trim().split_once(char::is_whitespace) {
+                Some((_, rest)) => rest.trim_start().split_once(char::is_whitespace),
+                None => None,
+            }
+            .ok_or_else(|| error("expected `rule <event> <rule>`".into()))?;
+            let rule = Rule::parse(rule).map_err(|e| error(e.to_string()))?;
+
+            let existing = statements.iter_mut().find_map(|x| match x {
+                Statement::Rules { event: e, rules } if e.name() == name => Some(rules),
+                _ => None,
+            });
+            match existing {
+                Some(rules) => rules.push(rule),
+                None => statements.push(Statement::Rules {
+                    event: event(name),
+                    rules: vec![rule],
+                }),
+            }
+            continue;
+        }
+
+        let space = match statements.last_mut() {
+            Some(Statement::Space(space)) => &mut **space,
+            _ => return Err(error(format!("`{}` outside of a space", keyword))),
+        };
+        if let "reads" | "writes" | "sees" | "denies" | "include_space" | "exclude_space" = keyword
+        {
+            referred.extend(args.iter().map(|x| (*x, path, *number)));
+        }
+        let names = args.iter().map(|x| intern(x));
+        let builder = mem::take(space);
+
+        *space = match (keyword, &args[..]) {
+            ("reads", _) => builder.reads(names),
+            ("writes", _) => builder.writes(names),
+            ("sees", _) => builder.sees(names),
+            ("denies", _) => builder.denies(names),
+            ("monitors", [_, ..]) => builder.monitor_events(args.iter().map(|x| event(x))),
+            ("include_space", [name]) => builder.include_space(intern(name)),
+            ("exclude_space", [name]) => builder.exclude_space(intern(name)),
+            ("include_path", [path]) => builder.include_path(intern(path)),
+            ("include_path", [path, "recursive"]) => builder.include_path_recursive(intern(path)),
+            ("exclude_path", [path]) => builder.exclude_path(intern(path)),
+            ("exclude_path", [path, "recursive"]) => builder.exclude_path_recursive(intern(path)),
+            ("exclude_matching", [pattern]) => builder.exclude_matching(intern(pattern)),
+            _ => return Err(error(format!("invalid statement `{}`", line.trim()))),
+        };
+    }
+
+    for (name, path, line) in referred {
+        if !defined.contains_key(&format!("space `{}`", name)) && !known(name) {
+            return Err(PolicyError::ParseError {
+                path: path.clone(),
+                line,
+                message: format!("space `{}` is not defined", name),
+            });
+        }
+    }
+
+    Ok(statements)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expand(loader: &PolicyLoader, text: &str) -> Result<Vec<String>, PolicyError> {
+        let lines = text
+            .lines()
+            .enumerate()
+            .map(|(n, line)| (n + 1, strip_comment(line)))
+            .collect::<Vec<_>>();
+        let mut out = Vec::new();
+        loader.expand_lines(
+            Path::new("test.policy"),
+            0,
+            &lines,
+            &mut Vec::new(),
+            &mut out,
+        )?;
+        Ok(out.into_iter().map(|x| x.text.trim().to_owned()).collect())
+    }
+
+    fn error_line(res: Result<Vec<String>, PolicyError>) -> usize {
+        match res {
+            Err(PolicyError::ParseError { line, .. }) => line,
+            x => panic!(
+                "expected a parse error, found {:?}",
+                x.map_err(|e| e.to_string())
+            ),
+        }
+    }
+
+    #[test]
+    fn substitutes_variables() {
+        let loader = PolicyLoader::new().define("USERS", "alice bob");
+        let substitute = |line| loader.substitute(line, &[("user".into(), "carol".into())]);
+
+        assert_eq!(
+            substitute("home ${user} ${USERS}").unwrap(),
+            "home carol alice bob"
+        );
+        assert_eq!(
+            substitute("${RUSTABLE_TEST_UNDEFINED:-/srv} ${USERS:-x}").unwrap(),
+            "/srv alice bob"
+        );
+        assert_eq!(substitute("${RUSTABLE_TEST_UNDEFINED:-}x").unwrap(), "x");
+        assert!(substitute("${RUSTABLE_TEST_UNDEFINED}").is_err());
+        assert!(substitute("${USERS").is_err());
+        assert_eq!(
+            substitute("no $variables {here}").unwrap(),
+            "no $variables {here}"
+        );
+    }
+
+    #[test]
+    fn expands_nested_loops() {
+        let loader = PolicyLoader::new().define("USERS", "alice bob");
+        let text = "
+            for user in ${USERS}
+                space home_${user} fs/home/${user}
+                for dir in bin src
+                    include_path fs/home/${user}/${dir}
+                end
+            end
+            space tmp fs/tmp
+        ";
+        assert_eq!(
+            expand(&loader, text).unwrap(),
+            [
+                "",
+                "space home_alice fs/home/alice",
+                "include_path fs/home/alice/bin",
+                "include_path fs/home/alice/src",
+                "space home_bob fs/home/bob",
+                "include_path fs/home/bob/bin",
+                "include_path fs/home/bob/src",
+                "space tmp fs/tmp",
+                "",
+            ]
+        );
+    }
+
+    #[test]
+    fn shadows_outer_loop_variables() {
+        let text = "for x in a b\nfor x in c\nspace ${x} fs/${x}\nend\nspace ${x} fs/\nend";
+        assert_eq!(
+            expand(&PolicyLoader::new(), text).unwrap(),
+            ["space c fs/c", "space a fs/", "space c fs/c", "space b fs/"]
+        );
+    }
+
+    #[test]
+    fn rejects_unbalanced_loops() {
+        let loader = PolicyLoader::new();
+        assert_eq!(error_line(expand(&loader, "space a fs/\nfor x in a b")), 2);
+        assert_eq!(error_line(expand(&loader, "for x in a\nend\nend")), 3);
+        assert_eq!(error_line(expand(&loader, "for x a b\nend")), 1);
+        assert_eq!(
+            error_line(expand(&loader, "\nspace ${RUSTABLE_TEST_UNDEFINED} fs/")),
+            2
+        );
+    }
+
+    fn policy_dir(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir = env::temp_dir().join(format!("rustable-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        for (path, text) in files {
+            let path = dir.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, text).unwrap();
+        }
+        dir
+    }
+
+    fn expand_file(path: &Path) -> Result<Vec<(PathBuf, usize, String)>, PolicyError> {
+        let mut out = Vec::new();
+        PolicyLoader::new().expand_file(path, 0, &mut Vec::new(), &mut out)?;
+        Ok(out
+            .into_iter()
+            .map(|x| (x.path, x.number, x.text))
+            .collect())
+    }
+
+    #[test]
+    fn includes_fragments_in_order() {
+        let dir = policy_dir(
+            "policy-fragments",
+            &[
+                (
+                    "main.policy",
+                    "include conf.d\ninclude missing.policy optional\nspace main fs/",
+                ),
+                ("conf.d/50-b.policy", "space b fs/b"),
+                (
+                    "conf.d/10-a.policy",
+                    "include ../common/base.policy\nspace a fs/a",
+                ),
+                ("conf.d/.20-hidden.policy", "space hidden fs/"),
+                ("conf.d/30-notes.txt", "not a policy"),
+                ("common/base.policy", "space base fs/"),
+            ],
+        );
+
+        let lines = expand_file(&dir.join("main.policy")).unwrap();
+        assert_eq!(
+            lines.iter().map(|x| x.2.as_str()).collect::<Vec<_>>(),
+            [
+                "space base fs/",
+                "space a fs/a",
+                "space b fs/b",
+                "space main fs/"
+            ]
+        );
+        assert!(lines[1].0.ends_with("conf.d/10-a.policy"));
+        assert_eq!(lines[1].1, 2);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn rejects_invalid_includes() {
+        let dir = policy_dir(
+            "policy-includes",
+            &[
+                ("cycle.policy", "space a fs/\ninclude cycle.policy"),
+                ("missing.policy", "include nothing.policy"),
+            ],
+        );
+
+        match expand_file(&dir.join("cycle.policy")) {
+            Err(PolicyError::ParseError {
+                line: 2, message, ..
+            }) => {
+                assert!(message.contains("nested too deeply"), "{}", message)
+            }
+            x => panic!(
+                "expected a parse error, found {:?}",
+                x.map_err(|e| e.to_string())
+            ),
+        }
+        assert!(matches!(
+            expand_file(&dir.join("missing.policy")),
+            Err(PolicyError::IOError(path, _)) if path.ends_with("nothing.policy")
+        ));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn rejects_duplicate_definitions() {
+        for (name, first, second) in [
+            ("space", "space a fs/", "space a fs/etc"),
+            (
+                "hierarchy",
+                "hierarchy getfile fs",
+                "hierarchy getfile fs attribute=filename",
+            ),
+        ] {
+            let dir = policy_dir(
+                &format!("policy-duplicate-{}", name),
+                &[
+                    ("main.policy", "include conf.d"),
+                    ("conf.d/10-a.policy", first),
+                    ("conf.d/20-b.policy", &format!("\n{}", second)),
+                ],
+            );
+
+            match PolicyLoader::new().load(dir.join("main.policy")) {
+                Err(PolicyError::ParseError {
+                    path,
+                    line,
+                    message,
+                }) => {
+                    assert!(path.ends_with("20-b.policy"));
+                    assert_eq!(line, 2);
+                    assert!(message.ends_with("10-a.policy:1"), "{}", message);
+                }
+                x => panic!("expected a parse error, found {:?}", x.err()),
+            }
+
+            fs::remove_dir_all(&dir).unwrap();
+        }
+    }
+
+    #[test]
+    fn rejects_undefined_spaces() {
+        let dir = policy_dir(
+            "policy-undefined-space",
+            &[(
+                "main.policy",
+                "space users fs/home recursive\n    reads etc\n    include_space sytem\n\
+                 space etc fs/etc recursive\n",
+            )],
+        );
+
+        match PolicyLoader::new().load(dir.join("main.policy")) {
+            Err(PolicyError::ParseError { line, message, .. }) => {
+                assert_eq!(line, 3);
+                assert_eq!(message, "space `sytem` is not defined");
+            }
+            x => panic!("expected a parse error, found {:?}", x.err()),
+        }
+
+        let known = Config::builder().add_space(
+            SpaceBuilder::new()
+                .with_name("sytem")
+                .with_path_recursive("fs/usr"),
+        );
+        assert!(PolicyLoader::new()
+            .load_into(known, dir.join("main.policy"))
+            .is_ok());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn merges_rules_of_fragments() {
+        let dir = policy_dir(
+            "policy-rules",
+            &[
+                ("main.policy", "include conf.d"),
+                (
+                    "conf.d/10-a.policy",
+                    "rule getfile deny if subject.uid == 1",
+                ),
+                ("conf.d/20-b.policy", "rule getfile allow if true"),
+            ],
+        );
+
+        let lines = expand_file(&dir.join("main.policy"))
+            .unwrap()
+            .into_iter()
+            .map(|(path, number, text)| Line { path, number, text })
+            .collect::<Vec<_>>();
+        let statements = parse(&lines, |_| false).unwrap();
+        assert!(matches!(
+            &statements[..],
+            [Statement::Rules { rules, .. }] if rules.len() == 2
+                && rules[0].answer() == crate::medusa::MedusaAnswer::Deny
+        ));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn strips_comments_outside_quotes() {
+        assert_eq!(strip_comment("space a fs/ # all files"), "space a fs/ ");
+        assert_eq!(
+            strip_comment(r##"rule getfile deny if event.filename ~= "#x$" # comment"##),
+            r##"rule getfile deny if event.filename ~= "#x$" "##
+        );
+        assert_eq!(
+            strip_comment(r##"rule getfile deny if event.filename == "a\"#" #"##),
+            r##"rule getfile deny if event.filename == "a\"#" "##
+        );
+        assert_eq!(strip_comment("# only a comment"), "");
+    }
+}
@@ -0,0 +1,55 @@
+//! Positions of entities in each tree, see [`ConfigBuilder::separate_tree_positions`].
+//!
+//! [`ConfigBuilder::separate_tree_positions`]: crate::medusa::ConfigBuilder::separate_tree_positions
+
+use crate::medusa::{Config, FastDashMap, MedusaClass, SubjectId, Tree};
+
+/// Nodes entities were last entered into, by the identity of the entity and the name of the
+/// tree of the node.
+#[derive(Default)]
+pub(crate) struct TreePositions {
+    positions: FastDashMap<SubjectId, Vec<(String, usize)>>,
+}
+
+impl TreePositions {
+    /// Remembers that `entity` was entered into node `cinfo` of `config`. Entities of classes
+    /// without a primary key cannot be told apart and are not remembered.
+    pub(crate) fn record(&self, config: &Config, entity: &MedusaClass, cinfo: usize) {
+        let id = entity.subject_id();
+        if id.key().is_empty() {
+            return;
+        }
+        let tree = match config.tree_name_of(cinfo) {
+            Some(tree) => tree,
+            None => return,
+        };
+
+        let mut positions = self.positions.entry(id).or_default();
+        match positions.iter_mut().find(|(name, _)| name == tree) {
+            Some(position) => position.1 = cinfo,
+            None => positions.push((tree.to_owned(), cinfo)),
+        }
+    }
+
+    /// Returns the node of `tree` in which a hierarchy of `tree` continues for `entity`: the
+    /// node in its `o_cinfo` attribute if it is in `tree`, otherwise the node of `tree` the
+    /// entity was last entered into. Returns 0, standing for the root, if there is neither.
+    pub(crate) fn cinfo(&self, config: &Config, tree: &Tree, entity: &MedusaClass) -> usize {
+        let cinfo = entity.get_object_cinfo().unwrap_or_default();
+        if cinfo == 0 || config.tree_name_of(cinfo) == Some(tree.name()) {
+            return cinfo;
+        }
+
+        self.positions
+            .get(&entity.subject_id())
+            .and_then(|x| x.iter().find(|(name, _)| name == tree.name()).map(|x| x.1))
+            // nodes of a replaced config are gone
+            .filter(|x| config.node_by_cinfo(x).is_some())
+            .unwrap_or_default()
+    }
+
+    /// Forgets the positions of entity `id`.
+    pub(crate) fn forget(&self, id: &SubjectId) {
+        self.positions.remove(id);
+    }
+}
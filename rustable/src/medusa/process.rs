@@ -0,0 +1,87 @@
+//! Parent and child relationships of processes, see [`ConfigBuilder::track_processes`].
+//!
+//! [`ConfigBuilder::track_processes`]: crate::medusa::ConfigBuilder::track_processes
+
+use crate::bitmap;
+use crate::medusa::constants::MEDUSA_VS_ATTR_NAME;
+use crate::medusa::{FastDashMap, MedusaClass};
+use std::collections::HashSet;
+
+/// Attribute holding the id of a process.
+pub(crate) const PID_ATTR_NAME: &str = "pid";
+
+/// Attribute holding the id of the parent of a process.
+pub(crate) const PARENT_PID_ATTR_NAME: &str = "parent_pid";
+
+/// Process as seen in the last authorization request it took part in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProcessInfo {
+    /// Id of the process.
+    pub pid: i32,
+
+    /// Id of the parent of the process.
+    pub parent_pid: i32,
+
+    /// Virtual spaces the process was a member of.
+    pub vs: Vec<u8>,
+}
+
+impl ProcessInfo {
+    /// Returns the process `class` represents, or `None` if it is not a process.
+    fn from_class(class: &MedusaClass) -> Option<Self> {
+        Some(Self {
+            pid: pid_attribute(class, PID_ATTR_NAME)?,
+            parent_pid: pid_attribute(class, PARENT_PID_ATTR_NAME)?,
+            vs: class.get_attribute(MEDUSA_VS_ATTR_NAME).unwrap_or_default(),
+        })
+    }
+
+    /// Returns `true` if the process was a member of the virtual space `bit`.
+    pub fn is_member_of(&self, bit: usize) -> bool {
+        bit < self.vs.len() * 8 && bitmap::test_bit(&self.vs, bit)
+    }
+}
+
+/// Processes by their ids. A process is replaced once a new process reusing its id is seen.
+#[derive(Default)]
+pub(crate) struct ProcessTree {
+    processes: FastDashMap<i32, ProcessInfo>,
+}
+
+impl ProcessTree {
+    /// Remembers `class` if it is a process.
+    pub(crate) fn record(&self, class: &MedusaClass) {
+        if let Some(info) = ProcessInfo::from_class(class) {
+            self.processes.insert(info.pid, info);
+        }
+    }
+
+    /// Returns the known ancestors of process `class`, its parent first. The walk stops at the
+    /// first process which has not been seen.
+    pub(crate) fn ancestors(&self, class: &MedusaClass) -> Vec<ProcessInfo> {
+        let mut ancestors = Vec::new();
+        let mut parent_pid = match pid_attribute(class, PARENT_PID_ATTR_NAME) {
+            Some(pid) => pid,
+            None => return ancestors,
+        };
+
+        // reused ids may form a cycle
+        let mut seen = HashSet::new();
+        while parent_pid > 0 && seen.insert(parent_pid) {
+            let parent = match self.processes.get(&parent_pid) {
+                Some(parent) => parent.clone(),
+                None => break,
+            };
+            parent_pid = parent.parent_pid;
+            ancestors.push(parent);
+        }
+
+        ancestors
+    }
+}
+
+/// Returns process id attribute `name` of `class`, or `None` if `class` does not have it.
+pub(crate) fn pid_attribute(class: &MedusaClass, name: &str) -> Option<i32> {
+    let data = class.attributes.get(name).ok()?;
+    Some(i32::from_le_bytes(data.try_into().ok()?))
+}
@@ -0,0 +1,430 @@
+//! Low-level client of the Medusa communication protocol.
+//!
+//! Unlike [`Connection`], a [`Client`] has no configuration, trees nor handlers. It performs the
+//! greeting, reads messages of the security module and keeps the [`Registry`] of classes and
+//! events. Requests are written by the caller, see [`MedusaRequest`] and [`DecisionAnswer`], and
+//! answers to them can be routed by [`PendingRequests`]. [`OutstandingRequests`] keeps track of
+//! the authorization requests which are still to be answered. This is enough for tools such as a
+//! protocol inspector or a schema dumper.
+//!
+//! ```text
+//! let mut client = Client::new(read_handle)?;
+//! client.handshake().await?;
+//!
+//! loop {
+//!     match client.next_message().await? {
+//!         Message::ClassDef(class) => println!("class {}", class.name()),
+//!         Message::EvtypeDef(evtype) => println!("event {}", evtype.name()),
+//!         Message::AuthRequest(request) => break,
+//!         _ => (),
+//!     }
+//! }
+//! ```
+//!
+//! [`Connection`]: crate::medusa::Connection
+//! [`MedusaRequest`]: crate::medusa::MedusaRequest
+//! [`DecisionAnswer`]: crate::medusa::DecisionAnswer
+
+use crate::medusa::constants::*;
+#[cfg(feature = "recording")]
+use crate::medusa::recording::SessionRecorder;
+use crate::medusa::{
+    AsyncReader, AuthRequestData, Command, CommunicationError, ConnectionError, FastDashMap,
+    FetchAnswer, MedusaClass, MedusaEvtype, NativeByteOrderReader, ReaderError, UpdateAnswer,
+};
+use std::io::Read;
+use std::iter;
+use std::os::unix::io::AsRawFd;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+pub use crate::medusa::outstanding::OutstandingRequests;
+pub use crate::medusa::pending::PendingRequests;
+
+/// Commands starting the messages handled by `read_message`.
+const MESSAGE_COMMANDS: [Command; 5] = [
+    MEDUSA_COMM_KCLASSDEF,
+    MEDUSA_COMM_EVTYPEDEF,
+    MEDUSA_COMM_UPDATE_ANSWER,
+    MEDUSA_COMM_FETCH_ANSWER,
+    MEDUSA_COMM_FETCH_ERROR,
+];
+
+/// Beginning of a message received from the security module.
+#[derive(Clone, Copy, Debug)]
+pub enum Frame {
+    /// Command, such as a class definition.
+    Command(Command),
+
+    /// Authorization request of the event with id `evtype_id`.
+    AuthRequest { evtype_id: u64, request_id: u64 },
+}
+
+/// Message received from the security module.
+#[derive(Debug)]
+pub enum Message {
+    /// Definition of a class.
+    ClassDef(MedusaClass),
+
+    /// Definition of an event.
+    EvtypeDef(MedusaEvtype),
+
+    /// Answer to a fetch request.
+    FetchAnswer(FetchAnswer),
+
+    /// Failure of a fetch request.
+    FetchError,
+
+    /// Answer to an update request.
+    UpdateAnswer(UpdateAnswer),
+
+    /// Authorization request waiting for an answer.
+    AuthRequest(Box<RawAuthRequest>),
+}
+
+/// Classes and events registered by the security module.
+#[derive(Debug, Default)]
+pub struct Registry {
+    pub(crate) classes: FastDashMap<u64, MedusaClass>,
+    pub(crate) evtypes: FastDashMap<u64, MedusaEvtype>,
+
+    pub(crate) class_id: FastDashMap<String, u64>,
+    pub(crate) evtype_id: FastDashMap<String, u64>,
+}
+
+impl Registry {
+    /// Registers `class`, replacing a class with the same id.
+    pub fn define_class(&self, class: MedusaClass) {
+        self.class_id
+            .insert(class.header.name().to_owned(), class.header.id);
+        self.classes.insert(class.header.id, class);
+    }
+
+    /// Registers `evtype`, replacing an event with the same id.
+    pub fn define_evtype(&self, evtype: MedusaEvtype) {
+        self.evtype_id
+            .insert(evtype.header.name().to_owned(), evtype.header.evid);
+        self.evtypes.insert(evtype.header.evid, evtype);
+    }
+
+    /// Returns identification of a class having the given name.
+    pub fn class_id_from_name(&self, class_name: &str) -> Option<u64> {
+        self.class_id.get(class_name).map(|x| *x)
+    }
+
+    /// Returns identification of an event having the given name.
+    pub fn evtype_id_from_name(&self, evtype_name: &str) -> Option<u64> {
+        self.evtype_id.get(evtype_name).map(|x| *x)
+    }
+
+    /// Returns an empty class having the given id with no attribute data.
+    pub fn empty_class_from_id(&self, class_id: &u64) -> Option<MedusaClass> {
+        self.classes.get(class_id).map(|x| x.value().clone())
+    }
+
+    /// Returns an empty event having the given id with no attribute data.
+    pub fn empty_evtype_from_id(&self, evtype_id: &u64) -> Option<MedusaEvtype> {
+        self.evtypes.get(evtype_id).map(|x| x.value().clone())
+    }
+
+    /// Returns all registered classes with no attribute data.
+    pub fn classes(&self) -> Vec<MedusaClass> {
+        self.classes.iter().map(|x| x.value().clone()).collect()
+    }
+
+    /// Returns all registered events with no attribute data.
+    pub fn evtypes(&self) -> Vec<MedusaEvtype> {
+        self.evtypes.iter().map(|x| x.value().clone()).collect()
+    }
+}
+
+/// Client reading messages of the security module.
+pub struct Client<R: Read + Unpin> {
+    // TODO endian based reader
+    reader: NativeByteOrderReader<R>,
+    registry: Arc<Registry>,
+}
+
+impl<R: Read + AsRawFd + Unpin + Send> Client<R> {
+    /// Creates new `Client` reading from `read_handle` with an empty registry.
+    pub fn new(read_handle: R) -> Result<Self, ReaderError> {
+        Ok(Self {
+            reader: NativeByteOrderReader::new(read_handle)?,
+            registry: Default::default(),
+        })
+    }
+
+    /// Records every message read from now on with `recorder`.
+    #[cfg(feature = "recording")]
+    pub(crate) fn record(&mut self, recorder: SessionRecorder) {
+        self.reader.record(recorder);
+    }
+
+    /// Returns classes and events registered so far.
+    pub fn registry(&self) -> &Arc<Registry> {
+        &self.registry
+    }
+
+    /// Reads the greeting of the security module and checks its byte order and protocol
+    /// version.
+    ///
+    /// Returns the protocol version.
+    pub async fn handshake(&mut self) -> Result<u64, ConnectionError> {
+        let greeting = self.reader.read_u64().await?;
+        if greeting == GREETING_REVERSED_BYTE_ORDER {
+            unimplemented!("reversed byte order");
+        } else if greeting != GREETING_NATIVE_BYTE_ORDER {
+            return Err(ConnectionError::UnknownByteOrder(greeting));
+        }
+
+        let version = self.reader.read_u64().await?;
+        if version != PROTOCOL_VERSION {
+            return Err(ConnectionError::UnsupportedVersionError(version));
+        }
+
+        Ok(version)
+    }
+
+    /// Waits at most `timeout` for a message. Returns `false` on timeout.
+    pub fn wait_readable(&mut self, timeout: Duration) -> Result<bool, ReaderError> {
+        self.reader.wait_readable(timeout)
+    }
+
+    /// Reads the next message and applies definitions of classes and events to the registry.
+    pub async fn next_message(&mut self) -> Result<Message, CommunicationError> {
+        let frame = self.read_frame().await?;
+        let message = self.read_message(frame).await?;
+
+        match &message {
+            Message::ClassDef(class) => self.registry.define_class(class.clone()),
+            Message::EvtypeDef(evtype) => self.registry.define_evtype(evtype.clone()),
+            _ => (),
+        }
+
+        Ok(message)
+    }
+
+    /// Reads the beginning of the next message.
+    pub async fn read_frame(&mut self) -> Result<Frame, CommunicationError> {
+        let id = self.reader.read_u64().await?;
+
+        if id == 0 {
+            let cmd = self.reader.read_command().await?;
+            Ok(Frame::Command(cmd))
+        } else {
+            let request_id = self.reader.read_u64().await?;
+            Ok(Frame::AuthRequest {
+                evtype_id: id,
+                request_id,
+            })
+        }
+    }
+
+    /// Reads the rest of the message starting with `frame`. Definitions of classes and events
+    /// are not applied to the registry, which is left to the caller.
+    pub async fn read_message(&mut self, frame: Frame) -> Result<Message, CommunicationError> {
+        let cmd = match frame {
+            Frame::Command(cmd) => cmd,
+            Frame::AuthRequest {
+                evtype_id,
+                request_id,
+            } => {
+                let request = self.read_auth_request(evtype_id, request_id).await?;
+                return Ok(Message::AuthRequest(Box::new(request)));
+            }
+        };
+
+        Ok(match cmd {
+            MEDUSA_COMM_KCLASSDEF => Message::ClassDef(self.read_class().await?),
+            MEDUSA_COMM_EVTYPEDEF => Message::EvtypeDef(self.read_evtype().await?),
+            MEDUSA_COMM_UPDATE_ANSWER => {
+                Message::UpdateAnswer(self.reader.read_update_answer().await?)
+            }
+            MEDUSA_COMM_FETCH_ANSWER => Message::FetchAnswer(
+                self.reader
+                    .read_fetch_answer(&self.registry.classes)
+                    .await?,
+            ),
+            MEDUSA_COMM_FETCH_ERROR => Message::FetchError,
+            _ => return Err(CommunicationError::UnknownCommandError(cmd)),
+        })
+    }
+
+    /// Skips bytes until something that looks like the beginning of a frame is found. That is
+    /// either a zero followed by a command handled by `read_message` or an identification of a registered event.
+    pub async fn resynchronize(&mut self) -> Result<Frame, CommunicationError> {
+        let mut window = [0; 8];
+        self.reader.read_exact(&mut window).await?;
+
+        let mut skipped = 0;
+        let frame = loop {
+            let id = u64::from_ne_bytes(window);
+
+            if id == 0 {
+                let cmd = self.reader.read_command().await?;
+                if MESSAGE_COMMANDS.contains(&cmd) {
+                    break Frame::Command(cmd);
+                }
+
+                // the command may still contain the start of the next frame
+                window.rotate_left(4);
+                window[4..].copy_from_slice(&cmd.to_ne_bytes());
+                skipped += 4;
+                continue;
+            } else if self.registry.evtypes.contains_key(&id) {
+                let request_id = self.reader.read_u64().await?;
+                break Frame::AuthRequest {
+                    evtype_id: id,
+                    request_id,
+                };
+            }
+
+            window.rotate_left(1);
+            self.reader.read_exact(&mut window[7..]).await?;
+            skipped += 1;
+        };
+
+        eprintln!("resynchronized after skipping {} bytes", skipped);
+
+        Ok(frame)
+    }
+
+    async fn read_class(&mut self) -> Result<MedusaClass, CommunicationError> {
+        let mut class = self.reader.read_class().await?;
+
+        let attrs = self.reader.read_attributes().await?;
+        for attr in attrs {
+            class.attributes.push(attr);
+        }
+
+        Ok(class)
+    }
+
+    async fn read_evtype(&mut self) -> Result<MedusaEvtype, CommunicationError> {
+        let mut evtype = self.reader.read_evtype().await?;
+        let ev_sub = evtype.header.ev_sub;
+        let ev_obj = evtype.header.ev_obj.expect("ev_obj is 0").get(); // should always be non-zero from medusa
+
+        if ev_sub == ev_obj && evtype.header.ev_name[0] == evtype.header.ev_name[1] {
+            evtype.header.ev_obj = None;
+            evtype.header.ev_name[1] = Arc::from("");
+        }
+
+        let attrs = self.reader.read_attributes().await?;
+        for attr in attrs {
+            evtype.attributes.push(attr);
+        }
+
+        Ok(evtype)
+    }
+
+    /// Reads an authorization request. Its attributes are parsed later, see
+    /// [`RawAuthRequest::parse`].
+    async fn read_auth_request(
+        &mut self,
+        id: u64,
+        request_id: u64,
+    ) -> Result<RawAuthRequest, CommunicationError> {
+        let evtype = self
+            .registry
+            .empty_evtype_from_id(&id)
+            .ok_or(CommunicationError::UnknownAccessTypeError(id))?;
+
+        let evtype_raw = self.read_shared(evtype.header.size as usize).await?;
+
+        let ev_sub = evtype.header.ev_sub;
+        let ev_obj = evtype.header.ev_obj;
+
+        // subject type
+        let subject = self
+            .registry
+            .empty_class_from_id(&ev_sub)
+            .ok_or(CommunicationError::UnknownSubjectTypeError(ev_sub))?;
+
+        // there seems to be padding so store into buffer first
+        let subject_raw = self.read_shared(subject.header.size as usize).await?;
+
+        // object type
+        let object = match ev_obj.map(|x| x.get()) {
+            Some(ev_obj) => {
+                let object = self
+                    .registry
+                    .empty_class_from_id(&ev_obj)
+                    .ok_or(CommunicationError::UnknownObjectTypeError(ev_obj))?;
+
+                let object_raw = self.read_shared(object.header.size as usize).await?;
+
+                Some((object, object_raw))
+            }
+            None => None,
+        };
+
+        Ok(RawAuthRequest {
+            request_id,
+            evtype: (evtype, evtype_raw),
+            subject: (subject, subject_raw),
+            object,
+            received: Instant::now(),
+        })
+    }
+
+    /// Reads `len` bytes into a buffer which is shared by copies of the request.
+    async fn read_shared(&mut self, len: usize) -> Result<Arc<[u8]>, ReaderError> {
+        let mut buf: Arc<[u8]> = iter::repeat_n(0, len).collect();
+        let data = Arc::get_mut(&mut buf).expect("buffer is not shared");
+        self.reader.read_exact(data).await?;
+        Ok(buf)
+    }
+}
+
+/// Authorization request read from the security module, but with attributes not parsed yet, so
+/// that parsing a large request does not delay reading the next one.
+#[derive(Debug)]
+pub struct RawAuthRequest {
+    pub(crate) request_id: u64,
+    pub(crate) evtype: (MedusaEvtype, Arc<[u8]>),
+    pub(crate) subject: (MedusaClass, Arc<[u8]>),
+    pub(crate) object: Option<(MedusaClass, Arc<[u8]>)>,
+
+    // when reading of the request finished
+    pub(crate) received: Instant,
+}
+
+impl RawAuthRequest {
+    /// Returns identification of this request.
+    pub fn request_id(&self) -> u64 {
+        self.request_id
+    }
+
+    /// Returns the event of this request with no attribute data.
+    pub fn evtype(&self) -> &MedusaEvtype {
+        &self.evtype.0
+    }
+
+    /// Parses attributes of the event, subject and object.
+    pub fn parse(self) -> AuthRequestData {
+        let (mut evtype, evtype_raw) = self.evtype;
+        evtype.attributes.set_from_shared(&evtype_raw);
+
+        let (mut subject, subject_raw) = self.subject;
+        subject.attributes.set_from_shared(&subject_raw);
+
+        let (object, object_raw) = match self.object {
+            Some((mut object, object_raw)) => {
+                object.attributes.set_from_shared(&object_raw);
+                (Some(object), Some(object_raw))
+            }
+            None => (None, None),
+        };
+
+        AuthRequestData {
+            request_id: self.request_id,
+            evtype,
+            subject,
+            object,
+            evtype_raw,
+            subject_raw,
+            object_raw,
+        }
+    }
+}
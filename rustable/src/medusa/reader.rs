@@ -1,15 +1,17 @@
 use crate::medusa::constants::*;
+#[cfg(feature = "recording")]
+use crate::medusa::recording::SessionRecorder;
 use crate::medusa::{
-    parser, Command, FetchAnswer, MedusaAttribute, MedusaAttributeHeader, MedusaClass,
+    parser, Command, FastDashMap, FetchAnswer, MedusaAttribute, MedusaAttributeHeader, MedusaClass,
     MedusaClassHeader, MedusaEvtype, MedusaEvtypeHeader, ReaderError, UpdateAnswer,
 };
 use async_trait::async_trait;
-use dashmap::DashMap;
 use polling::{Event, Poller};
-use std::io::Read;
+use std::io::{self, Read};
 use std::marker::Unpin;
 use std::mem;
 use std::os::unix::io::AsRawFd;
+use std::time::Duration;
 
 #[async_trait]
 pub(crate) trait AsyncReader
@@ -74,9 +76,17 @@ where
                 break;
             }
 
+            if header.data_type == AttributeDataType::Unknown {
+                eprintln!(
+                    "attribute \"{}\" has unknown type 0x{:02x}, treating it as bytes",
+                    header.name(),
+                    header.raw_type()
+                );
+            }
+
             res.push(MedusaAttribute {
                 header,
-                data: Vec::new(),
+                data: Default::default(),
             });
         }
 
@@ -93,7 +103,7 @@ where
 
     async fn read_fetch_answer(
         &mut self,
-        classes: &DashMap<u64, MedusaClass>,
+        classes: &FastDashMap<u64, MedusaClass>,
     ) -> Result<FetchAnswer, ReaderError> {
         let mut buf = [0; 2 * mem::size_of::<u64>()];
         self.read_exact(&mut buf).await?;
@@ -120,6 +130,8 @@ where
 pub(crate) struct NativeByteOrderReader<R: Read + Unpin> {
     read_handle: R,
     poller: Poller,
+    #[cfg(feature = "recording")]
+    recorder: Option<SessionRecorder>,
 }
 
 impl<R: Read + AsRawFd + Unpin> NativeByteOrderReader<R> {
@@ -129,8 +141,31 @@ impl<R: Read + AsRawFd + Unpin> NativeByteOrderReader<R> {
         Ok(Self {
             read_handle,
             poller,
+            #[cfg(feature = "recording")]
+            recorder: None,
         })
     }
+
+    /// Records every byte read from now on with `recorder`.
+    #[cfg(feature = "recording")]
+    pub(crate) fn record(&mut self, recorder: SessionRecorder) {
+        self.recorder = Some(recorder);
+    }
+
+    /// Waits at most `timeout` for data to become available. Returns `false` on timeout.
+    pub(crate) fn wait_readable(&mut self, timeout: Duration) -> Result<bool, ReaderError> {
+        let mut events = Vec::new();
+        wait(&self.poller, &mut events, Some(timeout))?;
+
+        if events.is_empty() {
+            return Ok(false);
+        }
+
+        // `read_exact` waits for its own event
+        self.poller.modify(&self.read_handle, Event::readable(0))?;
+
+        Ok(true)
+    }
 }
 
 #[async_trait]
@@ -140,8 +175,20 @@ impl<R: Read + AsRawFd + Unpin + Send> AsyncReader for NativeByteOrderReader<R>
         let mut events = Vec::new();
 
         while total != buf.len() {
-            self.poller.wait(&mut events, None)?;
-            total += self.read_handle.read(buf)?;
+            wait(&self.poller, &mut events, None)?;
+
+            let n = match self.read_handle.read(&mut buf[total..]) {
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                res => res?,
+            };
+            if n == 0 {
+                return Err(ReaderError::Disconnected);
+            }
+            #[cfg(feature = "recording")]
+            if let Some(recorder) = &mut self.recorder {
+                recorder.record(&buf[total..total + n]);
+            }
+            total += n;
 
             // Another interest in I/O requires reset
             self.poller.modify(&self.read_handle, Event::readable(0))?;
@@ -150,3 +197,16 @@ impl<R: Read + AsRawFd + Unpin + Send> AsyncReader for NativeByteOrderReader<R>
         Ok(total)
     }
 }
+
+/// Waits for `poller` like [`Poller::wait`], but is not interrupted by signals, e.g. those
+/// handled by [`ConfigBuilder::snapshot_on_signal`].
+///
+/// [`ConfigBuilder::snapshot_on_signal`]: crate::medusa::ConfigBuilder::snapshot_on_signal
+fn wait(poller: &Poller, events: &mut Vec<Event>, timeout: Option<Duration>) -> io::Result<()> {
+    loop {
+        match poller.wait(events, timeout) {
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            res => return res.map(drop),
+        }
+    }
+}
@@ -0,0 +1,475 @@
+//! Recording of the traffic from the security module, see [`ConfigBuilder::record_session`].
+//!
+//! A recording is a directory of chunks, each a zstd frame holding the next
+//! [`SessionRecording::chunk_size`] bytes read from the security module, and a file `index`
+//! listing the chunks in order, one per line:
+//!
+//! ```text
+//! <file name> <offset> <length> <compressed length> <start in milliseconds since the epoch>
+//! ```
+//!
+//! Offset and length are of the uncompressed bytes, so the chunk covering a part of the session
+//! is found without decompressing the others. Only the messages of the security module are
+//! recorded, answers are not. A recording is replayed by [`SessionReplay`].
+//!
+//! [`ConfigBuilder::record_session`]: crate::medusa::ConfigBuilder::record_session
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::Shutdown;
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::thread::{self, JoinHandle};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const INDEX_FILE: &str = "index";
+const DEFAULT_CHUNK_SIZE: u64 = 64 << 20;
+const DEFAULT_COMPRESSION_LEVEL: i32 = 3;
+
+// bytes read from the security module are handed to the compressing thread in blocks of this size
+const BLOCK_SIZE: usize = 64 << 10;
+const QUEUED_BLOCKS: usize = 64;
+
+/// Settings of a session recording, see [`ConfigBuilder::record_session`].
+///
+/// [`ConfigBuilder::record_session`]: crate::medusa::ConfigBuilder::record_session
+#[derive(Debug, Clone)]
+pub struct SessionRecording {
+    dir: PathBuf,
+    chunk_size: u64,
+    compression_level: i32,
+}
+
+impl SessionRecording {
+    /// Creates new `SessionRecording` into the directory `dir`, which is created if it does not
+    /// exist and must not hold another recording.
+    pub fn new<P: AsRef<Path>>(dir: P) -> Self {
+        Self {
+            dir: dir.as_ref().to_owned(),
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            compression_level: DEFAULT_COMPRESSION_LEVEL,
+        }
+    }
+
+    /// Starts a new chunk after every `bytes` uncompressed bytes, 64 MiB by default.
+    ///
+    /// Returns `Self`.
+    pub fn chunk_size(mut self, bytes: u64) -> Self {
+        self.chunk_size = bytes.max(1);
+        self
+    }
+
+    /// Sets the zstd compression level, 3 by default.
+    ///
+    /// Returns `Self`.
+    pub fn compression_level(mut self, level: i32) -> Self {
+        self.compression_level = level;
+        self
+    }
+
+    /// Returns the directory of the recording.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+}
+
+/// Records the bytes read from the security module. They are compressed and written by a
+/// background thread; when it falls behind, reading waits rather than losing data.
+pub(crate) struct SessionRecorder {
+    block: Vec<u8>,
+    sender: Option<SyncSender<Vec<u8>>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl SessionRecorder {
+    pub(crate) fn start(recording: &SessionRecording) -> io::Result<Self> {
+        let writer = ChunkWriter::create(recording)?;
+        let (sender, receiver) = mpsc::sync_channel(QUEUED_BLOCKS);
+        let thread = thread::Builder::new()
+            .name("medusa-recorder".to_owned())
+            .spawn(move || write_blocks(writer, receiver))?;
+
+        Ok(Self {
+            block: Vec::with_capacity(BLOCK_SIZE),
+            sender: Some(sender),
+            thread: Some(thread),
+        })
+    }
+
+    pub(crate) fn record(&mut self, data: &[u8]) {
+        self.block.extend_from_slice(data);
+        if self.block.len() >= BLOCK_SIZE {
+            self.flush();
+        }
+    }
+
+    fn flush(&mut self) {
+        if self.block.is_empty() {
+            return;
+        }
+
+        let block = std::mem::replace(&mut self.block, Vec::with_capacity(BLOCK_SIZE));
+        if let Some(sender) = &self.sender {
+            // the thread stopped on an error which it already reported
+            if sender.send(block).is_err() {
+                self.sender = None;
+            }
+        }
+    }
+}
+
+impl Drop for SessionRecorder {
+    fn drop(&mut self) {
+        self.flush();
+        self.sender = None;
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn write_blocks(mut writer: ChunkWriter, receiver: Receiver<Vec<u8>>) {
+    let res = receiver
+        .iter()
+        .try_for_each(|block| writer.write(&block))
+        .and_then(|_| writer.finish());
+    if let Err(e) = res {
+        eprintln!(
+            "session recording into {} stopped: {}",
+            writer.dir.display(),
+            e
+        );
+    }
+}
+
+struct Chunk {
+    name: String,
+    encoder: zstd::Encoder<'static, File>,
+    offset: u64,
+    length: u64,
+    start: u128,
+}
+
+struct ChunkWriter {
+    dir: PathBuf,
+    chunk_size: u64,
+    compression_level: i32,
+    index: File,
+    chunk: Option<Chunk>,
+    chunks: usize,
+    offset: u64,
+}
+
+impl ChunkWriter {
+    fn create(recording: &SessionRecording) -> io::Result<Self> {
+        fs::create_dir_all(&recording.dir)?;
+        let index = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(recording.dir.join(INDEX_FILE))?;
+
+        Ok(Self {
+            dir: recording.dir.clone(),
+            chunk_size: recording.chunk_size,
+            compression_level: recording.compression_level,
+            index,
+            chunk: None,
+            chunks: 0,
+            offset: 0,
+        })
+    }
+
+    fn write(&mut self, mut data: &[u8]) -> io::Result<()> {
+        while !data.is_empty() {
+            if self.chunk.is_none() {
+                self.chunk = Some(self.open_chunk()?);
+            }
+            let chunk = self.chunk.as_mut().expect("chunk was opened");
+
+            let n = data.len().min((self.chunk_size - chunk.length) as usize);
+            chunk.encoder.write_all(&data[..n])?;
+            chunk.length += n as u64;
+            self.offset += n as u64;
+            data = &data[n..];
+
+            if chunk.length == self.chunk_size {
+                self.close_chunk()?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn open_chunk(&mut self) -> io::Result<Chunk> {
+        let name = format!("chunk-{:06}.zst", self.chunks);
+        let file = File::create(self.dir.join(&name))?;
+        self.chunks += 1;
+
+        Ok(Chunk {
+            name,
+            encoder: zstd::Encoder::new(file, self.compression_level)?,
+            offset: self.offset,
+            length: 0,
+            start: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis(),
+        })
+    }
+
+    // a chunk is listed in the index only once it is complete
+    fn close_chunk(&mut self) -> io::Result<()> {
+        let chunk = match self.chunk.take() {
+            Some(chunk) => chunk,
+            None => return Ok(()),
+        };
+
+        let file = chunk.encoder.finish()?;
+        file.sync_all()?;
+        let compressed = file.metadata()?.len();
+        writeln!(
+            self.index,
+            "{} {} {} {} {}",
+            chunk.name, chunk.offset, chunk.length, compressed, chunk.start
+        )?;
+        self.index.flush()
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.close_chunk()?;
+        self.index.sync_all()
+    }
+}
+
+/// A chunk of a recording as listed in its index.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecordedChunk {
+    /// File name of the chunk within the recording.
+    pub name: String,
+
+    /// Offset of the chunk within the uncompressed session.
+    pub offset: u64,
+
+    /// Number of uncompressed bytes in the chunk.
+    pub length: u64,
+
+    /// Size of the chunk file.
+    pub compressed_length: u64,
+
+    /// Time the chunk was started, in milliseconds since the epoch.
+    pub start: u128,
+}
+
+impl RecordedChunk {
+    fn parse(line: &str) -> Option<Self> {
+        let mut fields = line.split_whitespace();
+        let chunk = Self {
+            name: fields.next()?.to_owned(),
+            offset: fields.next()?.parse().ok()?,
+            length: fields.next()?.parse().ok()?,
+            compressed_length: fields.next()?.parse().ok()?,
+            start: fields.next()?.parse().ok()?,
+        };
+
+        // names are never paths, so that an index cannot point outside of the recording
+        if fields.next().is_some() || chunk.name.contains('/') {
+            return None;
+        }
+        Some(chunk)
+    }
+}
+
+/// Reader of a recording made with [`ConfigBuilder::record_session`], yielding the bytes of the
+/// security module as they were read.
+///
+/// [`ConfigBuilder::record_session`]: crate::medusa::ConfigBuilder::record_session
+pub struct SessionReplay {
+    dir: PathBuf,
+    chunks: Vec<RecordedChunk>,
+    next: usize,
+    current: Option<(zstd::Decoder<'static, BufReader<File>>, u64)>,
+}
+
+impl SessionReplay {
+    /// Opens the recording in the directory `dir`.
+    ///
+    /// Returns `SessionReplay` or `io::Error` if the index could not be read or lists the chunks
+    /// out of order.
+    pub fn open<P: AsRef<Path>>(dir: P) -> io::Result<Self> {
+        let dir = dir.as_ref().to_owned();
+        let index = BufReader::new(File::open(dir.join(INDEX_FILE))?);
+
+        let mut chunks = Vec::new();
+        let mut offset = 0;
+        for (n, line) in index.lines().enumerate() {
+            let chunk = RecordedChunk::parse(&line?)
+                .filter(|chunk| chunk.offset == offset)
+                .ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("invalid line {} of the recording index", n + 1),
+                    )
+                })?;
+            offset += chunk.length;
+            chunks.push(chunk);
+        }
+
+        Ok(Self {
+            dir,
+            chunks,
+            next: 0,
+            current: None,
+        })
+    }
+
+    /// Returns the chunks of the recording in order.
+    pub fn chunks(&self) -> &[RecordedChunk] {
+        &self.chunks
+    }
+
+    /// Returns the number of recorded uncompressed bytes.
+    pub fn len(&self) -> u64 {
+        self.chunks.iter().map(|chunk| chunk.length).sum()
+    }
+
+    /// Returns `true` if nothing was recorded.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Replays the recording through a Unix domain socket, which can be passed to
+    /// [`Connection::new`] in place of the security module. Answers written to the socket are
+    /// discarded and the socket is shut down at the end of the recording.
+    ///
+    /// Returns `UnixStream` or `io::Error` if the socket could not be created.
+    ///
+    /// [`Connection::new`]: crate::medusa::Connection::new
+    pub fn into_stream(mut self) -> io::Result<UnixStream> {
+        let (module, server) = UnixStream::pair()?;
+        let mut answers = module.try_clone()?;
+
+        thread::Builder::new()
+            .name("medusa-replay".to_owned())
+            .spawn(move || {
+                let mut module = module;
+                if let Err(e) = io::copy(&mut self, &mut module) {
+                    eprintln!("replay of {} stopped: {}", self.dir.display(), e);
+                }
+                let _ = module.shutdown(Shutdown::Write);
+            })?;
+        thread::Builder::new()
+            .name("medusa-replay-answers".to_owned())
+            .spawn(move || io::copy(&mut answers, &mut io::sink()))?;
+
+        Ok(server)
+    }
+
+    fn open_next(&mut self) -> io::Result<bool> {
+        let chunk = match self.chunks.get(self.next) {
+            Some(chunk) => chunk,
+            None => return Ok(false),
+        };
+        let file = File::open(self.dir.join(&chunk.name))?;
+        self.current = Some((zstd::Decoder::new(file)?, chunk.length));
+        self.next += 1;
+        Ok(true)
+    }
+}
+
+impl Read for SessionReplay {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        loop {
+            if self.current.is_none() && !self.open_next()? {
+                return Ok(0);
+            }
+            let (decoder, remaining) = self.current.as_mut().expect("chunk was opened");
+
+            let n = decoder.read(buf)?;
+            if n != 0 {
+                *remaining = remaining.saturating_sub(n as u64);
+                return Ok(n);
+            }
+            if *remaining != 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    format!("chunk {} of the recording is truncated", self.next - 1),
+                ));
+            }
+            self.current = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("rustable-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn round_trip_across_chunks() {
+        let dir = temp_dir("recording");
+        let data: Vec<u8> = (0..300_000u32).map(|i| (i % 251) as u8).collect();
+
+        let mut recorder =
+            SessionRecorder::start(&SessionRecording::new(&dir).chunk_size(100_000)).unwrap();
+        for part in data.chunks(777) {
+            recorder.record(part);
+        }
+        drop(recorder);
+
+        let mut replay = SessionReplay::open(&dir).unwrap();
+        assert_eq!(replay.chunks().len(), 3);
+        assert_eq!(replay.chunks()[2].offset, 200_000);
+        assert_eq!(replay.len(), data.len() as u64);
+        assert!(replay
+            .chunks()
+            .iter()
+            .all(|c| c.compressed_length < c.length));
+
+        let mut replayed = Vec::new();
+        replay.read_to_end(&mut replayed).unwrap();
+        assert_eq!(replayed, data);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn refuses_to_overwrite_a_recording() {
+        let dir = temp_dir("recording-exists");
+        drop(SessionRecorder::start(&SessionRecording::new(&dir)).unwrap());
+        assert!(SessionRecorder::start(&SessionRecording::new(&dir)).is_err());
+
+        let replay = SessionReplay::open(&dir).unwrap();
+        assert!(replay.is_empty());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn detects_truncated_chunks() {
+        let dir = temp_dir("recording-truncated");
+        let mut recorder = SessionRecorder::start(&SessionRecording::new(&dir)).unwrap();
+        recorder.record(&[1; 1000]);
+        drop(recorder);
+        fs::write(dir.join(INDEX_FILE), "chunk-000000.zst 0 2000 10 0\n").unwrap();
+
+        let mut replayed = Vec::new();
+        let err = SessionReplay::open(&dir)
+            .unwrap()
+            .read_to_end(&mut replayed)
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
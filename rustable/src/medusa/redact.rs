@@ -0,0 +1,88 @@
+//! Redaction of attribute values in verbose output, see [`ConfigBuilder::redact`].
+//!
+//! [`ConfigBuilder::redact`]: crate::medusa::ConfigBuilder::redact
+
+use crate::medusa::audit::to_hex;
+use crate::medusa::constants::AttributeDataType;
+use crate::medusa::{AttributeValue, Config, MedusaClass};
+use sha2::{Digest, Sha256};
+
+/// Redactions used unless the config sets others for the attributes, see
+/// [`ConfigBuilder::redact`].
+///
+/// [`ConfigBuilder::redact`]: crate::medusa::ConfigBuilder::redact
+pub(crate) const DEFAULT_REDACTIONS: [(&str, Redaction); 2] = [
+    ("cmdline", Redaction::HashArguments),
+    ("environ", Redaction::Omit),
+];
+
+/// Number of hexadecimal digits of the hash written in place of a redacted value.
+const HASH_DIGITS: usize = 16;
+
+/// How values of an attribute are written into verbose output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Redaction {
+    /// The value is written as it is.
+    Keep,
+
+    /// The attribute is left out.
+    Omit,
+
+    /// The value is replaced with its hash, so that equal values can still be matched.
+    Hash,
+
+    /// The first word of the value, e.g. the executable of a `cmdline`, is kept and the
+    /// arguments following a NUL or a space are replaced with their hash.
+    HashArguments,
+}
+
+impl Redaction {
+    /// Returns `value` redacted for output, `None` if it is left out.
+    pub(crate) fn apply(self, value: &[u8]) -> Option<String> {
+        match self {
+            Self::Keep => Some(value.escape_ascii().to_string()),
+            Self::Omit => None,
+            Self::Hash => Some(hash(value)),
+            Self::HashArguments => {
+                let end = value
+                    .iter()
+                    .position(|&b| b == 0 || b == b' ')
+                    .unwrap_or(value.len());
+                let (exe, arguments) = value.split_at(end);
+                if arguments.is_empty() {
+                    return Some(exe.escape_ascii().to_string());
+                }
+
+                Some(format!("{} {}", exe.escape_ascii(), hash(arguments)))
+            }
+        }
+    }
+}
+
+/// Returns attributes of `class` as `name=value` pairs redacted according to `config`.
+/// Bitmaps such as virtual spaces are left out.
+pub(crate) fn format_attributes(config: &Config, class: &MedusaClass) -> String {
+    class
+        .attributes()
+        .iter()
+        .filter(|x| x.header.data_type != AttributeDataType::Bitmap)
+        .filter_map(|x| {
+            let value = match x.value() {
+                AttributeValue::Unsigned(value) => value.to_string().into_bytes(),
+                AttributeValue::Signed(value) => value.to_string().into_bytes(),
+                // strings are matched as raw bytes, which may hold arguments after a NUL
+                AttributeValue::String(_) => x.bytes_trimmed().to_vec(),
+                AttributeValue::Bytes(value) => to_hex(&value).into_bytes(),
+            };
+            let value = config.redaction(x.name()).apply(&value)?;
+
+            Some(format!("{}={}", x.name(), value))
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn hash(data: &[u8]) -> String {
+    let digest = to_hex(&Sha256::digest(data));
+    format!("sha256:{}", &digest[..HASH_DIGITS])
+}
@@ -0,0 +1,75 @@
+//! Relabeling of renamed entities, see [`ConfigBuilder::add_rename_event_handler`].
+//!
+//! [`ConfigBuilder::add_rename_event_handler`]: crate::medusa::ConfigBuilder::add_rename_event_handler
+
+use crate::cstr_to_string;
+use crate::medusa::{Context, HandlerArgs, HandlerFlags, MedusaAnswer};
+use anyhow::Context as _;
+
+/// Moves the subject of a rename event to the node of its new name, so that it does not keep
+/// the labels of its old location. If the new name is not covered by the tree, the subject is
+/// removed from its node and all virtual spaces.
+pub(crate) async fn rename_handler(
+    ctx: &Context,
+    args: HandlerArgs<'_>,
+) -> anyhow::Result<MedusaAnswer> {
+    let config = ctx.config();
+    let HandlerArgs {
+        mut subject,
+        object,
+        evtype,
+        handler_data,
+    } = args;
+
+    let tree = config
+        .tree_by_name(&handler_data.primary_tree)
+        .unwrap_or_else(|| panic!("primary tree `{}` not found", handler_data.primary_tree));
+
+    let path_attr = handler_data.attribute.as_deref().unwrap_or("");
+    let path = cstr_to_string(evtype.get_attribute(path_attr)?);
+
+    let target = if handler_data.flags.contains(HandlerFlags::FROM_OBJECT) {
+        // the new name is relative to the new parent, the object of the event
+        let parent = object.as_ref().context("event has no object")?;
+        let parent_cinfo = parent.get_object_cinfo()?;
+        config
+            .node_by_cinfo(&parent_cinfo)
+            .and_then(|parent| config.child_node(parent, &path))
+    } else {
+        tree.resolve(&path)
+    };
+
+    match target {
+        Some((node, recursed)) => {
+            println!(
+                "{}: \"{}\" -> \"{}\"{}",
+                evtype.header.name,
+                path,
+                node.path(),
+                if recursed { " (recursion)" } else { "" }
+            );
+            subject.set_node(ctx, &evtype, node, recursed);
+        }
+        None => {
+            println!(
+                "{}: \"{}\" not covered by tree {}, labels cleared",
+                evtype.header.name,
+                path,
+                tree.name()
+            );
+            subject.clear_node();
+        }
+    }
+
+    let name = subject.header.name().to_owned();
+    subject.update_no_wait(
+        ctx,
+        Some(Box::new(move |answer| {
+            if let Err(e) = answer.result() {
+                eprintln!("{}: {}", name, e);
+            }
+        })),
+    );
+
+    Ok(MedusaAnswer::Allow)
+}
@@ -0,0 +1,273 @@
+//! Interactive queries of a [`Config`] without a connection to the security module.
+//!
+//! Available with the `repl` feature. Every query is a single line of text:
+//!
+//! ```text
+//! > resolve domains /usr/sbin/sshd
+//! domains: / -> usr -> sbin -> sshd
+//! > spaces of domains/usr/sbin/sshd
+//! member  sshd
+//! reads   all_files sshd
+//! writes  sshd
+//! sees    all_files sshd
+//! denies
+//! > simulate getfile subject=domains/usr/sbin/sshd object=shadow
+//! relation: Deny
+//! > explain kill subject=sshd object=all_files
+//! event kill
+//! relation none
+//! handler check_kill not applicable, object not in sshd
+//! answer Allow
+//! ```
+//!
+//! Entities are given either as `<tree>/<path>`, which is resolved like
+//! [`MedusaClass::enter_tree`] does, or as the name of a single virtual space. Simulation
+//! evaluates static relations, see [`ConfigBuilder::allow_relation`], and lists the handlers
+//! that would be run. The handlers themselves are not run, as they need the attributes and
+//! answers of the security module.
+//!
+//! [`MedusaClass::enter_tree`]: crate::medusa::MedusaClass::enter_tree
+//! [`ConfigBuilder::allow_relation`]: crate::medusa::ConfigBuilder::allow_relation
+
+use crate::bitmap;
+use crate::medusa::constants::DEFAULT_ANSWER;
+use crate::medusa::{AccessType, Config, Explanation, MedusaAnswer, Node};
+use std::io::{self, BufRead, Write};
+use std::sync::Arc;
+
+const HELP: &str = "\
+help                                          show this help
+resolve <tree> <path>                         show the node an entity at <path> is entered into
+spaces of <tree>/<path>                       show virtual spaces of the node of <path>
+simulate <event> subject=<entity> [object=<entity>]
+                                              show the answer of relations or the handlers run
+explain <event> subject=<entity> [object=<entity>]
+                                              show why each handler is or is not run
+quit                                          leave the REPL
+
+<entity> is either <tree>/<path> or the name of a virtual space";
+
+/// Access types named like the methods of [`SpaceBuilder`].
+///
+/// [`SpaceBuilder`]: crate::medusa::SpaceBuilder
+const ACCESS_TYPES: [(AccessType, &str); 4] = [
+    (AccessType::Member, "member"),
+    (AccessType::Read, "reads"),
+    (AccessType::Write, "writes"),
+    (AccessType::See, "sees"),
+];
+
+/// Read-eval-print loop answering queries about a [`Config`].
+pub struct Repl {
+    config: Config,
+}
+
+impl Repl {
+    /// Creates new `Repl` querying `config`.
+    pub fn new(config: Config) -> Self {
+        Self { config }
+    }
+
+    /// Reads queries from `input` and writes their results to `output` until the input ends or
+    /// `quit` is entered.
+    pub fn run<I: BufRead, O: Write>(&self, input: I, mut output: O) -> io::Result<()> {
+        let mut lines = input.lines();
+
+        loop {
+            write!(output, "> ")?;
+            output.flush()?;
+
+            let line = match lines.next() {
+                Some(line) => line?,
+                None => break,
+            };
+
+            let line = line.trim();
+            if line == "quit" || line == "exit" {
+                break;
+            }
+
+            match self.execute(line) {
+                Ok(result) if result.is_empty() => (),
+                Ok(result) => writeln!(output, "{}", result)?,
+                Err(reason) => writeln!(output, "error: {}", reason)?,
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Executes a single query and returns its result.
+    pub fn execute(&self, line: &str) -> Result<String, String> {
+        let args = line.split_whitespace().collect::<Vec<_>>();
+
+        match args[..] {
+            [] => Ok(String::new()),
+            ["help"] => Ok(HELP.to_owned()),
+            ["resolve", tree, path] => self.resolve(tree, path),
+            ["spaces", "of", entity] => {
+                let (tree, path) = split_entity(entity)
+                    .ok_or_else(|| format!("`{}` is not of the form <tree>/<path>", entity))?;
+                self.spaces(tree, &path)
+            }
+            ["simulate", event, ref entities @ ..] => self.simulate(event, entities),
+            ["explain", event, ref entities @ ..] => {
+                let (svs, ovs) = self.request_vs(entities)?;
+                let explanation = Explanation::new_vs(&self.config, event, &svs, ovs.as_deref());
+                Ok(explanation.to_string())
+            }
+            _ => Err(format!("unknown query `{}`, try `help`", line)),
+        }
+    }
+
+    fn resolve(&self, tree: &str, path: &str) -> Result<String, String> {
+        let (node, recursed) = self.node(tree, path)?;
+        let cinfo = node.cinfo();
+
+        let location = match self.config.node_location(&cinfo) {
+            Some((tree, paths)) => {
+                let paths = paths.into_iter().map(display_path).collect::<Vec<_>>();
+                format!("{}: {}", tree, paths.join(" -> "))
+            }
+            None => format!("{}: {}", tree, display_path(node.path())),
+        };
+
+        Ok(if recursed {
+            format!("{} (recursion)", location)
+        } else {
+            location
+        })
+    }
+
+    fn spaces(&self, tree: &str, path: &str) -> Result<String, String> {
+        let (node, _) = self.node(tree, path)?;
+        let vs = node.virtual_space();
+
+        let lines = ACCESS_TYPES
+            .iter()
+            .map(|(at, name)| (*name, vs.to_at_bytes(*at)))
+            .chain([("denies", vs.denied().to_vec())])
+            .map(|(name, bytes)| {
+                let line = format!("{:<8}{}", name, self.space_names(&bytes));
+                line.trim_end().to_owned()
+            })
+            .collect::<Vec<_>>();
+
+        Ok(lines.join("\n"))
+    }
+
+    fn simulate(&self, event: &str, entities: &[&str]) -> Result<String, String> {
+        let (svs, ovs) = self.request_vs(entities)?;
+
+        let default = if self.config.enforce {
+            format!("{:?} once enforcing", MedusaAnswer::Deny)
+        } else {
+            format!("{:?}", DEFAULT_ANSWER)
+        };
+
+        let id = match self.config.event_id(event) {
+            Some(id) => id,
+            None => {
+                return Ok(format!(
+                    "no handlers nor relations, default answer {}",
+                    default
+                ))
+            }
+        };
+
+        if let Some(table) = &self.config.decision_table {
+            if let Some(answer) = table.lookup_vs(id, &svs, ovs.as_deref()) {
+                return Ok(format!("relation: {:?}", answer));
+            }
+        }
+
+        let handlers = self
+            .config
+            .handlers_by_event_id(id)
+            .iter()
+            .filter(|x| x.is_applicable_vs(&svs, ovs.as_deref()))
+            .map(|x| x.name())
+            .collect::<Vec<_>>();
+
+        if handlers.is_empty() {
+            return Ok(format!("no applicable handler, default answer {}", default));
+        }
+
+        Ok(format!("handlers: {}", handlers.join(" ")))
+    }
+
+    /// Returns the member virtual spaces of the subject and of the object given as
+    /// `subject=<entity>` and `object=<entity>`.
+    fn request_vs(&self, entities: &[&str]) -> Result<(Vec<u8>, Option<Vec<u8>>), String> {
+        let mut svs = None;
+        let mut ovs = None;
+        for entity in entities {
+            match entity.split_once('=') {
+                Some(("subject", entity)) => svs = Some(self.entity_vs(entity)?),
+                Some(("object", entity)) => ovs = Some(self.entity_vs(entity)?),
+                _ => {
+                    return Err(format!(
+                        "expected subject=<entity> or object=<entity>, got `{}`",
+                        entity
+                    ))
+                }
+            }
+        }
+        let svs = svs.ok_or("subject is missing")?;
+
+        Ok((svs, ovs))
+    }
+
+    /// Returns the member virtual spaces of `entity`.
+    fn entity_vs(&self, entity: &str) -> Result<Vec<u8>, String> {
+        if let Some((tree, path)) = split_entity(entity) {
+            let (node, _) = self.node(tree, &path)?;
+            return Ok(node.virtual_space().to_at_bytes(AccessType::Member));
+        }
+
+        let bit = *self
+            .config
+            .name_to_space_bit(entity)
+            .ok_or_else(|| format!("no space named `{}`", entity))?;
+
+        // bits of removed spaces may leave gaps, see `ConfigBuilder::replacing`
+        let nbits = self.config.space_names().map(|(_, bit)| bit + 1).max();
+        let mut vs = vec![0; nbits.unwrap_or_default().div_ceil(8)];
+        bitmap::set_bit(&mut vs, bit);
+        Ok(vs)
+    }
+
+    fn node(&self, tree: &str, path: &str) -> Result<(&Arc<Node>, bool), String> {
+        if !path.starts_with('/') {
+            return Err(format!("path `{}` is not absolute", path));
+        }
+
+        self.config
+            .tree_by_name(tree)
+            .ok_or_else(|| format!("no tree named `{}`", tree))?
+            .resolve(path)
+            .ok_or_else(|| format!("`{}` is not covered by tree `{}`", path, tree))
+    }
+
+    fn space_names(&self, vs: &[u8]) -> String {
+        let mut names = (0..vs.len() * 8)
+            .filter(|&bit| bitmap::test_bit(vs, bit))
+            .filter_map(|bit| self.config.space_bit_to_name(&bit).map(String::as_str))
+            .collect::<Vec<_>>();
+        names.sort_unstable();
+
+        names.join(" ")
+    }
+}
+
+/// Splits `<tree>/<path>` into the tree name and the absolute path.
+fn split_entity(entity: &str) -> Option<(&str, String)> {
+    let (tree, path) = entity.split_once('/')?;
+    Some((tree, format!("/{}", path)))
+}
+
+/// Returns the path of a node without the anchors of its regular expression.
+fn display_path(path: &str) -> &str {
+    let path = path.strip_prefix('^').unwrap_or(path);
+    path.strip_suffix('$').unwrap_or(path)
+}
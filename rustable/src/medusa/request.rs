@@ -1,6 +1,7 @@
 use crate::medusa::constants::*;
-use crate::medusa::{MedusaClass, MedusaEvtype};
+use crate::medusa::{MedusaClass, MedusaEvtype, UpdateError};
 use std::mem;
+use std::sync::Arc;
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum RequestType {
@@ -26,7 +27,7 @@ pub struct MedusaRequest<'a> {
     pub data: &'a [u8],
 }
 
-impl<'a> MedusaRequest<'_> {
+impl MedusaRequest<'_> {
     // TODO big endian - check rust core to_le_bytes() implementation
     /// Converts `MedusaRequest` into byte vector.
     pub fn to_vec(self) -> Vec<u8> {
@@ -47,7 +48,7 @@ impl<'a> MedusaRequest<'_> {
 }
 
 #[derive(Clone, Copy, Debug)]
-#[repr(packed)]
+#[repr(C, packed)]
 pub struct DecisionAnswer {
     /// Identification of the authorization request.
     pub request_id: u64,
@@ -60,17 +61,11 @@ impl DecisionAnswer {
     // TODO big endian
     /// Converts `DecisionAnswer` into byte array.
     pub fn to_vec(self) -> [u8; 8 + std::mem::size_of::<Self>()] {
-        let answer = MEDUSA_COMM_AUTHANSWER.to_le_bytes();
-        let request = self.request_id.to_le_bytes();
-        let status = self.status.to_le_bytes();
-        answer
-            .iter()
-            .copied()
-            .chain(request.iter().copied())
-            .chain(status.iter().copied())
-            .collect::<Vec<u8>>()
-            .try_into()
-            .unwrap()
+        let mut buf = [0; 8 + std::mem::size_of::<Self>()];
+        buf[..8].copy_from_slice(&MEDUSA_COMM_AUTHANSWER.to_le_bytes());
+        buf[8..16].copy_from_slice(&{ self.request_id }.to_le_bytes());
+        buf[16..].copy_from_slice(&{ self.status }.to_le_bytes());
+        buf
     }
 }
 
@@ -84,7 +79,7 @@ pub struct UpdateAnswer {
     pub msg_seq: u64,
 
     /// Verdict of the update request.
-    pub status: i32,
+    pub status: UpdateStatus,
 }
 
 impl UpdateAnswer {
@@ -92,6 +87,47 @@ impl UpdateAnswer {
     pub const fn size() -> usize {
         mem::size_of::<u64>() + mem::size_of::<u64>() + mem::size_of::<i32>()
     }
+
+    /// Returns `Ok` if the entity was updated, otherwise `UpdateError` describing the status.
+    pub fn result(&self) -> Result<(), UpdateError> {
+        match self.status {
+            UpdateStatus::Ok => Ok(()),
+            UpdateStatus::Err => Err(UpdateError::FailedError(self.class_id)),
+            UpdateStatus::TimedOut => Err(UpdateError::TimeoutError(self.class_id)),
+            UpdateStatus::Unknown(status) => {
+                Err(UpdateError::UnknownStatusError(self.class_id, status))
+            }
+        }
+    }
+}
+
+/// Status of an update request as answered by the security module.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UpdateStatus {
+    /// The entity was updated.
+    Ok,
+
+    /// The security module failed to update the entity, e.g. because it no longer exists.
+    Err,
+
+    /// No answer arrived in time, see [`ConfigBuilder::pending_request_max_age`]. Never sent by
+    /// the security module.
+    ///
+    /// [`ConfigBuilder::pending_request_max_age`]: crate::medusa::ConfigBuilder::pending_request_max_age
+    TimedOut,
+
+    /// Status not known to this implementation.
+    Unknown(i32),
+}
+
+impl From<i32> for UpdateStatus {
+    fn from(status: i32) -> Self {
+        match status {
+            0 => UpdateStatus::Ok,
+            -1 => UpdateStatus::Err,
+            status => UpdateStatus::Unknown(status),
+        }
+    }
 }
 
 #[allow(dead_code)]
@@ -105,6 +141,34 @@ pub struct FetchAnswer {
 
     /// Data returned from the security module.
     pub data: Vec<u8>,
+
+    /// Whether the object was fetched.
+    pub status: FetchStatus,
+}
+
+impl FetchAnswer {
+    /// Creates the answer to a fetch request which was abandoned before its answer arrived.
+    pub(crate) fn timed_out(class_id: u64, msg_seq: u64) -> Self {
+        Self {
+            class_id,
+            msg_seq,
+            data: Vec::new(),
+            status: FetchStatus::TimedOut,
+        }
+    }
+}
+
+/// Status of a fetch request.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FetchStatus {
+    /// The object was fetched, its attributes are in [`FetchAnswer::data`].
+    Ok,
+
+    /// No answer arrived in time, see [`ConfigBuilder::pending_request_max_age`]. Never sent by
+    /// the security module.
+    ///
+    /// [`ConfigBuilder::pending_request_max_age`]: crate::medusa::ConfigBuilder::pending_request_max_age
+    TimedOut,
 }
 
 #[repr(u16)]
@@ -134,4 +198,91 @@ pub struct AuthRequestData {
 
     /// Object which may not be present for certain events.
     pub object: Option<MedusaClass>,
+
+    /// Attribute data of the event as sent by the security module, including attributes which
+    /// are not known to the parser.
+    pub evtype_raw: Arc<[u8]>,
+
+    /// Attribute data of the subject as sent by the security module.
+    pub subject_raw: Arc<[u8]>,
+
+    /// Attribute data of the object as sent by the security module.
+    pub object_raw: Option<Arc<[u8]>>,
+}
+
+/// Authorization request together with the answer that was sent to the security module.
+#[derive(Clone, Debug)]
+pub struct CompletedRequest {
+    /// The original authorization request.
+    pub data: AuthRequestData,
+
+    /// Final verdict of the authorization request.
+    pub answer: MedusaAnswer,
+}
+
+#[cfg(test)]
+impl AuthRequestData {
+    /// Returns a request of `event` by a process in the virtual spaces `subject` of `config` on
+    /// a file in the virtual spaces `object`, or on no object.
+    pub(crate) fn for_test(
+        config: &crate::medusa::Config,
+        event: &str,
+        subject: &[&str],
+        object: Option<&[&str]>,
+    ) -> Self {
+        use crate::bitmap;
+        use crate::medusa::attribute::{
+            intern_name, AttributeData, MedusaAttribute, MedusaAttributeHeader,
+        };
+        use crate::medusa::{MedusaAttributes, MedusaClassHeader, MedusaEvtypeHeader};
+
+        let class = |name: &str, spaces: &[&str]| {
+            let mut vs = vec![0; 8];
+            for space in spaces {
+                let (_, bit) = config
+                    .space_names()
+                    .find(|(x, _)| x == space)
+                    .unwrap_or_else(|| panic!("space `{}` is not defined", space));
+                bitmap::set_bit(&mut vs, bit);
+            }
+
+            let mut attributes = MedusaAttributes::default();
+            attributes.push(MedusaAttribute {
+                header: MedusaAttributeHeader {
+                    offset: 0,
+                    length: 8,
+                    mods: AttributeMods::empty(),
+                    endianness: AttributeEndianness::Native,
+                    data_type: AttributeDataType::Bitmap,
+                    raw_type: AttributeDataType::Bitmap as u8,
+                    name: intern_name(MEDUSA_VS_ATTR_NAME),
+                },
+                data: AttributeData::Owned(vs),
+            });
+            MedusaClass {
+                header: MedusaClassHeader {
+                    id: 0,
+                    size: 8,
+                    name: name.to_owned(),
+                },
+                attributes,
+            }
+        };
+
+        Self {
+            request_id: 0,
+            evtype: MedusaEvtype {
+                header: MedusaEvtypeHeader {
+                    name: event.into(),
+                    ..Default::default()
+                },
+                attributes: Default::default(),
+            },
+            subject: class("process", subject),
+            object: object.map(|spaces| class("file", spaces)),
+            evtype_raw: Arc::from([]),
+            subject_raw: Arc::from([]),
+            object_raw: None,
+        }
+    }
 }
@@ -0,0 +1,110 @@
+//! Retries of failed update requests, see [`ConfigBuilder::update_retries`].
+//!
+//! [`ConfigBuilder::update_retries`]: crate::medusa::ConfigBuilder::update_retries
+
+use crate::medusa::executor::Executor;
+use crate::medusa::pending::PendingRequests;
+use crate::medusa::{
+    Config, MedusaRequest, RequestType, UpdateAnswer, UpdateEscalation, UpdateStatus, Writer,
+};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::oneshot;
+
+/// Update request written to the security module, with the receiver of its answer.
+pub(crate) type SentUpdate = (u64, oneshot::Receiver<UpdateAnswer>);
+
+/// Sends update requests and retries them according to the config they were made with.
+pub(crate) struct UpdateSender {
+    writer: Writer,
+    pending: Arc<PendingRequests>,
+    executor: Arc<dyn Executor>,
+    retries: u32,
+    backoff: Duration,
+    escalation: Option<UpdateEscalation>,
+}
+
+impl UpdateSender {
+    pub(crate) fn new(
+        config: &Config,
+        writer: Writer,
+        pending: Arc<PendingRequests>,
+        executor: Arc<dyn Executor>,
+    ) -> Self {
+        Self {
+            writer,
+            pending,
+            executor,
+            retries: config.update_retries,
+            backoff: config.update_backoff,
+            escalation: config.update_escalation.clone(),
+        }
+    }
+
+    /// Writes update request of `data` to the security module.
+    pub(crate) fn send(&self, class_id: u64, data: &[u8]) -> SentUpdate {
+        let (id, receiver) = self.pending.register_update();
+        let req = MedusaRequest {
+            req_type: RequestType::Update,
+            class_id,
+            id,
+            data,
+        };
+
+        self.writer.write(Arc::from(req.to_vec()));
+
+        (id, receiver)
+    }
+
+    /// Waits for the answer to `sent` and sends `data` again while the update fails and
+    /// retries are left, doubling the backoff every time. A request abandoned before its answer
+    /// arrived is answered as [`UpdateStatus::TimedOut`]. If the last answer is a failure, the
+    /// escalation callback is invoked.
+    pub(crate) async fn answer(
+        &self,
+        class_id: u64,
+        data: &[u8],
+        sent: SentUpdate,
+    ) -> UpdateAnswer {
+        let (mut id, mut receiver) = sent;
+        let mut backoff = self.backoff;
+        let mut retry = 0;
+
+        loop {
+            let answer = receiver.await.unwrap_or(UpdateAnswer {
+                class_id,
+                msg_seq: id,
+                status: UpdateStatus::TimedOut,
+            });
+
+            let e = match answer.result() {
+                Ok(()) => return answer,
+                Err(e) => e,
+            };
+
+            if retry == self.retries || !is_retryable(answer.status) {
+                if let Some(escalation) = &self.escalation {
+                    escalation(&e);
+                }
+                return answer;
+            }
+
+            retry += 1;
+            eprintln!("{}, retry {}/{} in {:?}", e, retry, self.retries, backoff);
+            self.executor.sleep(backoff).await;
+            backoff = backoff.saturating_mul(2);
+
+            (id, receiver) = self.send(class_id, data);
+        }
+    }
+}
+
+/// Returns whether an update answered with `status` may succeed when sent again. Positive
+/// statuses unknown to this implementation are not errors reported by the security module.
+fn is_retryable(status: UpdateStatus) -> bool {
+    match status {
+        UpdateStatus::Err | UpdateStatus::TimedOut => true,
+        UpdateStatus::Unknown(status) => status < 0,
+        UpdateStatus::Ok => false,
+    }
+}
@@ -0,0 +1,514 @@
+//! Inline allow/deny rules for cases which do not warrant a custom handler.
+//!
+//! A rule consists of an answer and a condition, for example:
+//!
+//! ```text
+//! allow if subject.uid == 0 && event.filename ~= "^/var/log/"
+//! deny if !(object.uid == subject.uid || subject.gid == 0)
+//! ```
+//!
+//! Conditions refer to attributes of the `event`, `subject` and `object` and compare them with
+//! integer (decimal or `0x` hexadecimal), string and boolean literals using `==`, `!=`, `<`,
+//! `<=`, `>` and `>=`. Operator `~=` matches a string against a regular expression literal.
+//! Strings are compared as the bytes sent by the security module, up to their NUL padding.
+//! Conditions are combined with `&&`, `||`, `!` and parentheses. Within string literals, `\"`
+//! and `\\` stand for `"` and `\`, any other backslash is kept, so that a regular expression
+//! such as `"\.gz$"` is written as it is.
+//!
+//! Rules are compiled when they are parsed, so syntax errors and invalid regular expressions are
+//! reported before the config is built. See [`ConfigBuilder::add_rule_event_handler`] for how a
+//! list of rules is evaluated.
+//!
+//! [`ConfigBuilder::add_rule_event_handler`]: crate::medusa::ConfigBuilder::add_rule_event_handler
+
+use crate::medusa::{AttributeValue, Context, HandlerArgs, MedusaAnswer, RuleError};
+use regex::bytes::Regex;
+use std::str::FromStr;
+use std::{fmt, mem};
+
+/// Rule answering requests whose attributes satisfy a condition.
+#[derive(Debug, Clone)]
+pub struct Rule {
+    text: String,
+    answer: MedusaAnswer,
+    condition: Expr,
+}
+
+impl Rule {
+    /// Parses a rule of the form `<answer> if <condition>`, where answer is one of `allow`,
+    /// `deny`, `skip` and `yes`.
+    ///
+    /// Returns `Rule` or `RuleError` if the rule is not valid.
+    pub fn parse(text: &str) -> Result<Self, RuleError> {
+        let mut parser = Parser::new(text)?;
+
+        let answer = match parser.peek() {
+            Some(Token::Ident(x)) if x == "allow" => MedusaAnswer::Allow,
+            Some(Token::Ident(x)) if x == "deny" => MedusaAnswer::Deny,
+            Some(Token::Ident(x)) if x == "skip" => MedusaAnswer::Skip,
+            Some(Token::Ident(x)) if x == "yes" => MedusaAnswer::Yes,
+            _ => return Err(parser.error("expected `allow`, `deny`, `skip` or `yes`")),
+        };
+        parser.next();
+
+        if !matches!(parser.peek(), Some(Token::Ident(x)) if x == "if") {
+            return Err(parser.error("expected `if`"));
+        }
+        parser.next();
+
+        let condition = parser.expr()?;
+        if parser.peek().is_some() {
+            return Err(parser.error("unexpected token"));
+        }
+
+        Ok(Self {
+            text: text.trim().to_owned(),
+            answer,
+            condition,
+        })
+    }
+
+    /// Returns the answer of this rule.
+    pub fn answer(&self) -> MedusaAnswer {
+        self.answer
+    }
+
+    /// Returns whether the condition of this rule holds for the request.
+    pub fn matches(&self, args: &HandlerArgs<'_>) -> anyhow::Result<bool> {
+        match self.condition.eval(args)? {
+            Value::Bool(x) => Ok(x),
+            x => anyhow::bail!("condition of `{}` is {}, not a boolean", self.text, x),
+        }
+    }
+}
+
+impl FromStr for Rule {
+    type Err = RuleError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s)
+    }
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.text)
+    }
+}
+
+pub(crate) async fn rule_handler(
+    ctx: &Context,
+    args: HandlerArgs<'_>,
+) -> anyhow::Result<MedusaAnswer> {
+    for rule in args.handler_data.rules.iter() {
+        if rule.matches(&args)? {
+            if ctx.is_handler_debugged(&args.handler_data.name) {
+                println!("[{}] matched `{}`", args.handler_data.name, rule);
+            }
+            return Ok(rule.answer);
+        }
+    }
+
+    Ok(MedusaAnswer::Allow)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Entity {
+    Event,
+    Subject,
+    Object,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CmpOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Debug, Clone)]
+enum Expr {
+    Literal(Value),
+    Attribute(Entity, String),
+    Not(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Cmp(CmpOp, Box<Expr>, Box<Expr>),
+    Match(Box<Expr>, Regex),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+enum Value {
+    Bool(bool),
+    // wide enough for both signed and unsigned attributes
+    Int(i128),
+    // raw bytes up to the NUL padding, so that embedded NULs and invalid UTF-8 take part
+    Str(Vec<u8>),
+    Bytes(Vec<u8>),
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Bool(x) => write!(f, "{}", x),
+            Value::Int(x) => write!(f, "{}", x),
+            Value::Str(x) => write!(f, "\"{}\"", x.escape_ascii()),
+            Value::Bytes(x) => write!(f, "{:x?}", x),
+        }
+    }
+}
+
+impl Expr {
+    fn eval(&self, args: &HandlerArgs<'_>) -> anyhow::Result<Value> {
+        let value = match self {
+            Expr::Literal(x) => x.clone(),
+            Expr::Attribute(entity, name) => {
+                let attribute = match entity {
+                    Entity::Event => args.evtype.attributes.attribute(name),
+                    Entity::Subject => args.subject.attributes.attribute(name),
+                    Entity::Object => match &args.object {
+                        Some(object) => object.attributes.attribute(name),
+                        None => anyhow::bail!("event {} has no object", args.evtype.name()),
+                    },
+                }?;
+
+                match attribute.value() {
+                    AttributeValue::Unsigned(x) => Value::Int(x.into()),
+                    AttributeValue::Signed(x) => Value::Int(x.into()),
+                    AttributeValue::String(_) => Value::Str(attribute.bytes_trimmed().to_vec()),
+                    AttributeValue::Bytes(x) => Value::Bytes(x),
+                }
+            }
+            Expr::Not(x) => Value::Bool(!x.eval_bool(args)?),
+            Expr::And(x, y) => Value::Bool(x.eval_bool(args)? && y.eval_bool(args)?),
+            Expr::Or(x, y) => Value::Bool(x.eval_bool(args)? || y.eval_bool(args)?),
+            Expr::Cmp(op, x, y) => {
+                let (x, y) = (x.eval(args)?, y.eval(args)?);
+                if mem::discriminant(&x) != mem::discriminant(&y) {
+                    anyhow::bail!("cannot compare {} with {}", x, y);
+                }
+
+                Value::Bool(match op {
+                    CmpOp::Eq => x == y,
+                    CmpOp::Ne => x != y,
+                    CmpOp::Lt => x < y,
+                    CmpOp::Le => x <= y,
+                    CmpOp::Gt => x > y,
+                    CmpOp::Ge => x >= y,
+                })
+            }
+            Expr::Match(x, regex) => match x.eval(args)? {
+                Value::Str(x) => Value::Bool(regex.is_match(&x)),
+                x => anyhow::bail!("cannot match {} against a regular expression", x),
+            },
+        };
+
+        Ok(value)
+    }
+
+    fn eval_bool(&self, args: &HandlerArgs<'_>) -> anyhow::Result<bool> {
+        match self.eval(args)? {
+            Value::Bool(x) => Ok(x),
+            x => anyhow::bail!("expected a boolean, found {}", x),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Ident(String),
+    Int(i128),
+    Str(String),
+    Op(&'static str),
+}
+
+// longer operators first, so that `<=` is not read as `<`
+const OPERATORS: [&str; 13] = [
+    "==", "!=", "<=", ">=", "~=", "&&", "||", "<", ">", "!", "(", ")", ".",
+];
+
+struct Parser {
+    // tokens with their columns, in reverse order
+    tokens: Vec<(usize, Token)>,
+    end: usize,
+}
+
+impl Parser {
+    fn new(text: &str) -> Result<Self, RuleError> {
+        let mut tokens = Vec::new();
+        let mut rest = text;
+
+        loop {
+            rest = rest.trim_start();
+            let column = text.len() - rest.len() + 1;
+            let c = match rest.chars().next() {
+                Some(c) => c,
+                None => break,
+            };
+
+            let (token, len) = if c.is_ascii_alphabetic() || c == '_' {
+                let len = rest
+                    .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                    .unwrap_or(rest.len());
+                (Token::Ident(rest[..len].to_owned()), len)
+            } else if c.is_ascii_digit() {
+                let len = rest
+                    .find(|c: char| !c.is_ascii_alphanumeric())
+                    .unwrap_or(rest.len());
+                let literal = &rest[..len];
+                let value = match literal.strip_prefix("0x") {
+                    Some(hex) => i128::from_str_radix(hex, 16),
+                    None => literal.parse(),
+                }
+                .map_err(|_| RuleError::SyntaxError(column, "invalid integer".into()))?;
+                (Token::Int(value), len)
+            } else if c == '"' {
+                let mut value = String::new();
+                let mut chars = rest.char_indices().skip(1);
+                let len = loop {
+                    match chars.next() {
+                        Some((i, '"')) => break i + 1,
+                        Some((_, '\\')) => match chars.next() {
+                            Some((_, c @ ('"' | '\\'))) => value.push(c),
+                            // kept, so that regular expressions such as `\.` need no escaping
+                            Some((_, c)) => {
+                                value.push('\\');
+                                value.push(c);
+                            }
+                            None => break 0,
+                        },
+                        Some((_, c)) => value.push(c),
+                        None => break 0,
+                    }
+                };
+                if len == 0 {
+                    return Err(RuleError::SyntaxError(column, "unterminated string".into()));
+                }
+                (Token::Str(value), len)
+            } else {
+                match OPERATORS.iter().find(|x| rest.starts_with(*x)) {
+                    Some(op) => (Token::Op(op), op.len()),
+                    None => {
+                        let message = format!("unexpected character `{}`", c);
+                        return Err(RuleError::SyntaxError(column, message));
+                    }
+                }
+            };
+
+            tokens.push((column, token));
+            rest = &rest[len..];
+        }
+
+        tokens.reverse();
+        Ok(Self {
+            tokens,
+            end: text.len() + 1,
+        })
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.last().map(|(_, x)| x)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        self.tokens.pop().map(|(_, x)| x)
+    }
+
+    fn eat(&mut self, op: &str) -> bool {
+        if matches!(self.peek(), Some(Token::Op(x)) if *x == op) {
+            self.next();
+            return true;
+        }
+        false
+    }
+
+    fn error(&self, message: &str) -> RuleError {
+        let column = self.tokens.last().map_or(self.end, |(x, _)| *x);
+        RuleError::SyntaxError(column, message.to_owned())
+    }
+
+    fn expr(&mut self) -> Result<Expr, RuleError> {
+        let mut expr = self.and()?;
+        while self.eat("||") {
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr, RuleError> {
+        let mut expr = self.unary()?;
+        while self.eat("&&") {
+            expr = Expr::And(Box::new(expr), Box::new(self.unary()?));
+        }
+        Ok(expr)
+    }
+
+    fn unary(&mut self) -> Result<Expr, RuleError> {
+        if self.eat("!") {
+            return Ok(Expr::Not(Box::new(self.unary()?)));
+        }
+        self.comparison()
+    }
+
+    fn comparison(&mut self) -> Result<Expr, RuleError> {
+        let left = self.operand()?;
+
+        if self.eat("~=") {
+            let regex = match self.peek() {
+                Some(Token::Str(pattern)) => Regex::new(pattern)?,
+                _ => return Err(self.error("expected a regular expression string")),
+            };
+            self.next();
+
+            return Ok(Expr::Match(Box::new(left), regex));
+        }
+
+        let op = match self.peek() {
+            Some(Token::Op("==")) => CmpOp::Eq,
+            Some(Token::Op("!=")) => CmpOp::Ne,
+            Some(Token::Op("<")) => CmpOp::Lt,
+            Some(Token::Op("<=")) => CmpOp::Le,
+            Some(Token::Op(">")) => CmpOp::Gt,
+            Some(Token::Op(">=")) => CmpOp::Ge,
+            _ => return Ok(left),
+        };
+        self.next();
+
+        Ok(Expr::Cmp(op, Box::new(left), Box::new(self.operand()?)))
+    }
+
+    fn operand(&mut self) -> Result<Expr, RuleError> {
+        let expr = match self.peek() {
+            Some(Token::Op("(")) => {
+                self.next();
+                let expr = self.expr()?;
+                if !self.eat(")") {
+                    return Err(self.error("expected `)`"));
+                }
+                return Ok(expr);
+            }
+            Some(Token::Int(x)) => Expr::Literal(Value::Int(*x)),
+            Some(Token::Str(x)) => Expr::Literal(Value::Str(x.clone().into_bytes())),
+            Some(Token::Ident(x)) if x == "true" => Expr::Literal(Value::Bool(true)),
+            Some(Token::Ident(x)) if x == "false" => Expr::Literal(Value::Bool(false)),
+            Some(Token::Ident(x)) => {
+                let entity = match x.as_str() {
+                    "event" => Entity::Event,
+                    "subject" => Entity::Subject,
+                    "object" => Entity::Object,
+                    _ => return Err(self.error("expected `event`, `subject` or `object`")),
+                };
+                self.next();
+
+                if !self.eat(".") {
+                    return Err(self.error("expected `.`"));
+                }
+                match self.peek() {
+                    Some(Token::Ident(name)) => Expr::Attribute(entity, name.clone()),
+                    _ => return Err(self.error("expected an attribute name")),
+                }
+            }
+            _ => return Err(self.error("expected an operand")),
+        };
+        self.next();
+
+        Ok(expr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokens(text: &str) -> Vec<Token> {
+        let mut parser = Parser::new(text).unwrap();
+        std::iter::from_fn(|| parser.next()).collect()
+    }
+
+    fn regex(rule: &Rule) -> &Regex {
+        match &rule.condition {
+            Expr::Match(_, regex) => regex,
+            x => panic!("expected a match, found {:?}", x),
+        }
+    }
+
+    #[test]
+    fn lexes_operators_and_literals() {
+        assert_eq!(
+            tokens("subject.uid<=0x10||!x"),
+            [
+                Token::Ident("subject".into()),
+                Token::Op("."),
+                Token::Ident("uid".into()),
+                Token::Op("<="),
+                Token::Int(16),
+                Token::Op("||"),
+                Token::Op("!"),
+                Token::Ident("x".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn lexes_escapes_in_strings() {
+        assert_eq!(tokens(r#""a\"b""#), [Token::Str(r#"a"b"#.into())]);
+        assert_eq!(tokens(r#""a\\b""#), [Token::Str(r"a\b".into())]);
+        assert_eq!(tokens(r#""\.gz$""#), [Token::Str(r"\.gz$".into())]);
+    }
+
+    #[test]
+    fn rejects_invalid_tokens() {
+        assert!(matches!(
+            Parser::new(r#"x == "abc"#),
+            Err(RuleError::SyntaxError(6, _))
+        ));
+        assert!(matches!(
+            Parser::new("x == 12z"),
+            Err(RuleError::SyntaxError(6, _))
+        ));
+        assert!(matches!(
+            Parser::new("x @ 1"),
+            Err(RuleError::SyntaxError(3, _))
+        ));
+    }
+
+    #[test]
+    fn keeps_regex_escapes() {
+        let rule = Rule::parse(r#"deny if event.filename ~= "\.gz$""#).unwrap();
+        assert!(regex(&rule).is_match(b"a.gz"));
+        assert!(!regex(&rule).is_match(b"agz"));
+    }
+
+    #[test]
+    fn parses_precedence() {
+        let rule = Rule::parse("allow if !subject.uid == 0 || event.a && object.b").unwrap();
+        assert_eq!(rule.answer(), MedusaAnswer::Allow);
+        assert!(matches!(
+            &rule.condition,
+            Expr::Or(x, y) if matches!(**x, Expr::Not(_)) && matches!(**y, Expr::And(_, _))
+        ));
+    }
+
+    #[test]
+    fn rejects_invalid_rules() {
+        for text in [
+            "permit if true",
+            "allow true",
+            "allow if (true",
+            "allow if true false",
+            "allow if user.uid == 0",
+            "allow if subject.",
+            "allow if subject.name ~= 1",
+        ] {
+            assert!(
+                matches!(Rule::parse(text), Err(RuleError::SyntaxError(..))),
+                "{}",
+                text
+            );
+        }
+        assert!(Rule::parse(r#"allow if event.a ~= "(""#).is_err());
+    }
+}
@@ -0,0 +1,83 @@
+//! Scheduling of the threads communicating with the security module, see
+//! [`ConfigBuilder::io_scheduling`].
+//!
+//! [`ConfigBuilder::io_scheduling`]: crate::medusa::ConfigBuilder::io_scheduling
+
+use std::io;
+use std::mem;
+
+/// Scheduling policy and CPU affinity of the threads reading from and writing to the security
+/// module.
+///
+/// Every confined process waits in a system call until its authorization request is answered,
+/// so the latency of these threads matters to the whole machine. Setting a realtime priority
+/// requires `CAP_SYS_NICE`.
+#[derive(Debug, Clone, Default)]
+pub struct ThreadScheduling {
+    fifo_priority: Option<i32>,
+    cpus: Option<Vec<usize>>,
+}
+
+impl ThreadScheduling {
+    /// Creates new `ThreadScheduling` keeping the default scheduling.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Sets the `SCHED_FIFO` policy with `priority`, from 1 (lowest) to 99 (highest).
+    ///
+    /// Returns `Self`.
+    pub fn with_fifo_priority(mut self, priority: i32) -> Self {
+        self.fifo_priority = Some(priority);
+        self
+    }
+
+    /// Restricts the threads to `cpus`.
+    ///
+    /// Returns `Self`.
+    pub fn with_cpus<I: IntoIterator<Item = usize>>(mut self, cpus: I) -> Self {
+        self.cpus = Some(cpus.into_iter().collect());
+        self
+    }
+
+    /// Applies the scheduling to the calling thread.
+    pub(crate) fn apply(&self) -> io::Result<()> {
+        if let Some(cpus) = &self.cpus {
+            // SAFETY: `cpu_set_t` is a plain bit mask, all zeroes is an empty set
+            let mut set: libc::cpu_set_t = unsafe { mem::zeroed() };
+            for &cpu in cpus {
+                if cpu >= libc::CPU_SETSIZE as usize {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("cpu {} is out of range", cpu),
+                    ));
+                }
+
+                // SAFETY: `cpu` was checked to fit into the set
+                unsafe { libc::CPU_SET(cpu, &mut set) };
+            }
+
+            // SAFETY: `set` is initialized and its size is passed along, 0 is the calling thread
+            let res = unsafe { libc::sched_setaffinity(0, mem::size_of_val(&set), &set) };
+            if res != 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+
+        if let Some(priority) = self.fifo_priority {
+            let param = libc::sched_param {
+                sched_priority: priority,
+            };
+
+            // SAFETY: `param` is initialized and `pthread_self` is always a valid thread
+            let res = unsafe {
+                libc::pthread_setschedparam(libc::pthread_self(), libc::SCHED_FIFO, &param)
+            };
+            if res != 0 {
+                return Err(io::Error::from_raw_os_error(res));
+            }
+        }
+
+        Ok(())
+    }
+}
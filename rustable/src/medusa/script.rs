@@ -0,0 +1,328 @@
+//! Handlers written as [Rhai] scripts, so that simple policies can be edited as text files.
+//!
+//! [Rhai]: https://rhai.rs
+
+use crate::medusa::audit::space_names;
+use crate::medusa::tree::Node;
+use crate::medusa::{
+    AttributeValue, Context, HandlerArgs, MedusaAnswer, MedusaAttributes, MedusaClass, PluginError,
+    PluginHandler,
+};
+use async_trait::async_trait;
+use rhai::{Array, Blob, Dynamic, Engine, EvalAltResult, Map, Scope, AST, INT};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant, SystemTime};
+
+/// Default limit of operations performed by a script for a single request, see
+/// [`ScriptHandler::with_max_operations`].
+pub const SCRIPT_DEFAULT_MAX_OPERATIONS: u64 = 1_000_000;
+
+/// How often the script file is checked for modifications.
+const MODIFICATION_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum EntityKind {
+    Event,
+    Subject,
+    Object,
+}
+
+struct RequestState {
+    // roots of the trees by name
+    trees: Vec<(String, Arc<Node>)>,
+
+    enter: Vec<(EntityKind, String, String)>,
+}
+
+/// Event, subject or object as seen by a script.
+#[derive(Clone)]
+struct ScriptEntity {
+    kind: EntityKind,
+    name: String,
+    attributes: Arc<Map>,
+    spaces: Arc<Vec<String>>,
+    state: Arc<Mutex<RequestState>>,
+}
+
+impl ScriptEntity {
+    fn new(
+        kind: EntityKind,
+        name: &str,
+        attributes: &MedusaAttributes,
+        spaces: Vec<String>,
+        state: &Arc<Mutex<RequestState>>,
+    ) -> Self {
+        let attributes = attributes
+            .iter()
+            .map(|x| (x.name().into(), to_dynamic(x.value())))
+            .collect();
+
+        Self {
+            kind,
+            name: name.to_owned(),
+            attributes: Arc::new(attributes),
+            spaces: Arc::new(spaces),
+            state: Arc::clone(state),
+        }
+    }
+
+    fn class(
+        kind: EntityKind,
+        ctx: &Context,
+        class: &MedusaClass,
+        state: &Arc<Mutex<RequestState>>,
+    ) -> Self {
+        let spaces = space_names(&ctx.config(), class);
+        Self::new(kind, class.header.name(), &class.attributes, spaces, state)
+    }
+
+    fn attribute(&mut self, name: &str) -> Result<Dynamic, Box<EvalAltResult>> {
+        self.attributes
+            .get(name)
+            .cloned()
+            .ok_or_else(|| format!("{} has no attribute `{}`", self.name, name).into())
+    }
+
+    fn enter_tree(&mut self, tree: &str, path: &str) -> bool {
+        if self.kind == EntityKind::Event {
+            return false;
+        }
+
+        let mut state = self.state.lock().unwrap();
+        let covered = state
+            .trees
+            .iter()
+            .any(|(name, root)| name == tree && root.covers(path));
+        if covered {
+            state
+                .enter
+                .push((self.kind, tree.to_owned(), path.to_owned()));
+        }
+
+        covered
+    }
+}
+
+struct Compiled {
+    modified: Option<SystemTime>,
+    checked: Instant,
+    ast: Arc<AST>,
+}
+
+/// Plugin implemented by a Rhai script. The script is compiled again once its file is modified,
+/// which is checked at most once a second, if the new version fails to compile, the previous one
+/// is kept.
+///
+/// For each request, the script is evaluated with variables `event`, `subject` and `object`
+/// (which is `()` if the event has no object). Their attributes are accessed as properties,
+/// e.g. `subject.uid`, and they have the following methods:
+///
+/// * `name()` returns the name of the event or class,
+/// * `spaces()` returns names of the virtual spaces the subject or object is a member of,
+/// * `in_space(space)` returns whether the subject or object is a member of `space`,
+/// * `enter_tree(tree, path)` enters the subject or object into the node at `path` of `tree`,
+///   or returns `false` if the path is not covered by the tree. Entities are entered once the
+///   script finishes.
+///
+/// The value of the script is the answer, one of constants `ALLOW`, `DENY`, `SKIP` and `YES`:
+///
+/// ```text
+/// if subject.uid == 0 || event.filename.starts_with("/tmp/") { ALLOW } else { DENY }
+/// ```
+pub struct ScriptHandler {
+    name: String,
+    path: PathBuf,
+    engine: Engine,
+    script: RwLock<Compiled>,
+}
+
+impl ScriptHandler {
+    /// Compiles a plugin filling the slot `name` from the script at `path`.
+    ///
+    /// Returns `ScriptHandler` or `PluginError` if the script could not be read or compiled.
+    pub fn load<P: AsRef<Path>>(name: &str, path: P) -> Result<Self, PluginError> {
+        let path = path.as_ref().to_owned();
+        let engine = engine();
+
+        let modified = modified(&path);
+        let ast = engine
+            .compile_file(path.clone())
+            .map_err(|e| PluginError::ScriptError(path.clone(), e))?;
+
+        println!("loaded plugin {} from {}", name, path.display());
+
+        Ok(Self {
+            name: name.to_owned(),
+            path,
+            engine,
+            script: RwLock::new(Compiled {
+                modified,
+                checked: Instant::now(),
+                ast: Arc::new(ast),
+            }),
+        })
+    }
+
+    /// Sets the limit of operations performed by the script for a single request.
+    ///
+    /// Returns `Self`.
+    pub fn with_max_operations(mut self, operations: u64) -> Self {
+        self.engine.set_max_operations(operations);
+        self
+    }
+
+    /// Returns the path the plugin was loaded from.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // Returns the current version of the script, compiling it again if the file was modified.
+    fn ast(&self) -> Arc<AST> {
+        {
+            let script = self.script.read().unwrap();
+            if script.checked.elapsed() < MODIFICATION_CHECK_INTERVAL {
+                return Arc::clone(&script.ast);
+            }
+        }
+
+        let mut script = self.script.write().unwrap();
+        // another request may have checked the file meanwhile
+        if script.checked.elapsed() < MODIFICATION_CHECK_INTERVAL {
+            return Arc::clone(&script.ast);
+        }
+        script.checked = Instant::now();
+
+        let modified = modified(&self.path);
+        if script.modified != modified {
+            // do not try again until the file is modified again
+            script.modified = modified;
+
+            match self.engine.compile_file(self.path.clone()) {
+                Ok(ast) => {
+                    println!("plugin {} reloaded from {}", self.name, self.path.display());
+                    script.ast = Arc::new(ast);
+                }
+                Err(e) => eprintln!("plugin {}: keeping the previous version, {}", self.name, e),
+            }
+        }
+
+        Arc::clone(&script.ast)
+    }
+}
+
+#[async_trait]
+impl PluginHandler for ScriptHandler {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn handle(&self, ctx: &Context, args: HandlerArgs<'_>) -> anyhow::Result<MedusaAnswer> {
+        let HandlerArgs {
+            evtype,
+            mut subject,
+            mut object,
+            ..
+        } = args;
+
+        let state = Arc::new(Mutex::new(RequestState {
+            trees: ctx
+                .config()
+                .trees()
+                .iter()
+                .map(|x| (x.name().to_owned(), Arc::clone(x.root())))
+                .collect(),
+            enter: Vec::new(),
+        }));
+
+        let answer = {
+            let mut scope = Scope::new();
+            scope.push_constant("ALLOW", MedusaAnswer::Allow);
+            scope.push_constant("DENY", MedusaAnswer::Deny);
+            scope.push_constant("SKIP", MedusaAnswer::Skip);
+            scope.push_constant("YES", MedusaAnswer::Yes);
+
+            let event = ScriptEntity::new(
+                EntityKind::Event,
+                evtype.name(),
+                &evtype.attributes,
+                Vec::new(),
+                &state,
+            );
+            scope.push_constant("event", event);
+            scope.push_constant(
+                "subject",
+                ScriptEntity::class(EntityKind::Subject, ctx, &subject, &state),
+            );
+            match &object {
+                Some(object) => scope.push_constant(
+                    "object",
+                    ScriptEntity::class(EntityKind::Object, ctx, object, &state),
+                ),
+                None => scope.push_constant("object", ()),
+            };
+
+            self.engine
+                .eval_ast_with_scope::<Dynamic>(&mut scope, &self.ast())
+                .map_err(|e| anyhow::anyhow!("plugin {}: {}", self.name, e))?
+        };
+
+        let enter = std::mem::take(&mut state.lock().unwrap().enter);
+        for (kind, tree, path) in enter {
+            let entity = match kind {
+                EntityKind::Object => object.as_mut().expect("object is entered by itself"),
+                _ => &mut subject,
+            };
+            if let Err(e) = entity.enter_tree(ctx, &evtype, &tree, &path).await {
+                eprintln!("plugin {}: {}", self.name, e);
+            }
+        }
+
+        answer
+            .try_cast::<MedusaAnswer>()
+            .ok_or_else(|| anyhow::anyhow!("plugin {} did not return an answer", self.name))
+    }
+}
+
+fn engine() -> Engine {
+    let mut engine = Engine::new();
+    engine.set_max_operations(SCRIPT_DEFAULT_MAX_OPERATIONS);
+
+    engine
+        .register_type_with_name::<MedusaAnswer>("Answer")
+        .register_fn("to_string", |x: &mut MedusaAnswer| format!("{:?}", x))
+        .register_fn("==", |x: MedusaAnswer, y: MedusaAnswer| x == y);
+
+    engine
+        .register_type_with_name::<ScriptEntity>("Entity")
+        .register_indexer_get(ScriptEntity::attribute)
+        .register_fn("name", |x: &mut ScriptEntity| x.name.clone())
+        .register_fn("spaces", |x: &mut ScriptEntity| {
+            x.spaces
+                .iter()
+                .cloned()
+                .map(Dynamic::from)
+                .collect::<Array>()
+        })
+        .register_fn("in_space", |x: &mut ScriptEntity, space: &str| {
+            x.spaces.iter().any(|x| x == space)
+        })
+        .register_fn("enter_tree", ScriptEntity::enter_tree);
+
+    engine
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|x| x.modified()).ok()
+}
+
+fn to_dynamic(value: AttributeValue) -> Dynamic {
+    match value {
+        // scripts have only signed integers, large values wrap around
+        AttributeValue::Unsigned(x) => Dynamic::from(x as INT),
+        AttributeValue::Signed(x) => Dynamic::from(x as INT),
+        AttributeValue::String(x) => Dynamic::from(x),
+        AttributeValue::Bytes(x) => Dynamic::from_blob(Blob::from(x)),
+    }
+}
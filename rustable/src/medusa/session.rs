@@ -0,0 +1,67 @@
+//! Login sessions of subjects, see [`Session`].
+
+use crate::medusa::MedusaClass;
+use serde::Serialize;
+use std::fmt;
+
+/// Attributes which may hold the login uid, as named by the security module or by the audit
+/// subsystem.
+const LOGIN_UID_ATTR_NAMES: [&str; 3] = ["luid", "loginuid", "auid"];
+
+/// Attributes which may hold the audit session id.
+const SESSION_ID_ATTR_NAMES: [&str; 2] = ["sessionid", "ses"];
+
+/// Value of the login uid and of the session id which were never set, e.g. for daemons started
+/// at boot.
+const UNSET: u32 = u32::MAX;
+
+/// Login session a subject belongs to, known from its login uid and audit session id. Both are
+/// inherited by all processes started from the session, even across `setuid`, so handlers can
+/// apply per-session policies and audit records can be correlated with a login.
+///
+/// Attributes which the security module does not register, or which were never set, are
+/// `None`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+pub struct Session {
+    /// User who logged in.
+    pub login_uid: Option<u32>,
+
+    /// Audit session id of the login.
+    pub session_id: Option<u32>,
+}
+
+impl Session {
+    /// Returns the session of `class`, or `None` if it has neither a login uid nor a session id.
+    pub(crate) fn from_class(class: &MedusaClass) -> Option<Self> {
+        let session = Self {
+            login_uid: u32_attribute(class, &LOGIN_UID_ATTR_NAMES),
+            session_id: u32_attribute(class, &SESSION_ID_ATTR_NAMES),
+        };
+
+        (session != Session::default()).then_some(session)
+    }
+}
+
+impl fmt::Display for Session {
+    /// Formats the session as `auid=<login uid> ses=<session id>` like the audit subsystem,
+    /// leaving out unknown values.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let values = [("auid", self.login_uid), ("ses", self.session_id)]
+            .iter()
+            .filter_map(|(k, v)| v.map(|v| format!("{}={}", k, v)))
+            .collect::<Vec<_>>();
+
+        write!(f, "{}", values.join(" "))
+    }
+}
+
+/// Returns the first of attributes `names` `class` has, or `None` if it has none of them or the
+/// value is unset.
+fn u32_attribute(class: &MedusaClass, names: &[&str]) -> Option<u32> {
+    let data = names
+        .iter()
+        .find_map(|name| class.attributes.get(name).ok())?;
+    let value = u32::from_le_bytes(data.try_into().ok()?);
+
+    (value != UNSET).then_some(value)
+}
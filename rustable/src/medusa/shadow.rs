@@ -0,0 +1,109 @@
+//! Shadow evaluation of a candidate config, see [`ConfigBuilder::shadow`].
+//!
+//! [`ConfigBuilder::shadow`]: crate::medusa::ConfigBuilder::shadow
+
+use crate::bitmap;
+use crate::medusa::constants::*;
+use crate::medusa::executor::spawn_with_output;
+use crate::medusa::mcp::get_answer;
+use crate::medusa::{AuthRequestData, Config, Context, MedusaAnswer, MedusaClass};
+use std::sync::Arc;
+use tokio::sync::oneshot;
+
+/// Starts evaluating a copy of `auth_data` with the candidate config of `shadow`.
+pub(crate) fn spawn_evaluation(
+    ctx: &Context,
+    shadow: &Arc<Context>,
+    auth_data: &AuthRequestData,
+) -> oneshot::Receiver<MedusaAnswer> {
+    let active = ctx.config();
+    let candidate = shadow.config();
+
+    let mut auth_data = auth_data.clone();
+    let header = &mut auth_data.evtype.header;
+    header.event_id = candidate.event_id(header.name());
+    header.config_generation = candidate.generation;
+    translate(&active, &candidate, &mut auth_data.subject);
+    if let Some(object) = &mut auth_data.object {
+        translate(&active, &candidate, object);
+    }
+
+    let shadow = Arc::clone(shadow);
+    spawn_with_output(&*ctx.executor, async move {
+        get_answer(&shadow, &auth_data).await
+    })
+}
+
+/// Waits for the candidate answer and logs the request if it differs from the active `answer`.
+pub(crate) async fn compare(
+    evaluation: oneshot::Receiver<MedusaAnswer>,
+    auth_data: &AuthRequestData,
+    answer: MedusaAnswer,
+) {
+    let candidate = match evaluation.await {
+        Ok(candidate) => candidate,
+        Err(_) => {
+            eprintln!(
+                "shadow: evaluation of request {} panicked",
+                auth_data.request_id
+            );
+            MedusaAnswer::Err
+        }
+    };
+
+    if candidate != answer {
+        println!(
+            "shadow divergence: request {} {} ({} -> {}): active {:?}, candidate {:?}",
+            auth_data.request_id,
+            auth_data.evtype.name(),
+            auth_data.subject.header.name(),
+            auth_data
+                .object
+                .as_ref()
+                .map(|x| x.header.name())
+                .unwrap_or("-"),
+            answer,
+            candidate
+        );
+    }
+}
+
+/// Rewrites virtual spaces and the tree node of `class` from `active` config into `candidate`.
+/// Spaces and nodes unknown to the candidate are dropped.
+fn translate(active: &Config, candidate: &Config, class: &mut MedusaClass) {
+    for attr in [
+        MEDUSA_VS_ATTR_NAME,
+        MEDUSA_VSR_ATTR_NAME,
+        MEDUSA_VSW_ATTR_NAME,
+        MEDUSA_VSS_ATTR_NAME,
+    ] {
+        let vs = match class.attributes.get(attr) {
+            Ok(vs) => vs,
+            Err(_) => continue,
+        };
+
+        let mut translated = vec![0; vs.len()];
+        for bit in (0..vs.len() * 8).filter(|&bit| bitmap::test_bit(vs, bit)) {
+            let candidate_bit = active
+                .space_bit_to_name(&bit)
+                .and_then(|name| candidate.name_to_space_bit(name));
+
+            match candidate_bit {
+                Some(&n) if n < translated.len() * 8 => bitmap::set_bit(&mut translated, n),
+                _ => (),
+            }
+        }
+
+        let _ = class.attributes.set(attr, translated);
+    }
+
+    if let Ok(cinfo) = class.get_object_cinfo() {
+        if cinfo != 0 {
+            let translated = active
+                .node_location(&cinfo)
+                .and_then(|(tree, paths)| candidate.cinfo_by_location(tree, &paths))
+                .unwrap_or(0);
+            let _ = class.set_object_cinfo(translated);
+        }
+    }
+}
@@ -0,0 +1,183 @@
+//! Audit records in formats understood by SIEM systems.
+
+use crate::medusa::audit::{AuditRecord, AuditSink};
+use std::io::Write;
+use std::sync::Mutex;
+
+const VENDOR: &str = "Medusa";
+const PRODUCT: &str = "rustable";
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+// severity of a denied operation on the 0-10 scale used by both formats
+const DENIAL_SEVERITY: u8 = 5;
+
+/// Format of records produced by [`SiemSink`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SiemFormat {
+    /// ArcSight Common Event Format, version 0.
+    Cef,
+
+    /// IBM QRadar Log Event Extended Format, version 1.0.
+    Leef,
+}
+
+impl SiemFormat {
+    /// Encodes `record` as a single line without the trailing newline.
+    pub fn encode(&self, record: &AuditRecord) -> String {
+        let object = record.object.as_deref().unwrap_or("");
+        let subject_spaces = record.subject_spaces.join(",");
+        let object_spaces = record.object_spaces.join(",");
+        let policy_hash = record.policy_hash.as_deref().unwrap_or("");
+        let session = record.session.unwrap_or_default();
+        let login_uid = session.login_uid.map(|x| x.to_string()).unwrap_or_default();
+        let session_id = session
+            .session_id
+            .map(|x| x.to_string())
+            .unwrap_or_default();
+
+        match self {
+            SiemFormat::Cef => {
+                let mut extension = vec![
+                    format!("rt={}", record.timestamp_ms),
+                    format!("act={}", escape_cef_extension(&record.answer)),
+                    format!("cat={}", escape_cef_extension(&record.event)),
+                    format!("cn1Label=requestId cn1={}", record.request_id),
+                ];
+                if !login_uid.is_empty() {
+                    extension.push(format!("cn2Label=loginUid cn2={}", login_uid));
+                }
+                if !session_id.is_empty() {
+                    extension.push(format!("cn3Label=sessionId cn3={}", session_id));
+                }
+                if record.count > 1 {
+                    extension.push(format!("cnt={}", record.count));
+                }
+
+                // custom string fields are labeled, empty ones are left out
+                let custom = [
+                    ("cs1", "subject", record.subject.as_str()),
+                    ("cs2", "subjectSpaces", &subject_spaces),
+                    ("cs3", "object", object),
+                    ("cs4", "objectSpaces", &object_spaces),
+                    ("cs5", "policyHash", policy_hash),
+                    ("cs6", "subjectId", record.subject_id.as_str()),
+                ];
+                extension.extend(custom.iter().filter(|(_, _, v)| !v.is_empty()).map(
+                    |(k, label, v)| format!("{k}Label={label} {k}={}", escape_cef_extension(v)),
+                ));
+                let extension = extension.join(" ");
+
+                format!(
+                    "CEF:0|{}|{}|{}|{}|{}|{}|{}",
+                    VENDOR,
+                    PRODUCT,
+                    VERSION,
+                    escape_cef_header(&record.event),
+                    escape_cef_header(&format!("{} {}", record.event, record.answer)),
+                    severity(record),
+                    extension
+                )
+            }
+            SiemFormat::Leef => {
+                let attributes = [
+                    ("devTime", record.timestamp_ms.to_string()),
+                    ("sev", severity(record).to_string()),
+                    ("cat", record.event.clone()),
+                    ("action", record.answer.clone()),
+                    ("requestId", record.request_id.to_string()),
+                    ("subject", record.subject.clone()),
+                    ("subjectId", record.subject_id.clone()),
+                    ("loginUid", login_uid),
+                    ("sessionId", session_id),
+                    ("subjectSpaces", subject_spaces),
+                    ("object", object.to_owned()),
+                    ("objectSpaces", object_spaces),
+                    ("policyHash", policy_hash.to_owned()),
+                    ("count", record.count.to_string()),
+                ]
+                .iter()
+                .filter(|(k, v)| !v.is_empty() && (*k != "count" || record.count > 1))
+                .map(|(k, v)| format!("{}={}", k, escape_leef(v)))
+                .collect::<Vec<_>>()
+                .join("\t");
+
+                format!(
+                    "LEEF:1.0|{}|{}|{}|{}|{}",
+                    VENDOR,
+                    PRODUCT,
+                    VERSION,
+                    escape_leef(&record.event).replace('|', "\\|"),
+                    attributes
+                )
+            }
+        }
+    }
+}
+
+/// Audit sink which writes denials as CEF or LEEF records, one per line, so that they can be
+/// forwarded to a SIEM by syslog or a log collector.
+pub struct SiemSink<W: Write + Send> {
+    format: SiemFormat,
+    all_decisions: bool,
+    writer: Mutex<W>,
+}
+
+impl<W: Write + Send> SiemSink<W> {
+    /// Creates new `SiemSink` writing records in `format` into `writer`.
+    pub fn new(format: SiemFormat, writer: W) -> Self {
+        Self {
+            format,
+            all_decisions: false,
+            writer: Mutex::new(writer),
+        }
+    }
+
+    /// Writes records of all decisions, not only denials.
+    ///
+    /// Returns `Self`.
+    pub fn with_all_decisions(mut self) -> Self {
+        self.all_decisions = true;
+        self
+    }
+}
+
+impl<W: Write + Send> AuditSink for SiemSink<W> {
+    fn record(&self, record: &AuditRecord) {
+        if !self.all_decisions && !record.is_denial() {
+            return;
+        }
+
+        let line = self.format.encode(record);
+        let mut writer = self.writer.lock().unwrap();
+        if let Err(e) = writeln!(writer, "{}", line) {
+            eprintln!("failed to write audit record: {}", e);
+        }
+    }
+}
+
+fn severity(record: &AuditRecord) -> u8 {
+    if record.is_denial() {
+        DENIAL_SEVERITY
+    } else {
+        0
+    }
+}
+
+fn escape_cef_header(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('|', "\\|")
+        .replace(['\r', '\n'], " ")
+}
+
+fn escape_cef_extension(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('=', "\\=")
+        .replace('\r', "\\r")
+        .replace('\n', "\\n")
+}
+
+fn escape_leef(value: &str) -> String {
+    value.replace(['\t', '\r', '\n'], " ")
+}
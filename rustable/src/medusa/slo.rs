@@ -0,0 +1,225 @@
+//! Enforcement of a decision latency objective, see [`ConfigBuilder::latency_slo`].
+//!
+//! [`ConfigBuilder::latency_slo`]: crate::medusa::ConfigBuilder::latency_slo
+
+use crate::medusa::alert::{self, AlertAction};
+use crate::medusa::{AuthRequestData, Context, EventHandler, FastHandler, MedusaAnswer};
+use derivative::Derivative;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Default number of consecutive requests exceeding the objective after which handlers are
+/// degraded, see [`LatencySlo::breaches`].
+const SLO_DEFAULT_BREACHES: usize = 10;
+
+/// Default time for which handlers stay degraded, see [`LatencySlo::cooldown`].
+const SLO_DEFAULT_COOLDOWN: Duration = Duration::from_secs(60);
+
+/// What a non-critical handler does instead of running while it is degraded, see
+/// [`LatencySlo::degrade`].
+#[derive(Derivative, Clone, Copy)]
+#[derivative(Debug)]
+pub enum Degraded {
+    /// The handler answers at once, e.g. [`MedusaAnswer::Allow`] to be permissive.
+    Answer(MedusaAnswer),
+
+    /// The fast handler, e.g. one answering from a cache, runs instead.
+    Fast(#[derivative(Debug = "ignore")] FastHandler),
+}
+
+/// Objective for the time between receiving an authorization request and answering it.
+///
+/// When `breaches` consecutive requests take longer than the objective, handlers designated
+/// by [`LatencySlo::degrade`] are switched to their degraded variants for the cooldown and an
+/// alert is raised. Afterwards, they run normally until the objective is breached repeatedly
+/// again. Handlers not designated are never affected, so security-critical decisions keep
+/// their latency and their answers.
+#[derive(Debug, Clone)]
+pub struct LatencySlo {
+    pub(crate) objective: Duration,
+    pub(crate) breaches: usize,
+    pub(crate) cooldown: Duration,
+    pub(crate) degraded: HashMap<String, Degraded>,
+    pub(crate) alert: Option<AlertAction>,
+}
+
+impl LatencySlo {
+    /// Creates new `LatencySlo` with `objective`, degrading no handlers.
+    pub fn new(objective: Duration) -> Self {
+        Self {
+            objective,
+            breaches: SLO_DEFAULT_BREACHES,
+            cooldown: SLO_DEFAULT_COOLDOWN,
+            degraded: HashMap::new(),
+            alert: None,
+        }
+    }
+
+    /// Sets the number of consecutive requests exceeding the objective after which handlers
+    /// are degraded, 10 by default.
+    ///
+    /// Returns `Self`.
+    pub fn breaches(mut self, breaches: usize) -> Self {
+        self.breaches = breaches.max(1);
+        self
+    }
+
+    /// Sets the time for which handlers stay degraded, 60 seconds by default.
+    ///
+    /// Returns `Self`.
+    pub fn cooldown(mut self, cooldown: Duration) -> Self {
+        self.cooldown = cooldown;
+        self
+    }
+
+    /// Designates handler named `name` as non-critical, so that it is replaced with
+    /// `degraded` while the objective is breached, see [`EventHandlerBuilder::name`].
+    ///
+    /// Returns `Self`.
+    ///
+    /// [`EventHandlerBuilder::name`]: crate::medusa::EventHandlerBuilder::name
+    pub fn degrade(mut self, name: &str, degraded: Degraded) -> Self {
+        self.degraded.insert(name.to_owned(), degraded);
+        self
+    }
+
+    /// Sets the action run with a [`SloAlert`] when handlers are degraded.
+    ///
+    /// Returns `Self`.
+    pub fn alert(mut self, action: AlertAction) -> Self {
+        self.alert = Some(action);
+        self
+    }
+}
+
+/// Payload of the alert raised when handlers are degraded, see [`LatencySlo::alert`].
+#[derive(Debug, Clone, Serialize)]
+pub struct SloAlert {
+    /// The objective in microseconds.
+    pub objective_us: u64,
+
+    /// Number of consecutive requests which exceeded the objective.
+    pub breaches: usize,
+
+    /// Time taken by the last request in microseconds.
+    pub latency_us: u64,
+
+    /// Names of the degraded handlers.
+    pub degraded: Vec<String>,
+
+    /// Time for which the handlers stay degraded in seconds.
+    pub cooldown_secs: u64,
+}
+
+/// State of the latency objective of a connection.
+#[derive(Default)]
+pub(crate) struct SloState {
+    // consecutive requests exceeding the objective
+    breaches: AtomicUsize,
+    degraded: AtomicBool,
+    degraded_since: Mutex<Option<Instant>>,
+}
+
+impl SloState {
+    /// Returns `true` if designated handlers are degraded.
+    pub(crate) fn is_degraded(&self) -> bool {
+        self.degraded.load(Ordering::Relaxed)
+    }
+
+    /// Records that a request was answered after `latency`, degrading or restoring handlers
+    /// according to `slo`.
+    fn record(&self, slo: &LatencySlo, latency: Duration) {
+        if self.is_degraded() {
+            let mut since = self.degraded_since.lock().unwrap();
+            if since.is_some_and(|x| x.elapsed() >= slo.cooldown) {
+                *since = None;
+                self.breaches.store(0, Ordering::Relaxed);
+                self.degraded.store(false, Ordering::Relaxed);
+                println!("latency objective: degraded handlers restored");
+            }
+            return;
+        }
+
+        if latency <= slo.objective {
+            self.breaches.store(0, Ordering::Relaxed);
+            return;
+        }
+
+        let breaches = self.breaches.fetch_add(1, Ordering::Relaxed) + 1;
+        if breaches < slo.breaches {
+            return;
+        }
+
+        let mut since = self.degraded_since.lock().unwrap();
+        if since.is_some() {
+            // degraded by a concurrent request
+            return;
+        }
+        *since = Some(Instant::now());
+        self.degraded.store(true, Ordering::Relaxed);
+
+        let mut degraded = slo.degraded.keys().cloned().collect::<Vec<_>>();
+        degraded.sort();
+        eprintln!(
+            "latency objective of {:?} exceeded by {} consecutive requests, degrading {} for {:?}",
+            slo.objective,
+            breaches,
+            degraded.join(", "),
+            slo.cooldown
+        );
+
+        if let Some(action) = slo.alert.clone() {
+            let payload = SloAlert {
+                objective_us: slo.objective.as_micros() as u64,
+                breaches,
+                latency_us: latency.as_micros() as u64,
+                degraded,
+                cooldown_secs: slo.cooldown.as_secs(),
+            };
+            thread::spawn(move || {
+                if let Err(e) = alert::run_action(&action, &payload) {
+                    eprintln!("alert action {:?} failed: {}", action, e);
+                }
+            });
+        }
+    }
+}
+
+/// Records the latency of a request received at `received` which has just been answered, see
+/// [`ConfigBuilder::latency_slo`].
+///
+/// [`ConfigBuilder::latency_slo`]: crate::medusa::ConfigBuilder::latency_slo
+pub(crate) fn record(ctx: &Context, received: Instant) {
+    if let Some(slo) = &ctx.config.load().latency_slo {
+        ctx.slo.record(slo, received.elapsed());
+    }
+}
+
+/// Returns the answer of `event_handler` if it is degraded, `None` if it has to run.
+pub(crate) fn degraded_answer(
+    ctx: &Context,
+    event_handler: &EventHandler,
+    auth_data: &AuthRequestData,
+) -> Option<MedusaAnswer> {
+    if !ctx.slo.is_degraded() {
+        return None;
+    }
+
+    let config = ctx.config.load();
+    let degraded = config
+        .latency_slo
+        .as_ref()?
+        .degraded
+        .get(event_handler.name())?;
+
+    Some(match degraded {
+        Degraded::Answer(answer) => *answer,
+        Degraded::Fast(handler) => {
+            handler(ctx, auth_data, event_handler.data()).unwrap_or(MedusaAnswer::Err)
+        }
+    })
+}
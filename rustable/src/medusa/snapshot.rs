@@ -0,0 +1,254 @@
+//! Dump of the state of a running connection, see [`Context::snapshot`].
+//!
+//! [`Context::snapshot`]: crate::medusa::Context::snapshot
+
+use crate::medusa::{Context, MedusaAttribute, MedusaAttributes, MedusaClass, MedusaEvtype};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Weak};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::task::JoinHandle;
+
+/// State of a connection at a single point in time.
+#[derive(Clone, Debug, Serialize)]
+pub struct Snapshot {
+    /// Milliseconds since the Unix epoch at which the snapshot was taken.
+    pub timestamp_ms: u64,
+
+    /// Capabilities of the connected security module.
+    pub kernel_capabilities: String,
+
+    /// Whether deny-by-default enforcement is active, see [`Context::is_enforcing`].
+    pub enforcing: bool,
+
+    /// Classes registered by the security module, ordered by id.
+    pub classes: Vec<ClassSnapshot>,
+
+    /// Events registered by the security module, ordered by id.
+    pub evtypes: Vec<EvtypeSnapshot>,
+
+    /// Virtual spaces of the config with their bits.
+    pub spaces: BTreeMap<String, usize>,
+
+    /// Bits of removed virtual spaces which are not reused yet, see
+    /// [`ConfigBuilder::replacing`].
+    ///
+    /// [`ConfigBuilder::replacing`]: crate::medusa::ConfigBuilder::replacing
+    pub retired_space_bits: Vec<usize>,
+
+    /// Update requests waiting for an answer.
+    pub pending_updates: usize,
+
+    /// Fetch requests waiting for an answer.
+    pub pending_fetches: usize,
+
+    /// Updates queued by [`Context::queue_update`] and not sent yet.
+    pub queued_updates: usize,
+
+    /// Last updates of objects remembered for deduplication, see
+    /// [`ConfigBuilder::deduplicate_updates`].
+    ///
+    /// [`ConfigBuilder::deduplicate_updates`]: crate::medusa::ConfigBuilder::deduplicate_updates
+    pub remembered_updates: usize,
+
+    /// Requests abandoned since the connection was established.
+    pub abandoned_requests: u64,
+
+    /// Requests whose handlers are running, if [`ConfigBuilder::handler_watchdog`] is enabled.
+    ///
+    /// [`ConfigBuilder::handler_watchdog`]: crate::medusa::ConfigBuilder::handler_watchdog
+    pub running_handlers: Option<usize>,
+
+    /// Handlers with verbose output enabled.
+    pub debugged_handlers: Vec<String>,
+}
+
+/// Layout of a registered class.
+#[derive(Clone, Debug, Serialize)]
+pub struct ClassSnapshot {
+    /// Identification of the class.
+    pub id: u64,
+
+    /// Name of the class.
+    pub name: String,
+
+    /// Size of the attribute data in bytes.
+    pub size: usize,
+
+    /// Attributes ordered by their offset.
+    pub attributes: Vec<AttributeSnapshot>,
+}
+
+/// Layout of a registered event.
+#[derive(Clone, Debug, Serialize)]
+pub struct EvtypeSnapshot {
+    /// Identification of the event.
+    pub id: u64,
+
+    /// Name of the event.
+    pub name: String,
+
+    /// Size of the attribute data in bytes.
+    pub size: usize,
+
+    /// Identification of the class of the subject.
+    pub subject_class: u64,
+
+    /// Identification of the class of the object, if the event has an object.
+    pub object_class: Option<u64>,
+
+    /// Bit of the event in the monitoring bitmaps.
+    pub monitoring_bit: u16,
+
+    /// Number of handlers of the event.
+    pub handlers: usize,
+
+    /// Attributes ordered by their offset.
+    pub attributes: Vec<AttributeSnapshot>,
+}
+
+/// Layout of a single attribute.
+#[derive(Clone, Debug, Serialize)]
+pub struct AttributeSnapshot {
+    /// Name of the attribute.
+    pub name: String,
+
+    /// Offset of the attribute in the attribute data.
+    pub offset: i16,
+
+    /// Size of the attribute in bytes.
+    pub length: i16,
+
+    /// Type byte exactly as it was received from the security module.
+    pub raw_type: u8,
+}
+
+impl Snapshot {
+    /// Takes a snapshot of `ctx`.
+    pub(crate) fn new(ctx: &Context) -> Self {
+        let timestamp_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|x| x.as_millis() as u64)
+            .unwrap_or_default();
+
+        let mut classes = ctx
+            .registry
+            .classes()
+            .iter()
+            .map(ClassSnapshot::new)
+            .collect::<Vec<_>>();
+        classes.sort_by_key(|x| x.id);
+
+        let mut evtypes = ctx
+            .registry
+            .evtypes()
+            .iter()
+            .map(|x| EvtypeSnapshot::new(ctx, x))
+            .collect::<Vec<_>>();
+        evtypes.sort_by_key(|x| x.id);
+
+        let config = ctx.config();
+        let spaces = config
+            .space_names()
+            .map(|(name, bit)| (name.to_owned(), bit))
+            .collect();
+
+        let (pending_updates, pending_fetches) = ctx.pending.lens();
+        let (queued_updates, remembered_updates) = ctx.update_lens();
+
+        Self {
+            timestamp_ms,
+            kernel_capabilities: format!("{:?}", ctx.kernel_capabilities),
+            enforcing: ctx.is_enforcing(),
+            classes,
+            evtypes,
+            spaces,
+            retired_space_bits: config.retired_space_bits.bits().into_iter().collect(),
+            pending_updates,
+            pending_fetches,
+            queued_updates,
+            remembered_updates,
+            abandoned_requests: ctx.stats.abandoned_requests(),
+            running_handlers: ctx.running_handlers.as_ref().map(|x| x.len()),
+            debugged_handlers: ctx.debugged_handlers(),
+        }
+    }
+
+    /// Returns the snapshot as pretty-printed JSON.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("snapshot is serializable")
+    }
+}
+
+impl ClassSnapshot {
+    fn new(class: &MedusaClass) -> Self {
+        Self {
+            id: class.header.id(),
+            name: class.header.name().to_owned(),
+            size: class.header.data_size(),
+            attributes: attribute_snapshots(&class.attributes),
+        }
+    }
+}
+
+impl EvtypeSnapshot {
+    fn new(ctx: &Context, evtype: &MedusaEvtype) -> Self {
+        let header = &evtype.header;
+        let config = ctx.config();
+        let handlers = config
+            .event_id_of(header)
+            .map(|id| config.handlers_by_event_id(id).len())
+            .unwrap_or_default();
+
+        Self {
+            id: header.id(),
+            name: header.name().to_owned(),
+            size: header.data_size(),
+            subject_class: header.subject_class(),
+            object_class: header.object_class(),
+            monitoring_bit: header.monitoring_bit(),
+            handlers,
+            attributes: attribute_snapshots(&evtype.attributes),
+        }
+    }
+}
+
+fn attribute_snapshots(attributes: &MedusaAttributes) -> Vec<AttributeSnapshot> {
+    attributes
+        .iter()
+        .map(|MedusaAttribute { header, .. }| AttributeSnapshot {
+            name: header.name().to_owned(),
+            offset: header.offset,
+            length: header.length,
+            raw_type: header.raw_type(),
+        })
+        .collect()
+}
+
+/// Writes a snapshot of `ctx` to `path` whenever the process receives `SIGUSR1`, until the
+/// returned task is aborted, see [`ConfigBuilder::snapshot_on_signal`].
+///
+/// [`ConfigBuilder::snapshot_on_signal`]: crate::medusa::ConfigBuilder::snapshot_on_signal
+pub(crate) fn spawn_on_signal(path: &Path, ctx: &Arc<Context>) -> io::Result<JoinHandle<()>> {
+    let mut signals = signal(SignalKind::user_defined1())?;
+    let path = PathBuf::from(path);
+    let ctx = Arc::downgrade(ctx);
+
+    Ok(tokio::spawn(async move {
+        while signals.recv().await.is_some() {
+            let ctx = match Weak::upgrade(&ctx) {
+                Some(ctx) => ctx,
+                None => break,
+            };
+
+            match fs::write(&path, ctx.snapshot().to_json()) {
+                Ok(()) => println!("snapshot written to {}", path.display()),
+                Err(e) => eprintln!("cannot write snapshot to {}: {}", path.display(), e),
+            }
+        }
+    }))
+}
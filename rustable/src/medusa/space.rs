@@ -1,20 +1,29 @@
 use crate::bitmap;
-use crate::medusa::constants::AccessType;
-use std::collections::HashMap;
+use crate::medusa::constants::*;
+use crate::medusa::{ConfigError, Event, MedusaClass};
+use arc_swap::ArcSwap;
+use std::collections::{BTreeSet, HashMap};
+use std::sync::Mutex;
 
 /// Builder for virtual space.
 #[derive(Debug, Default, Clone)]
 pub struct SpaceBuilder {
     pub(crate) name: Option<&'static str>,
     pub(crate) path: Option<(&'static str, bool)>,
+    pub(crate) priority: u16,
 
     pub(crate) at_names: [Vec<&'static str>; AccessType::Length as usize],
+    pub(crate) denied: Vec<&'static str>,
 
     pub(crate) include_space: Vec<&'static str>,
     pub(crate) exclude_space: Vec<&'static str>,
 
-    pub(crate) include_path: Vec<(&'static str, bool)>,
+    // paths with their priority, `None` for the priority of the space
+    pub(crate) include_path: Vec<(&'static str, bool, Option<u16>)>,
     pub(crate) exclude_path: Vec<(&'static str, bool)>,
+    pub(crate) exclude_matching: Vec<&'static str>,
+
+    pub(crate) monitored_events: Option<Vec<&'static str>>,
 }
 
 impl SpaceBuilder {
@@ -62,6 +71,21 @@ impl SpaceBuilder {
         self
     }
 
+    /// Sets the priority of the node at the path of this space among its siblings, see
+    /// [`NodeBuilder::add_node_with_priority`]. The lower the value, the higher the priority.
+    /// Included paths get the same priority unless given their own. Nodes on the way to the
+    /// path keep the highest priority. By default, the priority is the highest.
+    ///
+    /// A node is shared only by spaces of the same path and priority.
+    ///
+    /// Returns `Self`.
+    ///
+    /// [`NodeBuilder::add_node_with_priority`]: crate::medusa::NodeBuilder::add_node_with_priority
+    pub fn with_priority(mut self, priority: u16) -> Self {
+        self.priority = priority;
+        self
+    }
+
     /// Extends access rights for type `read`.
     ///
     /// Returns `Self`.
@@ -95,6 +119,37 @@ impl SpaceBuilder {
         self
     }
 
+    /// Denies reading, writing and seeing the spaces `names`, overriding access granted by
+    /// [`SpaceBuilder::reads`], [`SpaceBuilder::writes`] and [`SpaceBuilder::sees`] of this or
+    /// any other space sharing the node at its path. An exception such as a domain reading all
+    /// of `/home` but `/home/*/.ssh` then does not require splitting the spaces of the objects
+    /// by included and excluded paths.
+    ///
+    /// Returns `Self`.
+    pub fn denies<I>(mut self, names: I) -> Self
+    where
+        I: IntoIterator<Item = &'static str>,
+    {
+        self.denied.extend(names);
+        self
+    }
+
+    /// Restricts monitoring of objects and subjects at the path of this space to `events`, see
+    /// [`NodeBuilder::monitor_events`].
+    ///
+    /// Returns `Self`.
+    ///
+    /// [`NodeBuilder::monitor_events`]: crate::medusa::NodeBuilder::monitor_events
+    pub fn monitor_events<I>(mut self, events: I) -> Self
+    where
+        I: IntoIterator<Item = Event>,
+    {
+        self.monitored_events
+            .get_or_insert_with(Vec::new)
+            .extend(events.into_iter().map(|x| x.name()));
+        self
+    }
+
     /// Includes the provided virtual space by name.
     ///
     /// Returns `Self`.
@@ -115,7 +170,7 @@ impl SpaceBuilder {
     ///
     /// Returns `Self`.
     pub fn include_path(mut self, path: &'static str) -> Self {
-        self.include_path.push((path, false));
+        self.include_path.push((path, false, None));
         self
     }
 
@@ -123,7 +178,29 @@ impl SpaceBuilder {
     ///
     /// Returns `Self`.
     pub fn include_path_recursive(mut self, path: &'static str) -> Self {
-        self.include_path.push((path, true));
+        self.include_path.push((path, true, None));
+        self
+    }
+
+    /// Includes the provided path with a node of the given priority, see
+    /// [`SpaceBuilder::with_priority`].
+    ///
+    /// Returns `Self`.
+    pub fn include_path_with_priority(mut self, path: &'static str, priority: u16) -> Self {
+        self.include_path.push((path, false, Some(priority)));
+        self
+    }
+
+    /// Includes the provided path recursively with a node of the given priority, see
+    /// [`SpaceBuilder::with_priority`].
+    ///
+    /// Returns `Self`.
+    pub fn include_path_recursive_with_priority(
+        mut self,
+        path: &'static str,
+        priority: u16,
+    ) -> Self {
+        self.include_path.push((path, true, Some(priority)));
         self
     }
 
@@ -142,6 +219,18 @@ impl SpaceBuilder {
         self.exclude_path.push((path, true));
         self
     }
+
+    /// Excludes entities whose name matches the regular expression `pattern`, such as
+    /// `r"\.gz$"`, from this space within its path and included paths, including their
+    /// recursive subtrees. Unlike [`SpaceBuilder::exclude_path`], matching entities keep the
+    /// access and other memberships they would otherwise have. Nodes added for explicit paths
+    /// take precedence over the pattern.
+    ///
+    /// Returns `Self`.
+    pub fn exclude_matching(mut self, pattern: &'static str) -> Self {
+        self.exclude_matching.push(pattern);
+        self
+    }
 }
 
 /// Virtual space reference without the need of using special symbols.
@@ -156,9 +245,17 @@ pub enum Space {
 
 #[derive(Debug, Default, Clone)]
 pub(crate) struct SpaceDef {
+    // width of the bitmaps, one more than the highest id
     id_cn: usize,
     name_to_id: HashMap<&'static str, usize>,
     id_to_name: HashMap<usize, &'static str>,
+
+    // ids of spaces of a replaced config, kept by spaces of the same name
+    previous: HashMap<String, usize>,
+    // ids which are not given to new spaces, see `SpaceDef::with_previous`
+    taken: BTreeSet<usize>,
+    // no id below is free
+    next_free: usize,
 }
 
 impl SpaceDef {
@@ -166,15 +263,58 @@ impl SpaceDef {
         Default::default()
     }
 
+    /// Creates definitions in which spaces named as in `previous` keep their ids. New spaces
+    /// get the lowest ids which are neither in `previous` nor `reserved`.
+    pub(crate) fn with_previous(
+        previous: HashMap<String, usize>,
+        reserved: BTreeSet<usize>,
+    ) -> Self {
+        let mut taken = reserved;
+        taken.extend(previous.values().copied());
+
+        Self {
+            previous,
+            taken,
+            ..Default::default()
+        }
+    }
+
     pub(crate) fn define_space(&mut self, name: &'static str) {
         if self.name_to_id.contains_key(name) {
             return;
         }
 
-        let id = self.new_id();
+        let id = match self.previous.get(name) {
+            Some(&id) => id,
+            None => self.new_id(),
+        };
         self.insert_space(name, id);
     }
 
+    /// Defines spaces `names` having consecutive ids, in the order of `names`.
+    pub(crate) fn define_consecutive_spaces(&mut self, names: &[&'static str]) {
+        let previous = names
+            .iter()
+            .map(|name| self.previous.get(*name).copied())
+            .collect::<Option<Vec<_>>>();
+        let first = match previous {
+            Some(ids) if ids.windows(2).all(|x| x[1] == x[0] + 1) => ids.first().copied(),
+            _ => None,
+        };
+
+        let first = first.unwrap_or_else(|| {
+            let mut first = self.next_free;
+            while let Some(id) = (first..first + names.len()).find(|x| !self.is_free(*x)) {
+                first = id + 1;
+            }
+            first
+        });
+
+        for (i, name) in names.iter().enumerate() {
+            self.insert_space(name, first + i);
+        }
+    }
+
     pub(crate) fn name_to_id_owned(&self) -> HashMap<String, usize> {
         self.name_to_id
             .iter()
@@ -190,19 +330,95 @@ impl SpaceDef {
     }
 
     pub(crate) fn bitmap_nbytes(&self) -> usize {
-        (self.id_cn + 7) / 8
+        self.id_cn.div_ceil(8)
+    }
+
+    pub(crate) fn space_id(&self, name: &str) -> Option<usize> {
+        self.name_to_id.get(name).copied()
     }
 
     fn insert_space(&mut self, name: &'static str, id: usize) {
         self.name_to_id.insert(name, id);
         self.id_to_name.insert(id, name);
+        self.id_cn = self.id_cn.max(id + 1);
+    }
+
+    fn is_free(&self, id: usize) -> bool {
+        !self.id_to_name.contains_key(&id) && !self.taken.contains(&id)
     }
 
     fn new_id(&mut self) -> usize {
-        let id = self.id_cn;
-        self.id_cn += 1;
+        while !self.is_free(self.next_free) {
+            self.next_free += 1;
+        }
+
+        self.next_free
+    }
+}
+
+/// Bits of virtual spaces removed by replacing a config, which entities may still carry, see
+/// [`ConfigBuilder::replacing`]. Shared by the configs replacing each other.
+///
+/// [`ConfigBuilder::replacing`]: crate::medusa::ConfigBuilder::replacing
+#[derive(Debug, Default)]
+pub(crate) struct RetiredSpaceBits {
+    // replaced as a whole, so that every request reads them without locking
+    bits: ArcSwap<BTreeSet<usize>>,
+    // held while the bits are replaced, so that no change is lost
+    changing: Mutex<()>,
+}
+
+impl RetiredSpaceBits {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.bits.load().is_empty()
+    }
+
+    /// Returns the retired bits, which are not given to new spaces.
+    pub(crate) fn bits(&self) -> BTreeSet<usize> {
+        BTreeSet::clone(&self.bits.load())
+    }
+
+    /// Retires bits used by `previous` and not by `used`, and forgets the ones `used` again.
+    pub(crate) fn replace(&self, previous: &HashMap<usize, String>, used: &HashMap<usize, String>) {
+        let _changing = self.changing.lock().unwrap();
+        let mut bits = BTreeSet::clone(&self.bits.load());
+        bits.retain(|bit| !used.contains_key(bit));
+        bits.extend(previous.keys().filter(|x| !used.contains_key(x)));
+
+        self.bits.store(bits.into());
+    }
+
+    /// Forgets all retired bits, so that they are given to new spaces. Returns the bits.
+    pub(crate) fn release(&self) -> BTreeSet<usize> {
+        let _changing = self.changing.lock().unwrap();
+        BTreeSet::clone(&self.bits.swap(Default::default()))
+    }
+
+    /// Clears retired bits from the virtual spaces of `class`. Returns whether it carried any.
+    pub(crate) fn clear(&self, class: &mut MedusaClass) -> bool {
+        let bits = self.bits.load();
+        let mut cleared = false;
+
+        for attr_name in [
+            MEDUSA_VS_ATTR_NAME,
+            MEDUSA_VSR_ATTR_NAME,
+            MEDUSA_VSW_ATTR_NAME,
+            MEDUSA_VSS_ATTR_NAME,
+        ] {
+            let vs = match class.attributes.get_mut(attr_name) {
+                Ok(vs) => vs,
+                Err(_) => continue,
+            };
+
+            for &bit in bits.iter() {
+                if bit < vs.len() * 8 && bitmap::test_bit(vs, bit) {
+                    bitmap::clear_bit(vs, bit);
+                    cleared = true;
+                }
+            }
+        }
 
-        id
+        cleared
     }
 }
 
@@ -210,6 +426,8 @@ impl SpaceDef {
 #[derive(Debug, Default, Clone)]
 pub struct VirtualSpace {
     access_types: [Vec<u8>; AccessType::Length as usize],
+    // spaces to which access of all types but membership is denied, see `SpaceBuilder::denies`
+    denied: Vec<u8>,
 }
 
 impl VirtualSpace {
@@ -222,19 +440,70 @@ impl VirtualSpace {
         &mut self,
         def: &SpaceDef,
         spaces: &[Vec<Space>; AccessType::Length as usize],
-    ) {
+    ) -> Result<(), ConfigError> {
         for (at, space) in self.access_types.iter_mut().zip(spaces.iter()) {
-            *at = spaces_to_bitmap(space, def);
+            *at = spaces_to_bitmap(space, def)?;
         }
+        Ok(())
     }
 
-    /// Returns a vector of defined `at` access types.
+    pub(crate) fn set_denied(
+        &mut self,
+        def: &SpaceDef,
+        spaces: &[Space],
+    ) -> Result<(), ConfigError> {
+        self.denied = spaces_to_bitmap(spaces, def)?;
+        Ok(())
+    }
+
+    /// Returns a vector of defined `at` access types. Denied spaces are left out of access
+    /// types other than [`AccessType::Member`], see [`SpaceBuilder::denies`].
     pub fn to_at_bytes(&self, at: AccessType) -> Vec<u8> {
-        self.access_types[at as usize].clone()
+        let mut bytes = self.access_types[at as usize].clone();
+        if at != AccessType::Member {
+            for (byte, denied) in bytes.iter_mut().zip(&self.denied) {
+                *byte &= !denied;
+            }
+        }
+
+        bytes
     }
+
+    /// Returns the bitmap of spaces denied by [`SpaceBuilder::denies`].
+    #[cfg(feature = "repl")]
+    pub(crate) fn denied(&self) -> &[u8] {
+        &self.denied
+    }
+
+    /// Adds the spaces of all access types and the denied spaces of `other` to this virtual
+    /// space.
+    pub(crate) fn merge(&mut self, other: &VirtualSpace) {
+        for (at, other) in self.access_types.iter_mut().zip(&other.access_types) {
+            union(at, other);
+        }
+        union(&mut self.denied, &other.denied);
+    }
+
+    /// Returns a copy of this virtual space with `bit` added to all access types.
+    pub(crate) fn with_space(&self, bit: usize) -> Self {
+        let mut vs = self.clone();
+        for at in vs.access_types.iter_mut() {
+            at.resize(at.len().max(bit / 8 + 1), 0);
+            bitmap::set_bit(at, bit);
+        }
+
+        vs
+    }
+}
+
+/// Adds the bits of `right` to `left`, extending `left` if it is shorter.
+fn union(left: &mut Vec<u8>, right: &[u8]) {
+    left.resize(left.len().max(right.len()), 0);
+    bitmap::or(left, right);
 }
 
-pub(crate) fn spaces_to_bitmap(spaces: &[Space], def: &SpaceDef) -> Vec<u8> {
+/// Returns the bitmap of `spaces`, or `UnknownSpaceError` if one of them is not defined in `def`.
+pub(crate) fn spaces_to_bitmap(spaces: &[Space], def: &SpaceDef) -> Result<Vec<u8>, ConfigError> {
     let nbytes = def.bitmap_nbytes();
     let ids = &def.name_to_id;
 
@@ -249,12 +518,12 @@ pub(crate) fn spaces_to_bitmap(spaces: &[Space], def: &SpaceDef) -> Vec<u8> {
             Space::ByName(name) if !name.is_empty() => {
                 let id = ids
                     .get(name)
-                    .unwrap_or_else(|| panic!("no such id for space: {}", name));
+                    .ok_or_else(|| ConfigError::UnknownSpaceError(name.to_string()))?;
                 bitmap::set_bit(&mut vec, *id);
             }
             _ => (),
         }
     }
 
-    vec
+    Ok(vec)
 }
@@ -0,0 +1,200 @@
+//! Timings of the stages authorization requests pass through, so that it can be told whether
+//! latency comes from handlers or from I/O, and counters of requests to the security module.
+
+use crate::medusa::FastDashMap;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// Stage of handling an authorization request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    /// Reading the event, subject and object of a request.
+    Decode,
+
+    /// Parsing the attributes of the event, subject and object of a request.
+    Parse,
+
+    /// Waiting from the read request until a handler task picks it up, including the wait in
+    /// the dispatch queue and parsing.
+    DispatchWait,
+
+    /// Running the handlers.
+    Handler,
+
+    /// Waiting from a message being queued for the security module until it is written. This
+    /// includes answers as well as update and fetch requests.
+    WriteQueue,
+}
+
+impl Stage {
+    /// All stages in the order a request passes through them.
+    pub const ALL: [Stage; 5] = [
+        Stage::Decode,
+        Stage::Parse,
+        Stage::DispatchWait,
+        Stage::Handler,
+        Stage::WriteQueue,
+    ];
+
+    /// Returns the name of this stage.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Stage::Decode => "decode",
+            Stage::Parse => "parse",
+            Stage::DispatchWait => "dispatch_wait",
+            Stage::Handler => "handler",
+            Stage::WriteQueue => "write_queue",
+        }
+    }
+}
+
+/// Timings of a single stage.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct StageTimings {
+    /// Number of times the stage was passed.
+    pub count: u64,
+
+    /// Total time spent in the stage.
+    pub total: Duration,
+
+    /// Longest time spent in the stage.
+    pub max: Duration,
+}
+
+impl StageTimings {
+    /// Returns the average time spent in the stage.
+    pub fn mean(&self) -> Duration {
+        match self.count {
+            0 => Duration::ZERO,
+            count => Duration::from_nanos((self.total.as_nanos() / count as u128) as u64),
+        }
+    }
+}
+
+impl fmt::Display for StageTimings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "count={} mean={:?} max={:?} total={:?}",
+            self.count,
+            self.mean(),
+            self.max,
+            self.total
+        )
+    }
+}
+
+/// Numbers of decisions members of a virtual space took part in. Requests answered with an
+/// error are not counted.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct SpaceCounts {
+    /// Granted requests of subjects in the space.
+    pub subject_granted: u64,
+
+    /// Denied requests of subjects in the space.
+    pub subject_denied: u64,
+
+    /// Granted requests on objects in the space.
+    pub object_granted: u64,
+
+    /// Denied requests on objects in the space.
+    pub object_denied: u64,
+}
+
+impl fmt::Display for SpaceCounts {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "subject granted={} denied={} object granted={} denied={}",
+            self.subject_granted, self.subject_denied, self.object_granted, self.object_denied
+        )
+    }
+}
+
+#[derive(Default)]
+struct StageCounters {
+    count: AtomicU64,
+    total_ns: AtomicU64,
+    max_ns: AtomicU64,
+}
+
+/// Timings of all stages since the connection was established, see
+/// [`Context::pipeline_stats`](crate::medusa::Context::pipeline_stats).
+#[derive(Default)]
+pub struct PipelineStats {
+    stages: [StageCounters; Stage::ALL.len()],
+    abandoned_requests: AtomicU64,
+    // subject granted, subject denied, object granted and object denied by space name
+    spaces: FastDashMap<String, [AtomicU64; 4]>,
+}
+
+impl PipelineStats {
+    pub(crate) fn record(&self, stage: Stage, elapsed: Duration) {
+        let counters = &self.stages[stage as usize];
+        let ns = elapsed.as_nanos().min(u64::MAX as u128) as u64;
+
+        counters.count.fetch_add(1, Ordering::Relaxed);
+        counters.total_ns.fetch_add(ns, Ordering::Relaxed);
+        counters.max_ns.fetch_max(ns, Ordering::Relaxed);
+    }
+
+    /// Returns timings of `stage`.
+    pub fn timings(&self, stage: Stage) -> StageTimings {
+        let counters = &self.stages[stage as usize];
+
+        StageTimings {
+            count: counters.count.load(Ordering::Relaxed),
+            total: Duration::from_nanos(counters.total_ns.load(Ordering::Relaxed)),
+            max: Duration::from_nanos(counters.max_ns.load(Ordering::Relaxed)),
+        }
+    }
+
+    pub(crate) fn record_abandoned(&self, count: u64) {
+        self.abandoned_requests.fetch_add(count, Ordering::Relaxed);
+    }
+
+    /// Returns the number of update and fetch requests which were removed without an answer,
+    /// see [`ConfigBuilder::pending_request_max_age`].
+    ///
+    /// [`ConfigBuilder::pending_request_max_age`]: crate::medusa::ConfigBuilder::pending_request_max_age
+    pub fn abandoned_requests(&self) -> u64 {
+        self.abandoned_requests.load(Ordering::Relaxed)
+    }
+
+    /// Counts a decision in which a member of virtual space `space` took part as the subject or
+    /// as the object.
+    pub(crate) fn record_space(&self, space: &str, as_object: bool, denied: bool) {
+        let index = 2 * as_object as usize + denied as usize;
+        match self.spaces.get(space) {
+            Some(counters) => counters[index].fetch_add(1, Ordering::Relaxed),
+            None => self.spaces.entry(space.to_owned()).or_default()[index]
+                .fetch_add(1, Ordering::Relaxed),
+        };
+    }
+
+    /// Returns numbers of decisions members of each virtual space took part in, sorted by the
+    /// name of the space. Spaces whose members never took part in a decision are left out, so
+    /// the counts tell which parts of the policy are actually exercised.
+    pub fn space_counts(&self) -> Vec<(String, SpaceCounts)> {
+        let mut counts = self
+            .spaces
+            .iter()
+            .map(|x| {
+                let [subject_granted, subject_denied, object_granted, object_denied] =
+                    x.value().each_ref().map(|x| x.load(Ordering::Relaxed));
+                let counts = SpaceCounts {
+                    subject_granted,
+                    subject_denied,
+                    object_granted,
+                    object_denied,
+                };
+
+                (x.key().clone(), counts)
+            })
+            .collect::<Vec<_>>();
+        counts.sort_by(|a, b| a.0.cmp(&b.0));
+
+        counts
+    }
+}
@@ -0,0 +1,309 @@
+//! Suppression of repeated identical denials.
+
+use crate::medusa::audit::{AuditRecord, AuditSink};
+use crate::medusa::{CompletedRequest, Context};
+use hashlink::LruCache;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct DenialKey {
+    event: String,
+    subject: String,
+    subject_spaces: Vec<String>,
+    object: Option<String>,
+    object_spaces: Vec<String>,
+}
+
+impl DenialKey {
+    fn new(record: &AuditRecord) -> Self {
+        Self {
+            event: record.event.clone(),
+            subject: record.subject.clone(),
+            subject_spaces: record.subject_spaces.clone(),
+            object: record.object.clone(),
+            object_spaces: record.object_spaces.clone(),
+        }
+    }
+}
+
+struct Suppressed {
+    since: Instant,
+    count: u64,
+    last: Option<AuditRecord>,
+}
+
+/// Audit sink wrapper which aggregates repeated identical denials. The first denial of the same
+/// event, subject and object is passed to the inner sink, the following ones within `window` are
+/// counted and passed as a single record with [`AuditRecord::count`] set once the window ends.
+/// Other decisions are passed unchanged.
+///
+/// At most `capacity` distinct denials are tracked, the least recently seen one is flushed when
+/// another one arrives.
+pub struct SuppressingSink<S: AuditSink> {
+    inner: S,
+    window: Duration,
+    denials: Mutex<LruCache<DenialKey, Suppressed>>,
+}
+
+impl<S: AuditSink> SuppressingSink<S> {
+    /// Creates new `SuppressingSink` passing records into `inner`.
+    pub fn new(inner: S, window: Duration, capacity: usize) -> Self {
+        Self {
+            inner,
+            window,
+            denials: Mutex::new(LruCache::new(capacity.max(1))),
+        }
+    }
+
+    /// Passes aggregated records of all suppressed denials to the inner sink, even if their
+    /// windows did not end yet.
+    pub fn flush(&self) {
+        let drained = self
+            .denials
+            .lock()
+            .unwrap()
+            .drain()
+            .filter_map(|(_, x)| aggregate(x))
+            .collect::<Vec<_>>();
+
+        for record in drained {
+            self.inner.record(&record);
+        }
+    }
+
+    // Passes aggregated records whose windows ended and returns whether `record` itself should
+    // be passed to the inner sink.
+    fn admit(&self, record: &AuditRecord) -> bool {
+        if !record.is_denial() {
+            return true;
+        }
+
+        let (forward, flushed) = self.suppress(record, Instant::now());
+        for aggregated in &flushed {
+            self.inner.record(aggregated);
+        }
+
+        forward
+    }
+
+    // Returns whether `record` should be passed to the inner sink and aggregated records which
+    // have to be passed before it.
+    fn suppress(&self, record: &AuditRecord, now: Instant) -> (bool, Vec<AuditRecord>) {
+        let mut denials = self.denials.lock().unwrap();
+        let mut flushed = Vec::new();
+
+        // windows of the least recently seen denials are checked, so they do not linger
+        while let Some((_, oldest)) = denials.iter().next() {
+            if now.duration_since(oldest.since) < self.window {
+                break;
+            }
+            let (_, oldest) = denials.remove_lru().unwrap();
+            flushed.extend(aggregate(oldest));
+        }
+
+        let key = DenialKey::new(record);
+        if let Some(suppressed) = denials.get_mut(&key) {
+            if now.duration_since(suppressed.since) < self.window {
+                suppressed.count += 1;
+                suppressed.last = Some(record.clone());
+                return (false, flushed);
+            }
+
+            // the window has ended, this denial starts a new one
+            let expired = denials.remove(&key).unwrap();
+            flushed.extend(aggregate(expired));
+        }
+
+        if denials.len() == denials.capacity() {
+            if let Some((_, evicted)) = denials.remove_lru() {
+                flushed.extend(aggregate(evicted));
+            }
+        }
+
+        denials.insert(
+            key,
+            Suppressed {
+                since: now,
+                count: 0,
+                last: None,
+            },
+        );
+
+        (true, flushed)
+    }
+}
+
+impl<S: AuditSink> AuditSink for SuppressingSink<S> {
+    fn record(&self, record: &AuditRecord) {
+        if self.admit(record) {
+            self.inner.record(record);
+        }
+    }
+
+    fn record_completed(&self, record: &AuditRecord, completed: &CompletedRequest) {
+        if self.admit(record) {
+            self.inner.record_completed(record, completed);
+        }
+    }
+
+    fn record_on(&self, ctx: &Context, record: &AuditRecord, completed: &CompletedRequest) {
+        if self.admit(record) {
+            self.inner.record_on(ctx, record, completed);
+        }
+    }
+}
+
+impl<S: AuditSink> Drop for SuppressingSink<S> {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
+
+fn aggregate(suppressed: Suppressed) -> Option<AuditRecord> {
+    let mut record = suppressed.last?;
+    record.count = suppressed.count;
+    Some(record)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::medusa::audit::answer_name;
+    use crate::medusa::MedusaAnswer;
+    use std::sync::Arc;
+
+    const WINDOW: Duration = Duration::from_secs(10);
+
+    #[derive(Clone, Default)]
+    struct Collected(Arc<Mutex<Vec<AuditRecord>>>);
+
+    impl Collected {
+        /// Returns events and counts of the records collected since the last call.
+        fn take(&self) -> Vec<(String, u64)> {
+            let records = std::mem::take(&mut *self.0.lock().unwrap());
+            records.into_iter().map(|x| (x.event, x.count)).collect()
+        }
+    }
+
+    impl AuditSink for Collected {
+        fn record(&self, record: &AuditRecord) {
+            self.0.lock().unwrap().push(record.clone());
+        }
+    }
+
+    fn record(event: &str, answer: MedusaAnswer) -> AuditRecord {
+        AuditRecord {
+            timestamp_ms: 0,
+            request_id: 0,
+            event: event.into(),
+            subject: "process".into(),
+            subject_id: "pid 1".into(),
+            session: None,
+            subject_spaces: vec!["app".into()],
+            object: Some("file".into()),
+            object_id: None,
+            object_spaces: vec!["etc".into()],
+            answer: answer_name(answer).into(),
+            policy_hash: None,
+            count: 1,
+        }
+    }
+
+    fn denial(event: &str) -> AuditRecord {
+        record(event, MedusaAnswer::Deny)
+    }
+
+    fn events(records: Vec<AuditRecord>) -> Vec<(String, u64)> {
+        records.into_iter().map(|x| (x.event, x.count)).collect()
+    }
+
+    #[test]
+    fn aggregates_denials_within_window() {
+        let sink = SuppressingSink::new(Collected::default(), WINDOW, 8);
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+
+        let (forward, flushed) = sink.suppress(&denial("open"), at(0));
+        assert!(forward && flushed.is_empty());
+        let (forward, flushed) = sink.suppress(&denial("open"), at(1));
+        assert!(!forward && flushed.is_empty());
+        let (forward, flushed) = sink.suppress(&denial("open"), at(9));
+        assert!(!forward && flushed.is_empty());
+
+        // the window has ended, the suppressed denials are passed as one
+        let (forward, flushed) = sink.suppress(&denial("open"), at(10));
+        assert!(forward);
+        assert_eq!(events(flushed), [("open".into(), 2)]);
+
+        // nothing was suppressed in this window
+        let (forward, flushed) = sink.suppress(&denial("open"), at(25));
+        assert!(forward && flushed.is_empty());
+    }
+
+    #[test]
+    fn flushes_ended_windows_of_other_denials() {
+        let sink = SuppressingSink::new(Collected::default(), WINDOW, 8);
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+
+        sink.suppress(&denial("open"), at(0));
+        sink.suppress(&denial("open"), at(1));
+        sink.suppress(&denial("mkdir"), at(5));
+        sink.suppress(&denial("mkdir"), at(6));
+
+        let (forward, flushed) = sink.suppress(&denial("kill"), at(12));
+        assert!(forward);
+        assert_eq!(events(flushed), [("open".into(), 1)]);
+
+        let (_, flushed) = sink.suppress(&denial("kill"), at(20));
+        assert_eq!(events(flushed), [("mkdir".into(), 1)]);
+    }
+
+    #[test]
+    fn evicts_least_recently_seen_denials() {
+        let sink = SuppressingSink::new(Collected::default(), WINDOW, 2);
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+
+        sink.suppress(&denial("open"), at(0));
+        sink.suppress(&denial("mkdir"), at(0));
+        sink.suppress(&denial("mkdir"), at(1));
+        sink.suppress(&denial("open"), at(2));
+        sink.suppress(&denial("open"), at(3));
+
+        let (forward, flushed) = sink.suppress(&denial("kill"), at(4));
+        assert!(forward);
+        assert_eq!(events(flushed), [("mkdir".into(), 1)]);
+
+        // a new denial of the evicted kind starts over
+        let (forward, flushed) = sink.suppress(&denial("mkdir"), at(5));
+        assert!(forward);
+        assert_eq!(events(flushed), [("open".into(), 2)]);
+    }
+
+    #[test]
+    fn flushes_on_drop() {
+        let collected = Collected::default();
+        let sink = SuppressingSink::new(collected.clone(), WINDOW, 8);
+
+        for _ in 0..3 {
+            sink.record(&denial("open"));
+        }
+        sink.record(&record("open", MedusaAnswer::Allow));
+        sink.record(&record("open", MedusaAnswer::Allow));
+        sink.record(&denial("mkdir"));
+        assert_eq!(
+            collected.take(),
+            [
+                ("open".into(), 1),
+                ("open".into(), 1),
+                ("open".into(), 1),
+                ("mkdir".into(), 1)
+            ]
+        );
+
+        drop(sink);
+        assert_eq!(collected.take(), [("open".into(), 2)]);
+    }
+}
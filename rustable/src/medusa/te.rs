@@ -0,0 +1,521 @@
+//! Type enforcement policies in the idiom of SELinux, see [`TePolicy`].
+//!
+//! A policy consists of statements terminated by `;`, everything after `#` on a line is a
+//! comment:
+//!
+//! ```text
+//! attribute domain;
+//! type sshd_t, domain;
+//! type etc_t;
+//! type shadow_t;
+//!
+//! context sshd_t domains/usr/sbin/sshd;
+//! context etc_t fs/etc recursive;
+//! context shadow_t fs/etc/shadow;
+//!
+//! allow sshd_t { etc_t shadow_t }:file { read getattr };
+//! allow domain self:process { fork signal };
+//! ```
+//!
+//! Supported statements are `type <type> [, <attribute>]...`, `attribute <attribute>`,
+//! `typeattribute <type> <attribute> [, <attribute>]...`, `context <type> <path> [recursive]`
+//! and `allow <source> <target>:<class> <permission>`. Sources, targets, classes and
+//! permissions are either single names or sets of names in braces. Sources and targets are
+//! types or attributes, standing for all types which have them, the target `self` stands for
+//! the source type itself.
+
+use crate::medusa::policy::intern;
+use crate::medusa::{AccessType, ConfigBuilder, Event, Space, SpaceBuilder, TeError};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Type enforcement policy, a pragmatic subset of SELinux type enforcement expressed by virtual
+/// spaces, see [`te`](crate::medusa::te).
+///
+/// Every type becomes a virtual space of the same name. As SELinux labels files by file
+/// contexts, a type is given the paths of its entities in the trees by `context` statements,
+/// the first one becomes the path of the space and the others are included in it. Permissions
+/// to read, write or get attributes of a target are granted by the access types of the
+/// space, see [`SpaceBuilder::reads`], other permissions by relations allowing the matching
+/// events, see [`ConfigBuilder::allow_relation`].
+///
+/// Unlike SELinux, requests allowed by no rule are not denied, they are left to the handlers.
+///
+/// [`ConfigBuilder::allow_relation`]: crate::medusa::ConfigBuilder::allow_relation
+#[derive(Debug, Default, Clone)]
+pub struct TePolicy {
+    types: BTreeMap<String, TypeDef>,
+    // event, source type, target type
+    relations: BTreeSet<(&'static str, String, String)>,
+}
+
+#[derive(Debug, Default, Clone)]
+struct TypeDef {
+    // line of the declaration
+    line: usize,
+    attributes: BTreeSet<String>,
+    contexts: Vec<(String, bool)>,
+    access: [BTreeSet<String>; AccessType::Length as usize],
+}
+
+/// Rule of an `allow` statement before its attributes are expanded.
+struct Allow<'a> {
+    line: usize,
+    sources: Vec<&'a str>,
+    targets: Vec<&'a str>,
+    classes: Vec<&'a str>,
+    permissions: Vec<&'a str>,
+}
+
+#[derive(Clone, Copy)]
+struct Token<'a> {
+    text: &'a str,
+    line: usize,
+}
+
+impl TePolicy {
+    /// Parses policy `text`.
+    ///
+    /// Returns `TePolicy` or `TeError` if a statement is invalid, a name is not declared, a
+    /// permission is not supported or a type has no context.
+    pub fn parse(text: &str) -> Result<Self, TeError> {
+        let tokens = tokenize(text);
+        let mut policy = Self::default();
+        let mut attributes = BTreeSet::new();
+        let mut allows = Vec::new();
+
+        for statement in tokens.split(|x| x.text == ";") {
+            let (keyword, args) = match statement.split_first() {
+                Some(x) => x,
+                None => continue,
+            };
+            let line = keyword.line;
+            let error = |message: String| TeError::SyntaxError(line, message);
+            let words = args.iter().map(|x| x.text).collect::<Vec<_>>();
+
+            match (keyword.text, &words[..]) {
+                ("attribute", [name]) => {
+                    attributes.insert(name.to_string());
+                }
+                ("type", [name, rest @ ..]) => {
+                    let def = TypeDef {
+                        line,
+                        attributes: comma_list(rest, true).ok_or_else(|| {
+                            error("expected `type <type> [, <attribute>]...`".into())
+                        })?,
+                        ..Default::default()
+                    };
+                    if policy.types.insert(name.to_string(), def).is_some() {
+                        return Err(error(format!("type `{}` is already declared", name)));
+                    }
+                }
+                ("typeattribute", [name, rest @ ..]) => {
+                    let added = comma_list(rest, false).ok_or_else(|| {
+                        error(
+                            "expected `typeattribute <type> <attribute> [, <attribute>]...`".into(),
+                        )
+                    })?;
+                    policy
+                        .types
+                        .get_mut(*name)
+                        .ok_or_else(|| error(format!("type `{}` is not declared", name)))?
+                        .attributes
+                        .extend(added);
+                }
+                ("context", [name, path, ref flags @ ..]) => {
+                    let recursive = match flags {
+                        [] => false,
+                        ["recursive"] => true,
+                        _ => {
+                            return Err(error(
+                                "expected `context <type> <path> [recursive]`".into(),
+                            ))
+                        }
+                    };
+                    policy
+                        .types
+                        .get_mut(*name)
+                        .ok_or_else(|| error(format!("type `{}` is not declared", name)))?
+                        .contexts
+                        .push((path.to_string(), recursive));
+                }
+                ("allow", _) => allows.push(parse_allow(line, &words).ok_or_else(|| {
+                    error("expected `allow <source> <target>:<class> <permission>`".into())
+                })?),
+                _ => return Err(error(format!("invalid statement `{}`", keyword.text))),
+            }
+        }
+
+        for (name, def) in &policy.types {
+            if let Some(attribute) = def.attributes.iter().find(|x| !attributes.contains(*x)) {
+                return Err(TeError::SyntaxError(
+                    def.line,
+                    format!(
+                        "attribute `{}` of type `{}` is not declared",
+                        attribute, name
+                    ),
+                ));
+            }
+            if def.contexts.is_empty() {
+                return Err(TeError::SyntaxError(
+                    def.line,
+                    format!("type `{}` has no context", name),
+                ));
+            }
+        }
+
+        for allow in allows {
+            policy.add_allow(&attributes, allow)?;
+        }
+
+        Ok(policy)
+    }
+
+    /// Adds virtual spaces and relations of this policy to `config`.
+    pub fn apply(self, config: ConfigBuilder) -> ConfigBuilder {
+        let spaces = self.types.into_iter().map(|(name, def)| {
+            let mut contexts = def.contexts.into_iter();
+            let mut space = match contexts.next() {
+                Some((path, false)) => SpaceBuilder::new().with_path(intern(&path)),
+                Some((path, true)) => SpaceBuilder::new().with_path_recursive(intern(&path)),
+                None => unreachable!("type without context"),
+            };
+            for (path, recursive) in contexts {
+                space = if recursive {
+                    space.include_path_recursive(intern(&path))
+                } else {
+                    space.include_path(intern(&path))
+                };
+            }
+
+            let [_, sees, reads, writes] = def.access.map(|x| x.into_iter().map(|x| intern(&x)));
+            space
+                .with_name(intern(&name))
+                .reads(reads)
+                .writes(writes)
+                .sees(sees)
+        });
+        let config = config.add_spaces(spaces.collect::<Vec<_>>());
+
+        self.relations
+            .into_iter()
+            .fold(config, |config, (event, source, target)| {
+                config.allow_relation(
+                    Event::from_name(event),
+                    Space::ByName(intern(&source)),
+                    Some(Space::ByName(intern(&target))),
+                )
+            })
+    }
+
+    fn add_allow(&mut self, attributes: &BTreeSet<String>, allow: Allow) -> Result<(), TeError> {
+        let error = |message: String| TeError::SyntaxError(allow.line, message);
+        let sources = self
+            .expand(attributes, &allow.sources)
+            .map_err(|name| error(format!("type or attribute `{}` is not declared", name)))?;
+
+        let mut granted = Vec::new();
+        for class in &allow.classes {
+            for perm in &allow.permissions {
+                granted.push(permission(class, perm).ok_or_else(|| {
+                    error(format!(
+                        "permission `{}` of class `{}` is not supported",
+                        perm, class
+                    ))
+                })?);
+            }
+        }
+
+        for source in &sources {
+            let targets = allow
+                .targets
+                .iter()
+                .map(|x| if *x == "self" { source.as_str() } else { x })
+                .collect::<Vec<_>>();
+            let targets = self
+                .expand(attributes, &targets)
+                .map_err(|name| error(format!("type or attribute `{}` is not declared", name)))?;
+
+            for (access_types, events) in &granted {
+                for target in &targets {
+                    let def = self.types.get_mut(source).expect("type is declared");
+                    for at in access_types.iter() {
+                        def.access[*at as usize].insert(target.clone());
+                    }
+                    for event in events.iter() {
+                        self.relations
+                            .insert((event.name(), source.clone(), target.clone()));
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns types named by `names`, attributes are replaced by the types which have them.
+    /// Returns the first undeclared name as an error.
+    fn expand<'a>(
+        &self,
+        attributes: &BTreeSet<String>,
+        names: &[&'a str],
+    ) -> Result<BTreeSet<String>, &'a str> {
+        let mut types = BTreeSet::new();
+        for name in names {
+            if self.types.contains_key(*name) {
+                types.insert(name.to_string());
+            } else if attributes.contains(*name) {
+                types.extend(
+                    self.types
+                        .iter()
+                        .filter(|(_, def)| def.attributes.contains(*name))
+                        .map(|(name, _)| name.clone()),
+                );
+            } else {
+                return Err(name);
+            }
+        }
+
+        Ok(types)
+    }
+}
+
+/// Returns access types and events granted by permission `perm` of objects of `class`.
+fn permission(class: &str, perm: &str) -> Option<(&'static [AccessType], &'static [Event])> {
+    use AccessType::{Read, See, Write};
+
+    Some(match (class, perm) {
+        ("capability" | "capability2", _) => (&[], &[Event::Capable]),
+        ("process", "fork") => (&[], &[Event::Fork]),
+        ("process", "transition") => (&[], &[Event::Exec, Event::Sexec]),
+        ("process", "signal" | "sigkill" | "sigstop" | "sigchld" | "signull") => {
+            (&[], &[Event::Kill])
+        }
+        ("process", "ptrace") => (&[], &[Event::Ptrace]),
+        ("process", "setuid") => (&[], &[Event::Setresuid]),
+        ("process", "getattr") => (&[See], &[]),
+        ("msgq", "enqueue") | ("msg", "send") => (&[], &[Event::IpcMsgsnd]),
+        ("msgq", "receive") | ("msg", "receive") => (&[], &[Event::IpcMsgrcv]),
+        ("msgq" | "sem" | "shm", "associate") => (&[], &[Event::IpcAssociate]),
+        ("msgq" | "sem" | "shm", "getattr" | "setattr" | "destroy") => (&[], &[Event::IpcCtl]),
+        ("sem", "read" | "write") => (&[], &[Event::IpcSemop]),
+        ("shm", "read" | "write") => (&[], &[Event::IpcShmat]),
+        ("dir", "create") => (&[], &[Event::Mkdir]),
+        ("dir", "rmdir") => (&[], &[Event::Rmdir]),
+        ("dir", "search") => (&[See], &[]),
+        ("dir", "add_name" | "remove_name") => (&[Write], &[]),
+        ("lnk_file", "create") => (&[], &[Event::Symlink]),
+        ("lnk_file", "read") => (&[Read], &[Event::Readlink]),
+        (_, "create") => (&[], &[Event::Mknod]),
+        (_, "read" | "open" | "ioctl" | "lock") => (&[Read], &[]),
+        (_, "write" | "append") => (&[Write], &[]),
+        (_, "getattr") => (&[See], &[]),
+        (_, "setattr") => (&[], &[Event::Chmod, Event::Chown, Event::Truncate]),
+        (_, "execute" | "execute_no_trans" | "entrypoint") => (&[], &[Event::Exec]),
+        (_, "link") => (&[], &[Event::Link]),
+        (_, "unlink") => (&[], &[Event::Unlink]),
+        (_, "rename") => (&[], &[Event::Rename]),
+        _ => return None,
+    })
+}
+
+/// Splits `text` into names and punctuation, with the lines they are on.
+fn tokenize(text: &str) -> Vec<Token<'_>> {
+    let mut tokens = Vec::new();
+
+    for (n, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or_default();
+        let mut push = |text| tokens.push(Token { text, line: n + 1 });
+
+        let mut start = None;
+        for (i, c) in line.char_indices() {
+            let punctuation = "{};:,".contains(c);
+            if c.is_whitespace() || punctuation {
+                if let Some(start) = start.take() {
+                    push(&line[start..i]);
+                }
+                if punctuation {
+                    push(&line[i..i + 1]);
+                }
+            } else if start.is_none() {
+                start = Some(i);
+            }
+        }
+        if let Some(start) = start {
+            push(&line[start..]);
+        }
+    }
+
+    tokens
+}
+
+/// Parses names separated by commas, starting with a comma if `leading` is set.
+fn comma_list(words: &[&str], leading: bool) -> Option<BTreeSet<String>> {
+    let mut names = BTreeSet::new();
+    let mut expect_comma = leading;
+    for word in words {
+        match (*word, expect_comma) {
+            (",", true) => expect_comma = false,
+            (",", false) | (_, true) => return None,
+            (name, false) => {
+                names.insert(name.to_owned());
+                expect_comma = true;
+            }
+        }
+    }
+
+    // a trailing comma, or no name at all when one is required
+    if !expect_comma {
+        return None;
+    }
+
+    Some(names)
+}
+
+/// Parses a name or a set of names in braces starting at `words[*pos]`.
+fn name_set<'a>(words: &[&'a str], pos: &mut usize) -> Option<Vec<&'a str>> {
+    let first = *words.get(*pos)?;
+    *pos += 1;
+    if first != "{" {
+        return match first {
+            "}" | ":" | "," => None,
+            name => Some(vec![name]),
+        };
+    }
+
+    let mut names = Vec::new();
+    loop {
+        let word = *words.get(*pos)?;
+        *pos += 1;
+        match word {
+            "}" if !names.is_empty() => return Some(names),
+            "{" | "}" | ":" | "," => return None,
+            name => names.push(name),
+        }
+    }
+}
+
+fn parse_allow<'a>(line: usize, words: &[&'a str]) -> Option<Allow<'a>> {
+    let mut pos = 0;
+    let sources = name_set(words, &mut pos)?;
+    let targets = name_set(words, &mut pos)?;
+    if words.get(pos) != Some(&":") {
+        return None;
+    }
+    pos += 1;
+    let classes = name_set(words, &mut pos)?;
+    let permissions = name_set(words, &mut pos)?;
+    if pos != words.len() {
+        return None;
+    }
+
+    Some(Allow {
+        line,
+        sources,
+        targets,
+        classes,
+        permissions,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const POLICY: &str = "
+        attribute domain;
+        type sshd_t, domain;
+        type cron_t;
+        typeattribute cron_t domain;
+        type etc_t;
+        type shadow_t;
+
+        context sshd_t domains/usr/sbin/sshd;
+        context cron_t domains/usr/sbin/cron;
+        context etc_t fs/etc recursive;
+        context shadow_t fs/etc/shadow;
+
+        allow sshd_t { etc_t shadow_t }:file { read getattr };
+        allow domain self:process { fork signal }; # comment ;
+    ";
+
+    fn relations(policy: &TePolicy, event: &str) -> Vec<(String, String)> {
+        policy
+            .relations
+            .iter()
+            .filter(|(e, _, _)| *e == event)
+            .map(|(_, source, target)| (source.clone(), target.clone()))
+            .collect()
+    }
+
+    fn access<'a>(policy: &'a TePolicy, name: &str, at: AccessType) -> Vec<&'a str> {
+        policy.types[name].access[at as usize]
+            .iter()
+            .map(String::as_str)
+            .collect()
+    }
+
+    #[test]
+    fn grants_access_types_of_file_permissions() {
+        let policy = TePolicy::parse(POLICY).unwrap();
+        assert_eq!(
+            access(&policy, "sshd_t", AccessType::Read),
+            ["etc_t", "shadow_t"]
+        );
+        assert_eq!(
+            access(&policy, "sshd_t", AccessType::See),
+            ["etc_t", "shadow_t"]
+        );
+        assert!(access(&policy, "sshd_t", AccessType::Write).is_empty());
+        assert!(access(&policy, "cron_t", AccessType::Read).is_empty());
+        assert_eq!(policy.types["etc_t"].contexts, [("fs/etc".into(), true)]);
+    }
+
+    #[test]
+    fn expands_attributes_and_self() {
+        let policy = TePolicy::parse(POLICY).unwrap();
+        let expected = [
+            ("cron_t".to_owned(), "cron_t".to_owned()),
+            ("sshd_t".to_owned(), "sshd_t".to_owned()),
+        ];
+        assert_eq!(relations(&policy, "fork"), expected);
+        assert_eq!(relations(&policy, "kill"), expected);
+    }
+
+    #[test]
+    fn expands_attributes_of_targets() {
+        let policy = TePolicy::parse(
+            "attribute domain;
+             type a_t, domain; type b_t, domain;
+             context a_t domains/a; context b_t domains/b;
+             allow a_t domain:process ptrace;",
+        )
+        .unwrap();
+        assert_eq!(
+            relations(&policy, "ptrace"),
+            [
+                ("a_t".to_owned(), "a_t".to_owned()),
+                ("a_t".to_owned(), "b_t".to_owned())
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_policies() {
+        for (text, line) in [
+            ("type a_t;\ncontext a_t fs/a;\nallow a_t b_t:file read;", 3),
+            ("type a_t, domain;\ncontext a_t fs/a;", 1),
+            ("type a_t;", 1),
+            ("type a_t;\ntype a_t;", 2),
+            ("type a_t;\ncontext a_t fs/a;\nallow a_t a_t:file fly;", 3),
+            ("type a_t;\ncontext a_t fs/a;\nallow a_t a_t file read;", 3),
+            ("type a_t;\ncontext a_t fs/a forever;", 2),
+            ("type a_t;\n\nrole r;", 3),
+        ] {
+            assert!(
+                matches!(TePolicy::parse(text), Err(TeError::SyntaxError(x, _)) if x == line),
+                "{}",
+                text
+            );
+        }
+    }
+}
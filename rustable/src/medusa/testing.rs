@@ -0,0 +1,90 @@
+//! Utilities for writing deterministic tests of handlers and configurations.
+//!
+//! Available with the `testing` feature.
+
+use crate::medusa::constants::{GREETING_NATIVE_BYTE_ORDER, PROTOCOL_VERSION};
+use crate::medusa::{CompletedRequest, MedusaAnswer};
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+use tokio::runtime::{Builder, Runtime};
+use tokio::sync::Notify;
+
+/// Creates a single-threaded runtime with paused time. Timers complete as soon as every task is
+/// idle, so timeouts can be tested without real sleeps.
+pub fn paused_runtime() -> io::Result<Runtime> {
+    Builder::new_current_thread()
+        .enable_all()
+        .start_paused(true)
+        .build()
+}
+
+/// Writes the greeting and protocol version as the security module would do after opening the
+/// connection.
+pub fn write_greeting<W: Write>(kernel: &mut W) -> io::Result<()> {
+    kernel.write_all(&GREETING_NATIVE_BYTE_ORDER.to_ne_bytes())?;
+    kernel.write_all(&PROTOCOL_VERSION.to_ne_bytes())?;
+    kernel.flush()
+}
+
+#[derive(Default)]
+struct RecorderInner {
+    completed: Mutex<Vec<CompletedRequest>>,
+    notify: Notify,
+}
+
+/// Records every completed authorization request. Install it with
+/// [`ConfigBuilder::on_completion`] and await [`CompletionRecorder::wait_for`] instead of sleeping.
+///
+/// [`ConfigBuilder::on_completion`]: crate::medusa::ConfigBuilder::on_completion
+#[derive(Default, Clone)]
+pub struct CompletionRecorder {
+    inner: Arc<RecorderInner>,
+}
+
+impl CompletionRecorder {
+    /// Creates new `CompletionRecorder`.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Returns a closure suitable for [`ConfigBuilder::on_completion`].
+    ///
+    /// [`ConfigBuilder::on_completion`]: crate::medusa::ConfigBuilder::on_completion
+    pub fn hook(&self) -> impl Fn(&CompletedRequest) + Send + Sync + 'static {
+        let inner = Arc::clone(&self.inner);
+        move |completed| {
+            inner.completed.lock().unwrap().push(completed.clone());
+            inner.notify.notify_waiters();
+        }
+    }
+
+    /// Returns all requests completed so far, in the order of completion.
+    pub fn completed(&self) -> Vec<CompletedRequest> {
+        self.inner.completed.lock().unwrap().clone()
+    }
+
+    /// Returns request ids and answers of all requests completed so far.
+    pub fn answers(&self) -> Vec<(u64, MedusaAnswer)> {
+        self.inner
+            .completed
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|x| (x.data.request_id, x.answer))
+            .collect()
+    }
+
+    /// Waits until at least `count` requests have been completed and returns them.
+    pub async fn wait_for(&self, count: usize) -> Vec<CompletedRequest> {
+        loop {
+            let notified = self.inner.notify.notified();
+            {
+                let completed = self.inner.completed.lock().unwrap();
+                if completed.len() >= count {
+                    return completed.clone();
+                }
+            }
+            notified.await;
+        }
+    }
+}
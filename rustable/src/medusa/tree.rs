@@ -1,9 +1,219 @@
 use crate::medusa::constants::{AccessType, NODE_HIGHEST_PRIORITY};
 use crate::medusa::space::{Space, SpaceDef, VirtualSpace};
-use crate::medusa::ConfigError;
-use regex::Regex;
+use crate::medusa::{ConfigError, Event, FastHashMap};
+use regex::{Regex, RegexBuilder};
 use std::collections::{BTreeMap, HashMap, HashSet};
-use std::sync::Arc;
+use std::mem;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock, RwLock};
+
+/// Maximum number of path components whose matching child a node remembers.
+const NODE_LOOKUP_CACHE_CAPACITY: usize = 4096;
+
+lazy_static! {
+    // cinfo of nodes of all configs by their position, see `node_cinfo`
+    static ref NODE_CINFOS: Mutex<FastHashMap<(usize, String, usize), usize>> = Default::default();
+}
+
+/// Returns the cinfo of the node at `path` under node `parent_cinfo`, `ordinal` telling it apart
+/// from preceding siblings with the same path. A node at the same position of another config
+/// has the same cinfo, so that entities entered into a node by a replaced config are found in
+/// the replacing one, and the cinfo of a node which is left out is never given to another one.
+fn node_cinfo(parent_cinfo: usize, path: &str, ordinal: usize) -> usize {
+    let mut cinfos = NODE_CINFOS.lock().unwrap();
+    // zero stands for no node
+    let next = cinfos.len() + 1;
+    *cinfos
+        .entry((parent_cinfo, path.to_owned(), ordinal))
+        .or_insert(next)
+}
+
+/// Returns cinfos of `builders`, the children of node `parent_cinfo`.
+fn children_cinfos(parent_cinfo: usize, builders: &[NodeBuilder]) -> Vec<usize> {
+    let mut ordinals = HashMap::new();
+    builders
+        .iter()
+        .map(|x| {
+            let ordinal = ordinals.entry(x.path).or_insert(0);
+            *ordinal += 1;
+            node_cinfo(parent_cinfo, x.path, *ordinal - 1)
+        })
+        .collect()
+}
+
+/// Mutable state of building the trees of a config.
+pub(crate) struct TreeBuildState<'a> {
+    pub(crate) def: &'a mut SpaceDef,
+    pub(crate) cinfo: &'a mut FastHashMap<usize, Arc<Node>>,
+    pub(crate) regexes: &'a NodeRegexes,
+    pub(crate) lazy_nodes: &'a Arc<LazyNodes>,
+}
+
+/// Children of a node.
+#[derive(Debug)]
+enum Children {
+    Built(Box<[Arc<Node>]>),
+    Lazy(Box<LazyChildren>),
+}
+
+impl Children {
+    /// Returns children built on first access from `builders`.
+    fn lazy(builders: Vec<NodeBuilder>, lazy_nodes: &Arc<LazyNodes>) -> Self {
+        if builders.is_empty() {
+            return Children::Built(Box::from([]));
+        }
+
+        Children::Lazy(Box::new(LazyChildren {
+            builders: Mutex::new(builders),
+            built: OnceLock::new(),
+            lazy_nodes: Arc::clone(lazy_nodes),
+        }))
+    }
+}
+
+/// Children of a node which are built on first access, see [`NodeBuilder::lazy`].
+#[derive(Debug)]
+struct LazyChildren {
+    builders: Mutex<Vec<NodeBuilder>>,
+    built: OnceLock<Box<[Arc<Node>]>>,
+    lazy_nodes: Arc<LazyNodes>,
+}
+
+impl LazyChildren {
+    /// Builds the children of `parent` and makes them known to the config.
+    fn build(&self, parent: &Node) -> Box<[Arc<Node>]> {
+        let builders = mem::take(&mut *self.builders.lock().unwrap());
+        let def = self.lazy_nodes.def.get().expect("spaces are defined");
+        let cinfos = children_cinfos(parent.cinfo, &builders);
+
+        let children = builders
+            .into_iter()
+            .zip(cinfos)
+            .filter_map(|(x, cinfo)| {
+                let path = x.path;
+                match x.build_lazily(def, &self.lazy_nodes, parent, cinfo) {
+                    Ok(child) => Some(child),
+                    Err(e) => {
+                        eprintln!("lazily compiled node `{}` is left out: {}", path, e);
+                        None
+                    }
+                }
+            })
+            .collect::<Box<[_]>>();
+
+        self.lazy_nodes.register(parent, &children);
+        children
+    }
+}
+
+/// Nodes of a config which are built on first access, see [`NodeBuilder::lazy`].
+#[derive(Debug, Default)]
+pub(crate) struct LazyNodes {
+    // spaces of the config, set once all of them are defined
+    def: OnceLock<SpaceDef>,
+    state: RwLock<LazyNodesState>,
+}
+
+#[derive(Debug, Default)]
+struct LazyNodesState {
+    // built nodes by their cinfo, as the cinfo of their closest eagerly built ancestor and
+    // indices of the children leading from it to them
+    positions: FastHashMap<usize, (usize, Box<[usize]>)>,
+    // events registered by the security module with their monitoring bits
+    events: Vec<(String, u16)>,
+}
+
+impl LazyNodes {
+    /// Sets the spaces lazily built nodes are members of.
+    pub(crate) fn set_def(&self, def: &SpaceDef) {
+        self.def.set(def.clone()).expect("spaces are set once");
+    }
+
+    /// Returns the cinfo of the closest eagerly built ancestor of built node `cinfo` and the
+    /// indices of the children leading from it to the node.
+    pub(crate) fn position(&self, cinfo: &usize) -> Option<(usize, Box<[usize]>)> {
+        self.state.read().unwrap().positions.get(cinfo).cloned()
+    }
+
+    /// Returns cinfos of all built nodes.
+    pub(crate) fn cinfos(&self) -> Vec<usize> {
+        self.state
+            .read()
+            .unwrap()
+            .positions
+            .keys()
+            .copied()
+            .collect()
+    }
+
+    /// Records the monitoring bit of an event registered by the security module, so that
+    /// nodes built later monitor it as well.
+    pub(crate) fn register_event(&self, event: &str, monitoring_bit: u16) {
+        let mut state = self.state.write().unwrap();
+        state.events.retain(|(x, _)| x != event);
+        state.events.push((event.to_owned(), monitoring_bit));
+    }
+
+    /// Makes `children` just built under `parent` known.
+    fn register(&self, parent: &Node, children: &[Arc<Node>]) {
+        // events are registered while the nodes become known, so that none is missed
+        let mut state = self.state.write().unwrap();
+        for child in children {
+            for (event, monitoring_bit) in &state.events {
+                child.register_event(event, *monitoring_bit);
+            }
+        }
+
+        let parent_cinfo = parent.cinfo;
+        let (root, indices) = state
+            .positions
+            .get(&parent_cinfo)
+            .cloned()
+            .unwrap_or((parent_cinfo, Box::from([])));
+        for (i, child) in children.iter().enumerate() {
+            let mut child_indices = indices.to_vec();
+            child_indices.push(i);
+            state
+                .positions
+                .insert(child.cinfo, (root, child_indices.into_boxed_slice()));
+        }
+    }
+}
+
+/// Compiled regular expressions of node paths by their anchored pattern and whether they match
+/// regardless of case.
+pub(crate) type NodeRegexes = FastHashMap<(String, bool), Regex>;
+
+/// Compiles the regular expressions of node paths, on all cores with the `parallel` feature.
+pub(crate) fn compile_regexes(
+    patterns: HashSet<(String, bool)>,
+) -> Result<NodeRegexes, ConfigError> {
+    let compile = |(pattern, case_insensitive): (String, bool)| {
+        let regex = RegexBuilder::new(&pattern)
+            .case_insensitive(case_insensitive)
+            .build()?;
+        Ok(((pattern, case_insensitive), regex))
+    };
+
+    #[cfg(feature = "parallel")]
+    {
+        use rayon::prelude::*;
+        patterns.into_par_iter().map(compile).collect()
+    }
+
+    #[cfg(not(feature = "parallel"))]
+    patterns.into_iter().map(compile).collect()
+}
+
+/// Returns `path` anchored to match the whole path component, otherwise "sbin".is_match("bin")
+/// would return true. Paths anchored at either end are kept as they are.
+fn anchored(path: &str) -> String {
+    if !path.starts_with('^') && !path.ends_with('$') {
+        format!(r"^{}$", path)
+    } else {
+        path.to_owned()
+    }
+}
 
 /// Node of structure [`Tree`].
 ///
@@ -12,11 +222,21 @@ use std::sync::Arc;
 pub struct Node {
     path_regex: Regex,
     recursive: bool,
+    case_insensitive: bool,
 
     vs: VirtualSpace,
 
-    children: Box<[Arc<Node>]>,
+    children: Children,
+    cinfo: usize,
     parent_cinfo: Option<usize>,
+
+    // events monitored in this node, `None` means all covered events
+    monitored_events: Option<Box<[&'static str]>>,
+    monitored_mask: AtomicU64,
+
+    // indices of children matching path components looked up before, `None` if no child
+    // matches, see `ConfigBuilder::warm_up`
+    lookups: RwLock<FastHashMap<Box<str>, Option<usize>>>,
 }
 
 /// Implement Default to be able to create some kind of parent<->child reference "safely"...
@@ -25,9 +245,14 @@ impl Default for Node {
         Self {
             path_regex: Regex::new("").unwrap(), // ...
             recursive: false,
+            case_insensitive: false,
             vs: VirtualSpace::default(),
-            children: Box::from([]),
+            children: Children::Built(Box::from([])),
+            cinfo: 0,
             parent_cinfo: None,
+            monitored_events: None,
+            monitored_mask: AtomicU64::new(0),
+            lookups: Default::default(),
         }
     }
 }
@@ -40,6 +265,30 @@ impl Node {
         NodeBuilder::new()
     }
 
+    /// Creates a child of `parent` at runtime, without adding it to `parent`. It has the access
+    /// of `parent` and it is also a member of virtual space `bit`, which it can read, write and
+    /// see.
+    pub(crate) fn new_child(parent: &Arc<Node>, path: &str, bit: usize) -> Arc<Node> {
+        let path_regex = RegexBuilder::new(&format!("^{}$", regex::escape(path)))
+            .case_insensitive(parent.case_insensitive)
+            .build()
+            .unwrap();
+
+        Arc::new(Node {
+            path_regex,
+            recursive: false,
+            case_insensitive: parent.case_insensitive,
+            vs: parent.vs.with_space(bit),
+            children: Children::Built(Box::from([])),
+            // apart from the static children of `parent`
+            cinfo: node_cinfo(parent.cinfo, path, usize::MAX),
+            parent_cinfo: Some(parent.cinfo),
+            monitored_events: parent.monitored_events.clone(),
+            monitored_mask: AtomicU64::new(parent.monitored_mask.load(Ordering::SeqCst)),
+            lookups: Default::default(),
+        })
+    }
+
     pub(crate) fn path(&self) -> &str {
         self.path_regex.as_str()
     }
@@ -48,12 +297,47 @@ impl Node {
         self.recursive
     }
 
+    /// Returns the children of this node, building them if they are compiled lazily.
+    pub(crate) fn children(&self) -> &[Arc<Node>] {
+        match &self.children {
+            Children::Built(children) => children,
+            Children::Lazy(lazy) => lazy.built.get_or_init(|| lazy.build(self)),
+        }
+    }
+
     pub(crate) fn has_children(&self) -> bool {
-        self.children.len() > 0
+        match &self.children {
+            Children::Built(children) => !children.is_empty(),
+            Children::Lazy(_) => true,
+        }
     }
 
+    /// Returns the child matching path component `path`. Results are remembered until the
+    /// cache of this node is full, so that the regular expressions of the children are matched
+    /// only once per component.
     pub(crate) fn child_by_path(&self, path: &str) -> Option<&Arc<Node>> {
-        self.children.iter().find(|x| x.path_regex.is_match(path))
+        if !self.has_children() {
+            return None;
+        }
+
+        let children = self.children();
+        if let Some(index) = self.lookups.read().unwrap().get(path) {
+            return index.map(|x| &children[x]);
+        }
+
+        let index = children.iter().position(|x| x.path_regex.is_match(path));
+
+        let mut lookups = self.lookups.write().unwrap();
+        if lookups.len() < NODE_LOOKUP_CACHE_CAPACITY {
+            lookups.insert(path.into(), index);
+        }
+
+        index.map(|x| &children[x])
+    }
+
+    /// Returns the value identifying this node in the `o_cinfo` attribute of entities.
+    pub(crate) fn cinfo(&self) -> usize {
+        self.cinfo
     }
 
     pub(crate) fn parent_cinfo(&self) -> Option<usize> {
@@ -63,6 +347,46 @@ impl Node {
     pub(crate) fn virtual_space(&self) -> &VirtualSpace {
         &self.vs
     }
+
+    /// Restricts `covered_events` to the events monitored in this node.
+    pub(crate) fn monitored_mask(&self, covered_events: u64) -> u64 {
+        match self.monitored_events {
+            Some(_) => covered_events & self.monitored_mask.load(Ordering::SeqCst),
+            None => covered_events,
+        }
+    }
+
+    /// Records the monitoring bit of an event registered by the security module.
+    pub(crate) fn register_event(&self, event: &str, monitoring_bit: u16) {
+        if let Some(events) = &self.monitored_events {
+            if events.contains(&event) {
+                self.monitored_mask
+                    .fetch_or(1 << monitoring_bit, Ordering::SeqCst);
+            }
+        }
+    }
+
+    /// Returns whether an entity at `path` can be entered into the tree rooted at this node,
+    /// that is the path ends in a node or passes through a recursive one.
+    pub(crate) fn covers(&self, path: &str) -> bool {
+        if !path.starts_with('/') {
+            return false;
+        }
+
+        let mut node = self;
+        let mut recursive = node.is_recursive();
+        for part in path.split_terminator('/').skip(1) {
+            match node.child_by_path(part) {
+                Some(child) => {
+                    recursive |= child.is_recursive();
+                    node = child.as_ref();
+                }
+                None => return recursive,
+            }
+        }
+
+        true
+    }
 }
 
 /// A tree structure that could represent, for example, a file system hierarchy.
@@ -88,6 +412,47 @@ impl Tree {
     pub(crate) fn root(&self) -> &Arc<Node> {
         &self.root
     }
+
+    /// Returns the node an entity at `path` is entered into and whether it was reached by
+    /// recursion, `None` if the path is not covered by this tree.
+    pub(crate) fn resolve(&self, path: &str) -> Option<(&Arc<Node>, bool)> {
+        let mut node = self.root();
+        let mut recursive_parent = if node.is_recursive() {
+            Some(node)
+        } else {
+            None
+        };
+        let mut recursed = false;
+        if path != "/" {
+            // skip empty string caused by leading '/'
+            for part in path.split_terminator('/').skip(1) {
+                match node.child_by_path(part) {
+                    Some(child) => {
+                        if child.is_recursive() {
+                            recursive_parent = Some(child);
+                        }
+                        node = child;
+                    }
+                    None => {
+                        node = recursive_parent?;
+                        recursed = true;
+                    }
+                }
+            }
+        }
+
+        Some((node, recursed))
+    }
+
+    /// Resolves `path` like [`Tree::resolve`] only to remember the children matching its
+    /// components, see [`ConfigBuilder::warm_up`].
+    ///
+    /// Returns `true` if the path is covered by this tree.
+    ///
+    /// [`ConfigBuilder::warm_up`]: crate::medusa::ConfigBuilder::warm_up
+    pub(crate) fn warm_up(&self, path: &str) -> bool {
+        path.starts_with('/') && self.resolve(path).is_some()
+    }
 }
 
 /// Builder for structure [`Node`].
@@ -97,10 +462,18 @@ impl Tree {
 pub struct NodeBuilder {
     path: &'static str,
     recursive: bool,
+    // `None` means inherited from the parent
+    case_insensitive: Option<bool>,
 
     at_names: [HashSet<&'static str>; AccessType::Length as usize],
+    denied: HashSet<&'static str>,
+    monitored_events: Option<Vec<&'static str>>,
 
     children: BTreeMap<u16, HashMap<String, NodeBuilder>>,
+    // patterns of names excluded from spaces, see `SpaceBuilder::exclude_matching`
+    exclusions: BTreeMap<&'static str, HashSet<&'static str>>,
+    // whether descendants are built on first access, see `NodeBuilder::lazy`
+    lazy: bool,
 }
 
 impl NodeBuilder {
@@ -117,6 +490,49 @@ impl NodeBuilder {
         self
     }
 
+    /// Sets whether the path of this node matches regardless of case, as on vfat or ntfs mounts
+    /// where `/MEDIA/USB` and `/media/usb` are the same file. Descendant nodes inherit the
+    /// setting unless they declare their own, see [`TreeBuilder::case_insensitive`].
+    ///
+    /// Returns `Self`.
+    pub fn case_insensitive(mut self, case_insensitive: bool) -> Self {
+        self.case_insensitive = Some(case_insensitive);
+        self
+    }
+
+    /// Compiles the descendants of this node lazily: the regular expressions and virtual spaces
+    /// of its children are built when the first entity is looked up among them, those of their
+    /// children when one is looked up among those, and so on. Subtrees which are rarely used
+    /// then do not slow down building of the config, at the cost of latency of their first
+    /// lookups. Spaces of the descendants are still assigned their bits when the config is
+    /// built.
+    ///
+    /// Descendants which are not built yet are left out of [`Config::diff`], snapshots and
+    /// readiness checks. An invalid path of a descendant is reported once it is compiled and
+    /// the node is left out.
+    ///
+    /// Returns `Self`.
+    ///
+    /// [`Config::diff`]: crate::medusa::Config::diff
+    pub fn lazy(mut self) -> Self {
+        self.lazy = true;
+        self
+    }
+
+    pub(crate) fn set_lazy(&mut self) {
+        self.lazy = true;
+    }
+
+    /// Returns names of the spaces of access type `at` of this node.
+    pub(crate) fn space_names(&self, at: AccessType) -> impl Iterator<Item = &'static str> + '_ {
+        self.at_names[at as usize].iter().copied()
+    }
+
+    /// Returns names of the spaces denied to this node.
+    pub(crate) fn denied_names(&self) -> impl Iterator<Item = &'static str> + '_ {
+        self.denied.iter().copied()
+    }
+
     /// Adds a new access name `name` for given access type `at`.
     ///
     /// Returns `Self`.
@@ -125,6 +541,30 @@ impl NodeBuilder {
         self
     }
 
+    /// Denies access of all types but membership to space `name`, overriding the access types
+    /// added by [`NodeBuilder::add_access_type`], see [`SpaceBuilder::denies`].
+    ///
+    /// Returns `Self`.
+    ///
+    /// [`SpaceBuilder::denies`]: crate::medusa::SpaceBuilder::denies
+    pub fn deny_space(mut self, name: &'static str) -> Self {
+        self.denied.insert(name);
+        self
+    }
+
+    /// Restricts monitoring of objects and subjects in this node to `events`, for example only
+    /// to events modifying files under `/usr`. Descendant nodes inherit the restriction unless
+    /// they declare their own. By default, all events having handlers are monitored.
+    ///
+    /// Returns `Self`.
+    pub fn monitor_events<I>(mut self, events: I) -> Self
+    where
+        I: IntoIterator<Item = Event>,
+    {
+        self.add_monitored_events(events.into_iter().map(|x| x.name()));
+        self
+    }
+
     /// Adds a new node.
     ///
     /// Returns `Self`.
@@ -149,6 +589,15 @@ impl NodeBuilder {
         self
     }
 
+    pub(crate) fn add_monitored_events<I>(&mut self, events: I)
+    where
+        I: IntoIterator<Item = &'static str>,
+    {
+        self.monitored_events
+            .get_or_insert_with(Vec::new)
+            .extend(events);
+    }
+
     pub(crate) fn set_recursive(&mut self, recursive: bool) {
         self.recursive = recursive;
     }
@@ -171,11 +620,20 @@ impl NodeBuilder {
     ) {
         for (r#type, set) in self.at_names.iter_mut().enumerate() {
             if r#type != AccessType::Member as usize {
-                set.extend(&at_names[r#type as usize]);
+                set.extend(&at_names[r#type]);
             }
         }
     }
 
+    pub(crate) fn add_denied(&mut self, names: &[&'static str]) {
+        self.denied.extend(names);
+    }
+
+    /// Excludes children whose name matches `pattern` from `space`.
+    pub(crate) fn exclude_matching(&mut self, pattern: &'static str, space: &'static str) {
+        self.exclusions.entry(pattern).or_default().insert(space);
+    }
+
     pub(crate) fn member_of_include_or_exclude(&mut self, name: &'static str, include: bool) {
         if include {
             self.at_names[AccessType::Member as usize].insert(name);
@@ -184,59 +642,198 @@ impl NodeBuilder {
         }
     }
 
-    fn build(
-        self,
-        def: &mut SpaceDef,
-        cinfo: &mut HashMap<usize, Arc<Node>>,
-        parent_cinfo: Option<usize>,
-    ) -> Result<Arc<Node>, ConfigError> {
-        // a pretty expensive way to have a reference to parent before creating the node itself
-        let mut node = Arc::new(Node::default());
-        let node_cinfo = Arc::as_ptr(&node) as usize;
+    /// Collects patterns of the paths of this node and of its descendants, see
+    /// [`compile_regexes`].
+    fn collect_patterns(
+        &self,
+        inherited_case_insensitive: bool,
+        patterns: &mut HashSet<(String, bool)>,
+    ) {
+        let case_insensitive = self.case_insensitive.unwrap_or(inherited_case_insensitive);
+        patterns.insert((anchored(self.path), case_insensitive));
+        if self.lazy {
+            return;
+        }
+
+        for pattern in self.exclusions.keys() {
+            patterns.insert((anchored(pattern), case_insensitive));
+        }
+        for child in self.children.values().flat_map(HashMap::values) {
+            child.collect_patterns(case_insensitive, patterns);
+        }
+    }
 
-        let children = self
-            .children
+    /// Returns the children of this node followed by the nodes of its exclusions, which keep
+    /// the access of this node and are tried after all other children.
+    fn take_children(&mut self) -> Vec<NodeBuilder> {
+        let exclusions = mem::take(&mut self.exclusions)
             .into_iter()
-            .map(|(_, hmap)| hmap)
-            .flatten()
-            .map(|(_, x)| x.build(def, cinfo, Some(node_cinfo)))
-            .collect::<Result<_, _>>()?;
-
-        let path_regex = if !self.path.starts_with('^') && !self.path.ends_with('$') {
-            // match the whole path, otherwise, "sbin".is_match("bin") would return true.
-            Regex::new(&format!(r"^{}$", self.path))?
-        } else {
-            Regex::new(self.path)?
-        };
+            .map(|(pattern, spaces)| {
+                let mut at_names = self.at_names.clone();
+                at_names[AccessType::Member as usize].retain(|x| !spaces.contains(x));
+
+                NodeBuilder {
+                    path: pattern,
+                    recursive: self.recursive,
+                    at_names,
+                    denied: self.denied.clone(),
+                    ..Default::default()
+                }
+            })
+            .collect::<Vec<_>>();
+
+        mem::take(&mut self.children)
+            .into_values()
+            .flat_map(|hmap| hmap.into_values())
+            .chain(exclusions)
+            .collect()
+    }
 
-        // define new spaces which may not exist yet (assign an id for every new name)
+    /// Defines the spaces of this node and of its descendants, so that nodes built later share
+    /// the bits of the config, see [`NodeBuilder::lazy`].
+    fn define_spaces(&self, def: &mut SpaceDef) {
+        for child in self.children.values().flat_map(HashMap::values) {
+            child.define_spaces(def);
+        }
+
+        // exclusions are members of a subset of the spaces of this node
         self.at_names
             .iter()
+            .chain([&self.denied])
             .for_each(|names| names.iter().for_each(|space| def.define_space(space)));
+    }
 
+    /// Creates the node of this builder. Spaces of the node must be defined in `def`.
+    fn into_node(
+        self,
+        def: &SpaceDef,
+        path_regex: Regex,
+        children: Children,
+        (cinfo, parent_cinfo): (usize, Option<usize>),
+        monitored_events: Option<Box<[&'static str]>>,
+        case_insensitive: bool,
+    ) -> Node {
         let spaces = self
             .at_names
             .into_iter()
             .map(|names| names.into_iter().map(Space::ByName).collect::<Vec<Space>>())
             .collect::<Vec<Vec<Space>>>();
 
-        let mut vs = VirtualSpace::new();
-        vs.set_access_types(def, &spaces.try_into().unwrap());
+        let denied = self
+            .denied
+            .into_iter()
+            .map(Space::ByName)
+            .collect::<Vec<_>>();
 
-        let recursive = self.recursive;
+        let mut vs = VirtualSpace::new();
+        vs.set_access_types(def, &spaces.try_into().unwrap())
+            .and_then(|_| vs.set_denied(def, &denied))
+            .expect("spaces of the node are defined");
 
-        *Arc::get_mut(&mut node).unwrap() = Node {
+        Node {
             path_regex,
-            recursive,
+            recursive: self.recursive,
+            case_insensitive,
             vs,
             children,
+            cinfo,
             parent_cinfo,
+            monitored_events,
+            monitored_mask: AtomicU64::new(0),
+            lookups: Default::default(),
+        }
+    }
+
+    fn build(
+        mut self,
+        state: &mut TreeBuildState,
+        (node_cinfo, parent_cinfo): (usize, Option<usize>),
+        inherited_events: Option<&[&'static str]>,
+        inherited_case_insensitive: bool,
+    ) -> Result<Arc<Node>, ConfigError> {
+        let monitored_events = match self.monitored_events.take() {
+            Some(events) => Some(events.into_boxed_slice()),
+            None => inherited_events.map(Box::from),
+        };
+        let case_insensitive = self.case_insensitive.unwrap_or(inherited_case_insensitive);
+
+        let children = self.take_children();
+        let children = if self.lazy {
+            // bits of the spaces are assigned now, the nodes once they are looked up
+            children.iter().for_each(|x| x.define_spaces(state.def));
+            Children::lazy(children, state.lazy_nodes)
+        } else {
+            let cinfos = children_cinfos(node_cinfo, &children);
+            let children = children
+                .into_iter()
+                .zip(cinfos)
+                .map(|(x, cinfo)| {
+                    x.build(
+                        state,
+                        (cinfo, Some(node_cinfo)),
+                        monitored_events.as_deref(),
+                        case_insensitive,
+                    )
+                })
+                .collect::<Result<_, _>>()?;
+            Children::Built(children)
+        };
+
+        let pattern = anchored(self.path);
+        let path_regex = match state.regexes.get(&(pattern.clone(), case_insensitive)) {
+            Some(regex) => regex.clone(),
+            None => RegexBuilder::new(&pattern)
+                .case_insensitive(case_insensitive)
+                .build()?,
         };
 
-        cinfo.insert(node_cinfo, Arc::clone(&node));
+        // define new spaces which may not exist yet (assign an id for every new name)
+        self.define_spaces(state.def);
+
+        let node = Arc::new(self.into_node(
+            state.def,
+            path_regex,
+            children,
+            (node_cinfo, parent_cinfo),
+            monitored_events,
+            case_insensitive,
+        ));
+
+        state.cinfo.insert(node_cinfo, Arc::clone(&node));
 
         Ok(node)
     }
+
+    /// Builds this child of lazily compiled node `parent` once it is looked up. Its own
+    /// children are compiled lazily as well.
+    fn build_lazily(
+        mut self,
+        def: &SpaceDef,
+        lazy_nodes: &Arc<LazyNodes>,
+        parent: &Node,
+        cinfo: usize,
+    ) -> Result<Arc<Node>, regex::Error> {
+        let monitored_events = match self.monitored_events.take() {
+            Some(events) => Some(events.into_boxed_slice()),
+            None => parent.monitored_events.clone(),
+        };
+        let case_insensitive = self.case_insensitive.unwrap_or(parent.case_insensitive);
+
+        let path_regex = RegexBuilder::new(&anchored(self.path))
+            .case_insensitive(case_insensitive)
+            .build()?;
+
+        let children = Children::lazy(self.take_children(), lazy_nodes);
+
+        Ok(Arc::new(self.into_node(
+            def,
+            path_regex,
+            children,
+            (cinfo, Some(parent.cinfo)),
+            monitored_events,
+            case_insensitive,
+        )))
+    }
 }
 
 /// Builder for structure [`Tree`].
@@ -246,6 +843,7 @@ impl NodeBuilder {
 pub struct TreeBuilder {
     name: &'static str,
     root: Option<NodeBuilder>,
+    case_insensitive: bool,
 }
 
 impl TreeBuilder {
@@ -267,6 +865,16 @@ impl TreeBuilder {
         self
     }
 
+    /// Matches paths of all nodes of this tree regardless of case, unless a node declares
+    /// otherwise, see [`NodeBuilder::case_insensitive`]. Spaces added to the config after this
+    /// tree get case-insensitive nodes as well.
+    ///
+    /// Returns `Self`.
+    pub fn case_insensitive(mut self) -> Self {
+        self.case_insensitive = true;
+        self
+    }
+
     /// Sets the root node of this tree.
     ///
     /// Returns `Self`.
@@ -280,17 +888,38 @@ impl TreeBuilder {
             .get_or_insert_with(|| NodeBuilder::new().with_path(path))
     }
 
-    pub(crate) fn build(
-        self,
-        def: &mut SpaceDef,
-        cinfo: &mut HashMap<usize, Arc<Node>>,
-    ) -> Result<Tree, ConfigError> {
+    /// Collects patterns of the paths of all nodes of this tree, see [`compile_regexes`].
+    pub(crate) fn collect_patterns(&self, patterns: &mut HashSet<(String, bool)>) {
+        if let Some(root) = &self.root {
+            root.collect_patterns(self.case_insensitive, patterns);
+        }
+    }
+
+    /// Returns the node at `items`, starting with the root.
+    pub(crate) fn node_mut(&mut self, items: &[&str]) -> Option<&mut NodeBuilder> {
+        let (root_path, items) = items.split_first()?;
+
+        let mut node = self.root.as_mut().filter(|x| x.path == *root_path)?;
+        for item in items {
+            node = node
+                .children
+                .values_mut()
+                .find_map(|children| children.get_mut(*item))?;
+        }
+
+        Some(node)
+    }
+
+    pub(crate) fn build(self, state: &mut TreeBuildState) -> Result<Tree, ConfigError> {
         Ok(Tree {
             name: self.name,
-            root: self
-                .root
-                .expect("Root is missing.")
-                .build(def, cinfo, None)?,
+            root: self.root.expect("Root is missing.").build(
+                state,
+                // roots are told apart by the name of their tree
+                (node_cinfo(0, self.name, 0), None),
+                None,
+                self.case_insensitive,
+            )?,
         })
     }
 }
@@ -0,0 +1,109 @@
+//! Honeypot spaces raising alerts on any access, see [`ConfigBuilder::tripwire`].
+//!
+//! [`ConfigBuilder::tripwire`]: crate::medusa::ConfigBuilder::tripwire
+
+use crate::medusa::alert::{self, AlertAction};
+use crate::medusa::audit::AuditRecord;
+use crate::medusa::{AuthRequestData, CompletedRequest, Context, HandlerData, MedusaAnswer};
+use anyhow::Context as _;
+use serde::Serialize;
+use std::thread;
+
+/// Space whose objects no legitimate process touches, such as a decoy `/root/.aws` or a
+/// honeypot share. Any access to them by any domain answers with [`Tripwire::answer`] and
+/// raises an alert.
+///
+/// Tripwires are checked by built-in handlers named `tripwire_<space>_<event>`, which run
+/// before all other handlers of each monitored event and before its relations, see
+/// [`ConfigBuilder::allow_relation`]. Only objects are checked, requests of events without an
+/// object never trip a wire.
+///
+/// [`ConfigBuilder::allow_relation`]: crate::medusa::ConfigBuilder::allow_relation
+#[derive(Debug, Clone)]
+pub struct Tripwire {
+    pub(crate) space: &'static str,
+    pub(crate) answer: MedusaAnswer,
+    pub(crate) alert: Option<AlertAction>,
+}
+
+impl Tripwire {
+    /// Creates new `Tripwire` on space `space`, denying any access and raising no alert
+    /// beyond the output.
+    pub fn new(space: &'static str) -> Self {
+        Self {
+            space,
+            answer: MedusaAnswer::Deny,
+            alert: None,
+        }
+    }
+
+    /// Sets the answer to accesses, [`MedusaAnswer::Deny`] by default. With
+    /// [`MedusaAnswer::Allow`], the intruder is not warned that it was noticed, and handlers
+    /// run after the tripwire still decide.
+    ///
+    /// Returns `Self`.
+    pub fn answer(mut self, answer: MedusaAnswer) -> Self {
+        self.answer = answer;
+        self
+    }
+
+    /// Sets the action run with a [`TripwireAlert`] on every access.
+    ///
+    /// Returns `Self`.
+    pub fn alert(mut self, action: AlertAction) -> Self {
+        self.alert = Some(action);
+        self
+    }
+}
+
+/// Payload of the alert raised when a tripwire is touched, see [`Tripwire::alert`].
+#[derive(Debug, Clone, Serialize)]
+pub struct TripwireAlert {
+    /// Name of the space of the tripwire.
+    pub tripwire: String,
+
+    /// The access, answered with the answer of the tripwire.
+    pub access: AuditRecord,
+}
+
+/// Answers an access to an object in the space of the tripwire of the handler and raises its
+/// alert.
+pub(crate) fn tripwire_handler(
+    ctx: &Context,
+    request: &AuthRequestData,
+    handler_data: &HandlerData,
+) -> anyhow::Result<MedusaAnswer> {
+    let tripwire = handler_data
+        .tripwire
+        .as_deref()
+        .context("handler has no tripwire")?;
+
+    let config = ctx.config.load();
+    let completed = CompletedRequest {
+        data: request.clone(),
+        answer: tripwire.answer,
+    };
+    let access = AuditRecord::new(&config, &completed);
+    println!(
+        "tripwire: {} touched by {} {}[{}] with {}",
+        tripwire.space,
+        access.subject,
+        access.subject_id,
+        access.subject_spaces.join(","),
+        access.event
+    );
+
+    if let Some(action) = tripwire.alert.clone() {
+        let payload = TripwireAlert {
+            tripwire: tripwire.space.to_owned(),
+            access,
+        };
+        thread::spawn(move || {
+            if let Err(e) = alert::run_action(&action, &payload) {
+                eprintln!("alert action {:?} failed: {}", action, e);
+            }
+        });
+    }
+
+    Ok(tripwire.answer)
+}
@@ -0,0 +1,336 @@
+//! Accounting of operations granted to domains, see [`ConfigBuilder::resource_accounting`].
+//!
+//! [`ConfigBuilder::resource_accounting`]: crate::medusa::ConfigBuilder::resource_accounting
+
+use crate::medusa::{
+    audit, AuthRequestData, Config, Event, FastDashMap, MedusaAnswer, MedusaClass,
+};
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// Number of buckets a sliding window is divided into, the precision of its counts.
+const WINDOW_BUCKETS: usize = 60;
+
+/// Category of operations counted by [`ResourceAccounting`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UsageCategory {
+    /// Operations creating or changing files, such as `mknod`, `rename` or `truncate`.
+    FileWrites,
+
+    /// Executions of programs.
+    Execs,
+
+    /// Messages sent to IPC message queues.
+    IpcSends,
+}
+
+impl UsageCategory {
+    /// All categories.
+    pub const ALL: [Self; 3] = [Self::FileWrites, Self::Execs, Self::IpcSends];
+
+    /// Returns name of the category.
+    pub const fn name(&self) -> &'static str {
+        match self {
+            Self::FileWrites => "file_writes",
+            Self::Execs => "execs",
+            Self::IpcSends => "ipc_sends",
+        }
+    }
+
+    /// Returns the events counted in the category unless set by [`ResourceAccounting::events`].
+    fn default_events(&self) -> Vec<Event> {
+        match self {
+            Self::FileWrites => vec![
+                Event::Mkdir,
+                Event::Rmdir,
+                Event::Mknod,
+                Event::Link,
+                Event::Unlink,
+                Event::Symlink,
+                Event::Rename,
+                Event::Truncate,
+                Event::Chmod,
+                Event::Chown,
+            ],
+            Self::Execs => vec![Event::Exec, Event::Sexec],
+            Self::IpcSends => vec![Event::IpcMsgsnd],
+        }
+    }
+}
+
+impl fmt::Display for UsageCategory {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Counting of the operations granted to each domain, the subject class and its virtual spaces,
+/// by [`UsageCategory`] over a sliding window.
+///
+/// The counts are shown by the `stats` command of the control socket and handlers read them by
+/// [`Context::usage`], so that quota-style policies such as "at most 100 new files per minute
+/// for this domain" can be expressed by a handler denying requests once the count is reached.
+/// A request is counted once it is answered, so the count read by a handler of a request does
+/// not include the request itself.
+///
+/// [`Context::usage`]: crate::medusa::Context::usage
+#[derive(Debug, Clone)]
+pub struct ResourceAccounting {
+    window: Duration,
+    events: [Vec<Event>; UsageCategory::ALL.len()],
+}
+
+impl ResourceAccounting {
+    /// Creates new `ResourceAccounting` counting the operations of the last `window`.
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            events: UsageCategory::ALL.map(|x| x.default_events()),
+        }
+    }
+
+    /// Sets the events counted in `category` instead of the default ones, e.g. to count only
+    /// `mknod` as a file write when limiting the number of new files.
+    ///
+    /// Returns `Self`.
+    pub fn events<I>(mut self, category: UsageCategory, events: I) -> Self
+    where
+        I: IntoIterator<Item = Event>,
+    {
+        self.events[category as usize] = events.into_iter().collect();
+        self
+    }
+
+    /// Returns the categories event `name` is counted in.
+    fn categories<'a>(&'a self, name: &'a str) -> impl Iterator<Item = UsageCategory> + 'a {
+        UsageCategory::ALL
+            .into_iter()
+            .filter(move |x| self.events[*x as usize].iter().any(|x| x.name() == name))
+    }
+}
+
+/// Operations granted to a domain within the window of [`ResourceAccounting`].
+#[derive(Debug, Clone)]
+pub struct Usage {
+    /// Domain the operations were granted to, such as `process[app,net]`.
+    pub domain: String,
+
+    /// Length of the window the operations were counted in.
+    pub window: Duration,
+
+    counts: [u64; UsageCategory::ALL.len()],
+}
+
+impl Usage {
+    /// Returns the number of operations of `category`.
+    pub fn count(&self, category: UsageCategory) -> u64 {
+        self.counts[category as usize]
+    }
+}
+
+impl fmt::Display for Usage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.domain)?;
+        for category in UsageCategory::ALL {
+            write!(f, " {} {}", category, self.count(category))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Counts of a category in the buckets of a sliding window, each with the index of the bucket
+/// it was counted in since the accounting started.
+#[derive(Clone, Copy)]
+struct SlidingCount {
+    buckets: [(u64, u64); WINDOW_BUCKETS],
+}
+
+impl SlidingCount {
+    fn add(&mut self, bucket: u64) {
+        let slot = &mut self.buckets[bucket as usize % WINDOW_BUCKETS];
+        if slot.0 != bucket {
+            *slot = (bucket, 0);
+        }
+        slot.1 += 1;
+    }
+
+    fn total(&self, bucket: u64) -> u64 {
+        self.buckets
+            .iter()
+            .filter(|(index, _)| bucket.saturating_sub(*index) < WINDOW_BUCKETS as u64)
+            .map(|(_, count)| count)
+            .sum()
+    }
+}
+
+impl Default for SlidingCount {
+    fn default() -> Self {
+        Self {
+            // empty buckets count nothing, whichever bucket they are taken for
+            buckets: [(0, 0); WINDOW_BUCKETS],
+        }
+    }
+}
+
+/// Sliding counts of the domains of a connection.
+pub(crate) struct UsageAccountant {
+    settings: ResourceAccounting,
+    started: Instant,
+    bucket_length: Duration,
+    domains: FastDashMap<String, [SlidingCount; UsageCategory::ALL.len()]>,
+}
+
+impl UsageAccountant {
+    pub(crate) fn new(settings: ResourceAccounting) -> Self {
+        let bucket_length = (settings.window / WINDOW_BUCKETS as u32).max(Duration::from_millis(1));
+
+        Self {
+            settings,
+            started: Instant::now(),
+            bucket_length,
+            domains: Default::default(),
+        }
+    }
+
+    /// Returns the index of the current bucket since the accounting started.
+    fn bucket(&self) -> u64 {
+        (self.started.elapsed().as_nanos() / self.bucket_length.as_nanos()) as u64
+    }
+
+    /// Counts the operation of a request answered with `answer` if it was granted.
+    pub(crate) fn record(
+        &self,
+        config: &Config,
+        auth_data: &AuthRequestData,
+        answer: MedusaAnswer,
+    ) {
+        if matches!(answer, MedusaAnswer::Deny | MedusaAnswer::Err) {
+            return;
+        }
+
+        let mut categories = self.settings.categories(auth_data.evtype.name()).peekable();
+        if categories.peek().is_none() {
+            return;
+        }
+
+        let bucket = self.bucket();
+        let domain = audit::domain_name(config, &auth_data.subject);
+        let mut counts = self.domains.entry(domain).or_default();
+        for category in categories {
+            counts[category as usize].add(bucket);
+        }
+    }
+
+    /// Returns the usage of the domain of `subject`.
+    pub(crate) fn usage(&self, config: &Config, subject: &MedusaClass) -> Usage {
+        let domain = audit::domain_name(config, subject);
+        let counts = match self.domains.get(&domain) {
+            Some(counts) => self.totals(&counts),
+            None => Default::default(),
+        };
+
+        Usage {
+            domain,
+            window: self.settings.window,
+            counts,
+        }
+    }
+
+    /// Returns the usage of each domain which was granted an operation, sorted by the domain.
+    pub(crate) fn usages(&self) -> Vec<Usage> {
+        let mut usages = self
+            .domains
+            .iter()
+            .map(|x| Usage {
+                domain: x.key().clone(),
+                window: self.settings.window,
+                counts: self.totals(x.value()),
+            })
+            .collect::<Vec<_>>();
+        usages.sort_by(|a, b| a.domain.cmp(&b.domain));
+
+        usages
+    }
+
+    fn totals(
+        &self,
+        counts: &[SlidingCount; UsageCategory::ALL.len()],
+    ) -> [u64; UsageCategory::ALL.len()] {
+        let bucket = self.bucket();
+        counts.map(|x| x.total(bucket))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::medusa::SpaceBuilder;
+
+    #[test]
+    fn slides_window() {
+        let mut count = SlidingCount::default();
+        count.add(0);
+        count.add(0);
+        count.add(5);
+
+        assert_eq!(count.total(5), 3);
+        assert_eq!(count.total(WINDOW_BUCKETS as u64 - 1), 3);
+        assert_eq!(count.total(WINDOW_BUCKETS as u64), 1);
+        assert_eq!(count.total(WINDOW_BUCKETS as u64 + 5), 0);
+    }
+
+    #[test]
+    fn resets_reused_buckets() {
+        let mut count = SlidingCount::default();
+        count.add(1);
+        count.add(1);
+        assert_eq!(count.buckets[1], (1, 2));
+
+        let reused = WINDOW_BUCKETS as u64 + 1;
+        count.add(reused);
+        assert_eq!(count.buckets[1], (reused, 1));
+        assert_eq!(count.total(reused), 1);
+    }
+
+    #[test]
+    fn counts_configured_events() {
+        let config = Config::builder()
+            .add_space(
+                SpaceBuilder::new()
+                    .with_name("app")
+                    .with_path_recursive("fs/srv/app"),
+            )
+            .build()
+            .unwrap();
+        let accountant = UsageAccountant::new(
+            ResourceAccounting::new(Duration::from_secs(3600))
+                .events(UsageCategory::FileWrites, [Event::Mknod]),
+        );
+        let request = |event| AuthRequestData::for_test(&config, event, &["app"], Some(&["app"]));
+
+        accountant.record(&config, &request("mknod"), MedusaAnswer::Allow);
+        accountant.record(&config, &request("mknod"), MedusaAnswer::Yes);
+        accountant.record(&config, &request("unlink"), MedusaAnswer::Allow);
+        accountant.record(&config, &request("exec"), MedusaAnswer::Allow);
+        accountant.record(&config, &request("open"), MedusaAnswer::Allow);
+        // only granted operations are counted
+        accountant.record(&config, &request("mknod"), MedusaAnswer::Deny);
+        accountant.record(&config, &request("exec"), MedusaAnswer::Err);
+
+        let usage = accountant.usage(&config, &request("mknod").subject);
+        assert_eq!(usage.domain, "process[app]");
+        assert_eq!(usage.count(UsageCategory::FileWrites), 2);
+        assert_eq!(usage.count(UsageCategory::Execs), 1);
+        assert_eq!(usage.count(UsageCategory::IpcSends), 0);
+
+        let other = AuthRequestData::for_test(&config, "mknod", &[], None);
+        assert_eq!(
+            accountant
+                .usage(&config, &other.subject)
+                .count(UsageCategory::FileWrites),
+            0
+        );
+        assert_eq!(accountant.usages().len(), 1);
+    }
+}
@@ -0,0 +1,121 @@
+//! Detection of handlers running for too long, see [`ConfigBuilder::handler_watchdog`].
+//!
+//! [`ConfigBuilder::handler_watchdog`]: crate::medusa::ConfigBuilder::handler_watchdog
+
+use crate::medusa::{AuthRequestData, Context, FastDashMap};
+use std::io;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Weak};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Handler running for a request.
+struct Running {
+    started: Instant,
+    event: Arc<str>,
+    subject: String,
+    // whether the handler was already reported as stuck
+    reported: bool,
+}
+
+/// Handlers currently running, by request id.
+#[derive(Default)]
+pub(crate) struct RunningHandlers {
+    running: FastDashMap<u64, Running>,
+}
+
+impl RunningHandlers {
+    /// Records that handlers of `auth_data` started. They are considered finished once the
+    /// returned guard is dropped.
+    pub(crate) fn start<'a>(&'a self, auth_data: &AuthRequestData) -> RunningGuard<'a> {
+        let running = Running {
+            started: Instant::now(),
+            event: Arc::clone(&auth_data.evtype.header.name),
+            subject: auth_data.subject.header.name().to_owned(),
+            reported: false,
+        };
+        self.running.insert(auth_data.request_id, running);
+
+        RunningGuard {
+            handlers: self,
+            request_id: auth_data.request_id,
+        }
+    }
+
+    /// Returns the number of requests whose handlers are running.
+    pub(crate) fn len(&self) -> usize {
+        self.running.len()
+    }
+
+    /// Reports handlers which have been running for longer than `threshold` and were not
+    /// reported yet.
+    fn report_stuck(&self, threshold: Duration) {
+        let now = Instant::now();
+        for mut entry in self.running.iter_mut() {
+            let request_id = *entry.key();
+            let running = entry.value_mut();
+            let elapsed = now.duration_since(running.started);
+            if running.reported || elapsed < threshold {
+                continue;
+            }
+
+            running.reported = true;
+            eprintln!(
+                "handlers of request {} ({} of {}) have been running for {:?}",
+                request_id, running.event, running.subject, elapsed
+            );
+        }
+    }
+}
+
+/// Marks handlers of a request as finished when dropped, see [`RunningHandlers::start`].
+pub(crate) struct RunningGuard<'a> {
+    handlers: &'a RunningHandlers,
+    request_id: u64,
+}
+
+impl Drop for RunningGuard<'_> {
+    fn drop(&mut self) {
+        if let Some((_, running)) = self.handlers.running.remove(&self.request_id) {
+            if running.reported {
+                eprintln!(
+                    "handlers of request {} finished after {:?}",
+                    self.request_id,
+                    running.started.elapsed()
+                );
+            }
+        }
+    }
+}
+
+/// Periodically reports stuck handlers of `ctx` until the returned sender is dropped. Returns
+/// `None` if the watchdog is disabled.
+///
+/// The watchdog runs on its own thread, as a blocking handler may stall the runtime of the
+/// connection.
+pub(crate) fn spawn(ctx: &Arc<Context>) -> io::Result<Option<mpsc::Sender<()>>> {
+    let threshold = match ctx.config().handler_watchdog {
+        Some(threshold) => threshold,
+        None => return Ok(None),
+    };
+    let (stop, stopped) = mpsc::channel();
+    let ctx = Arc::downgrade(ctx);
+
+    thread::Builder::new()
+        .name("medusa-watchdog".to_owned())
+        .spawn(move || {
+            // a stuck handler is reported at most half of the threshold late
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(threshold / 2) {
+                let ctx = match Weak::upgrade(&ctx) {
+                    Some(ctx) => ctx,
+                    None => break,
+                };
+
+                if let Some(running) = &ctx.running_handlers {
+                    running.report_stuck(threshold);
+                }
+            }
+        })?;
+
+    Ok(Some(stop))
+}
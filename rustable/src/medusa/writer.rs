@@ -1,28 +1,274 @@
-use std::io::Write;
+use crate::medusa::{DecisionAnswer, Executor, PipelineStats, Stage, ThreadScheduling};
+use std::collections::VecDeque;
+use std::io::{self, Write};
 use std::sync::Arc;
+use std::thread;
+use std::time::Instant;
 use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
 
+/// Message queued for the security module.
+pub(crate) enum Message {
+    // fixed-size answers are passed by value, so that answering a request does not allocate
+    Decision(DecisionAnswer),
+    Data(Arc<[u8]>),
+}
+
+impl From<DecisionAnswer> for Message {
+    fn from(decision: DecisionAnswer) -> Self {
+        Message::Decision(decision)
+    }
+}
+
+impl From<Arc<[u8]>> for Message {
+    fn from(data: Arc<[u8]>) -> Self {
+        Message::Data(data)
+    }
+}
+
+/// Message with the time it was queued and whether it is latency-critical.
+type Queued = (Instant, bool, Message);
+
+/// Messages received but not written yet. Decisions of latency-critical events are written
+/// before other decisions, see [`ConfigBuilder::latency_critical`]. Fetch and update requests
+/// keep their place, so that no decision overtakes a request queued before it.
+///
+/// [`ConfigBuilder::latency_critical`]: crate::medusa::ConfigBuilder::latency_critical
+#[derive(Default)]
+struct Backlog {
+    entries: VecDeque<Entry>,
+}
+
+enum Entry {
+    Decisions {
+        critical: VecDeque<(Instant, Message)>,
+        bulk: VecDeque<(Instant, Message)>,
+    },
+    Data(Instant, Message),
+}
+
+impl Backlog {
+    fn push(&mut self, (queued, is_critical, message): Queued) {
+        if let Message::Data(_) = message {
+            self.entries.push_back(Entry::Data(queued, message));
+            return;
+        }
+
+        if !matches!(self.entries.back(), Some(Entry::Decisions { .. })) {
+            self.entries.push_back(Entry::Decisions {
+                critical: VecDeque::new(),
+                bulk: VecDeque::new(),
+            });
+        }
+        if let Some(Entry::Decisions { critical, bulk }) = self.entries.back_mut() {
+            let decisions = if is_critical { critical } else { bulk };
+            decisions.push_back((queued, message));
+        }
+    }
+
+    fn pop(&mut self) -> Option<(Instant, Message)> {
+        loop {
+            if let Entry::Decisions { critical, bulk } = self.entries.front_mut()? {
+                if let Some(decision) = critical.pop_front().or_else(|| bulk.pop_front()) {
+                    return Some(decision);
+                }
+            }
+
+            // written decisions leave their entry empty
+            if let Some(Entry::Data(queued, message)) = self.entries.pop_front() {
+                return Some((queued, message));
+            }
+        }
+    }
+}
+
+#[derive(Clone)]
 pub(crate) struct Writer {
-    sender: UnboundedSender<Arc<[u8]>>,
+    sender: UnboundedSender<Queued>,
 }
 
 impl Writer {
-    pub(crate) fn new<W>(mut write_handle: W) -> Self
+    /// Creates new `Writer`. Messages are written by a task spawned on `executor`, or by a
+    /// dedicated thread if `scheduling` is set.
+    pub(crate) fn new<W>(
+        mut write_handle: W,
+        stats: Arc<PipelineStats>,
+        scheduling: Option<ThreadScheduling>,
+        executor: &dyn Executor,
+    ) -> io::Result<Self>
     where
         W: Write + Unpin + Send + 'static,
     {
-        let (sender, mut receiver): (_, UnboundedReceiver<Arc<[u8]>>) = mpsc::unbounded_channel();
+        let (sender, mut receiver) = mpsc::unbounded_channel::<Queued>();
+
+        let scheduling = match scheduling {
+            Some(scheduling) => scheduling,
+            None => {
+                executor.spawn(Box::pin(async move {
+                    let mut backlog = Backlog::default();
+                    while let Some(queued) = receiver.recv().await {
+                        backlog.push(queued);
+                        write_backlog(&mut write_handle, &stats, &mut receiver, &mut backlog);
+                    }
+                }));
 
-        tokio::spawn(async move {
-            while let Some(data) = receiver.recv().await {
-                write_handle.write_all(&data).unwrap();
+                return Ok(Self { sender });
             }
-        });
+        };
+
+        let (started, result) = std::sync::mpsc::channel();
+        thread::Builder::new()
+            .name("medusa-writer".to_owned())
+            .spawn(move || {
+                let res = scheduling.apply();
+                let failed = res.is_err();
+                let _ = started.send(res);
+                if !failed {
+                    write_blocking(write_handle, &stats, receiver);
+                }
+            })?;
+        result.recv().expect("writer thread is gone")?;
 
-        Self { sender }
+        Ok(Self { sender })
     }
 
-    pub(crate) fn write(&self, data: Arc<[u8]>) {
-        self.sender.send(data).expect("writer is disconnected");
+    pub(crate) fn write(&self, message: impl Into<Message>) {
+        self.sender
+            .send((Instant::now(), false, message.into()))
+            .expect("writer is disconnected");
+    }
+
+    /// Same as [`Writer::write`] for the decision of a latency-critical event, which is written
+    /// before decisions of other events waiting in the queue.
+    pub(crate) fn write_critical(&self, decision: DecisionAnswer) {
+        self.sender
+            .send((Instant::now(), true, decision.into()))
+            .expect("writer is disconnected");
+    }
+}
+
+fn write<W: Write>(write_handle: &mut W, stats: &PipelineStats, queued: Instant, message: Message) {
+    match message {
+        Message::Decision(decision) => write_handle.write_all(&decision.to_vec()),
+        Message::Data(data) => write_handle.write_all(&data),
+    }
+    .unwrap();
+    stats.record(Stage::WriteQueue, queued.elapsed());
+}
+
+fn write_blocking<W: Write>(
+    mut write_handle: W,
+    stats: &PipelineStats,
+    mut receiver: UnboundedReceiver<Queued>,
+) {
+    let mut backlog = Backlog::default();
+    while let Some(queued) = receiver.blocking_recv() {
+        backlog.push(queued);
+        write_backlog(&mut write_handle, stats, &mut receiver, &mut backlog);
+    }
+}
+
+/// Writes messages until none are waiting. Messages queued meanwhile are taken into the
+/// backlog before each write, so that a critical decision waits for one write at most.
+fn write_backlog<W: Write>(
+    write_handle: &mut W,
+    stats: &PipelineStats,
+    receiver: &mut UnboundedReceiver<Queued>,
+    backlog: &mut Backlog,
+) {
+    loop {
+        while let Ok(queued) = receiver.try_recv() {
+            backlog.push(queued);
+        }
+        match backlog.pop() {
+            Some((queued, message)) => write(write_handle, stats, queued, message),
+            None => break,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::iter;
+
+    fn decision(request_id: u64, is_critical: bool) -> Queued {
+        let decision = DecisionAnswer {
+            request_id,
+            status: 0,
+        };
+        (Instant::now(), is_critical, decision.into())
+    }
+
+    fn data(tag: u8) -> Queued {
+        (Instant::now(), false, Message::Data(Arc::from([tag])))
+    }
+
+    fn drain(backlog: &mut Backlog) -> Vec<String> {
+        iter::from_fn(|| backlog.pop())
+            .map(|(_, message)| match message {
+                Message::Decision(decision) => format!("decision {}", { decision.request_id }),
+                Message::Data(data) => format!("data {}", data[0]),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn writes_critical_decisions_first_within_runs() {
+        let mut backlog = Backlog::default();
+        backlog.push(decision(1, false));
+        backlog.push(decision(2, true));
+        backlog.push(data(3));
+        backlog.push(decision(4, false));
+        backlog.push(decision(5, true));
+        backlog.push(decision(6, true));
+
+        assert_eq!(
+            drain(&mut backlog),
+            [
+                "decision 2",
+                "decision 1",
+                "data 3",
+                "decision 5",
+                "decision 6",
+                "decision 4"
+            ]
+        );
+    }
+
+    #[test]
+    fn never_overtakes_data() {
+        let mut backlog = Backlog::default();
+        backlog.push(data(1));
+        backlog.push(decision(2, true));
+        backlog.push(data(3));
+        backlog.push(data(4));
+        backlog.push(decision(5, true));
+
+        assert_eq!(
+            drain(&mut backlog),
+            ["data 1", "decision 2", "data 3", "data 4", "decision 5"]
+        );
+    }
+
+    #[test]
+    fn skips_written_decisions() {
+        let mut backlog = Backlog::default();
+        backlog.push(decision(1, true));
+        assert!(backlog.pop().is_some());
+
+        // the emptied run is still the last entry and takes the next decisions
+        backlog.push(decision(2, false));
+        backlog.push(decision(3, true));
+        assert_eq!(backlog.entries.len(), 1);
+        assert_eq!(drain(&mut backlog), ["decision 3", "decision 2"]);
+        assert!(backlog.entries.is_empty());
+
+        backlog.push(decision(4, false));
+        assert!(backlog.pop().is_some());
+        backlog.push(data(5));
+        backlog.push(decision(6, false));
+        assert_eq!(backlog.entries.len(), 3);
+        assert_eq!(drain(&mut backlog), ["data 5", "decision 6"]);
+        assert!(backlog.entries.is_empty());
     }
 }